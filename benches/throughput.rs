@@ -0,0 +1,79 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Benchmark, Criterion, Throughput};
+use spatialos_specs::bench_support::*;
+
+const ENTITIES: u32 = 10_000;
+const COMMANDS: u32 = 1_000;
+const BLOBS: u32 = 16;
+const BLOB_SIZE: usize = 4 << 20;
+
+fn reader(c: &mut Criterion) {
+    c.bench(
+        "reader",
+        Benchmark::new("checkout", |b| {
+            b.iter_with_setup(setup_world, |world| {
+                checkout_entities(&world, i64::from(ENTITIES))
+            })
+        })
+        .throughput(Throughput::Elements(ENTITIES)),
+    );
+
+    c.bench(
+        "reader",
+        Benchmark::new("component_updates", |b| {
+            let world = setup_world();
+            checkout_entities(&world, i64::from(ENTITIES));
+            b.iter(|| apply_updates(&world))
+        })
+        .throughput(Throughput::Elements(ENTITIES)),
+    );
+}
+
+fn writer(c: &mut Criterion) {
+    c.bench(
+        "writer",
+        Benchmark::new("serialize_updates", |b| {
+            let world = setup_world();
+            checkout_entities(&world, i64::from(ENTITIES));
+            b.iter(|| serialize_updates(&world))
+        })
+        .throughput(Throughput::Elements(ENTITIES)),
+    );
+}
+
+fn commands(c: &mut Criterion) {
+    c.bench(
+        "commands",
+        Benchmark::new("round_trip", |b| {
+            let world = setup_world();
+            b.iter(|| command_round_trip(&world, COMMANDS as usize))
+        })
+        .throughput(Throughput::Elements(COMMANDS)),
+    );
+}
+
+// Received payloads going from the op into storage through the reader, with a
+// `SharedBytes` field and with a `Vec<u8>` field.
+fn blobs(c: &mut Criterion) {
+    c.bench(
+        "blobs",
+        Benchmark::new("shared", |b| {
+            let world = setup_world();
+            checkout_entities(&world, i64::from(BLOBS));
+            let received = received_blob(BLOB_SIZE);
+            b.iter(|| add_received(&world, &received, i64::from(BLOBS)))
+        })
+        .with_function("copied", |b| {
+            let world = setup_world();
+            checkout_entities(&world, i64::from(BLOBS));
+            let received = received_byte_vec(BLOB_SIZE);
+            b.iter(|| add_received(&world, &received, i64::from(BLOBS)))
+        })
+        .throughput(Throughput::Bytes(BLOBS * BLOB_SIZE as u32)),
+    );
+}
+
+criterion_group!(benches, reader, writer, commands, blobs);
+criterion_main!(benches);
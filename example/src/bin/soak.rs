@@ -0,0 +1,343 @@
+//! A headless soak test which connects several simulated workers to a local deployment.
+//!
+//! Each worker spawns entities which only it may write to, then moves them at a fixed rate
+//! for the duration of the test. Once the updates stop and the deployment has had time to
+//! settle, every worker's view of every spawned entity is checked against the final
+//! position written by its owner. The run fails if any view diverges, or if fewer entities
+//! were spawned and moved than requested, so that a run which observed nothing can't pass.
+//!
+//! cargo run --bin soak -- --workers 4 --entities 50 --updates-per-second 30 --duration-secs 120
+extern crate structopt;
+
+use example::generated::improbable::*;
+use example::{get_connection, Command, Opt};
+use spatialos_sdk::worker::entity::Entity as WorkerEntity;
+use spatialos_sdk::worker::entity_builder::EntityBuilder;
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use spatialos_specs::prelude::*;
+use spatialos_specs::TickRateController;
+use specs::prelude::*;
+use std::collections::HashMap;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(
+    name = "soak",
+    about = "Soak tests spatialos-specs against a local deployment."
+)]
+struct SoakOpt {
+    /// The number of simulated workers to connect.
+    #[structopt(long = "workers", default_value = "4")]
+    workers: usize,
+
+    /// The number of entities spawned by each worker.
+    #[structopt(long = "entities", default_value = "20")]
+    entities: usize,
+
+    /// How many times per second each worker moves each of its entities.
+    #[structopt(long = "updates-per-second", default_value = "10")]
+    updates_per_second: u32,
+
+    /// How long to move entities for.
+    #[structopt(long = "duration-secs", default_value = "60")]
+    duration_secs: u64,
+
+    /// How long to wait after the last update before checking convergence.
+    #[structopt(long = "settle-secs", default_value = "10")]
+    settle_secs: u64,
+
+    #[structopt(long = "worker-type", default_value = "RustWorker")]
+    worker_type: String,
+
+    #[structopt(long = "host")]
+    host: Option<String>,
+
+    #[structopt(long = "port")]
+    port: Option<u16>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    Mutating,
+    Settling,
+}
+
+impl Default for Phase {
+    fn default() -> Self {
+        Phase::Mutating
+    }
+}
+
+#[derive(Default)]
+struct SoakState {
+    worker_id: String,
+    entities_to_spawn: usize,
+    spawn_requested: bool,
+    spawned: Vec<WorkerEntityId>,
+    failed_spawns: usize,
+    updates_sent: u64,
+    final_positions: HashMap<EntityId, [f64; 3]>,
+}
+
+struct SpawnEntitiesSys;
+
+impl<'a> System<'a> for SpawnEntitiesSys {
+    type SystemData = (Write<'a, SoakState>, SystemCommandSender<'a>);
+
+    fn run(&mut self, (mut state, mut sys_command_sender): Self::SystemData) {
+        if state.spawn_requested {
+            return;
+        }
+        state.spawn_requested = true;
+
+        let entities = (0..state.entities_to_spawn)
+            .map(|_| soak_entity(&state.worker_id))
+            .collect();
+
+        sys_command_sender.create_entities(entities, |results, system_data| {
+            let (mut state, _) = system_data.fetch::<Self>();
+
+            for result in results {
+                match result {
+                    Ok(entity_id) => state.spawned.push(entity_id),
+                    Err(status) => {
+                        println!("[{}] Failed to spawn entity: {:?}", state.worker_id, status);
+                        state.failed_spawns += 1;
+                    }
+                }
+            }
+        });
+    }
+}
+
+struct MoveEntitiesSys {
+    interval: Duration,
+    last_move: Option<Instant>,
+    step: u64,
+}
+
+impl<'a> System<'a> for MoveEntitiesSys {
+    type SystemData = (
+        Read<'a, Phase>,
+        Write<'a, SoakState>,
+        EntityIds<'a>,
+        SpatialWriteStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (phase, mut state, entity_ids, mut positions): Self::SystemData) {
+        if *phase != Phase::Mutating {
+            return;
+        }
+
+        let now = Instant::now();
+        match self.last_move {
+            Some(last_move) if now.duration_since(last_move) < self.interval => return,
+            _ => self.last_move = Some(now),
+        }
+        self.step += 1;
+
+        for entity_id in state.spawned.clone() {
+            let entity_id = EntityId(entity_id);
+            let entity = match entity_ids.get_entity(entity_id) {
+                Some(entity) => entity,
+                None => continue,
+            };
+
+            if let Some(position) = positions.get_mut(entity) {
+                let coords = step_position(entity_id, self.step);
+                position.coords = Coordinates {
+                    x: coords[0],
+                    y: coords[1],
+                    z: coords[2],
+                };
+                state.final_positions.insert(entity_id, coords);
+                state.updates_sent += 1;
+            }
+        }
+    }
+}
+
+struct WorkerReport {
+    worker_id: String,
+    failed_spawns: usize,
+    updates_sent: u64,
+    final_positions: HashMap<EntityId, [f64; 3]>,
+    view: HashMap<EntityId, [f64; 3]>,
+}
+
+fn main() {
+    let opt = SoakOpt::from_args();
+
+    let workers: Vec<_> = (0..opt.workers)
+        .map(|index| {
+            let opt = opt.clone();
+            thread::spawn(move || run_worker(index, &opt))
+        })
+        .collect();
+
+    let reports: Vec<WorkerReport> = workers
+        .into_iter()
+        .map(|worker| worker.join().expect("Soak worker panicked."))
+        .collect();
+
+    let mut expected = HashMap::new();
+    let mut failed_spawns = 0;
+    let mut updates_sent = 0;
+    for report in &reports {
+        expected.extend(report.final_positions.iter().map(|(k, v)| (*k, *v)));
+        failed_spawns += report.failed_spawns;
+        updates_sent += report.updates_sent;
+    }
+
+    let mut divergent = 0;
+    for report in &reports {
+        for (entity_id, position) in &expected {
+            match report.view.get(entity_id) {
+                Some(seen) if seen == position => {}
+                seen => {
+                    divergent += 1;
+                    println!(
+                        "[{}] Entity {} expected at {:?}, seen at {:?}",
+                        report.worker_id, entity_id, position, seen
+                    );
+                }
+            }
+        }
+    }
+
+    println!(
+        "{} workers, {} entities, {} updates, {} failed spawns, {} divergent views",
+        reports.len(),
+        expected.len(),
+        updates_sent,
+        failed_spawns,
+        divergent
+    );
+
+    // Every requested entity must have been spawned and moved at least once.
+    let expected_entities = opt.workers * opt.entities;
+    let mut passed = divergent == 0 && failed_spawns == 0;
+    if expected.len() < expected_entities {
+        println!(
+            "Only {} of {} entities were spawned and moved.",
+            expected.len(),
+            expected_entities
+        );
+        passed = false;
+    }
+    if expected_entities == 0 || updates_sent < expected_entities as u64 {
+        println!(
+            "Only {} updates were sent for {} entities.",
+            updates_sent, expected_entities
+        );
+        passed = false;
+    }
+
+    if !passed {
+        process::exit(1);
+    }
+}
+
+fn run_worker(index: usize, opt: &SoakOpt) -> WorkerReport {
+    let worker_id = format!("soak-{}-{}", index, process::id());
+
+    let connection = get_connection(Opt {
+        worker_type: opt.worker_type.clone(),
+        worker_id: Some(worker_id.clone()),
+        connect_with_poll: false,
+        command: Command::Receptionist {
+            connect_with_external_ip: false,
+            host: opt.host.clone(),
+            port: opt.port,
+        },
+    })
+    .unwrap_or_else(|e| panic!("[{}] {}", worker_id, e));
+
+    let mut world = World::new();
+    world.insert(connection);
+    world.insert(SoakState {
+        worker_id: worker_id.clone(),
+        entities_to_spawn: opt.entities,
+        ..Default::default()
+    });
+
+    let mut dispatcher = DispatcherBuilder::new()
+        .with(SpatialReaderSystem, "reader", &[])
+        .with_barrier()
+        .with(SpawnEntitiesSys, "spawn", &[])
+        .with(
+            MoveEntitiesSys {
+                interval: Duration::from_secs(1) / opt.updates_per_second.max(1),
+                last_move: None,
+                step: 0,
+            },
+            "move",
+            &[],
+        )
+        .with_barrier()
+        .with(SpatialWriterSystem, "writer", &[])
+        .build();
+
+    dispatcher.setup(&mut world);
+    world.insert(TickRateController::default());
+
+    let start = Instant::now();
+    let mutate_until = start + Duration::from_secs(opt.duration_secs);
+    let settle_until = mutate_until + Duration::from_secs(opt.settle_secs);
+
+    while Instant::now() < settle_until {
+        if Instant::now() >= mutate_until {
+            *world.write_resource::<Phase>() = Phase::Settling;
+        }
+
+        dispatcher.dispatch(&world);
+
+        thread::sleep(
+            world
+                .read_resource::<TickRateController>()
+                .recommended_sleep(),
+        );
+    }
+
+    let view = {
+        let (entity_ids, positions) =
+            world.system_data::<(EntityIds, SpatialReadStorage<Position>)>();
+        positions
+            .join_with_ids(&entity_ids)
+            .map(|(entity_id, position)| {
+                (
+                    entity_id,
+                    [position.coords.x, position.coords.y, position.coords.z],
+                )
+            })
+            .collect()
+    };
+
+    let mut state = world.write_resource::<SoakState>();
+    WorkerReport {
+        worker_id,
+        failed_spawns: state.failed_spawns,
+        updates_sent: state.updates_sent,
+        final_positions: std::mem::replace(&mut state.final_positions, HashMap::new()),
+        view,
+    }
+}
+
+fn soak_entity(worker_id: &str) -> WorkerEntity {
+    let write_access = format!("workerId:{}", worker_id);
+    let mut builder = EntityBuilder::new(0.0, 0.0, 0.0, &write_access);
+
+    builder.set_metadata("SoakEntity", &write_access);
+    builder.set_entity_acl_write_access(&write_access);
+
+    builder.build().unwrap()
+}
+
+// A deterministic position for each step, so that every update changes the position.
+fn step_position(entity_id: EntityId, step: u64) -> [f64; 3] {
+    let angle = (step as f64) * 0.1 + (entity_id.id().id as f64);
+    [angle.cos() * 50.0, 0.0, angle.sin() * 50.0]
+}
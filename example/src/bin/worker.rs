@@ -4,10 +4,10 @@ use example::player::*;
 use example::player_connection::*;
 use example::{connection_handler::*, opt::*};
 use spatialos_sdk::worker::connection::Connection;
-use spatialos_specs::*;
+use spatialos_specs::prelude::*;
+use spatialos_specs::TickRateController;
 use specs::prelude::*;
 use std::thread;
-use std::time::Duration;
 use structopt::StructOpt;
 
 fn main() {
@@ -21,7 +21,7 @@ fn main() {
 
     let mut world = World::new();
 
-    world.add_resource(connection);
+    world.insert(connection);
 
     let mut dispatcher = DispatcherBuilder::new()
         .with(SpatialReaderSystem, "reader", &[])
@@ -39,11 +39,17 @@ fn main() {
         .with(SpatialWriterSystem, "writer", &[])
         .build();
 
-    dispatcher.setup(&mut world.res);
+    dispatcher.setup(&mut world);
+
+    world.insert(TickRateController::default());
 
     loop {
-        dispatcher.dispatch(&world.res);
+        dispatcher.dispatch(&world);
 
-        thread::sleep(Duration::from_millis(30));
+        thread::sleep(
+            world
+                .read_resource::<TickRateController>()
+                .recommended_sleep(),
+        );
     }
 }
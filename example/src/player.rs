@@ -1,6 +1,6 @@
 use crate::generated::game::*;
 use crate::generated::improbable::*;
-use spatialos_specs::*;
+use spatialos_specs::prelude::*;
 use specs::prelude::*;
 
 pub struct MovePlayerSys;
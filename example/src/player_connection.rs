@@ -1,7 +1,7 @@
 use crate::generated::game::*;
 use spatialos_sdk::worker::entity::Entity as WorkerEntity;
 use spatialos_sdk::worker::entity_builder::EntityBuilder;
-use spatialos_specs::*;
+use spatialos_specs::prelude::*;
 use specs::prelude::*;
 
 pub struct ClientBootstrap {
@@ -17,12 +17,12 @@ impl<'a> System<'a> for ClientBootstrap {
 
     fn run(&mut self, (creator, entity_ids, mut player_command_sender): Self::SystemData) {
         if !self.has_requested_player {
-            match (&creator, &entity_ids).join().next() {
-                Some((_, player_creator_entity_id)) => {
+            match creator.join_with_ids(&entity_ids).next() {
+                Some((player_creator_entity_id, _)) => {
                     self.has_requested_player = true;
 
                     player_command_sender.send_command(
-                        *player_creator_entity_id,
+                        player_creator_entity_id,
                         PlayerCreatorCommandRequest::CreatePlayer(CreatePlayerRequest {
                             name: "MyName".to_string(),
                         }),
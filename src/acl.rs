@@ -0,0 +1,187 @@
+//! Evaluating `EntityAcl` requirement sets against worker attributes.
+//!
+//! A `WorkerRequirementSet` is satisfied by a worker with every attribute of any one of its
+//! attribute sets. Implementing these traits for the generated standard library types
+//! allows asking questions such as whether a worker would be able to write a component:
+//!
+//! ```ignore
+//! impl AttributeSet for WorkerAttributeSet {
+//!     fn attributes(&self) -> &[String] {
+//!         &self.attribute
+//!     }
+//! }
+//!
+//! impl RequirementSet for WorkerRequirementSet {
+//!     type AttributeSet = WorkerAttributeSet;
+//!
+//!     fn attribute_sets(&self) -> &[WorkerAttributeSet] {
+//!         &self.attribute_set
+//!     }
+//! }
+//!
+//! impl Acl for EntityAcl {
+//!     type RequirementSet = WorkerRequirementSet;
+//!
+//!     fn read_requirement(&self) -> &WorkerRequirementSet {
+//!         &self.read_acl
+//!     }
+//!
+//!     fn write_requirement(&self, component_id: ComponentId) -> Option<&WorkerRequirementSet> {
+//!         self.component_write_acl.get(&component_id)
+//!     }
+//! }
+//!
+//! let client = ["workerId:UnityClient0".to_string(), "client".to_string()];
+//! if acl.can_write(PlayerInput::ID, &client) {
+//!     // ...
+//! }
+//!
+//! // The client a component is delegated to, if any.
+//! let owner = acl.writer(PlayerInput::ID);
+//! ```
+use spatialos_sdk::worker::component::ComponentId;
+
+/// Returns the worker ID of a worker's unique attribute, such as `workerId:UnityClient0`.
+pub fn worker_id_from_attribute(attribute: &str) -> Option<&str> {
+    if attribute.starts_with("workerId:") {
+        Some(&attribute["workerId:".len()..])
+    } else {
+        None
+    }
+}
+
+/// A set of attributes which a worker must all have.
+pub trait AttributeSet {
+    fn attributes(&self) -> &[String];
+
+    /// The worker ID of the unique attribute in the set, if there is one.
+    fn worker_id(&self) -> Option<&str> {
+        self.attributes()
+            .iter()
+            .filter_map(|attribute| worker_id_from_attribute(attribute))
+            .next()
+    }
+
+    /// Returns whether a worker with the given attributes has all of these.
+    fn is_satisfied_by(&self, worker_attributes: &[String]) -> bool {
+        self.attributes()
+            .iter()
+            .all(|attribute| worker_attributes.contains(attribute))
+    }
+}
+
+/// Alternative attribute sets, any of which a worker may have.
+pub trait RequirementSet {
+    type AttributeSet: AttributeSet;
+
+    fn attribute_sets(&self) -> &[Self::AttributeSet];
+
+    /// Returns whether a worker with the given attributes satisfies any attribute set. A
+    /// requirement set without attribute sets matches no worker.
+    fn matches(&self, worker_attributes: &[String]) -> bool {
+        self.attribute_sets()
+            .iter()
+            .any(|set| set.is_satisfied_by(worker_attributes))
+    }
+
+    /// The worker ID of the only worker which satisfies the requirement, if it has a single
+    /// attribute set which requires a worker's unique attribute.
+    fn single_worker(&self) -> Option<&str> {
+        match self.attribute_sets() {
+            [set] => set.worker_id(),
+            _ => None,
+        }
+    }
+}
+
+/// Access to the read and write requirements of an entity's ACL.
+pub trait Acl {
+    type RequirementSet: RequirementSet;
+
+    fn read_requirement(&self) -> &Self::RequirementSet;
+
+    /// The requirement for writing the component, or `None` if no worker may write it.
+    fn write_requirement(&self, component_id: ComponentId) -> Option<&Self::RequirementSet>;
+
+    fn can_read(&self, worker_attributes: &[String]) -> bool {
+        self.read_requirement().matches(worker_attributes)
+    }
+
+    fn can_write(&self, component_id: ComponentId, worker_attributes: &[String]) -> bool {
+        self.write_requirement(component_id)
+            .map_or(false, |requirement| requirement.matches(worker_attributes))
+    }
+
+    /// The worker ID of the only worker which may write the component, such as the client a
+    /// component has been delegated to.
+    fn writer(&self, component_id: ComponentId) -> Option<&str> {
+        self.write_requirement(component_id)
+            .and_then(|requirement| requirement.single_worker())
+    }
+}
+
+impl AttributeSet for Vec<String> {
+    fn attributes(&self) -> &[String] {
+        self
+    }
+}
+
+impl<A: AttributeSet> RequirementSet for Vec<A> {
+    type AttributeSet = A;
+
+    fn attribute_sets(&self) -> &[A] {
+        self
+    }
+}
+
+#[test]
+fn requirement_sets_should_match_any_complete_attribute_set() {
+    use std::collections::HashMap;
+
+    let attributes =
+        |attributes: &[&str]| -> Vec<String> { attributes.iter().map(|a| a.to_string()).collect() };
+
+    struct TestAcl {
+        read: Vec<Vec<String>>,
+        write: HashMap<ComponentId, Vec<Vec<String>>>,
+    }
+
+    impl Acl for TestAcl {
+        type RequirementSet = Vec<Vec<String>>;
+
+        fn read_requirement(&self) -> &Vec<Vec<String>> {
+            &self.read
+        }
+
+        fn write_requirement(&self, component_id: ComponentId) -> Option<&Vec<Vec<String>>> {
+            self.write.get(&component_id)
+        }
+    }
+
+    let acl = TestAcl {
+        read: vec![attributes(&["server"]), attributes(&["client"])],
+        write: vec![(54, vec![attributes(&["workerId:Client0", "client"])])]
+            .into_iter()
+            .collect(),
+    };
+
+    let client = attributes(&["workerId:Client0", "client"]);
+    let other_client = attributes(&["workerId:Client1", "client"]);
+
+    assert!(acl.can_read(&client));
+    assert!(acl.can_read(&attributes(&["server"])));
+    assert!(!acl.can_read(&attributes(&["observer"])));
+    assert!(acl.can_write(54, &client));
+    assert!(!acl.can_write(54, &other_client));
+    assert!(!acl.can_write(1000, &client));
+    assert!(!Vec::<Vec<String>>::new().matches(&client));
+
+    assert_eq!(Some("Client0"), acl.writer(54));
+    assert_eq!(None, acl.writer(1000));
+    assert_eq!(None, acl.read_requirement().single_worker());
+    assert_eq!(
+        Some("Client0"),
+        worker_id_from_attribute("workerId:Client0")
+    );
+    assert_eq!(None, worker_id_from_attribute("client"));
+}
@@ -0,0 +1,136 @@
+//! Restricting a worker to the components it actually uses.
+//!
+//! Only components whose storages are set up, or which are otherwise referenced, are
+//! compiled into a worker, so build times and binary size already depend only on the
+//! components a worker uses. With a large schema it is still easy to pull in components by
+//! accident, and ops for components which aren't registered are silently ignored. A
+//! `ComponentAllowlist` is a runtime check which declares the worker's components up
+//! front:
+//!
+//! ```ignore
+//! world.insert(
+//!     ComponentAllowlist::new()
+//!         .with_component::<Position>()
+//!         .with_component::<Player>(),
+//! );
+//! ```
+//!
+//! Ops which reference any other component are dropped by the `SpatialReaderSystem`, and an
+//! error naming the component is logged the first time each one is seen. Command requests
+//! for them are failed with `EXCLUDED_COMPONENT`, so that callers don't wait for them to
+//! time out. An excluded component is usually a sign that the worker's interest or its
+//! worker type's component delegation needs updating, or that the component should be
+//! added to the allowlist.
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::connection_handle::SpatialConnectionHandle;
+use crate::logging::{self, LogKind, LogLevel};
+use crate::sdk::SdkConnection;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::connection::WorkerConnection;
+use spatialos_sdk::worker::op::CommandRequestOp;
+use specs::prelude::World;
+use std::collections::HashSet;
+
+/// The reason given when failing command requests for components outside the allowlist.
+pub const EXCLUDED_COMPONENT: &str = "component not handled by this worker";
+
+/// A resource listing the only components this worker handles ops for.
+#[derive(Default)]
+pub struct ComponentAllowlist {
+    allowed: HashSet<ComponentId>,
+    excluded: HashSet<ComponentId>,
+    excluded_ops: usize,
+}
+
+impl ComponentAllowlist {
+    pub fn new() -> ComponentAllowlist {
+        Default::default()
+    }
+
+    /// Allows the component, registering it even if none of its storages are set up.
+    pub fn with_component<T: 'static + WorkerComponent>(mut self) -> ComponentAllowlist {
+        ComponentRegistry::register_component::<T>();
+        self.allowed.insert(T::ID);
+        self
+    }
+
+    pub fn allows(&self, component_id: ComponentId) -> bool {
+        self.allowed.contains(&component_id)
+    }
+
+    /// The excluded components which ops have been received for.
+    pub fn excluded_components(&self) -> impl Iterator<Item = ComponentId> + '_ {
+        self.excluded.iter().cloned()
+    }
+
+    /// The number of ops dropped because they referenced an excluded component.
+    pub fn excluded_ops(&self) -> usize {
+        self.excluded_ops
+    }
+
+    // Returns whether the first time the component was excluded.
+    fn exclude(&mut self, component_id: ComponentId) -> bool {
+        self.excluded_ops += 1;
+        self.excluded.insert(component_id)
+    }
+
+    /// Returns whether an op for the component should be applied, reporting it if not.
+    pub(crate) fn admits(res: &World, component_id: ComponentId) -> bool {
+        if !res.has_value::<ComponentAllowlist>() {
+            return true;
+        }
+
+        let first = {
+            let mut allowlist = res.fetch_mut::<ComponentAllowlist>();
+            if allowlist.allows(component_id) {
+                return true;
+            }
+            allowlist.exclude(component_id)
+        };
+
+        if first {
+            logging::log(
+                res,
+                LogLevel::Error,
+                LogKind::ComponentNotAllowed,
+                &format!(
+                    "Dropping ops for component {}, which is not in the ComponentAllowlist.",
+                    describe_component(component_id)
+                ),
+            );
+        }
+
+        false
+    }
+
+    /// Fails a command request which was dropped because its component is excluded.
+    pub(crate) fn reject_request(res: &World, command_request: &CommandRequestOp) {
+        let mut connection = res.fetch_mut::<WorkerConnection>();
+        SpatialConnectionHandle::new(res, &mut connection)
+            .send_failure(command_request.request_id, EXCLUDED_COMPONENT);
+    }
+}
+
+#[test]
+fn allowlist_should_drop_ops_for_excluded_components() {
+    use crate::generated_test::*;
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+    assert!(ComponentAllowlist::admits(&world, Blob::ID));
+
+    world.insert(ComponentAllowlist::new().with_component::<Position>());
+    assert!(ComponentRegistry::get_interface(Position::ID).is_some());
+
+    assert!(ComponentAllowlist::admits(&world, Position::ID));
+    assert!(!ComponentAllowlist::admits(&world, Blob::ID));
+    assert!(!ComponentAllowlist::admits(&world, Blob::ID));
+
+    let allowlist = world.fetch::<ComponentAllowlist>();
+    assert_eq!(2, allowlist.excluded_ops());
+    assert_eq!(
+        vec![Blob::ID],
+        allowlist.excluded_components().collect::<Vec<_>>()
+    );
+}
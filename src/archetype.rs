@@ -0,0 +1,243 @@
+//! Counts and update rates per entity archetype, taken from the `entity_type` of each
+//! entity's `Metadata`, to find problems such as thousands of trees sending `Position`
+//! updates.
+//!
+//! ```ignore
+//! world.insert(ArchetypeStats::new(|metadata: &Metadata| metadata.entity_type.clone()));
+//! ```
+//!
+//! The rates are recomputed by the `SpatialWriterSystem` at the end of every window, at
+//! which point they are also logged.
+use crate::logging::{self, LogKind, LogLevel};
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Entity, World};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+type ArchetypeExtractor = Box<Fn(&Any) -> Option<String> + Send + Sync>;
+
+/// The statistics of a single archetype.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ArchetypeStat {
+    /// The number of checked out entities of this archetype.
+    pub entities: usize,
+    /// Component updates received per second during the last window.
+    pub updates_received_per_second: f64,
+    /// Component updates sent per second during the last window.
+    pub updates_sent_per_second: f64,
+}
+
+#[derive(Default)]
+struct ArchetypeCounts {
+    entities: usize,
+    updates_received: usize,
+    updates_sent: usize,
+}
+
+/// A resource which enables per-archetype statistics.
+pub struct ArchetypeStats {
+    metadata_component: ComponentId,
+    extractor: ArchetypeExtractor,
+    window: Duration,
+    window_start: Option<Instant>,
+    entity_archetypes: HashMap<Entity, String>,
+    counts: HashMap<String, ArchetypeCounts>,
+    stats: HashMap<String, ArchetypeStat>,
+}
+
+impl ArchetypeStats {
+    /// Takes the archetype of each entity from the component `M`, usually `Metadata`.
+    pub fn new<M, F>(archetype: F) -> ArchetypeStats
+    where
+        M: 'static + WorkerComponent,
+        F: 'static + Fn(&M) -> String + Send + Sync,
+    {
+        ArchetypeStats {
+            metadata_component: M::ID,
+            extractor: Box::new(move |value| value.downcast_ref::<M>().map(&archetype)),
+            window: Duration::from_secs(10),
+            window_start: None,
+            entity_archetypes: HashMap::new(),
+            counts: HashMap::new(),
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Sets the window over which update rates are measured. The default is 10 seconds.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// The statistics of the archetype, as of the end of the last window.
+    pub fn get(&self, archetype: &str) -> Option<ArchetypeStat> {
+        self.stats.get(archetype).cloned()
+    }
+
+    /// The statistics of every archetype, as of the end of the last window.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a str, ArchetypeStat)> + 'a {
+        self.stats
+            .iter()
+            .map(|(archetype, stat)| (archetype.as_str(), *stat))
+    }
+
+    pub(crate) fn is_metadata_component(&self, component_id: ComponentId) -> bool {
+        component_id == self.metadata_component
+    }
+
+    pub(crate) fn entity_added(&mut self, entity: Entity, metadata: &Any) {
+        let archetype = match (self.extractor)(metadata) {
+            Some(archetype) => archetype,
+            None => return,
+        };
+
+        self.entity_removed(entity);
+        self.counts.entry(archetype.clone()).or_default().entities += 1;
+        self.entity_archetypes.insert(entity, archetype);
+    }
+
+    pub(crate) fn entity_removed(&mut self, entity: Entity) {
+        if let Some(archetype) = self.entity_archetypes.remove(&entity) {
+            if let Some(counts) = self.counts.get_mut(&archetype) {
+                counts.entities -= 1;
+            }
+        }
+    }
+
+    fn counts_of(&mut self, entity: Entity) -> Option<&mut ArchetypeCounts> {
+        let archetype = self.entity_archetypes.get(&entity)?;
+        self.counts.get_mut(archetype)
+    }
+
+    pub(crate) fn update_received(&mut self, entity: Entity) {
+        if let Some(counts) = self.counts_of(entity) {
+            counts.updates_received += 1;
+        }
+    }
+
+    pub(crate) fn update_sent(&mut self, entity: Entity) {
+        if let Some(counts) = self.counts_of(entity) {
+            counts.updates_sent += 1;
+        }
+    }
+
+    // Returns whether a window has ended, in which case the statistics were recomputed.
+    fn end_frame(&mut self, now: Instant) -> bool {
+        let window_start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(window_start);
+        if elapsed < self.window || elapsed == Duration::from_secs(0) {
+            return false;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        self.stats = self
+            .counts
+            .iter()
+            .map(|(archetype, counts)| {
+                let stat = ArchetypeStat {
+                    entities: counts.entities,
+                    updates_received_per_second: counts.updates_received as f64 / seconds,
+                    updates_sent_per_second: counts.updates_sent as f64 / seconds,
+                };
+                (archetype.clone(), stat)
+            })
+            .collect();
+
+        self.counts.retain(|_, counts| counts.entities > 0);
+        for counts in self.counts.values_mut() {
+            counts.updates_received = 0;
+            counts.updates_sent = 0;
+        }
+
+        self.window_start = Some(now);
+        true
+    }
+
+    fn describe(&self) -> String {
+        let mut stats: Vec<(&str, ArchetypeStat)> = self.iter().collect();
+        stats.sort_by(|a, b| {
+            let a = a.1.updates_received_per_second + a.1.updates_sent_per_second;
+            let b = b.1.updates_received_per_second + b.1.updates_sent_per_second;
+            b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut output = String::from("Archetypes:");
+        for (archetype, stat) in stats {
+            write!(
+                output,
+                " {} ({} entities, {:.1} updates/s in, {:.1} updates/s out);",
+                archetype,
+                stat.entities,
+                stat.updates_received_per_second,
+                stat.updates_sent_per_second
+            )
+            .unwrap();
+        }
+        output
+    }
+
+    pub(crate) fn finish_frame(res: &World, now: Instant) {
+        let report = {
+            let mut stats = res.fetch_mut::<ArchetypeStats>();
+            if !stats.end_frame(now) {
+                return;
+            }
+            stats.describe()
+        };
+
+        logging::log(res, LogLevel::Info, LogKind::ArchetypeReport, &report);
+    }
+}
+
+#[test]
+fn archetype_stats_should_count_entities_and_rates() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let tree = world.create_entity().build();
+    let player = world.create_entity().build();
+
+    let metadata = |x: f64| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    let mut stats = ArchetypeStats::new(|position: &Position| {
+        if position.coords.x > 0.0 {
+            "Tree".to_string()
+        } else {
+            "Player".to_string()
+        }
+    });
+    stats.set_window(Duration::from_secs(2));
+
+    let now = Instant::now();
+    assert!(!stats.end_frame(now));
+
+    stats.entity_added(tree, &metadata(1.0));
+    stats.entity_added(player, &metadata(0.0));
+    for _ in 0..10 {
+        stats.update_sent(tree);
+    }
+    stats.update_received(player);
+
+    assert!(stats.end_frame(now + Duration::from_secs(2)));
+    let trees = stats.get("Tree").unwrap();
+    assert_eq!(1, trees.entities);
+    assert_eq!(5.0, trees.updates_sent_per_second);
+    assert_eq!(
+        0.5,
+        stats.get("Player").unwrap().updates_received_per_second
+    );
+    assert!(stats.describe().starts_with("Archetypes: Tree"));
+
+    stats.entity_removed(tree);
+    assert!(stats.end_frame(now + Duration::from_secs(4)));
+    assert_eq!(0, stats.get("Tree").unwrap().entities);
+    assert_eq!(0.0, stats.get("Tree").unwrap().updates_sent_per_second);
+
+    assert!(stats.end_frame(now + Duration::from_secs(6)));
+    assert!(stats.get("Tree").is_none());
+}
@@ -0,0 +1,193 @@
+//! Recording of what was replicated each frame, to debug changes which did not replicate.
+use crate::entities::EntityId;
+use spatialos_sdk::worker::component::ComponentId;
+use std::collections::VecDeque;
+
+/// Why a component update was recorded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplicationReason {
+    /// The component was mutably dereferenced, so the entire component was sent.
+    MutablyDereferenced,
+    /// One or more partial updates were sent with `send_update`.
+    SendUpdate,
+    /// The entire component was sent because of `mark_full_resend`.
+    FullResend,
+    /// The component had a pending update, but it was held back until a later frame
+    /// because the `ConnectionCalls` limit on component updates was reached.
+    Deferred,
+    /// The update was received from SpatialOS and applied to the local component.
+    Received,
+}
+
+/// A single component update sent, deferred or received during a frame.
+#[derive(Debug, Clone)]
+pub struct ReplicationRecord {
+    pub entity_id: EntityId,
+    pub component_id: ComponentId,
+    pub reason: ReplicationReason,
+    /// The `Debug` representation of the update which was sent or received. This is empty
+    /// for deferred updates.
+    pub update: String,
+}
+
+/// Every component update sent, deferred or received during a single frame.
+#[derive(Debug, Clone, Default)]
+pub struct FrameAudit {
+    pub frame: u64,
+    pub records: Vec<ReplicationRecord>,
+}
+
+/// A resource which, when enabled, records every component update sent or deferred by the
+/// `SpatialWriterSystem`, and every update received by the `SpatialReaderSystem`, for the
+/// most recent frames. A frame ends when the `SpatialWriterSystem` runs.
+///
+/// Add it to the world and enable it to start recording:
+///
+/// ```ignore
+/// let mut audit = ReplicationAudit::new(60);
+/// audit.enable();
+/// world.insert(audit);
+/// ```
+///
+/// If a change is missing from the audit, the component was neither mutably dereferenced
+/// nor given an update with `send_update` on an entity this worker is authoritative over.
+pub struct ReplicationAudit {
+    enabled: bool,
+    max_frames: usize,
+    frame: u64,
+    frames: VecDeque<FrameAudit>,
+    // Whether the most recent frame is still being recorded.
+    in_frame: bool,
+}
+
+impl ReplicationAudit {
+    /// Creates a disabled audit which keeps the records of the last `max_frames` frames.
+    pub fn new(max_frames: usize) -> ReplicationAudit {
+        ReplicationAudit {
+            enabled: false,
+            max_frames,
+            frame: 0,
+            frames: VecDeque::new(),
+            in_frame: false,
+        }
+    }
+
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The recorded frames, oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = &FrameAudit> {
+        self.frames.iter()
+    }
+
+    /// The most recently recorded frame.
+    pub fn latest(&self) -> Option<&FrameAudit> {
+        self.frames.back()
+    }
+
+    /// Every recorded update for the given component of the given entity, with the frame
+    /// it was recorded in.
+    pub fn find<'a>(
+        &'a self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+    ) -> impl Iterator<Item = (u64, &'a ReplicationRecord)> + 'a {
+        self.frames.iter().flat_map(move |frame| {
+            frame
+                .records
+                .iter()
+                .filter(move |record| {
+                    record.entity_id == entity_id && record.component_id == component_id
+                })
+                .map(move |record| (frame.frame, record))
+        })
+    }
+
+    fn begin_frame(&mut self) {
+        self.frame += 1;
+
+        if self.frames.len() >= self.max_frames {
+            self.frames.pop_front();
+        }
+
+        self.frames.push_back(FrameAudit {
+            frame: self.frame,
+            records: Vec::new(),
+        });
+        self.in_frame = true;
+    }
+
+    pub(crate) fn record(&mut self, record: ReplicationRecord) {
+        if !self.in_frame {
+            self.begin_frame();
+        }
+
+        if let Some(frame) = self.frames.back_mut() {
+            frame.records.push(record);
+        }
+    }
+
+    pub(crate) fn finish_frame(&mut self) {
+        if !self.in_frame {
+            self.begin_frame();
+        }
+
+        self.in_frame = false;
+    }
+}
+
+impl Default for ReplicationAudit {
+    fn default() -> Self {
+        ReplicationAudit::new(60)
+    }
+}
+
+#[test]
+fn audit_should_keep_recent_frames() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let entity_id = EntityId(WorkerEntityId::new(5));
+    let mut audit = ReplicationAudit::new(2);
+
+    for _ in 0..3 {
+        audit.record(ReplicationRecord {
+            entity_id,
+            component_id: 54,
+            reason: ReplicationReason::Received,
+            update: String::new(),
+        });
+        audit.record(ReplicationRecord {
+            entity_id,
+            component_id: 54,
+            reason: ReplicationReason::SendUpdate,
+            update: String::new(),
+        });
+        audit.finish_frame();
+    }
+    // A frame without any records is still kept.
+    audit.finish_frame();
+
+    assert_eq!(2, audit.frames().count());
+    assert_eq!(4, audit.latest().unwrap().frame);
+    assert!(audit.latest().unwrap().records.is_empty());
+    assert_eq!(
+        vec![
+            (3, ReplicationReason::Received),
+            (3, ReplicationReason::SendUpdate)
+        ],
+        audit
+            .find(entity_id, 54)
+            .map(|(frame, record)| (frame, record.reason))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(0, audit.find(entity_id, 1002).count());
+}
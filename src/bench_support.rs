@@ -0,0 +1,159 @@
+//! Entry points into the crate's internals for the benchmarks in `benches/`.
+//!
+//! These bypass the `WorkerConnection`, so that the cost of the dispatcher and storage
+//! layers can be measured without a running SpatialOS deployment.
+use crate::commands::{CommandSender, CommandSenderRes};
+use crate::component_registry::add_received_component;
+use crate::entities::{EntityId, EntityIds, SpatialEntitiesRes};
+use crate::shared_bytes::SharedBytes;
+use crate::storage::SpatialWriteStorage;
+use crate::SpatialComponent;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::op::{CommandResponseOp, StatusCode};
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use specs::prelude::{Join, SystemData, World, WorldExt};
+use std::fmt::Debug;
+
+pub use crate::generated_test::{
+    Blob, Constraint, Coordinates, Loadout, Position, PositionCommandRequest, PositionUpdate,
+    SchemaShapes,
+};
+
+fn position(x: f64) -> Position {
+    Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    }
+}
+
+/// Creates a world with the storages for `Position` set up.
+pub fn setup_world() -> World {
+    let mut world = World::new();
+
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+    SpatialWriteStorage::<Blob>::setup(&mut world);
+    SpatialWriteStorage::<SchemaShapes>::setup(&mut world);
+    CommandSender::<Position>::setup(&mut world);
+
+    world
+}
+
+/// Checks out `count` entities, each with a `Position` component.
+pub fn checkout_entities(world: &World, count: i64) {
+    let res = world;
+    let mut entities_res = res.fetch_mut::<SpatialEntitiesRes>();
+
+    for id in 0..count {
+        let entity_id = EntityId(WorkerEntityId::new(id));
+        entities_res.got_new_entity(res, entity_id);
+
+        let entity = entities_res.get_entity(entity_id).unwrap();
+        SpatialWriteStorage::<Position>::try_fetch_component_storage(res)
+            .unwrap()
+            .insert(entity, SpatialComponent::new(position(id as f64)))
+            .unwrap();
+    }
+}
+
+/// Applies an incoming update to every `Position` component.
+pub fn apply_updates(world: &World) {
+    let mut storage = SpatialWriteStorage::<Position>::try_fetch_component_storage(world).unwrap();
+
+    for component in (&mut storage).join() {
+        component.apply_update_to_value(PositionUpdate {
+            coords: Some(Coordinates {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            }),
+        });
+    }
+}
+
+/// Queues an outgoing update on every `Position` component, then takes and serializes
+/// every pending update as the writer would. Returns the number of updates serialized.
+pub fn serialize_updates(world: &World) -> usize {
+    let mut storage = SpatialWriteStorage::<Position>::try_fetch_component_storage(world).unwrap();
+    let mut serialized = 0;
+
+    for component in (&mut storage).join() {
+        component.send_update(PositionUpdate {
+            coords: Some(Coordinates {
+                x: 4.0,
+                y: 5.0,
+                z: 6.0,
+            }),
+        });
+
+        if let Some((_, update)) = component.take_pending_update() {
+            Position::to_update(&update).unwrap();
+            serialized += 1;
+        }
+    }
+
+    serialized
+}
+
+/// Sends `count` commands and delivers a response to each.
+pub fn command_round_trip(world: &World, count: usize) {
+    let res = world;
+    let entity_id = EntityId(WorkerEntityId::new(1));
+
+    let request_ids = {
+        let mut sender = CommandSender::<Position>::fetch(res);
+        for _ in 0..count {
+            sender.send_command(entity_id, PositionCommandRequest::UpdateCoords, |_, _| {});
+        }
+        sender.assign_request_ids_without_sending()
+    };
+
+    for request_id in request_ids {
+        CommandSenderRes::<Position>::got_command_response(
+            res,
+            CommandResponseOp {
+                request_id,
+                entity_id: entity_id.id(),
+                component_id: Position::ID,
+                response: StatusCode::Timeout(String::from("Timeout")),
+            },
+        );
+    }
+}
+
+/// Creates a `Blob` as it would be received in an op, with a payload of `size` bytes.
+pub fn received_blob(size: usize) -> Blob {
+    Blob {
+        data: SharedBytes::from(vec![0xAB; size]),
+    }
+}
+
+/// Creates `SchemaShapes` as it would be received in an op, with a `Vec<u8>` payload of
+/// `size` bytes.
+pub fn received_byte_vec(size: usize) -> SchemaShapes {
+    SchemaShapes {
+        constraint: Constraint {
+            entity_id_constraint: None,
+            and_constraint: Vec::new(),
+            or_constraint: Vec::new(),
+        },
+        loadout: Loadout { primary: None },
+        anchors: Default::default(),
+        blob: vec![0xAB; size],
+    }
+}
+
+/// Adds the received component to the first `count` entities through the same path as an
+/// `AddComponentOp` read by the `SpatialReaderSystem`, from the deserialized op data to
+/// storage.
+pub fn add_received<T>(world: &World, received: &T, count: i64)
+where
+    T: 'static + WorkerComponent + Sync + Send + Clone + Debug,
+{
+    for id in 0..count {
+        let entity = world
+            .fetch::<SpatialEntitiesRes>()
+            .get_entity(EntityId(WorkerEntityId::new(id)))
+            .unwrap();
+        add_received_component::<T>(world, entity, Some(received));
+    }
+}
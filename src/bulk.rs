@@ -0,0 +1,551 @@
+//! Transfers of payloads too large for a single command, such as map data or inventories,
+//! split into chunks which are sent as separate command requests and reassembled by the
+//! receiver.
+//!
+//! The component must have a command which carries a chunk, for example:
+//!
+//! ```ignore
+//! type BulkChunk {
+//!     uint64 transfer_id = 1;
+//!     uint32 sequence = 2;
+//!     uint32 total = 3;
+//!     bytes bytes = 4;
+//! }
+//! type BulkAck {}
+//!
+//! component MapStreamer {
+//!     id = 1500;
+//!     command BulkAck send_chunk(BulkChunk);
+//! }
+//! ```
+//!
+//! which is mapped to `BulkChunk` by implementing `BulkCommand` for the component. The
+//! sender then sends whole payloads:
+//!
+//! ```ignore
+//! bulk_sender.send(&mut command_sender, entity_id, map_bytes, |result, _| {
+//!     result.expect("Failed to send the map");
+//! });
+//! ```
+//!
+//! and the receiver collects them once every chunk has arrived. Chunks which claim more
+//! than `set_max_chunks` chunks, or disagree with the earlier chunks of their transfer,
+//! are acknowledged but discarded, as are chunks which would start a new transfer while
+//! their caller, or all callers together, already have as many incomplete transfers as
+//! `set_max_transfers_per_caller` or `set_max_transfers` allow. Transfers which receive no
+//! chunks for `set_transfer_timeout`, according to the `SpatialClock`, are dropped. The
+//! receiving system reads the clock with `Read<'a, SpatialClock>`:
+//!
+//! ```ignore
+//! for (entity, requests) in (&entities, &mut requests).join() {
+//!     for transfer in bulk_receiver.receive(&clock, entity, requests) {
+//!         load_map(transfer.payload);
+//!     }
+//! }
+//! ```
+use crate::clock::SpatialClock;
+use crate::commands::{CommandRequestsComp, CommandSenderRes};
+use crate::entities::EntityId;
+use crate::SystemDataFetch;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use specs::prelude::{Entity, World, Write};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The default size of a chunk, in bytes.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The default maximum number of chunks in a received transfer, which is 64 MiB of
+/// chunks of the default size.
+pub const DEFAULT_MAX_CHUNKS: u32 = 1024;
+
+/// The default maximum number of incomplete transfers from a single caller.
+pub const DEFAULT_MAX_TRANSFERS_PER_CALLER: usize = 4;
+
+/// The default maximum number of incomplete transfers from all callers together.
+pub const DEFAULT_MAX_TRANSFERS: usize = 32;
+
+/// The default time after which an incomplete transfer which receives no chunks is dropped.
+pub const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A single chunk of a transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkChunk {
+    pub transfer_id: u64,
+    pub sequence: u32,
+    pub total: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Maps chunks to and from the command of a component which carries them.
+pub trait BulkCommand: WorkerComponent {
+    fn chunk_request(chunk: BulkChunk) -> Self::CommandRequest;
+
+    /// Returns `None` if the request is for a different command.
+    fn as_chunk(request: &Self::CommandRequest) -> Option<BulkChunk>;
+
+    fn chunk_response(transfer_id: u64, sequence: u32) -> Self::CommandResponse;
+}
+
+/// A payload which has been completely received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkTransfer {
+    pub entity: Entity,
+    pub caller_worker_id: String,
+    pub transfer_id: u64,
+    pub payload: Vec<u8>,
+}
+
+type BulkCallback = Box<FnOnce(Result<(), String>, SystemDataFetch) + Send + Sync>;
+
+struct OutgoingTransfer {
+    chunks_left: usize,
+    callback: Option<BulkCallback>,
+}
+
+impl OutgoingTransfer {
+    fn complete(state: &Mutex<OutgoingTransfer>, res: &World, result: Result<(), String>) {
+        let finished = {
+            let mut state = state.lock().unwrap();
+            state.chunks_left -= 1;
+
+            // The first failure is reported immediately, and later chunks are ignored.
+            if result.is_err() || state.chunks_left == 0 {
+                state.callback.take()
+            } else {
+                None
+            }
+        };
+
+        if let Some(callback) = finished {
+            callback(result, SystemDataFetch::new(res));
+        }
+    }
+}
+
+/// Splits payloads into chunks and sends them with the component's `CommandSender`.
+pub type BulkSender<'a, T> = Write<'a, BulkSenderRes<T>>;
+
+pub struct BulkSenderRes<T: BulkCommand> {
+    chunk_size: usize,
+    next_transfer_id: u64,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: 'static + BulkCommand> BulkSenderRes<T> {
+    /// Sets the maximum size of a chunk. The default is `DEFAULT_CHUNK_SIZE`.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    /// Sends the payload to the entity, calling `callback` once every chunk has been
+    /// acknowledged or any chunk has failed. Returns the ID of the transfer.
+    pub fn send<F>(
+        &mut self,
+        command_sender: &mut CommandSenderRes<T>,
+        entity_id: EntityId,
+        payload: Vec<u8>,
+        callback: F,
+    ) -> u64
+    where
+        F: 'static + FnOnce(Result<(), String>, SystemDataFetch) + Send + Sync,
+    {
+        let transfer_id = self.next_transfer_id;
+        self.next_transfer_id += 1;
+
+        let chunks = split(transfer_id, &payload, self.chunk_size);
+        let state = Arc::new(Mutex::new(OutgoingTransfer {
+            chunks_left: chunks.len(),
+            callback: Some(Box::new(callback)),
+        }));
+
+        for chunk in chunks {
+            let state = state.clone();
+            let sequence = chunk.sequence;
+            command_sender.send_command(
+                entity_id,
+                T::chunk_request(chunk),
+                move |response, system_data| {
+                    let result = response.map(|_| ()).map_err(|status| {
+                        format!(
+                            "Chunk {} of transfer {} failed: {:?}",
+                            sequence, transfer_id, status
+                        )
+                    });
+                    OutgoingTransfer::complete(&state, system_data.res, result);
+                },
+            );
+        }
+
+        transfer_id
+    }
+}
+
+impl<T: BulkCommand> Default for BulkSenderRes<T> {
+    fn default() -> Self {
+        BulkSenderRes {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_transfer_id: 1,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+fn split(transfer_id: u64, payload: &[u8], chunk_size: usize) -> Vec<BulkChunk> {
+    // An empty payload is still sent as a single empty chunk.
+    let total = ((payload.len() + chunk_size - 1) / chunk_size).max(1);
+    (0..total)
+        .map(|sequence| {
+            let start = (sequence * chunk_size).min(payload.len());
+            let end = (start + chunk_size).min(payload.len());
+            BulkChunk {
+                transfer_id,
+                sequence: sequence as u32,
+                total: total as u32,
+                bytes: payload[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Reassembles payloads from chunks received as command requests.
+pub type BulkReceiver<'a, T> = Write<'a, BulkReceiverRes<T>>;
+
+pub struct BulkReceiverRes<T: BulkCommand> {
+    incoming: IncomingTransfers,
+    limits: Limits,
+    transfer_timeout: Duration,
+    rejected_chunks: u64,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: 'static + BulkCommand> BulkReceiverRes<T> {
+    /// Sets the maximum number of chunks in a transfer. The default is
+    /// `DEFAULT_MAX_CHUNKS`.
+    pub fn set_max_chunks(&mut self, max_chunks: u32) {
+        self.limits.max_chunks = max_chunks;
+    }
+
+    /// Sets the maximum number of incomplete transfers from a single caller. The default is
+    /// `DEFAULT_MAX_TRANSFERS_PER_CALLER`.
+    pub fn set_max_transfers_per_caller(&mut self, max_transfers: usize) {
+        self.limits.max_transfers_per_caller = max_transfers;
+    }
+
+    /// Sets the maximum number of incomplete transfers from all callers together. The
+    /// default is `DEFAULT_MAX_TRANSFERS`.
+    pub fn set_max_transfers(&mut self, max_transfers: usize) {
+        self.limits.max_transfers = max_transfers;
+    }
+
+    /// Sets how long an incomplete transfer may go without receiving a chunk before it is
+    /// dropped. The default is `DEFAULT_TRANSFER_TIMEOUT`.
+    pub fn set_transfer_timeout(&mut self, timeout: Duration) {
+        self.transfer_timeout = timeout;
+    }
+
+    /// Responds to every chunk in the entity's command requests, returning the transfers
+    /// which are now complete. Requests for other commands are left for other systems.
+    ///
+    /// Incomplete transfers which have timed out, according to the `clock`, are dropped
+    /// first.
+    pub fn receive(
+        &mut self,
+        clock: &SpatialClock,
+        entity: Entity,
+        requests: &mut CommandRequestsComp<T>,
+    ) -> Vec<BulkTransfer> {
+        let now = clock.now();
+        expire(&mut self.incoming, now, self.transfer_timeout);
+
+        let mut completed = Vec::new();
+        let limits = self.limits;
+        let incoming = &mut self.incoming;
+        let rejected_chunks = &mut self.rejected_chunks;
+
+        requests.respond(|request, caller_worker_id, _| {
+            let chunk = T::as_chunk(request)?;
+            let response = T::chunk_response(chunk.transfer_id, chunk.sequence);
+
+            match add_chunk(incoming, limits, entity, caller_worker_id, chunk, now) {
+                Ok(Some(transfer)) => completed.push(transfer),
+                Ok(None) => {}
+                Err(()) => *rejected_chunks += 1,
+            }
+
+            Some(response)
+        });
+
+        completed
+    }
+
+    /// The number of chunks discarded because their transfer was too large, because they
+    /// disagreed with the earlier chunks of their transfer, or because too many transfers
+    /// were already incomplete.
+    pub fn rejected_chunks(&self) -> u64 {
+        self.rejected_chunks
+    }
+
+    /// The number of transfers for which some, but not all, chunks have been received.
+    pub fn incomplete_transfers(&self) -> usize {
+        self.incoming.len()
+    }
+
+    /// Discards the chunks of every incomplete transfer, for example after the sending
+    /// worker has disconnected.
+    pub fn clear_incomplete(&mut self) {
+        self.incoming.clear();
+    }
+}
+
+impl<T: BulkCommand> Default for BulkReceiverRes<T> {
+    fn default() -> Self {
+        BulkReceiverRes {
+            incoming: HashMap::new(),
+            limits: Limits {
+                max_chunks: DEFAULT_MAX_CHUNKS,
+                max_transfers_per_caller: DEFAULT_MAX_TRANSFERS_PER_CALLER,
+                max_transfers: DEFAULT_MAX_TRANSFERS,
+            },
+            transfer_timeout: DEFAULT_TRANSFER_TIMEOUT,
+            rejected_chunks: 0,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Limits {
+    max_chunks: u32,
+    max_transfers_per_caller: usize,
+    max_transfers: usize,
+}
+
+struct IncomingTransfer {
+    chunks: Vec<Option<Vec<u8>>>,
+    last_chunk: Instant,
+}
+
+type IncomingTransfers = HashMap<(Entity, String, u64), IncomingTransfer>;
+
+fn expire(incoming: &mut IncomingTransfers, now: Instant, timeout: Duration) {
+    incoming.retain(|_, transfer| now.duration_since(transfer.last_chunk) < timeout);
+}
+
+// Returns the transfer if the chunk completes it, or `Err` if the chunk can't belong to a
+// valid transfer, or would start a transfer beyond the limits.
+fn add_chunk(
+    incoming: &mut IncomingTransfers,
+    limits: Limits,
+    entity: Entity,
+    caller_worker_id: &str,
+    chunk: BulkChunk,
+    now: Instant,
+) -> Result<Option<BulkTransfer>, ()> {
+    if chunk.total == 0 || chunk.total > limits.max_chunks || chunk.sequence >= chunk.total {
+        return Err(());
+    }
+
+    let key = (entity, caller_worker_id.to_string(), chunk.transfer_id);
+    if !incoming.contains_key(&key) {
+        let from_caller = incoming
+            .keys()
+            .filter(|(_, caller, _)| caller == caller_worker_id)
+            .count();
+        if from_caller >= limits.max_transfers_per_caller || incoming.len() >= limits.max_transfers
+        {
+            return Err(());
+        }
+    }
+
+    let complete = {
+        let transfer = incoming
+            .entry(key.clone())
+            .or_insert_with(|| IncomingTransfer {
+                chunks: vec![None; chunk.total as usize],
+                last_chunk: now,
+            });
+        if transfer.chunks.len() != chunk.total as usize {
+            return Err(());
+        }
+        transfer.last_chunk = now;
+
+        // A repeated chunk is ignored, as it was already acknowledged.
+        let slot = &mut transfer.chunks[chunk.sequence as usize];
+        if slot.is_none() {
+            *slot = Some(chunk.bytes);
+        }
+
+        transfer.chunks.iter().all(Option::is_some)
+    };
+
+    if !complete {
+        return Ok(None);
+    }
+
+    let transfer = incoming.remove(&key).unwrap();
+    Ok(Some(BulkTransfer {
+        entity,
+        caller_worker_id: key.1,
+        transfer_id: key.2,
+        payload: transfer
+            .chunks
+            .into_iter()
+            .flat_map(Option::unwrap)
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+fn limits(max_chunks: u32) -> Limits {
+    Limits {
+        max_chunks,
+        max_transfers_per_caller: DEFAULT_MAX_TRANSFERS_PER_CALLER,
+        max_transfers: DEFAULT_MAX_TRANSFERS,
+    }
+}
+
+#[test]
+fn bulk_transfers_should_be_reassembled() {
+    use crate::clock::{Clock, ManualClock};
+    use specs::prelude::{Builder, WorldExt};
+
+    assert_eq!(1, split(1, &[], 4).len());
+    let chunks = split(7, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 4);
+    assert_eq!(3, chunks.len());
+    assert_eq!(vec![9, 10], chunks[2].bytes);
+    assert!(chunks.iter().all(|chunk| chunk.total == 3));
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+
+    let now = ManualClock::new().now();
+    let mut incoming = HashMap::new();
+    let mut add = |chunk: &BulkChunk| {
+        add_chunk(
+            &mut incoming,
+            limits(3),
+            entity,
+            "worker",
+            chunk.clone(),
+            now,
+        )
+        .unwrap()
+    };
+
+    assert!(add(&chunks[2]).is_none());
+    assert!(add(&chunks[0]).is_none());
+    assert!(add(&chunks[0]).is_none());
+    let transfer = add(&chunks[1]).unwrap();
+
+    assert_eq!(7, transfer.transfer_id);
+    assert_eq!("worker", transfer.caller_worker_id);
+    assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10], transfer.payload);
+    assert!(incoming.is_empty());
+}
+
+#[test]
+fn bulk_receiver_should_reject_invalid_chunks_and_expire_transfers() {
+    use crate::clock::{Clock, ManualClock};
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+    let now = ManualClock::new().now();
+    let chunk = |sequence, total| BulkChunk {
+        transfer_id: 1,
+        sequence,
+        total,
+        bytes: vec![1],
+    };
+
+    let mut incoming = HashMap::new();
+    let mut add =
+        |chunk: BulkChunk| add_chunk(&mut incoming, limits(4), entity, "worker", chunk, now);
+    assert!(add(chunk(0, 0)).is_err());
+    assert!(add(chunk(0, 5)).is_err());
+    assert!(add(chunk(2, 2)).is_err());
+    assert_eq!(Ok(None), add(chunk(0, 2)));
+    assert!(add(chunk(1, 3)).is_err());
+
+    expire(
+        &mut incoming,
+        now + Duration::from_secs(1),
+        Duration::from_secs(2),
+    );
+    assert_eq!(1, incoming.len());
+    expire(
+        &mut incoming,
+        now + Duration::from_secs(2),
+        Duration::from_secs(2),
+    );
+    assert!(incoming.is_empty());
+}
+
+#[test]
+fn bulk_receiver_should_limit_incomplete_transfers_and_expire_them_by_the_clock() {
+    use crate::clock::{Clock, ManualClock};
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    impl BulkCommand for Counter {
+        fn chunk_request(chunk: BulkChunk) -> CounterCommandRequest {
+            CounterCommandRequest::Increment(IncrementRequest {
+                amount: chunk.sequence,
+            })
+        }
+
+        fn as_chunk(_: &CounterCommandRequest) -> Option<BulkChunk> {
+            None
+        }
+
+        fn chunk_response(_: u64, sequence: u32) -> CounterCommandResponse {
+            CounterCommandResponse::Increment(IncrementResponse { total: sequence })
+        }
+    }
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+    let clock = ManualClock::new();
+    let spatial_clock = SpatialClock::new(clock.clone());
+    let chunk = |transfer_id| BulkChunk {
+        transfer_id,
+        sequence: 0,
+        total: 2,
+        bytes: vec![1],
+    };
+
+    let mut receiver = BulkReceiverRes::<Counter>::default();
+    receiver.set_max_transfers_per_caller(1);
+    receiver.set_max_transfers(2);
+    let add = |receiver: &mut BulkReceiverRes<Counter>, caller, transfer_id| {
+        let limits = receiver.limits;
+        add_chunk(
+            &mut receiver.incoming,
+            limits,
+            entity,
+            caller,
+            chunk(transfer_id),
+            clock.now(),
+        )
+    };
+
+    assert_eq!(Ok(None), add(&mut receiver, "a", 1));
+    assert!(add(&mut receiver, "a", 2).is_err());
+    assert_eq!(Ok(None), add(&mut receiver, "b", 1));
+    assert!(add(&mut receiver, "c", 1).is_err());
+    // Chunks of transfers which are already incomplete are still accepted.
+    assert_eq!(Ok(None), add(&mut receiver, "a", 1));
+    assert_eq!(2, receiver.incomplete_transfers());
+
+    let mut requests = CommandRequestsComp::<Counter>::default();
+    clock.advance(DEFAULT_TRANSFER_TIMEOUT - Duration::from_secs(1));
+    receiver.receive(&spatial_clock, entity, &mut requests);
+    assert_eq!(2, receiver.incomplete_transfers());
+
+    clock.advance(Duration::from_secs(1));
+    receiver.receive(&spatial_clock, entity, &mut requests);
+    assert_eq!(0, receiver.incomplete_transfers());
+}
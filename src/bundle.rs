@@ -0,0 +1,281 @@
+//! Integration with Amethyst, so that a game can replicate its entities with SpatialOS by
+//! adding a single bundle.
+//!
+//! The `SpatialBundle` adds the `SpatialReaderSystem` before any other system, and the
+//! `SpatialWriterSystem` as a thread local system so that it runs after every other system.
+//! The `Transform` of each entity is kept in sync with its position component:
+//!
+//! ```ignore
+//! let game_data = GameDataBuilder::default()
+//!     .with_bundle(SpatialBundle::new(
+//!         connection,
+//!         |position: &Position| [position.coords.x, position.coords.y, position.coords.z],
+//!         |position: &mut Position, [x, y, z]: [f64; 3]| {
+//!             position.coords = Coordinates { x, y, z };
+//!         },
+//!     ))?
+//!     .with_bundle(TransformBundle::new())?;
+//! ```
+use crate::storage::{ReadAuthority, SpatialReadStorage, SpatialWriteStorage};
+use crate::{SpatialReaderSystem, SpatialWriterSystem};
+use amethyst::core::{SystemBundle, Transform};
+use amethyst::ecs::{DispatcherBuilder, Entities, Join, ReadStorage, System, World, WriteStorage};
+use amethyst::Error;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::connection::WorkerConnection;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A bundle which adds the SpatialOS reader and writer, the worker connection and the
+/// systems which sync `Transform` with the position component `P`.
+pub struct SpatialBundle<P, G, S> {
+    connection: WorkerConnection,
+    position: G,
+    set_position: S,
+    _phantom: PhantomData<P>,
+}
+
+impl<P, G, S> SpatialBundle<P, G, S>
+where
+    P: 'static + WorkerComponent,
+    G: 'static + Fn(&P) -> [f64; 3] + Send + Sync,
+    S: 'static + Fn(&mut P, [f64; 3]) + Send + Sync,
+{
+    pub fn new(connection: WorkerConnection, position: G, set_position: S) -> Self {
+        SpatialBundle {
+            connection,
+            position,
+            set_position,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, 'b, P, G, S> SystemBundle<'a, 'b> for SpatialBundle<P, G, S>
+where
+    P: 'static + WorkerComponent,
+    G: 'static + Fn(&P) -> [f64; 3] + Send + Sync,
+    S: 'static + Fn(&mut P, [f64; 3]) + Send + Sync,
+{
+    fn build(
+        self,
+        world: &mut World,
+        builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        world.insert(self.connection);
+
+        let position = Arc::new(self.position);
+
+        builder.add(SpatialReaderSystem, "spatial_reader", &[]);
+        builder.add(
+            PositionToTransformSystem::new(position.clone()),
+            "spatial_position_to_transform",
+            &["spatial_reader"],
+        );
+
+        builder.add_thread_local(TransformToPositionSystem::new(position, self.set_position));
+        builder.add_thread_local(SpatialWriterSystem);
+
+        Ok(())
+    }
+}
+
+/// A system which copies the position component `P` into the `Transform` of every entity
+/// this worker is not authoritative over, inserting the `Transform` if it is missing.
+pub struct PositionToTransformSystem<P, G> {
+    position: Arc<G>,
+    _phantom: PhantomData<P>,
+}
+
+impl<P, G> PositionToTransformSystem<P, G>
+where
+    P: 'static + WorkerComponent,
+    G: Fn(&P) -> [f64; 3],
+{
+    pub fn new(position: Arc<G>) -> PositionToTransformSystem<P, G> {
+        PositionToTransformSystem {
+            position,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, P, G> System<'a> for PositionToTransformSystem<P, G>
+where
+    P: 'static + WorkerComponent,
+    G: Fn(&P) -> [f64; 3] + Send + Sync,
+{
+    type SystemData = (
+        Entities<'a>,
+        SpatialReadStorage<'a, P>,
+        ReadAuthority<'a, P>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(&mut self, (entities, positions, authority, mut transforms): Self::SystemData) {
+        for (entity, position) in (&entities, &positions).join() {
+            if authority.is_authoritative(entity) {
+                continue;
+            }
+
+            let [x, y, z] = (self.position)(&**position);
+
+            match transforms.get_mut(entity) {
+                Some(transform) => {
+                    transform.set_translation_xyz(x as f32, y as f32, z as f32);
+                }
+                None => {
+                    let mut transform = Transform::default();
+                    transform.set_translation_xyz(x as f32, y as f32, z as f32);
+                    transforms
+                        .insert(entity, transform)
+                        .expect("Error inserting Transform.");
+                }
+            }
+        }
+    }
+}
+
+/// A system which copies the `Transform` of every entity this worker is authoritative over
+/// into its position component `P`.
+///
+/// The position component is only modified, and so replicated, when the translation has
+/// moved away from it.
+pub struct TransformToPositionSystem<P, G, S> {
+    position: Arc<G>,
+    set_position: S,
+    _phantom: PhantomData<P>,
+}
+
+impl<P, G, S> TransformToPositionSystem<P, G, S>
+where
+    P: 'static + WorkerComponent,
+    G: Fn(&P) -> [f64; 3],
+    S: Fn(&mut P, [f64; 3]),
+{
+    pub fn new(position: Arc<G>, set_position: S) -> TransformToPositionSystem<P, G, S> {
+        TransformToPositionSystem {
+            position,
+            set_position,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, P, G, S> System<'a> for TransformToPositionSystem<P, G, S>
+where
+    P: 'static + WorkerComponent,
+    G: Fn(&P) -> [f64; 3] + Send + Sync,
+    S: Fn(&mut P, [f64; 3]) + Send + Sync,
+{
+    type SystemData = (SpatialWriteStorage<'a, P>, ReadStorage<'a, Transform>);
+
+    fn run(&mut self, (mut positions, transforms): Self::SystemData) {
+        for (position, transform) in (&mut positions, &transforms).join() {
+            let translation = transform.translation();
+            let target = [translation.x, translation.y, translation.z];
+
+            let [x, y, z] = (self.position)(&**position);
+            if [x as f32, y as f32, z as f32] == target {
+                continue;
+            }
+
+            (self.set_position)(
+                &mut **position,
+                [
+                    f64::from(target[0]),
+                    f64::from(target[1]),
+                    f64::from(target[2]),
+                ],
+            );
+        }
+    }
+}
+
+#[test]
+fn bundle_systems_should_sync_transforms_with_positions() {
+    use crate::entities::{EntityId, EntityIds, SpatialEntitiesRes};
+    use crate::generated_test::*;
+    use crate::storage::ComponentAuthority;
+    use crate::SpatialComponent;
+    use amethyst::ecs::{RunNow, SystemData, WorldExt};
+    use spatialos_sdk::worker::Authority;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let get = |position: &Position| [position.coords.x, position.coords.y, position.coords.z];
+    let set = |position: &mut Position, [x, y, z]: [f64; 3]| {
+        position.coords = Coordinates { x, y, z };
+    };
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+    world.register::<Transform>();
+
+    let (remote, local) = {
+        let mut entities_res = world.fetch_mut::<SpatialEntitiesRes>();
+        let mut entity = |id| {
+            let entity_id = EntityId(WorkerEntityId::new(id));
+            entities_res.got_new_entity(&world, entity_id);
+            entities_res.get_entity(entity_id).unwrap()
+        };
+        (entity(1), entity(2))
+    };
+    {
+        let mut positions = SpatialWriteStorage::<Position>::unrestricted(&world);
+        for (entity, x) in [(remote, 1.0), (local, 2.0)].iter() {
+            let position = Position {
+                coords: Coordinates {
+                    x: *x,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            };
+            positions
+                .insert(*entity, SpatialComponent::new(position))
+                .unwrap();
+        }
+    }
+    world
+        .fetch_mut::<ComponentAuthority<Position>>()
+        .set_authority(local, Authority::Authoritative);
+
+    let position = Arc::new(get);
+    PositionToTransformSystem::new(position.clone()).run_now(&world);
+    {
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(1.0, transforms.get(remote).unwrap().translation().x);
+        assert!(transforms.get(local).is_none());
+
+        // Reading the positions doesn't cause them to be sent.
+        let positions = SpatialReadStorage::<Position>::fetch(&world);
+        assert!(!positions.get(remote).unwrap().has_pending_update());
+        assert!(!positions.get(local).unwrap().has_pending_update());
+    }
+
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(2.0, 0.0, 0.0);
+    world
+        .write_storage::<Transform>()
+        .insert(local, transform)
+        .unwrap();
+
+    // An unmoved transform leaves the position alone.
+    let mut to_position = TransformToPositionSystem::new(position, set);
+    to_position.run_now(&world);
+    {
+        let positions = SpatialReadStorage::<Position>::fetch(&world);
+        assert!(!positions.get(local).unwrap().has_pending_update());
+    }
+
+    world
+        .write_storage::<Transform>()
+        .get_mut(local)
+        .unwrap()
+        .set_translation_xyz(3.0, 0.0, 0.0);
+    to_position.run_now(&world);
+    let positions = SpatialReadStorage::<Position>::fetch(&world);
+    assert_eq!(3.0, positions.get(local).unwrap().coords.x);
+    assert!(positions.get(local).unwrap().has_pending_update());
+    assert!(!positions.get(remote).unwrap().has_pending_update());
+}
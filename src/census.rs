@@ -0,0 +1,123 @@
+use hibitset::BitSet;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::Authority;
+use specs::prelude::Entity;
+use std::collections::HashMap;
+
+/// The number of checked out and authoritative instances of a single component.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ComponentCount {
+    /// The number of entities which have this component checked out.
+    pub checked_out: usize,
+    /// The number of entities which this worker is authoritative over this component for.
+    ///
+    /// This includes entities in the `AuthorityLossImminent` state.
+    pub authoritative: usize,
+}
+
+#[derive(Default)]
+struct ComponentCensusEntry {
+    count: ComponentCount,
+    authority: BitSet,
+}
+
+/// A resource which tracks, per component, how many entities have that component checked
+/// out and how many of those this worker is authoritative over.
+///
+/// This is maintained incrementally as ops are applied by the `SpatialReaderSystem`, so reading
+/// it is cheap compared to joining over every storage each frame.
+#[derive(Default)]
+pub struct ComponentCensus {
+    entries: HashMap<ComponentId, ComponentCensusEntry>,
+}
+
+impl ComponentCensus {
+    /// Returns the counts for the given component, which are zero if the component
+    /// has never been seen.
+    pub fn get(&self, component_id: ComponentId) -> ComponentCount {
+        self.entries
+            .get(&component_id)
+            .map(|entry| entry.count)
+            .unwrap_or_default()
+    }
+
+    /// Iterates over the counts of every component which has been seen.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (ComponentId, ComponentCount)> + 'a {
+        self.entries.iter().map(|(id, entry)| (*id, entry.count))
+    }
+
+    pub(crate) fn component_added(&mut self, component_id: ComponentId) {
+        self.entries
+            .entry(component_id)
+            .or_default()
+            .count
+            .checked_out += 1;
+    }
+
+    pub(crate) fn component_removed(&mut self, component_id: ComponentId, entity: Entity) {
+        if let Some(entry) = self.entries.get_mut(&component_id) {
+            entry.count.checked_out = entry.count.checked_out.saturating_sub(1);
+
+            if entry.authority.remove(entity.id()) {
+                entry.count.authoritative -= 1;
+            }
+        }
+    }
+
+    pub(crate) fn authority_changed(
+        &mut self,
+        component_id: ComponentId,
+        entity: Entity,
+        authority: Authority,
+    ) {
+        let entry = self.entries.entry(component_id).or_default();
+        let was_authoritative = entry.authority.contains(entity.id());
+
+        if authority == Authority::NotAuthoritative {
+            if was_authoritative {
+                entry.authority.remove(entity.id());
+                entry.count.authoritative -= 1;
+            }
+        } else if !was_authoritative {
+            entry.authority.add(entity.id());
+            entry.count.authoritative += 1;
+        }
+    }
+}
+
+#[test]
+fn census_should_track_checkout_and_authority() {
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    let first = world.create_entity().build();
+    let second = world.create_entity().build();
+
+    let mut census = ComponentCensus::default();
+
+    census.component_added(54);
+    census.component_added(54);
+    census.authority_changed(54, first, Authority::Authoritative);
+    census.authority_changed(54, first, Authority::AuthorityLossImminent);
+
+    assert_eq!(
+        ComponentCount {
+            checked_out: 2,
+            authoritative: 1
+        },
+        census.get(54)
+    );
+
+    census.authority_changed(54, first, Authority::NotAuthoritative);
+    census.authority_changed(54, second, Authority::Authoritative);
+    census.component_removed(54, second);
+
+    assert_eq!(
+        ComponentCount {
+            checked_out: 1,
+            authoritative: 0
+        },
+        census.get(54)
+    );
+    assert_eq!(ComponentCount::default(), census.get(1002));
+}
@@ -0,0 +1,250 @@
+//! Fault injection, for checking that systems behave correctly under rare interleavings.
+//!
+//! When the `chaos` feature is enabled and a `ChaosMonkey` resource is present, faults are
+//! injected at fixed points in the reader, each with a configured probability. The faults
+//! are chosen by a seeded generator, so a failing run can be reproduced with the same seed.
+//!
+//! ```ignore
+//! let mut chaos = ChaosMonkey::new(seed);
+//! chaos.set_probability(ChaosFault::DroppedResponse, 0.05);
+//! chaos.set_authority_delay(3);
+//! world.insert(chaos);
+//! ```
+use crate::entities::EntityId;
+use crate::logging::{self, LogKind, LogLevel};
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::op::AuthorityChangeOp;
+use spatialos_sdk::worker::Authority;
+use specs::prelude::World;
+use std::collections::HashMap;
+
+/// A point at which a fault can be injected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ChaosFault {
+    /// Received component data is not inserted into storage, and the failure is reported as
+    /// if the insert had failed. Later updates to the component are dropped.
+    InsertFailure,
+    /// An authority change is applied a number of frames after it was received. Later
+    /// changes to the same component of the entity are delayed until after it, so they are
+    /// still applied in order.
+    DelayedAuthority,
+    /// A command response is discarded, so its callback is never called.
+    DroppedResponse,
+}
+
+/// A resource which enables fault injection.
+pub struct ChaosMonkey {
+    state: u64,
+    probabilities: HashMap<ChaosFault, f64>,
+    injected: HashMap<ChaosFault, usize>,
+    authority_delay: u32,
+    delayed_authority: Vec<(u32, EntityId, ComponentId, Authority)>,
+}
+
+impl ChaosMonkey {
+    /// Creates a `ChaosMonkey` which injects no faults until their probabilities are set.
+    pub fn new(seed: u64) -> ChaosMonkey {
+        ChaosMonkey {
+            state: seed,
+            probabilities: HashMap::new(),
+            injected: HashMap::new(),
+            authority_delay: 1,
+            delayed_authority: Vec::new(),
+        }
+    }
+
+    /// Sets the probability, between 0 and 1, that the fault is injected each time its
+    /// injection point is reached.
+    pub fn set_probability(&mut self, fault: ChaosFault, probability: f64) {
+        self.probabilities.insert(fault, probability);
+    }
+
+    /// Sets the number of frames by which a delayed authority change is delayed.
+    pub fn set_authority_delay(&mut self, frames: u32) {
+        self.authority_delay = frames.max(1);
+    }
+
+    /// The number of times the fault has been injected.
+    pub fn injected(&self, fault: ChaosFault) -> usize {
+        self.injected.get(&fault).cloned().unwrap_or(0)
+    }
+
+    // SplitMix64, so that a seed gives the same faults on every platform.
+    fn next_f64(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn roll(&mut self, fault: ChaosFault) -> bool {
+        let probability = match self.probabilities.get(&fault) {
+            Some(probability) if *probability > 0.0 => *probability,
+            _ => return false,
+        };
+
+        let inject = self.next_f64() < probability;
+        if inject {
+            *self.injected.entry(fault).or_insert(0) += 1;
+        }
+        inject
+    }
+
+    /// Returns whether the fault should be injected at this point.
+    pub(crate) fn inject(res: &World, fault: ChaosFault) -> bool {
+        if !res.has_value::<ChaosMonkey>() || !res.fetch_mut::<ChaosMonkey>().roll(fault) {
+            return false;
+        }
+
+        logging::log(
+            res,
+            LogLevel::Debug,
+            LogKind::Chaos,
+            &format!("Chaos: injecting {:?}.", fault),
+        );
+        true
+    }
+
+    /// Returns whether the authority change was delayed, in which case it must not be
+    /// applied now.
+    pub(crate) fn delay_authority_change(
+        res: &World,
+        authority_change: &AuthorityChangeOp,
+    ) -> bool {
+        if !res.has_value::<ChaosMonkey>() {
+            return false;
+        }
+
+        let entity_id = EntityId(authority_change.entity_id);
+        let queued_behind = res
+            .fetch::<ChaosMonkey>()
+            .last_delayed(entity_id, authority_change.component_id);
+        if queued_behind.is_none() && !ChaosMonkey::inject(res, ChaosFault::DelayedAuthority) {
+            return false;
+        }
+
+        res.fetch_mut::<ChaosMonkey>().push_delayed(
+            entity_id,
+            authority_change.component_id,
+            authority_change.authority,
+        );
+        true
+    }
+
+    // The frames left until the most recently delayed change to the component is applied.
+    fn last_delayed(&self, entity_id: EntityId, component_id: ComponentId) -> Option<u32> {
+        self.delayed_authority
+            .iter()
+            .rev()
+            .find(|delayed| delayed.1 == entity_id && delayed.2 == component_id)
+            .map(|delayed| delayed.0)
+    }
+
+    // Delays the change by the configured number of frames, or until after an earlier
+    // delayed change to the same component, whichever is later.
+    fn push_delayed(
+        &mut self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        authority: Authority,
+    ) {
+        let frames = self
+            .last_delayed(entity_id, component_id)
+            .map_or(self.authority_delay, |frames| {
+                frames.max(self.authority_delay)
+            });
+        self.delayed_authority
+            .push((frames, entity_id, component_id, authority));
+    }
+
+    /// Counts down the delayed authority changes, returning those which are now due in the
+    /// order they were received.
+    pub(crate) fn due_authority_changes(res: &World) -> Vec<AuthorityChangeOp> {
+        if !res.has_value::<ChaosMonkey>() {
+            return Vec::new();
+        }
+
+        let mut chaos = res.fetch_mut::<ChaosMonkey>();
+        let mut due = Vec::new();
+
+        chaos.delayed_authority.retain(|delayed| {
+            let (frames, entity_id, component_id, authority) = *delayed;
+            if frames <= 1 {
+                due.push(AuthorityChangeOp {
+                    entity_id: entity_id.id(),
+                    component_id,
+                    authority,
+                });
+                false
+            } else {
+                true
+            }
+        });
+
+        for delayed in &mut chaos.delayed_authority {
+            delayed.0 -= 1;
+        }
+
+        due
+    }
+}
+
+#[test]
+fn chaos_monkey_should_be_reproducible_from_seed() {
+    let rolls = |seed| {
+        let mut chaos = ChaosMonkey::new(seed);
+        chaos.set_probability(ChaosFault::InsertFailure, 0.5);
+        (0..64)
+            .map(|_| chaos.roll(ChaosFault::InsertFailure))
+            .collect::<Vec<bool>>()
+    };
+
+    assert_eq!(rolls(7), rolls(7));
+    assert_ne!(rolls(7), rolls(8));
+
+    let mut chaos = ChaosMonkey::new(7);
+    chaos.set_probability(ChaosFault::DroppedResponse, 1.0);
+    assert!(!chaos.roll(ChaosFault::InsertFailure));
+    assert!(chaos.roll(ChaosFault::DroppedResponse));
+    assert_eq!(1, chaos.injected(ChaosFault::DroppedResponse));
+    assert_eq!(0, chaos.injected(ChaosFault::InsertFailure));
+}
+
+#[test]
+fn delayed_authority_changes_should_stay_in_order_per_component() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+
+    let entity_id = EntityId(WorkerEntityId::new(1));
+    let mut chaos = ChaosMonkey::new(1);
+    chaos.set_authority_delay(3);
+    chaos.push_delayed(entity_id, 54, Authority::Authoritative);
+
+    // A shorter delay set later doesn't let a second change overtake the first.
+    chaos.set_authority_delay(1);
+    assert_eq!(Some(3), chaos.last_delayed(entity_id, 54));
+    chaos.push_delayed(entity_id, 54, Authority::NotAuthoritative);
+    chaos.push_delayed(entity_id, 58, Authority::Authoritative);
+    assert_eq!(None, chaos.last_delayed(entity_id, 60));
+
+    let mut world = World::new();
+    world.insert(chaos);
+    let due = |world: &World| -> Vec<(ComponentId, Authority)> {
+        ChaosMonkey::due_authority_changes(world)
+            .into_iter()
+            .map(|change| (change.component_id, change.authority))
+            .collect()
+    };
+
+    assert_eq!(vec![(58, Authority::Authoritative)], due(&world));
+    assert!(due(&world).is_empty());
+    assert_eq!(
+        vec![
+            (54, Authority::Authoritative),
+            (54, Authority::NotAuthoritative)
+        ],
+        due(&world)
+    );
+}
@@ -0,0 +1,216 @@
+//! Events for when a group of related entities, such as a player and their weapon, are all
+//! checked out, and for when any of them leaves, so that systems which need every member
+//! don't have to check each of them themselves.
+//!
+//! Adding a `CheckoutGroups` resource enables the tracking, and groups are declared by the
+//! SpatialOS entity IDs of their members:
+//!
+//! ```ignore
+//! world.insert(CheckoutGroups::default());
+//! world.insert(CheckoutGroupEvents::new());
+//!
+//! let group = world.fetch_mut::<CheckoutGroups>().add(vec![player_id, weapon_id]);
+//!
+//! // In a system:
+//! for event in events.read(&mut reader_id) {
+//!     match event {
+//!         CheckoutGroupEvent::Complete(id, members) if *id == group => equip(members),
+//!         CheckoutGroupEvent::Broken(id, left) if *id == group => unequip(left),
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! Groups are checked by the `SpatialReaderSystem` after it has applied the frame's ops, so
+//! a group which is already complete when it is declared is reported in the next frame. A
+//! member which is removed and checked out again within a frame, as a new specs entity,
+//! breaks the group and completes it again.
+use crate::entities::{EntityId, SpatialEntitiesRes};
+use specs::prelude::{Entity, World};
+use specs::shrev::EventChannel;
+use std::collections::{HashMap, HashSet};
+
+/// Identifies a group declared with `CheckoutGroups::add`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CheckoutGroupId(u64);
+
+/// An event emitted when a group's members are all checked out, or stop being so.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckoutGroupEvent {
+    /// Every member is checked out, as the given specs entities, in the declared order.
+    Complete(CheckoutGroupId, Vec<(EntityId, Entity)>),
+    /// The group was complete and the given members are no longer checked out, or were
+    /// replaced by a new specs entity.
+    Broken(CheckoutGroupId, Vec<EntityId>),
+}
+
+/// An event channel which receives a `CheckoutGroupEvent` whenever a group is completed
+/// or broken.
+pub type CheckoutGroupEvents = EventChannel<CheckoutGroupEvent>;
+
+struct Group {
+    members: Vec<EntityId>,
+    present: Option<Vec<Entity>>,
+}
+
+/// A resource holding the declared groups and whether each is complete.
+#[derive(Default)]
+pub struct CheckoutGroups {
+    groups: HashMap<CheckoutGroupId, Group>,
+    next_id: u64,
+    changed: HashSet<CheckoutGroupId>,
+}
+
+impl CheckoutGroups {
+    /// Declares a group of entities, which is complete once all of them are checked out.
+    pub fn add(&mut self, members: Vec<EntityId>) -> CheckoutGroupId {
+        let id = CheckoutGroupId(self.next_id);
+        self.next_id += 1;
+
+        self.groups.insert(
+            id,
+            Group {
+                members,
+                present: None,
+            },
+        );
+        self.changed.insert(id);
+        id
+    }
+
+    /// Stops tracking the group. No event is emitted, even if it was complete.
+    pub fn remove(&mut self, id: CheckoutGroupId) {
+        self.groups.remove(&id);
+        self.changed.remove(&id);
+    }
+
+    pub fn members(&self, id: CheckoutGroupId) -> Option<&[EntityId]> {
+        self.groups.get(&id).map(|group| group.members.as_slice())
+    }
+
+    /// Returns whether every member of the group was checked out when groups were last
+    /// checked.
+    pub fn is_complete(&self, id: CheckoutGroupId) -> bool {
+        self.groups
+            .get(&id)
+            .map_or(false, |group| group.present.is_some())
+    }
+
+    pub(crate) fn entity_changed(&mut self, entity_id: EntityId) {
+        for (id, group) in &self.groups {
+            if group.members.contains(&entity_id) {
+                self.changed.insert(*id);
+            }
+        }
+    }
+
+    pub(crate) fn update(res: &World) {
+        let events = {
+            let entities = res.fetch::<SpatialEntitiesRes>();
+            res.fetch_mut::<CheckoutGroups>().check_changed(&entities)
+        };
+
+        if res.has_value::<CheckoutGroupEvents>() {
+            res.fetch_mut::<CheckoutGroupEvents>().iter_write(events);
+        }
+    }
+
+    fn check_changed(&mut self, entities: &SpatialEntitiesRes) -> Vec<CheckoutGroupEvent> {
+        let mut changed: Vec<CheckoutGroupId> = self.changed.drain().collect();
+        changed.sort();
+
+        let mut events = Vec::new();
+        for id in changed {
+            let group = match self.groups.get_mut(&id) {
+                Some(group) => group,
+                None => continue,
+            };
+
+            let present: Option<Vec<Entity>> = group
+                .members
+                .iter()
+                .map(|entity_id| entities.get_entity(*entity_id))
+                .collect();
+            if present == group.present {
+                continue;
+            }
+
+            if let Some(previous) = &group.present {
+                let left = group
+                    .members
+                    .iter()
+                    .zip(previous)
+                    .filter(|(entity_id, entity)| {
+                        entities.get_entity(**entity_id) != Some(**entity)
+                    })
+                    .map(|(entity_id, _)| *entity_id)
+                    .collect();
+                events.push(CheckoutGroupEvent::Broken(id, left));
+            }
+
+            if let Some(present) = &present {
+                events.push(CheckoutGroupEvent::Complete(
+                    id,
+                    group.members.iter().cloned().zip(present.clone()).collect(),
+                ));
+            }
+
+            group.present = present;
+        }
+
+        events
+    }
+}
+
+#[test]
+fn checkout_groups_should_report_completion_and_breakage() {
+    use crate::entities::EntityIds;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::{SystemData, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    world.insert(CheckoutGroups::default());
+    world.insert(CheckoutGroupEvents::new());
+    let mut reader = world.fetch_mut::<CheckoutGroupEvents>().register_reader();
+
+    let player = EntityId(WorkerEntityId::new(1));
+    let weapon = EntityId(WorkerEntityId::new(2));
+    let group = world
+        .fetch_mut::<CheckoutGroups>()
+        .add(vec![player, weapon]);
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, player);
+    CheckoutGroups::update(&world);
+    assert!(!world.fetch::<CheckoutGroups>().is_complete(group));
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, weapon);
+    CheckoutGroups::update(&world);
+    assert!(world.fetch::<CheckoutGroups>().is_complete(group));
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .remove_entity(&world, weapon);
+    CheckoutGroups::update(&world);
+    assert!(!world.fetch::<CheckoutGroups>().is_complete(group));
+
+    let events: Vec<CheckoutGroupEvent> = world
+        .fetch::<CheckoutGroupEvents>()
+        .read(&mut reader)
+        .cloned()
+        .collect();
+    assert_eq!(2, events.len());
+    match &events[0] {
+        CheckoutGroupEvent::Complete(id, members) => {
+            assert_eq!(group, *id);
+            let ids: Vec<EntityId> = members.iter().map(|(entity_id, _)| *entity_id).collect();
+            assert_eq!(vec![player, weapon], ids);
+        }
+        event => panic!("Unexpected event {:?}", event),
+    }
+    assert_eq!(CheckoutGroupEvent::Broken(group, vec![weapon]), events[1]);
+}
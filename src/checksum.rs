@@ -0,0 +1,126 @@
+//! Periodic verification that updates reproduce the local value of a component, to detect
+//! drift caused by incorrect `merge` implementations in generated code.
+//!
+//! When a `ChecksumVerification` resource is present, every `interval` frames the
+//! `SpatialWriterSystem` serializes the local value of each component, and compares its
+//! checksum against that of the value rebuilt from a freshly deserialized copy of the
+//! update that would be sent. A pending partial update is applied to the value from before
+//! it, as SpatialOS will apply it, and a full update to the schema default value.
+//! Rebuilding the local value from its update should give the same value, so a mismatch
+//! means that schema serialization or `merge` lost or altered data. Mismatches are logged
+//! as errors and emitted as `ChecksumMismatch` events.
+//!
+//! ```ignore
+//! world.insert(ChecksumVerification::new(300));
+//! world.insert(ChecksumMismatchEvents::new());
+//! ```
+//!
+//! Verification serializes every component several times, so is only intended for debug
+//! builds and soak tests.
+use crate::component_registry::describe_component;
+use crate::entities::EntityId;
+use crate::logging::{self, LogKind, LogLevel};
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::World;
+use specs::shrev::EventChannel;
+
+/// A component whose value did not survive a round trip through its update.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    pub entity_id: EntityId,
+    pub component_id: ComponentId,
+    pub local_checksum: u64,
+    pub sent_checksum: u64,
+}
+
+/// An event channel which receives a `ChecksumMismatch` for every mismatch found.
+pub type ChecksumMismatchEvents = EventChannel<ChecksumMismatch>;
+
+/// A resource which enables checksum verification.
+pub struct ChecksumVerification {
+    interval: u64,
+    frame: u64,
+    mismatches: u64,
+}
+
+impl ChecksumVerification {
+    /// Verifies every component once every `interval` frames.
+    pub fn new(interval: u64) -> ChecksumVerification {
+        ChecksumVerification {
+            interval: interval.max(1),
+            frame: 0,
+            mismatches: 0,
+        }
+    }
+
+    /// The total number of mismatches found.
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches
+    }
+
+    fn next_frame(&mut self) -> bool {
+        self.frame += 1;
+        self.frame % self.interval == 0
+    }
+
+    /// Returns whether components should be verified this frame.
+    pub(crate) fn is_due(res: &World) -> bool {
+        res.fetch_mut::<ChecksumVerification>().next_frame()
+    }
+
+    pub(crate) fn report(res: &World, mismatch: ChecksumMismatch) {
+        res.fetch_mut::<ChecksumVerification>().mismatches += 1;
+
+        logging::log(
+            res,
+            LogLevel::Error,
+            LogKind::Checksum,
+            &format!(
+                "Checksum mismatch for component {} of entity {}: the local value has checksum {:016x} but the value after applying its update has {:016x}.",
+                describe_component(mismatch.component_id), mismatch.entity_id, mismatch.local_checksum, mismatch.sent_checksum
+            ),
+        );
+
+        if res.has_value::<ChecksumMismatchEvents>() {
+            res.fetch_mut::<ChecksumMismatchEvents>()
+                .single_write(mismatch);
+        }
+    }
+
+    pub(crate) fn report_error(
+        res: &World,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        error: &str,
+    ) {
+        logging::log(
+            res,
+            LogLevel::Error,
+            LogKind::Checksum,
+            &format!(
+                "Could not verify the checksum of component {} of entity {}: {}",
+                describe_component(component_id),
+                entity_id,
+                error
+            ),
+        );
+    }
+}
+
+// FNV-1a, which is stable across platforms and releases, unlike `DefaultHasher`.
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[test]
+fn checksum_verification_should_run_every_interval() {
+    assert_eq!(0xcbf2_9ce4_8422_2325, checksum(&[]));
+    assert_eq!(0xaf63_dc4c_8601_ec8c, checksum(b"a"));
+    assert_ne!(checksum(&[1, 2]), checksum(&[2, 1]));
+
+    let mut verification = ChecksumVerification::new(3);
+    let due: Vec<bool> = (0..6).map(|_| verification.next_frame()).collect();
+    assert_eq!(vec![false, false, true, false, false, true], due);
+}
@@ -0,0 +1,105 @@
+//! The source of time for every time-dependent feature of this crate, such as log rate
+//! limiting, spawn retries and eviction grace periods.
+//!
+//! By default the system clock is used. Adding a `SpatialClock` resource backed by a
+//! `ManualClock` lets tests drive time deterministically, and lets headless simulations
+//! run faster than real time by advancing the clock by a fixed step each frame.
+use specs::prelude::World;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A source of the current time.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// A `Clock` which reads the system's monotonic clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A `Clock` which only moves when it is advanced.
+///
+/// Clones share the same time, so a clone can be kept to advance the clock after the
+/// original has been added to the world.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A resource which overrides the clock used by this crate.
+///
+/// ```ignore
+/// let clock = ManualClock::new();
+/// world.insert(SpatialClock::new(clock.clone()));
+///
+/// clock.advance(Duration::from_secs(1));
+/// ```
+pub struct SpatialClock {
+    clock: Box<Clock + Send + Sync>,
+}
+
+impl SpatialClock {
+    pub fn new<C: 'static + Clock + Send + Sync>(clock: C) -> SpatialClock {
+        SpatialClock {
+            clock: Box::new(clock),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+}
+
+impl Default for SpatialClock {
+    fn default() -> Self {
+        SpatialClock::new(SystemClock)
+    }
+}
+
+pub(crate) fn now(res: &World) -> Instant {
+    if res.has_value::<SpatialClock>() {
+        res.fetch::<SpatialClock>().now()
+    } else {
+        Instant::now()
+    }
+}
+
+#[test]
+fn manual_clock_should_be_shared_between_clones() {
+    let clock = ManualClock::new();
+    let spatial_clock = SpatialClock::new(clock.clone());
+
+    let start = spatial_clock.now();
+    clock.advance(Duration::from_secs(5));
+
+    assert_eq!(Duration::from_secs(5), spatial_clock.now() - start);
+}
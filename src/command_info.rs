@@ -0,0 +1,66 @@
+//! Runtime descriptions of the commands of a component, so that generic tooling such as
+//! RPC loggers, fuzzers and debug consoles can enumerate them.
+//!
+//! `ComponentCommands` is not generated, so it is implemented by hand for each component
+//! whose commands should be described. Nothing is registered automatically: call
+//! `register_component_commands` for each of those components during setup, and
+//! `component_commands` returns an empty list for any component which wasn't registered:
+//!
+//! ```ignore
+//! impl ComponentCommands for Player {
+//!     const COMMANDS: &'static [CommandInfo] = &[CommandInfo {
+//!         index: 1,
+//!         name: "kick",
+//!         request_type: "game.KickRequest",
+//!         response_type: "game.KickResponse",
+//!     }];
+//! }
+//!
+//! register_component_commands::<Player>();
+//!
+//! for command in component_commands(Player::ID) {
+//!     println!("{} {}({})", command.response_type, command.name, command.request_type);
+//! }
+//! ```
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+
+/// The schema index of a command within its component.
+pub type CommandIndex = u32;
+
+/// A command of a component.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub index: CommandIndex,
+    /// The name of the command in schema.
+    pub name: &'static str,
+    /// The fully qualified schema name of the request type.
+    pub request_type: &'static str,
+    /// The fully qualified schema name of the response type.
+    pub response_type: &'static str,
+}
+
+/// A table describing the commands of a component.
+pub trait ComponentCommands: WorkerComponent {
+    /// Every command of the component, in ascending order of `CommandIndex`.
+    const COMMANDS: &'static [CommandInfo];
+
+    fn command_info(index: CommandIndex) -> Option<&'static CommandInfo> {
+        Self::COMMANDS
+            .binary_search_by_key(&index, |command| command.index)
+            .ok()
+            .map(|position| &Self::COMMANDS[position])
+    }
+
+    /// Describes the command of a request.
+    fn request_info(request: &Self::CommandRequest) -> Option<&'static CommandInfo> {
+        Self::command_info(Self::get_request_command_index(request))
+    }
+}
+
+#[test]
+fn command_info_should_be_found_by_index() {
+    use crate::generated_test::*;
+
+    assert_eq!("update_coords", Position::command_info(1).unwrap().name);
+    assert!(Position::command_info(2).is_none());
+}
@@ -1,18 +1,174 @@
-use crate::component_registry::ComponentRegistry;
+use crate::acl::RequirementSet;
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::connection_handle::{SdkCall, SpatialConnectionHandle};
 use crate::entities::EntityId;
-use crate::storage::SpatialUnprotectedStorage;
+use crate::logging::{self, LogKind, LogLevel};
+use crate::sdk::{self, SdkConnection};
+use crate::system_commands::map_status_code_error;
 use crate::SystemDataFetch;
+use hibitset::{BitSet, BitSetLike};
 use spatialos_sdk::worker::commands::{IncomingCommandRequest, OutgoingCommandRequest};
 use spatialos_sdk::worker::component::Component as WorkerComponent;
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
+use spatialos_sdk::worker::component::ComponentId;
 use spatialos_sdk::worker::op::{
     CommandResponse as WorkerCommandResponse, CommandResponseOp, StatusCode,
 };
-use spatialos_sdk::worker::RequestId;
+use spatialos_sdk::worker::{Authority, RequestId};
 use specs::prelude::{
-    Component, Entities, Entity, HashMapStorage, Join, Resources, SystemData, Write, WriteStorage,
+    Component, Entities, Entity, HashMapStorage, Join, SystemData, World, Write, WriteStorage,
 };
+use specs::shrev::EventChannel;
+use specs::storage::{DistinctStorage, UnprotectedStorage};
+use specs::world::Index;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// An event emitted when this worker gains or loses the ability to respond to commands
+/// on a component of an entity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommandAuthorityEvent {
+    Gained(Entity, ComponentId),
+    Lost(Entity, ComponentId),
+}
+
+/// An event channel which receives a `CommandAuthorityEvent` whenever command
+/// authority changes.
+pub type CommandAuthorityEvents = EventChannel<CommandAuthorityEvent>;
+
+/// A resource which tracks which components this worker can respond to commands on.
+///
+/// Command authority is the same as write authority over the component, but unlike
+/// `SpatialWriteStorage` it is tracked even if the component data itself is never
+/// fetched by any system.
+#[derive(Default)]
+pub struct CommandAuthority {
+    authority: HashMap<ComponentId, BitSet>,
+}
+
+impl CommandAuthority {
+    /// Returns whether this worker can respond to commands on the given component of the entity.
+    pub fn can_respond(&self, component_id: ComponentId, entity: Entity) -> bool {
+        self.authority
+            .get(&component_id)
+            .map(|mask| mask.contains(entity.id()))
+            .unwrap_or(false)
+    }
+
+    // Returns the event to emit if command authority has changed.
+    pub(crate) fn set_authority(
+        &mut self,
+        component_id: ComponentId,
+        entity: Entity,
+        authority: Authority,
+    ) -> Option<CommandAuthorityEvent> {
+        let mask = self
+            .authority
+            .entry(component_id)
+            .or_insert_with(BitSet::new);
+
+        if authority == Authority::NotAuthoritative {
+            if mask.remove(entity.id()) {
+                return Some(CommandAuthorityEvent::Lost(entity, component_id));
+            }
+        } else if !mask.add(entity.id()) {
+            return Some(CommandAuthorityEvent::Gained(entity, component_id));
+        }
+
+        None
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.authority.clear();
+    }
+}
+
+/// A resource which caps how many requests of a component are handled on an entity each
+/// frame, across every call to `respond` and `claim`, so that a burst of requests is spread
+/// over several frames rather than stalling one. Requests are taken from each caller in
+/// turn, and the rest wait in the queue for a later frame. The budget is refreshed by the
+/// `SpatialReaderSystem` at the start of each frame.
+///
+/// ```ignore
+/// let mut budget = ResponderBudget::default();
+/// budget.set_budget(Login::ID, 100);
+/// world.insert(budget);
+/// ```
+#[derive(Debug, Default)]
+pub struct ResponderBudget {
+    budgets: HashMap<ComponentId, usize>,
+}
+
+impl ResponderBudget {
+    pub fn set_budget(&mut self, component_id: ComponentId, requests_per_pass: usize) {
+        self.budgets.insert(component_id, requests_per_pass.max(1));
+    }
+
+    pub fn clear_budget(&mut self, component_id: ComponentId) {
+        self.budgets.remove(&component_id);
+    }
+
+    pub fn budget(&self, component_id: ComponentId) -> Option<usize> {
+        self.budgets.get(&component_id).cloned()
+    }
+}
+
+/// The reason given when failing command requests rejected by `CommandAuthorization`.
+pub const UNAUTHORIZED: &str = "unauthorized";
+
+/// The worker or client which sent a command request.
+#[derive(Debug, Copy, Clone)]
+pub struct CallerAttributes<'a> {
+    pub worker_id: &'a str,
+    pub attribute_set: &'a [String],
+}
+
+impl<'a> CallerAttributes<'a> {
+    pub fn has_attribute(&self, attribute: &str) -> bool {
+        self.attribute_set.iter().any(|a| a == attribute)
+    }
+
+    /// Returns whether the caller satisfies a requirement set, such as a write ACL.
+    pub fn satisfies<R: RequirementSet>(&self, requirement: &R) -> bool {
+        requirement.matches(self.attribute_set)
+    }
+}
+
+type AuthorizeFn = Box<Fn(ComponentId, u32, &CallerAttributes) -> bool + Send + Sync>;
+
+/// A resource holding the access-control policy for incoming command requests.
+///
+/// The policy is called with the component ID and command index of every request before
+/// it is queued in `CommandRequests`. Requests which it rejects are failed with
+/// `UNAUTHORIZED`, so responders only see requests from permitted callers:
+///
+/// ```ignore
+/// world.insert(CommandAuthorization::new(|component_id, _, caller| {
+///     component_id != Admin::ID || caller.has_attribute("admin")
+/// }));
+/// ```
+pub struct CommandAuthorization {
+    authorize: AuthorizeFn,
+}
+
+impl CommandAuthorization {
+    pub fn new<F>(authorize: F) -> CommandAuthorization
+    where
+        F: 'static + Fn(ComponentId, u32, &CallerAttributes) -> bool + Send + Sync,
+    {
+        CommandAuthorization {
+            authorize: Box::new(authorize),
+        }
+    }
+
+    pub fn authorize(
+        &self,
+        component_id: ComponentId,
+        command_index: u32,
+        caller: &CallerAttributes,
+    ) -> bool {
+        (self.authorize)(component_id, command_index, caller)
+    }
+}
 
 /// A storage which contains command requests for a given component
 /// that have not been responded to yet.
@@ -52,6 +208,10 @@ use std::collections::HashMap;
 /// not be responded to. Please note that a command request will stay in this
 /// component until it has been responded too.
 ///
+/// Requests are only received for components which this worker is authoritative over. If
+/// authority is lost, any requests which have not been responded to are dropped. See
+/// [CommandAuthority](struct.CommandAuthority.html).
+///
 /// A command can only be responded to in a single system. If `SysA` runs before
 /// `SysB` and `SysB` responds to a request, `SysB` cannot see that request.
 ///
@@ -69,20 +229,99 @@ pub struct CommandRequestsComp<T: WorkerComponent> {
         String,
         Vec<String>,
     )>,
+    claimed: Vec<(
+        RequestId<IncomingCommandRequest>,
+        T::CommandRequest,
+        String,
+        Vec<String>,
+    )>,
     responses: Vec<(RequestId<IncomingCommandRequest>, T::CommandResponse)>,
+    budget: Option<usize>,
+    // The number of requests passed to `respond` or `claim` since the budget was refreshed.
+    handled: usize,
+}
+
+/// A command request serialized with schema, so that in-flight work can be persisted
+/// across a graceful restart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerializedCommandRequest {
+    pub command_index: u32,
+    pub bytes: Vec<u8>,
+    pub caller_worker_id: String,
+    pub caller_attribute_set: Vec<String>,
 }
 
+impl SerializedCommandRequest {
+    pub fn deserialize<T: WorkerComponent>(&self) -> Result<T::CommandRequest, String> {
+        sdk::deserialize_request::<T>(self.command_index, &self.bytes)
+    }
+}
+
+/// A handle to a command request which has been claimed by a system with
+/// [`claim`](struct.CommandRequestsComp.html#method.claim).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CommandClaim(RequestId<IncomingCommandRequest>);
+
 impl<T: WorkerComponent> Default for CommandRequestsComp<T> {
     fn default() -> Self {
         CommandRequestsComp {
             requests: Vec::new(),
+            claimed: Vec::new(),
             responses: Vec::new(),
+            budget: None,
+            handled: 0,
         }
     }
 }
 
 impl<T: 'static + WorkerComponent> Component for CommandRequestsComp<T> {
-    type Storage = SpatialUnprotectedStorage<T, Self, HashMapStorage<Self>>;
+    type Storage = CommandRequestsStorage<T>;
+}
+
+/// The storage of command request queues. Queues only exist for entities which have
+/// pending requests or responses, independently of the storage of the component's data.
+///
+/// Creating the storage registers the component, so that requests are received by workers
+/// which respond to commands without reading the component's data.
+#[doc(hidden)]
+pub struct CommandRequestsStorage<T: 'static + WorkerComponent>(
+    HashMapStorage<CommandRequestsComp<T>>,
+);
+
+impl<T: 'static + WorkerComponent> UnprotectedStorage<CommandRequestsComp<T>>
+    for CommandRequestsStorage<T>
+{
+    unsafe fn clean<B>(&mut self, has: B)
+    where
+        B: BitSetLike,
+    {
+        self.0.clean(has);
+    }
+
+    unsafe fn get(&self, id: Index) -> &CommandRequestsComp<T> {
+        self.0.get(id)
+    }
+
+    unsafe fn get_mut(&mut self, id: Index) -> &mut CommandRequestsComp<T> {
+        self.0.get_mut(id)
+    }
+
+    unsafe fn insert(&mut self, id: Index, v: CommandRequestsComp<T>) {
+        self.0.insert(id, v);
+    }
+
+    unsafe fn remove(&mut self, id: Index) -> CommandRequestsComp<T> {
+        self.0.remove(id)
+    }
+}
+
+unsafe impl<T: 'static + WorkerComponent> DistinctStorage for CommandRequestsStorage<T> {}
+
+impl<T: 'static + WorkerComponent> Default for CommandRequestsStorage<T> {
+    fn default() -> Self {
+        ComponentRegistry::register_component::<T>();
+        CommandRequestsStorage(Default::default())
+    }
 }
 
 impl<T: 'static + WorkerComponent> CommandRequestsComp<T> {
@@ -97,6 +336,32 @@ impl<T: 'static + WorkerComponent> CommandRequestsComp<T> {
             .push((request_id, request, caller_worker_id, caller_attribute_set));
     }
 
+    pub(crate) fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+    }
+
+    pub(crate) fn refresh_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+        self.handled = 0;
+    }
+
+    // Selects the pending requests which can be handled within the remaining budget.
+    fn select(&mut self) -> Vec<bool> {
+        let remaining = self
+            .budget
+            .map(|budget| budget.saturating_sub(self.handled));
+        let selected = match remaining {
+            Some(remaining) if remaining < self.requests.len() => select_fairly(
+                self.requests.iter().map(|request| request.2.as_str()),
+                remaining,
+            ),
+            _ => vec![true; self.requests.len()],
+        };
+
+        self.handled += selected.iter().filter(|selected| **selected).count();
+        selected
+    }
+
     /// Respond to the pending command requests.
     ///
     /// The given closure accepts a command request object and returns:
@@ -104,6 +369,9 @@ impl<T: 'static + WorkerComponent> CommandRequestsComp<T> {
     /// * `Some(response)` to respond to the command.
     /// * `None` to not respond to the command, leaving the request for other systems or
     ///   the next frame.
+    ///
+    /// If the component has a `ResponderBudget`, only the requests remaining in this frame's
+    /// budget are passed to the closure, and the rest are left for a later frame.
     pub fn respond(
         &mut self,
         mut responder: impl FnMut(
@@ -112,9 +380,17 @@ impl<T: 'static + WorkerComponent> CommandRequestsComp<T> {
             &Vec<String>,
         ) -> Option<T::CommandResponse>,
     ) {
+        let selected = self.select();
+
         let mut requests_left = Vec::new();
-        for (request_id, request, caller_worker_id, caller_attribute_set) in self.requests.drain(..)
+        for ((request_id, request, caller_worker_id, caller_attribute_set), selected) in
+            self.requests.drain(..).zip(selected)
         {
+            if !selected {
+                requests_left.push((request_id, request, caller_worker_id, caller_attribute_set));
+                continue;
+            }
+
             match responder(&request, &caller_worker_id, &caller_attribute_set) {
                 Some(response) => self.responses.push((request_id, response)),
                 None => requests_left.push((
@@ -129,30 +405,221 @@ impl<T: 'static + WorkerComponent> CommandRequestsComp<T> {
         self.requests = requests_left;
     }
 
-    pub(crate) fn flush_responses(&mut self, connection: &mut WorkerConnection) {
+    /// Claim pending command requests without responding to them yet.
+    ///
+    /// Requests for which the closure returns `true` are removed from the pending requests,
+    /// so no later call to `respond` or `claim`, in this system or any other, will see them.
+    /// The claiming system must later respond with
+    /// [`respond_claimed`](struct.CommandRequestsComp.html#method.respond_claimed) or give up
+    /// the claim with [`release`](struct.CommandRequestsComp.html#method.release).
+    ///
+    /// This makes "first responder wins" explicit when multiple systems may handle the same
+    /// command type, rather than depending on the order the dispatcher runs systems in.
+    ///
+    /// As with `respond`, only the requests remaining in this frame's `ResponderBudget` are
+    /// passed to the closure.
+    pub fn claim(
+        &mut self,
+        mut filter: impl FnMut(&T::CommandRequest, &String, &Vec<String>) -> bool,
+    ) -> Vec<CommandClaim> {
+        let selected = self.select();
+        let mut claims = Vec::new();
+        let mut requests_left = Vec::new();
+
+        for (request, selected) in self.requests.drain(..).zip(selected) {
+            if selected && filter(&request.1, &request.2, &request.3) {
+                claims.push(CommandClaim(request.0));
+                self.claimed.push(request);
+            } else {
+                requests_left.push(request);
+            }
+        }
+
+        self.requests = requests_left;
+        claims
+    }
+
+    /// Returns the request, caller worker ID and caller attribute set of a claimed request.
+    pub fn get_claimed(
+        &self,
+        claim: CommandClaim,
+    ) -> Option<(&T::CommandRequest, &String, &Vec<String>)> {
+        self.claimed
+            .iter()
+            .find(|request| request.0 == claim.0)
+            .map(|request| (&request.1, &request.2, &request.3))
+    }
+
+    /// Respond to a claimed request. Returns `false` if the claim is not held on this entity,
+    /// for example because authority over the component was lost.
+    pub fn respond_claimed(&mut self, claim: CommandClaim, response: T::CommandResponse) -> bool {
+        match self.claimed.iter().position(|request| request.0 == claim.0) {
+            Some(index) => {
+                let request = self.claimed.remove(index);
+                self.responses.push((request.0, response));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Serializes every request which has not been responded to, including claimed requests.
+    ///
+    /// Request IDs are only valid on the connection the request was received on, so a
+    /// request restored after a restart can be processed but not responded to.
+    pub fn serialize_pending(&self) -> Result<Vec<SerializedCommandRequest>, String> {
+        self.requests
+            .iter()
+            .chain(self.claimed.iter())
+            .map(|(_, request, caller_worker_id, caller_attribute_set)| {
+                let (command_index, bytes) = sdk::serialize_request::<T>(request)?;
+                Ok(SerializedCommandRequest {
+                    command_index,
+                    bytes,
+                    caller_worker_id: caller_worker_id.clone(),
+                    caller_attribute_set: caller_attribute_set.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Give up a claim, returning the request to the pending requests.
+    pub fn release(&mut self, claim: CommandClaim) {
+        if let Some(index) = self.claimed.iter().position(|request| request.0 == claim.0) {
+            let request = self.claimed.remove(index);
+            self.requests.push(request);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.claimed.is_empty() && self.responses.is_empty()
+    }
+
+    // The number of requests which haven't been responded to, or whose response hasn't
+    // been sent.
+    pub(crate) fn unanswered(&self) -> usize {
+        self.requests.len() + self.claimed.len() + self.responses.len()
+    }
+
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub(crate) fn take_responses(
+        &mut self,
+    ) -> Vec<(RequestId<IncomingCommandRequest>, T::CommandResponse)> {
+        self.responses.drain(..).collect()
+    }
+
+    pub(crate) fn flush_responses<C: SdkConnection>(&mut self, connection: &mut C) -> usize {
+        let count = self.responses.len();
         for (request_id, response) in self.responses.drain(..) {
-            connection.send_command_response::<T>(request_id, response);
+            connection.send_response::<T>(request_id, response);
+        }
+        count
+    }
+}
+
+// Selects up to `budget` requests, taking one from each caller in turn so that a caller
+// with many requests cannot starve the others. Ties are broken by arrival order.
+fn select_fairly<'a, I: Iterator<Item = &'a str>>(callers: I, budget: usize) -> Vec<bool> {
+    let mut received_from: HashMap<&str, usize> = HashMap::new();
+    let turns: Vec<usize> = callers
+        .map(|caller| {
+            let received = received_from.entry(caller).or_insert(0);
+            *received += 1;
+            *received
+        })
+        .collect();
+
+    let mut order: Vec<usize> = (0..turns.len()).collect();
+    order.sort_by_key(|&index| (turns[index], index));
+
+    let mut selected = vec![false; turns.len()];
+    for &index in order.iter().take(budget) {
+        selected[index] = true;
+    }
+    selected
+}
+
+/// Responding to command requests with access to other data of the responding entity.
+pub trait RespondWithData<T: WorkerComponent> {
+    /// Respond to the pending command requests of every entity, with mutable access to a
+    /// bundle of other system data.
+    ///
+    /// This behaves like [`respond`](struct.CommandRequestsComp.html#method.respond), but the
+    /// closure is also given the entity the request was sent to and the `data` bundle, so it can
+    /// read or mutate other components of that entity without a manual join.
+    ///
+    /// ```ignore
+    /// impl<'a> System<'a> for InventorySys {
+    ///     type SystemData = (
+    ///         Entities<'a>,
+    ///         CommandRequests<'a, Inventory>,
+    ///         SpatialWriteStorage<'a, Inventory>,
+    ///     );
+    ///
+    ///     fn run(&mut self, (entities, mut requests, mut inventory): Self::SystemData) {
+    ///         requests.respond_with_data(&entities, &mut inventory, |request, _, _, entity, inventory| {
+    ///             let inventory = inventory.get_mut(entity)?;
+    ///             ...
+    ///         });
+    ///     }
+    /// }
+    /// ```
+    fn respond_with_data<'e, D, F>(&mut self, entities: &Entities<'e>, data: &mut D, responder: F)
+    where
+        F: FnMut(
+            &T::CommandRequest,
+            &String,
+            &Vec<String>,
+            Entity,
+            &mut D,
+        ) -> Option<T::CommandResponse>;
+}
+
+impl<'a, T: 'static + WorkerComponent> RespondWithData<T> for CommandRequests<'a, T> {
+    fn respond_with_data<'e, D, F>(
+        &mut self,
+        entities: &Entities<'e>,
+        data: &mut D,
+        mut responder: F,
+    ) where
+        F: FnMut(
+            &T::CommandRequest,
+            &String,
+            &Vec<String>,
+            Entity,
+            &mut D,
+        ) -> Option<T::CommandResponse>,
+    {
+        for (entity, requests) in (entities, &mut *self).join() {
+            requests.respond(|request, caller_worker_id, caller_attribute_set| {
+                responder(
+                    request,
+                    caller_worker_id,
+                    caller_attribute_set,
+                    entity,
+                    &mut *data,
+                )
+            });
         }
     }
 }
 
 pub(crate) trait CommandRequestsExt {
-    fn clear_empty_request_objects(&mut self, res: &Resources);
+    fn remove_empty_queues(&mut self, res: &World);
 }
 
 impl<'a, T: 'static + WorkerComponent> CommandRequestsExt for CommandRequests<'a, T> {
-    fn clear_empty_request_objects(&mut self, res: &Resources) {
-        let non_empty_requests: Vec<(CommandRequestsComp<T>, Entity)> =
-            (self.drain(), &Entities::fetch(res))
-                .join()
-                .filter(|r| r.0.requests.len() > 0)
-                .collect();
-
-        self.clear();
-
-        for (requests, entity) in non_empty_requests {
-            self.insert(entity, requests)
-                .expect("Error inserting command request object.");
+    // Queues are removed once everything in them has been handled, so that no memory is
+    // held for entities which are not currently receiving commands.
+    fn remove_empty_queues(&mut self, res: &World) {
+        let empty: Vec<Entity> = (&Entities::fetch(res), &*self)
+            .join()
+            .filter(|(_, requests)| requests.is_empty())
+            .map(|(entity, _)| entity)
+            .collect();
+
+        for entity in empty {
+            self.remove(entity);
         }
     }
 }
@@ -162,11 +629,85 @@ pub type CommandSender<'a, T> = Write<'a, CommandSenderRes<T>>;
 type CommandResponse<'a, T> =
     Result<&'a <T as WorkerComponent>::CommandResponse, StatusCode<WorkerCommandResponse<'a>>>;
 
-type CommandIntermediateCallback = Box<FnOnce(&Resources, CommandResponseOp) + Send + Sync>;
+type CommandIntermediateCallback = Box<FnOnce(&World, CommandResponseOp) + Send + Sync>;
+
+/// A resource which fails command requests before they are sent, when this worker's view
+/// shows they can't succeed, rather than letting them time out.
+///
+/// A request is rejected if its target entity is checked out but the component isn't,
+/// so this should only be used by workers whose interest covers every component of the
+/// entities they send commands to. Requiring targets to be checked out also rejects
+/// requests to entities this worker can't see.
+///
+/// Rejected requests call their callback with a `NotFound` status describing the problem,
+/// when the reader next runs.
+///
+/// ```ignore
+/// world.insert(CommandValidation::new().require_checked_out());
+/// ```
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CommandValidation {
+    require_checked_out: bool,
+}
+
+impl CommandValidation {
+    pub fn new() -> CommandValidation {
+        Default::default()
+    }
+
+    pub fn require_checked_out(mut self) -> CommandValidation {
+        self.require_checked_out = true;
+        self
+    }
+
+    /// Returns why a request to the component of the entity can't succeed, given whether
+    /// the entity and the component are checked out.
+    pub(crate) fn validate(
+        &self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        entity_checked_out: bool,
+        component_checked_out: bool,
+    ) -> Option<String> {
+        if !entity_checked_out {
+            if self.require_checked_out {
+                return Some(format!(
+                    "entity {} is not checked out by this worker.",
+                    entity_id
+                ));
+            }
+            return None;
+        }
+
+        if !component_checked_out {
+            return Some(format!(
+                "entity {} does not have component {}.",
+                entity_id,
+                describe_component(component_id)
+            ));
+        }
+
+        None
+    }
+}
+
+/// The result of a broadcast command for each entity it was sent to.
+pub type BroadcastResults<T> =
+    HashMap<EntityId, Result<<T as WorkerComponent>::CommandResponse, StatusCode<()>>>;
+
+type BroadcastCallback<T> = Box<FnOnce(BroadcastResults<T>, SystemDataFetch) + Send + Sync>;
+
+struct Broadcast<T: WorkerComponent> {
+    remaining: usize,
+    results: BroadcastResults<T>,
+    callback: Option<BroadcastCallback<T>>,
+}
 
 pub struct CommandSenderRes<T: WorkerComponent> {
-    callbacks: HashMap<RequestId<OutgoingCommandRequest>, CommandIntermediateCallback>,
+    callbacks: HashMap<RequestId<OutgoingCommandRequest>, (EntityId, CommandIntermediateCallback)>,
     buffered_requests: Vec<(EntityId, T::CommandRequest, CommandIntermediateCallback)>,
+    rejected_requests: Vec<(EntityId, String, CommandIntermediateCallback)>,
+    empty_broadcasts: Vec<BroadcastCallback<T>>,
 }
 
 impl<T: 'static + WorkerComponent> CommandSenderRes<T> {
@@ -187,7 +728,82 @@ impl<T: 'static + WorkerComponent> CommandSenderRes<T> {
         ));
     }
 
-    pub(crate) fn got_command_response(res: &Resources, response_op: CommandResponseOp) {
+    /// Sends the request to every entity, and calls `callback` once with the results of
+    /// all of them, when each has succeeded, failed or timed out.
+    ///
+    /// Each entity is sent the request once, even if it is listed more than once. If there
+    /// are no entities, the callback is called with no results when the reader next runs,
+    /// along with the callbacks of responses received from SpatialOS.
+    ///
+    /// ```ignore
+    /// let members = party.members.clone();
+    /// party_sender.broadcast(members, PartyCommandRequest::Notify(message), |results, _| {
+    ///     let unreachable = results.values().filter(|result| result.is_err()).count();
+    ///     println!("{} members could not be notified.", unreachable);
+    /// });
+    /// ```
+    pub fn broadcast<I, F>(&mut self, entity_ids: I, request: T::CommandRequest, callback: F)
+    where
+        I: IntoIterator<Item = EntityId>,
+        F: 'static + FnOnce(BroadcastResults<T>, SystemDataFetch) + Send + Sync,
+    {
+        let mut entity_ids: Vec<EntityId> = entity_ids.into_iter().collect();
+        entity_ids.sort();
+        entity_ids.dedup();
+
+        if entity_ids.is_empty() {
+            self.empty_broadcasts.push(Box::new(callback));
+            return;
+        }
+
+        let broadcast = Arc::new(Mutex::new(Broadcast::<T> {
+            remaining: entity_ids.len(),
+            results: HashMap::new(),
+            callback: Some(Box::new(callback)),
+        }));
+
+        for entity_id in entity_ids {
+            let broadcast = broadcast.clone();
+            self.send_command(entity_id, request.clone(), move |result, fetch| {
+                let finished = {
+                    let mut broadcast = broadcast.lock().unwrap();
+                    let result = match result {
+                        Ok(response) => Ok(response.clone()),
+                        Err(status_code) => Err(map_status_code_error(&status_code)),
+                    };
+                    broadcast.results.insert(entity_id, result);
+                    broadcast.remaining -= 1;
+
+                    if broadcast.remaining == 0 {
+                        let results = std::mem::replace(&mut broadcast.results, HashMap::new());
+                        broadcast
+                            .callback
+                            .take()
+                            .map(|callback| (callback, results))
+                    } else {
+                        None
+                    }
+                };
+
+                if let Some((callback, results)) = finished {
+                    callback(results, fetch);
+                }
+            });
+        }
+    }
+
+    pub(crate) fn finish_empty_broadcasts(res: &World) {
+        let callbacks: Vec<_> = CommandSender::<T>::fetch(res)
+            .empty_broadcasts
+            .drain(..)
+            .collect();
+
+        for callback in callbacks {
+            callback(HashMap::new(), SystemDataFetch::new(res));
+        }
+    }
+
+    pub(crate) fn got_command_response(res: &World, response_op: CommandResponseOp) {
         let callback = {
             CommandSender::<T>::fetch(res)
                 .callbacks
@@ -195,23 +811,126 @@ impl<T: 'static + WorkerComponent> CommandSenderRes<T> {
         };
 
         match callback {
-            Some(callback) => callback(res, response_op),
-            None => println!("Unknown request ID: {:?}", response_op.request_id),
+            Some((_, callback)) => callback(res, response_op),
+            None => logging::warn_unknown_request_id(res, response_op.request_id),
         }
     }
 
-    pub(crate) fn flush_requests(&mut self, connection: &mut WorkerConnection) {
-        for (entity_id, request, callback) in self.buffered_requests.drain(..) {
-            // TODO: Default command params like timeout
-            let request_id = connection.send_command_request::<T>(
-                entity_id.id(),
-                request,
-                None,
-                Default::default(),
+    // Removes the buffered requests for which `reason` returns an error, without sending
+    // them. Their callbacks are called by `fail_rejected_requests`, as the writer which
+    // validates requests holds the connection while it does so.
+    pub(crate) fn reject_requests<F: FnMut(EntityId) -> Option<String>>(
+        res: &World,
+        mut reason: F,
+    ) {
+        let errors: Vec<String> = {
+            let mut sender = CommandSender::<T>::fetch(res);
+            let requests = std::mem::replace(&mut sender.buffered_requests, Vec::new());
+
+            let mut errors = Vec::new();
+            for (entity_id, request, callback) in requests {
+                match reason(entity_id) {
+                    Some(error) => {
+                        errors.push(error.clone());
+                        sender.rejected_requests.push((entity_id, error, callback));
+                    }
+                    None => sender
+                        .buffered_requests
+                        .push((entity_id, request, callback)),
+                }
+            }
+            errors
+        };
+        drop(reason);
+
+        for error in errors {
+            logging::log(
+                res,
+                LogLevel::Warn,
+                LogKind::CommandRejected,
+                &format!("Failing command request without sending it: {}", error),
+            );
+        }
+    }
+
+    // Calls the callbacks of rejected requests with a `NotFound` status containing the
+    // error. This is called by the reader, along with the callbacks of responses received
+    // from SpatialOS.
+    pub(crate) fn fail_rejected_requests(res: &World) {
+        let rejected: Vec<_> = CommandSender::<T>::fetch(res)
+            .rejected_requests
+            .drain(..)
+            .collect();
+
+        for (entity_id, error, callback) in rejected {
+            callback(
+                res,
+                CommandResponseOp {
+                    request_id: RequestId::new(0),
+                    entity_id: entity_id.id(),
+                    component_id: T::ID,
+                    response: StatusCode::NotFound(error),
+                },
+            );
+        }
+    }
+
+    // Requests sent on a previous connection will never receive a response, so their
+    // callbacks are called with a `Timeout` status containing `message`.
+    pub(crate) fn fail_sent_requests(res: &World, message: &str) {
+        let sent: Vec<_> = CommandSender::<T>::fetch(res).callbacks.drain().collect();
+
+        for (request_id, (entity_id, callback)) in sent {
+            callback(
+                res,
+                CommandResponseOp {
+                    request_id,
+                    entity_id: entity_id.id(),
+                    component_id: T::ID,
+                    response: StatusCode::Timeout(message.to_string()),
+                },
             );
-            self.callbacks.insert(request_id, callback);
         }
     }
+
+    pub(crate) fn buffered_requests(&self) -> usize {
+        self.buffered_requests.len()
+    }
+
+    // Requests over the `ConnectionCalls` cap stay buffered until a later frame.
+    pub(crate) fn flush_requests(&mut self, connection: &mut SpatialConnectionHandle) -> usize {
+        let count = self
+            .buffered_requests
+            .len()
+            .min(connection.capacity(SdkCall::CommandRequest));
+        for (entity_id, request, callback) in self.buffered_requests.drain(..count) {
+            // TODO: Default command params like timeout
+            let request_id = connection.send_request::<T>(entity_id.id(), request);
+            self.callbacks.insert(request_id, (entity_id, callback));
+        }
+        count
+    }
+}
+
+#[cfg(any(test, feature = "bench-internals"))]
+impl<T: 'static + WorkerComponent> CommandSenderRes<T> {
+    // Moves buffered requests to the awaiting callbacks without a connection.
+    pub(crate) fn assign_request_ids_without_sending(
+        &mut self,
+    ) -> Vec<RequestId<OutgoingCommandRequest>> {
+        let mut request_ids = Vec::new();
+        let mut next_id = 1;
+
+        for (entity_id, _request, callback) in self.buffered_requests.drain(..) {
+            let request_id = RequestId::new(next_id);
+            next_id += 1;
+
+            self.callbacks.insert(request_id, (entity_id, callback));
+            request_ids.push(request_id);
+        }
+
+        request_ids
+    }
 }
 
 impl<T: 'static + WorkerComponent> Default for CommandSenderRes<T> {
@@ -220,6 +939,8 @@ impl<T: 'static + WorkerComponent> Default for CommandSenderRes<T> {
         CommandSenderRes {
             callbacks: HashMap::new(),
             buffered_requests: Vec::new(),
+            rejected_requests: Vec::new(),
+            empty_broadcasts: Vec::new(),
         }
     }
 }
@@ -229,7 +950,7 @@ fn command_flow_should_work() {
     use crate::entities::EntityId;
     use crate::generated_test::*;
     use spatialos_sdk::worker::EntityId as WorkerEntityId;
-    use specs::prelude::{System, World};
+    use specs::prelude::{System, World, WorldExt};
 
     let mut world = World::new();
 
@@ -241,10 +962,10 @@ fn command_flow_should_work() {
 
     let entity_id = EntityId(WorkerEntityId::new(5));
 
-    <Sys as System>::SystemData::setup(&mut world.res);
+    <Sys as System>::SystemData::setup(&mut world);
 
     {
-        let (mut command_sender, _) = <Sys as System>::SystemData::fetch(&world.res);
+        let (mut command_sender, _) = <Sys as System>::SystemData::fetch(&world);
         command_sender.send_command(
             entity_id,
             PositionCommandRequest::UpdateCoords,
@@ -258,23 +979,23 @@ fn command_flow_should_work() {
 
     {
         let mut requests = {
-            <Sys as System>::SystemData::fetch(&world.res)
+            <Sys as System>::SystemData::fetch(&world)
                 .0
                 .buffered_requests
                 .drain(..)
                 .collect::<Vec<_>>()
         };
 
-        for (_entity_id, _req, callback) in requests.drain(..) {
-            <Sys as System>::SystemData::fetch(&world.res)
+        for (entity_id, _req, callback) in requests.drain(..) {
+            <Sys as System>::SystemData::fetch(&world)
                 .0
                 .callbacks
-                .insert(RequestId::new(1), callback);
+                .insert(RequestId::new(1), (entity_id, callback));
         }
     }
 
     CommandSenderRes::<Position>::got_command_response(
-        &world.res,
+        &world,
         CommandResponseOp {
             request_id: RequestId::new(1),
             entity_id: entity_id.id(),
@@ -283,3 +1004,358 @@ fn command_flow_should_work() {
         },
     );
 }
+
+#[test]
+fn command_authority_should_track_changes() {
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+
+    let mut authority = CommandAuthority::default();
+    assert!(!authority.can_respond(54, entity));
+
+    assert_eq!(
+        Some(CommandAuthorityEvent::Gained(entity, 54)),
+        authority.set_authority(54, entity, Authority::Authoritative)
+    );
+    assert_eq!(
+        None,
+        authority.set_authority(54, entity, Authority::AuthorityLossImminent)
+    );
+    assert!(authority.can_respond(54, entity));
+    assert!(!authority.can_respond(1002, entity));
+
+    assert_eq!(
+        Some(CommandAuthorityEvent::Lost(entity, 54)),
+        authority.set_authority(54, entity, Authority::NotAuthoritative)
+    );
+    assert!(!authority.can_respond(54, entity));
+}
+
+#[test]
+fn respond_with_data_should_pass_entity_and_data() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    CommandRequests::<Position>::setup(&mut world);
+
+    let entity = world.create_entity().build();
+
+    {
+        let mut requests: CommandRequestsComp<Position> = Default::default();
+        requests.on_request(
+            RequestId::new(1),
+            PositionCommandRequest::UpdateCoords,
+            String::from("worker"),
+            vec![],
+        );
+        CommandRequests::<Position>::fetch(&world)
+            .insert(entity, requests)
+            .unwrap();
+    }
+
+    let mut responded_entities = Vec::new();
+
+    {
+        let entities = Entities::fetch(&world);
+        let mut requests = CommandRequests::<Position>::fetch(&world);
+        requests.respond_with_data(
+            &entities,
+            &mut responded_entities,
+            |_, caller_worker_id, _, entity, responded_entities| {
+                assert_eq!("worker", caller_worker_id);
+                responded_entities.push(entity);
+                Some(PositionCommandResponse::UpdateCoords)
+            },
+        );
+    }
+
+    assert_eq!(vec![entity], responded_entities);
+
+    let requests = CommandRequests::<Position>::fetch(&world);
+    let requests = requests.get(entity).unwrap();
+    assert!(requests.requests.is_empty());
+    assert_eq!(1, requests.responses.len());
+}
+
+#[test]
+fn claimed_requests_should_be_hidden_from_responders() {
+    use crate::generated_test::*;
+
+    let mut requests: CommandRequestsComp<Position> = Default::default();
+    for id in 1..=2 {
+        requests.on_request(
+            RequestId::new(id),
+            PositionCommandRequest::UpdateCoords,
+            format!("worker{}", id),
+            vec![],
+        );
+    }
+
+    let claims = requests.claim(|_, caller_worker_id, _| caller_worker_id == "worker1");
+    assert_eq!(1, claims.len());
+    assert_eq!("worker1", requests.get_claimed(claims[0]).unwrap().1);
+
+    let mut seen = Vec::new();
+    requests.respond(|_, caller_worker_id, _| {
+        seen.push(caller_worker_id.clone());
+        None
+    });
+    assert_eq!(vec!["worker2".to_string()], seen);
+
+    assert!(requests.respond_claimed(claims[0], PositionCommandResponse::UpdateCoords));
+    assert!(!requests.respond_claimed(claims[0], PositionCommandResponse::UpdateCoords));
+    assert_eq!(1, requests.responses.len());
+}
+
+#[test]
+fn empty_command_queues_should_be_removed() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    CommandRequests::<Position>::setup(&mut world);
+    assert!(ComponentRegistry::get_interface(Position::ID).is_some());
+
+    let idle = world.create_entity().build();
+    let busy = world.create_entity().build();
+
+    {
+        let mut storage = CommandRequests::<Position>::fetch(&world);
+        storage.insert(idle, Default::default()).unwrap();
+
+        let mut requests: CommandRequestsComp<Position> = Default::default();
+        requests.on_request(
+            RequestId::new(1),
+            PositionCommandRequest::UpdateCoords,
+            String::from("worker"),
+            vec![],
+        );
+        storage.insert(busy, requests).unwrap();
+
+        storage.remove_empty_queues(&world);
+    }
+
+    let storage = CommandRequests::<Position>::fetch(&world);
+    assert!(storage.get(idle).is_none());
+    assert!(!storage.get(busy).unwrap().is_empty());
+}
+
+#[test]
+fn command_authorization_should_apply_policy() {
+    let authorization = CommandAuthorization::new(|component_id, command_index, caller| {
+        component_id != 1000 || command_index == 1 || caller.has_attribute("admin")
+    });
+
+    let client = vec!["client".to_string()];
+    let admin = vec!["client".to_string(), "admin".to_string()];
+    let caller = |attribute_set| CallerAttributes {
+        worker_id: "worker",
+        attribute_set,
+    };
+
+    assert!(authorization.authorize(54, 2, &caller(&client)));
+    assert!(authorization.authorize(1000, 1, &caller(&client)));
+    assert!(!authorization.authorize(1000, 2, &caller(&client)));
+    assert!(authorization.authorize(1000, 2, &caller(&admin)));
+
+    let admin_requirement = vec![vec!["admin".to_string()]];
+    assert!(caller(&admin).satisfies(&admin_requirement));
+    assert!(!caller(&client).satisfies(&admin_requirement));
+}
+
+#[test]
+fn responder_budget_should_take_requests_from_each_caller_in_turn() {
+    use crate::generated_test::*;
+
+    let mut requests: CommandRequestsComp<Position> = Default::default();
+    requests.set_budget(Some(2));
+    for (id, caller) in vec!["storm", "storm", "storm", "other"]
+        .into_iter()
+        .enumerate()
+    {
+        requests.on_request(
+            RequestId::new(id as u32),
+            PositionCommandRequest::UpdateCoords,
+            caller.to_string(),
+            vec![],
+        );
+    }
+
+    let mut seen = Vec::new();
+    let mut respond = |requests: &mut CommandRequestsComp<Position>| {
+        requests.respond(|_, caller_worker_id, _| {
+            seen.push(caller_worker_id.clone());
+            Some(PositionCommandResponse::UpdateCoords)
+        })
+    };
+
+    respond(&mut requests);
+    // The budget is spent until it is refreshed for the next frame.
+    respond(&mut requests);
+    requests.refresh_budget(Some(2));
+    respond(&mut requests);
+    assert_eq!(vec!["storm", "other", "storm", "storm"], seen);
+    assert!(requests.requests.is_empty());
+}
+
+#[test]
+fn claim_should_only_take_requests_within_the_budget() {
+    use crate::generated_test::*;
+
+    let mut requests: CommandRequestsComp<Position> = Default::default();
+    requests.set_budget(Some(2));
+    for id in 0..3 {
+        requests.on_request(
+            RequestId::new(id),
+            PositionCommandRequest::UpdateCoords,
+            "client".to_string(),
+            vec![],
+        );
+    }
+
+    assert_eq!(2, requests.claim(|_, _, _| true).len());
+    assert_eq!(0, requests.claim(|_, _, _| true).len());
+
+    requests.refresh_budget(Some(2));
+    assert_eq!(1, requests.claim(|_, _, _| true).len());
+    assert!(requests.requests.is_empty());
+}
+
+#[test]
+fn rejected_command_requests_should_fail_without_sending() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+    use std::sync::{Arc, Mutex};
+
+    let mut world = World::new();
+    CommandSender::<Position>::setup(&mut world);
+
+    let validation = CommandValidation::new();
+    let missing = EntityId(WorkerEntityId::new(5));
+    let present = EntityId(WorkerEntityId::new(6));
+    assert!(validation
+        .validate(missing, Position::ID, true, false)
+        .is_some());
+    assert!(validation
+        .validate(missing, Position::ID, false, false)
+        .is_none());
+    assert!(validation
+        .require_checked_out()
+        .validate(missing, Position::ID, false, false)
+        .is_some());
+
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    for entity_id in &[missing, present] {
+        let failures = failures.clone();
+        CommandSender::<Position>::fetch(&world).send_command(
+            *entity_id,
+            PositionCommandRequest::UpdateCoords,
+            move |result, _| {
+                if let Err(StatusCode::NotFound(message)) = result {
+                    failures.lock().unwrap().push(message);
+                }
+            },
+        );
+    }
+
+    CommandSenderRes::<Position>::reject_requests(&world, |entity_id| {
+        validation.validate(entity_id, Position::ID, true, entity_id == present)
+    });
+    assert!(failures.lock().unwrap().is_empty());
+
+    CommandSenderRes::<Position>::fail_rejected_requests(&world);
+    assert_eq!(1, failures.lock().unwrap().len());
+    let sender = CommandSender::<Position>::fetch(&world);
+    assert_eq!(1, sender.buffered_requests.len());
+    assert_eq!(present, sender.buffered_requests[0].0);
+    assert!(sender.rejected_requests.is_empty());
+}
+
+#[test]
+fn broadcast_should_aggregate_results_of_every_entity() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+    CommandSender::<Position>::setup(&mut world);
+
+    let entity_ids = vec![5, 6, 5]
+        .into_iter()
+        .map(|id| EntityId(WorkerEntityId::new(id)))
+        .collect::<Vec<_>>();
+
+    let results = Arc::new(Mutex::new(Vec::new()));
+    {
+        let mut sender = CommandSender::<Position>::fetch(&world);
+        for ids in vec![entity_ids.clone(), vec![]] {
+            let results = results.clone();
+            sender.broadcast(ids, PositionCommandRequest::UpdateCoords, move |r, _| {
+                results.lock().unwrap().push(r)
+            });
+        }
+    }
+
+    CommandSenderRes::<Position>::finish_empty_broadcasts(&world);
+    assert_eq!(1, results.lock().unwrap().len());
+    assert!(results.lock().unwrap()[0].is_empty());
+
+    let requests: Vec<_> = CommandSender::<Position>::fetch(&world)
+        .buffered_requests
+        .drain(..)
+        .collect();
+    assert_eq!(2, requests.len());
+
+    for (index, (entity_id, _request, callback)) in requests.into_iter().enumerate() {
+        callback(
+            &world,
+            CommandResponseOp {
+                request_id: RequestId::new(1),
+                entity_id: entity_id.id(),
+                component_id: Position::ID,
+                response: StatusCode::Timeout(String::from("Timeout")),
+            },
+        );
+        assert_eq!(1 + index, results.lock().unwrap().len());
+    }
+
+    let results = results.lock().unwrap();
+    assert_eq!(2, results[1].len());
+    assert!(results[1][&entity_ids[1]].is_err());
+}
+
+#[test]
+fn pending_requests_should_round_trip_through_serialization() {
+    use crate::generated_test::*;
+
+    let mut requests = CommandRequestsComp::<Counter>::default();
+    for amount in 1..=2 {
+        requests.on_request(
+            RequestId::new(amount),
+            CounterCommandRequest::Increment(IncrementRequest { amount }),
+            "client".to_string(),
+            vec!["game".to_string()],
+        );
+    }
+    let claims = requests.claim(|request, _, _| match request {
+        CounterCommandRequest::Increment(request) => request.amount == 1,
+    });
+    assert_eq!(1, claims.len());
+
+    // Both the pending and the claimed request are serialized.
+    let serialized = requests.serialize_pending().unwrap();
+    assert_eq!(2, serialized.len());
+    let amounts: Vec<u32> = serialized
+        .iter()
+        .map(|request| match request.deserialize::<Counter>().unwrap() {
+            CounterCommandRequest::Increment(request) => request.amount,
+        })
+        .collect();
+    assert_eq!(vec![2, 1], amounts);
+    assert_eq!("client", serialized[1].caller_worker_id);
+    assert_eq!(vec!["game".to_string()], serialized[1].caller_attribute_set);
+}
@@ -1,24 +1,141 @@
+use crate::archetype::ArchetypeStats;
+use crate::audit::{ReplicationAudit, ReplicationReason, ReplicationRecord};
+use crate::census::ComponentCensus;
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosFault, ChaosMonkey};
+use crate::checksum::{ChecksumMismatch, ChecksumVerification};
+use crate::clock;
+use crate::command_info::{CommandInfo, ComponentCommands};
 use crate::commands::{
+    CallerAttributes, CommandAuthority, CommandAuthorityEvents, CommandAuthorization,
     CommandRequests, CommandRequestsComp, CommandRequestsExt, CommandSender, CommandSenderRes,
+    CommandValidation, ResponderBudget, UNAUTHORIZED,
 };
-use crate::entities::EntityIds;
-use crate::storage::{AuthorityBitSet, SpatialWriteStorage};
+use crate::connection_handle::{rotate_from, SdkCall, SpatialConnectionHandle};
+use crate::debug::ComponentDump;
+use crate::double_buffer::DoubleBuffered;
+use crate::entities::{EntityId, EntityIds};
+use crate::eviction::{ProxyEviction, RelevanceChange};
+use crate::field_watcher::FieldWatcher;
+use crate::frame_report::FrameReport;
+use crate::latency::UpdateLatency;
+use crate::logging::{self, LogKind, LogLevel};
+use crate::network_stats::NetworkStats;
+use crate::ownership::OwnershipConfig;
+use crate::persistence::Persistence;
+use crate::player_lifecycle::PlayerLifecycle;
+use crate::position_history::PositionHistoryConfig;
+use crate::sdk::{self, SdkConnection};
+use crate::shutdown::{ShutdownCoordinator, SHUTTING_DOWN};
+use crate::spatial_hash::SpatialHash;
+use crate::spatial_reader::CONNECTION_LOST;
+use crate::storage::{
+    ComponentAuthority, ComponentPolicy, ComponentRemoving, ComponentRemovingEvents, InsertFailed,
+    InsertFailedEvents, InsertFailurePolicy, SpatialWriteStorage, UpdateDropped,
+    UpdateDroppedEvents,
+};
+use crate::tags::EntityTags;
+use crate::tick_stamp::TickStamping;
 use crate::SpatialComponent;
 use spatialos_sdk::worker::component::Component as WorkerComponent;
-use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::component::{ComponentData, ComponentId};
 use spatialos_sdk::worker::connection::WorkerConnection;
+use spatialos_sdk::worker::entity::Entity as WorkerEntity;
 use spatialos_sdk::worker::op::{
     AddComponentOp, AuthorityChangeOp, CommandRequestOp, CommandResponseOp, ComponentUpdateOp,
 };
-use specs::prelude::{Entity, Join, Resources, SystemData};
+use spatialos_sdk::worker::Authority;
+use specs::prelude::{Entity, Join, SystemData, World, WorldExt, WriteStorage};
 use specs::storage::MaskedStorage;
+use specs::world::Index;
+use std::any::Any;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
-use std::sync::Mutex;
+use std::sync::{Mutex, RwLock};
 
 lazy_static! {
     static ref COMPONENT_REGISTRY: Mutex<ComponentRegistry> = Mutex::new(Default::default());
+    static ref COMPONENT_NAMES: RwLock<HashMap<ComponentId, &'static str>> =
+        RwLock::new(STANDARD_LIBRARY_NAMES.iter().cloned().collect());
+    static ref COMPONENT_COMMANDS: RwLock<HashMap<ComponentId, &'static [CommandInfo]>> =
+        RwLock::new(HashMap::new());
+}
+
+// The components of the standard schema library, which every deployment has.
+const STANDARD_LIBRARY_NAMES: &[(ComponentId, &str)] = &[
+    (50, "improbable.EntityAcl"),
+    (53, "improbable.Metadata"),
+    (54, "improbable.Position"),
+    (55, "improbable.Persistence"),
+    (58, "improbable.Interest"),
+    (59, "improbable.restricted.System"),
+    (60, "improbable.restricted.Worker"),
+    (61, "improbable.restricted.PlayerClient"),
+];
+
+/// Registers the fully qualified schema name of a component, so that it is shown in logs,
+/// stats and the debug dump alongside its ID. The components of the standard schema
+/// library are registered already.
+///
+/// ```ignore
+/// register_component_name(Player::ID, "example.Player");
+/// ```
+pub fn register_component_name(component_id: ComponentId, name: &'static str) {
+    COMPONENT_NAMES.write().unwrap().insert(component_id, name);
+}
+
+/// Returns the registered name of a component.
+pub fn component_name(component_id: ComponentId) -> Option<&'static str> {
+    COMPONENT_NAMES.read().unwrap().get(&component_id).cloned()
+}
+
+/// Returns the ID of the component registered with the name. This can be used to resolve
+/// component names in `query::parse_query`.
+pub fn component_id(name: &str) -> Option<ComponentId> {
+    COMPONENT_NAMES
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(_, registered)| **registered == name)
+        .map(|(component_id, _)| *component_id)
+}
+
+/// Publishes the commands of a component, so that they can be enumerated with
+/// `component_commands`.
+pub fn register_component_commands<T: ComponentCommands>() {
+    COMPONENT_COMMANDS
+        .write()
+        .unwrap()
+        .insert(T::ID, T::COMMANDS);
+}
+
+/// Returns the registered commands of a component, which are empty if none have been
+/// registered.
+pub fn component_commands(component_id: ComponentId) -> &'static [CommandInfo] {
+    COMPONENT_COMMANDS
+        .read()
+        .unwrap()
+        .get(&component_id)
+        .cloned()
+        .unwrap_or(&[])
+}
+
+/// Returns the ID of every component whose commands have been registered, in ascending
+/// order.
+pub fn components_with_commands() -> Vec<ComponentId> {
+    let mut component_ids: Vec<ComponentId> =
+        COMPONENT_COMMANDS.read().unwrap().keys().cloned().collect();
+    component_ids.sort();
+    component_ids
+}
+
+// Describes a component in messages, with its name if it has one.
+pub(crate) fn describe_component(component_id: ComponentId) -> String {
+    match component_name(component_id) {
+        Some(name) => format!("{} ({})", component_id, name),
+        None => component_id.to_string(),
+    }
 }
 
 pub(crate) struct ComponentRegistry {
@@ -43,15 +160,20 @@ impl ComponentRegistry {
         unsafe { &(*registry_ptr) }
     }
 
+    // Registering a component more than once, for example by setting up both its data
+    // and command request storages, keeps the original interface, so references to it
+    // which have already been handed out remain valid.
     pub(crate) fn register_component<T: 'static + WorkerComponent>() {
-        let interface = ComponentDispatcher::<T> {
-            _phantom: PhantomData,
-        };
         COMPONENT_REGISTRY
             .lock()
             .unwrap()
             .interfaces
-            .insert(T::ID, Box::new(interface));
+            .entry(T::ID)
+            .or_insert_with(|| {
+                Box::new(ComponentDispatcher::<T> {
+                    _phantom: PhantomData,
+                })
+            });
     }
 
     pub(crate) fn get_interface(
@@ -67,93 +189,741 @@ impl ComponentRegistry {
     }
 }
 
+// The generic `ComponentDispatcher<T>` is monomorphized for every component, so anything
+// which doesn't depend on `T` lives in these functions, which take the component ID and the
+// value as `&Any`, and are only compiled once.
+
+fn log_deserialization_failure(res: &World, component_id: ComponentId, kind: &str) {
+    logging::log(
+        res,
+        LogLevel::Warn,
+        LogKind::ComponentDeserialization,
+        &format!(
+            "Failed to deserialize {} for component {}.",
+            kind,
+            describe_component(component_id)
+        ),
+    );
+}
+
+// Whether any resource needs the value of a received component, so that the storage only
+// has to be fetched again when it does.
+fn observes_received_value(res: &World, component_id: ComponentId, added: bool) -> bool {
+    (res.has_value::<ProxyEviction>()
+        && res
+            .fetch::<ProxyEviction>()
+            .is_position_component(component_id))
+        || (res.has_value::<PositionHistoryConfig>()
+            && res
+                .fetch::<PositionHistoryConfig>()
+                .is_position_component(component_id))
+        || (res.has_value::<OwnershipConfig>()
+            && res
+                .fetch::<OwnershipConfig>()
+                .is_owner_component(component_id))
+        || (res.has_value::<EntityTags>()
+            && res
+                .fetch::<EntityTags>()
+                .is_metadata_component(component_id))
+        || (res.has_value::<Persistence>() && res.fetch::<Persistence>().is_persisted(component_id))
+        || (res.has_value::<SpatialHash>()
+            && res
+                .fetch::<SpatialHash>()
+                .is_position_component(component_id))
+        || (added
+            && res.has_value::<ArchetypeStats>()
+            && res
+                .fetch::<ArchetypeStats>()
+                .is_metadata_component(component_id))
+        || (added
+            && res.has_value::<PlayerLifecycle>()
+            && res
+                .fetch::<PlayerLifecycle>()
+                .is_worker_component(component_id))
+}
+
+// Notifies the resources which observe received values of the component. Returns whether the
+// entity has left the worker's relevance and should be evicted, which must happen after the
+// component storage is released. Evicted entities aren't recorded anywhere else.
+fn record_received_value(
+    res: &World,
+    entity: Entity,
+    component_id: ComponentId,
+    component: &Any,
+    added: bool,
+) -> bool {
+    if res.has_value::<ProxyEviction>()
+        && res
+            .fetch::<ProxyEviction>()
+            .is_position_component(component_id)
+    {
+        let change =
+            res.fetch_mut::<ProxyEviction>()
+                .position_changed(entity, component, clock::now(res));
+        if change == Some(RelevanceChange::Left) {
+            return true;
+        }
+    }
+
+    if res.has_value::<PositionHistoryConfig>()
+        && res
+            .fetch::<PositionHistoryConfig>()
+            .is_position_component(component_id)
+    {
+        PositionHistoryConfig::record(res, entity, component);
+    }
+
+    if res.has_value::<SpatialHash>() {
+        let mut spatial_hash = res.fetch_mut::<SpatialHash>();
+        if spatial_hash.is_position_component(component_id) {
+            spatial_hash.position_changed(entity, component);
+        }
+    }
+
+    if res.has_value::<OwnershipConfig>()
+        && res
+            .fetch::<OwnershipConfig>()
+            .is_owner_component(component_id)
+    {
+        OwnershipConfig::record(res, entity, component);
+    }
+
+    if res.has_value::<EntityTags>()
+        && res
+            .fetch::<EntityTags>()
+            .is_metadata_component(component_id)
+    {
+        res.fetch_mut::<EntityTags>()
+            .metadata_received(res, entity, component);
+    }
+
+    if res.has_value::<Persistence>() {
+        res.fetch_mut::<Persistence>()
+            .component_changed(entity, component_id);
+    }
+
+    if added
+        && res.has_value::<ArchetypeStats>()
+        && res
+            .fetch::<ArchetypeStats>()
+            .is_metadata_component(component_id)
+    {
+        res.fetch_mut::<ArchetypeStats>()
+            .entity_added(entity, component);
+    }
+
+    if added && res.has_value::<PlayerLifecycle>() {
+        let mut players = res.fetch_mut::<PlayerLifecycle>();
+        if players.is_worker_component(component_id) {
+            players.worker_added(entity, component);
+        }
+    }
+
+    false
+}
+
+fn received_value<T: 'static + WorkerComponent>(res: &World, entity: Entity, added: bool) {
+    if !observes_received_value(res, T::ID, added) {
+        return;
+    }
+
+    let evict = match SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+        Some(storage) => match storage.get(entity) {
+            Some(component) => {
+                record_received_value(res, entity, T::ID, &**component as &Any, added)
+            }
+            None => false,
+        },
+        None => false,
+    };
+
+    if evict {
+        ProxyEviction::evict_entity(res, entity);
+    }
+}
+
+// Whether received data for the component should be stored, rather than dropped to save
+// memory.
+fn should_store_received(res: &World, entity: Entity, component_id: ComponentId) -> bool {
+    !res.has_value::<ProxyEviction>()
+        || res
+            .fetch_mut::<ProxyEviction>()
+            .should_store(entity, component_id)
+}
+
+fn component_removing(res: &World, entity: Entity, component_id: ComponentId) {
+    if res.has_value::<TickStamping>() {
+        res.fetch_mut::<TickStamping>()
+            .component_removed(entity, component_id);
+    }
+
+    if res.has_value::<ComponentCensus>() {
+        res.fetch_mut::<ComponentCensus>()
+            .component_removed(component_id, entity);
+    }
+
+    if res.has_value::<ArchetypeStats>() {
+        let mut stats = res.fetch_mut::<ArchetypeStats>();
+        if stats.is_metadata_component(component_id) {
+            stats.entity_removed(entity);
+        }
+    }
+
+    if res.has_value::<OwnershipConfig>()
+        && res
+            .fetch::<OwnershipConfig>()
+            .is_owner_component(component_id)
+    {
+        OwnershipConfig::owner_component_removed(res, entity);
+    }
+
+    if res.has_value::<EntityTags>()
+        && res
+            .fetch::<EntityTags>()
+            .is_metadata_component(component_id)
+    {
+        res.fetch_mut::<EntityTags>().metadata_removed(res, entity);
+    }
+
+    if res.has_value::<SpatialHash>() {
+        let mut spatial_hash = res.fetch_mut::<SpatialHash>();
+        if spatial_hash.is_position_component(component_id) {
+            spatial_hash.remove(entity);
+        }
+    }
+
+    if res.has_value::<Persistence>() {
+        Persistence::component_removing(res, entity, component_id);
+    }
+
+    if res.has_value::<PlayerLifecycle>() {
+        let mut players = res.fetch_mut::<PlayerLifecycle>();
+        if players.is_worker_component(component_id) {
+            players.worker_removed(entity);
+        }
+    }
+}
+
+fn record_authority_change(
+    res: &World,
+    entity: Entity,
+    component_id: ComponentId,
+    authority: Authority,
+) {
+    if res.has_value::<ComponentCensus>() {
+        res.fetch_mut::<ComponentCensus>()
+            .authority_changed(component_id, entity, authority);
+    }
+
+    if res.has_value::<CommandAuthority>() {
+        let event =
+            res.fetch_mut::<CommandAuthority>()
+                .set_authority(component_id, entity, authority);
+
+        if let Some(event) = event {
+            if res.has_value::<CommandAuthorityEvents>() {
+                res.fetch_mut::<CommandAuthorityEvents>()
+                    .single_write(event);
+            }
+        }
+    }
+}
+
+// Returns whether a command request should be handed to the component's responders, having
+// responded to or logged it otherwise.
+fn admit_command_request(
+    res: &World,
+    entity: Entity,
+    component_id: ComponentId,
+    command_request: &CommandRequestOp,
+) -> bool {
+    if res.has_value::<PlayerLifecycle>() {
+        res.fetch_mut::<PlayerLifecycle>()
+            .heartbeat(&command_request.caller_worker_id, clock::now(res));
+    }
+
+    if res.has_value::<ShutdownCoordinator>()
+        && res.fetch::<ShutdownCoordinator>().is_shutting_down()
+    {
+        let mut connection = res.fetch_mut::<WorkerConnection>();
+        SpatialConnectionHandle::new(res, &mut connection)
+            .send_failure(command_request.request_id, SHUTTING_DOWN);
+        return false;
+    }
+
+    if res.has_value::<CommandAuthority>()
+        && !res
+            .fetch::<CommandAuthority>()
+            .can_respond(component_id, entity)
+    {
+        logging::log(
+            res,
+            LogLevel::Warn,
+            LogKind::CommandRejected,
+            &format!(
+                "Dropping command request for component {} without authority.",
+                describe_component(component_id)
+            ),
+        );
+        return false;
+    }
+
+    true
+}
+
+// Returns whether the caller may send the command, having rejected the request otherwise.
+fn authorize_command_request(
+    res: &World,
+    component_id: ComponentId,
+    command_index: u32,
+    command_request: &CommandRequestOp,
+) -> bool {
+    if !res.has_value::<CommandAuthorization>() {
+        return true;
+    }
+
+    let caller = CallerAttributes {
+        worker_id: &command_request.caller_worker_id,
+        attribute_set: &command_request.caller_attribute_set,
+    };
+
+    if res
+        .fetch::<CommandAuthorization>()
+        .authorize(component_id, command_index, &caller)
+    {
+        return true;
+    }
+
+    logging::log(
+        res,
+        LogLevel::Warn,
+        LogKind::CommandRejected,
+        &format!(
+            "Rejecting unauthorized request for command {} of component {} from {}.",
+            command_index,
+            describe_component(component_id),
+            command_request.caller_worker_id
+        ),
+    );
+    let mut connection = res.fetch_mut::<WorkerConnection>();
+    SpatialConnectionHandle::new(res, &mut connection)
+        .send_failure(command_request.request_id, UNAUTHORIZED);
+    false
+}
+
+// Reports received data which couldn't be inserted, returning whether the insert
+// succeeded.
+fn handle_insert_result<V>(
+    res: &World,
+    entity: Entity,
+    component_id: ComponentId,
+    result: Result<Option<V>, specs::error::Error>,
+) -> bool {
+    let error = match result {
+        Ok(_) => return true,
+        Err(error) => error,
+    };
+
+    let failure = InsertFailed {
+        entity,
+        entity_id: EntityIds::fetch(res).get_entity_id(entity),
+        component_id,
+        reason: error.to_string(),
+    };
+    report_insert_failure(res, failure);
+    false
+}
+
+fn report_insert_failure(res: &World, failure: InsertFailed) {
+    let message = format!(
+        "Failed to insert component {} for entity {:?} ({:?}): {}",
+        describe_component(failure.component_id),
+        failure.entity_id,
+        failure.entity,
+        failure.reason
+    );
+
+    if res.has_value::<InsertFailurePolicy>()
+        && *res.fetch::<InsertFailurePolicy>() == InsertFailurePolicy::Panic
+    {
+        panic!("{}", message);
+    }
+
+    logging::log(res, LogLevel::Error, LogKind::ComponentInsertion, &message);
+
+    if res.has_value::<InsertFailedEvents>() {
+        res.fetch_mut::<InsertFailedEvents>().single_write(failure);
+    }
+}
+
+// Inserts data received from SpatialOS, returning whether it was inserted.
+fn insert_received<T: 'static + WorkerComponent>(
+    res: &World,
+    storage: &mut WriteStorage<SpatialComponent<T>>,
+    entity: Entity,
+    data: T,
+) -> bool {
+    let result = storage.insert(entity, SpatialComponent::received(data, clock::now(res)));
+    handle_insert_result(res, entity, T::ID, result)
+}
+
+fn validate_command_requests<T: 'static + WorkerComponent>(res: &World) {
+    let validation = *res.fetch::<CommandValidation>();
+    let entity_ids = EntityIds::fetch(res);
+    let storage = SpatialWriteStorage::<T>::try_fetch_component_storage(res);
+    let authority = if res.has_value::<ComponentAuthority<T>>() {
+        Some(res.fetch::<ComponentAuthority<T>>())
+    } else {
+        None
+    };
+    let data_ignored = ComponentPolicy::<T>::ignores_data(res);
+
+    CommandSenderRes::<T>::reject_requests(res, move |entity_id| {
+        let entity = entity_ids.get_entity(entity_id);
+        // The component is only known not to be checked out if its data would have been
+        // stored, and this worker isn't authoritative over it. Without a storage, or when
+        // the data is ignored or evicted, whether it is checked out isn't known.
+        let component_checked_out = match (entity, &storage) {
+            (Some(entity), Some(storage)) => {
+                data_ignored
+                    || storage.contains(entity)
+                    || authority.as_ref().map_or(false, |authority| {
+                        authority.get(entity) != Authority::NotAuthoritative
+                    })
+                    || !should_store_received(res, entity, T::ID)
+            }
+            _ => true,
+        };
+        validation.validate(entity_id, T::ID, entity.is_some(), component_checked_out)
+    });
+}
+
+fn responder_budget(res: &World, component_id: ComponentId) -> Option<usize> {
+    if res.has_value::<ResponderBudget>() {
+        res.fetch::<ResponderBudget>().budget(component_id)
+    } else {
+        None
+    }
+}
+
+fn report_dropped_update(
+    res: &World,
+    entity: Entity,
+    entity_id: EntityId,
+    component_id: ComponentId,
+) {
+    logging::log(
+        res,
+        LogLevel::Warn,
+        LogKind::DroppedUpdate,
+        &format!(
+            "Dropped the pending update to component {} of entity {}, as the component was removed.",
+            describe_component(component_id),
+            entity_id
+        ),
+    );
+
+    if res.has_value::<UpdateDroppedEvents>() {
+        res.fetch_mut::<UpdateDroppedEvents>()
+            .single_write(UpdateDropped {
+                entity,
+                entity_id,
+                component_id,
+            });
+    }
+}
+
+// Reports a received update for a component whose data isn't stored, for example because
+// inserting it failed, or it was added and removed in the same op list. The update is
+// dropped.
+fn report_update_without_data(res: &World, entity: Entity, component_id: ComponentId) {
+    let failure = InsertFailed {
+        entity,
+        entity_id: EntityIds::fetch(res).get_entity_id(entity),
+        component_id,
+        reason: "an update was received for the component, but its data isn't stored, so the \
+                 update was dropped"
+            .to_string(),
+    };
+    report_insert_failure(res, failure);
+}
+
+pub(crate) fn add_received_component<T: 'static + WorkerComponent + Sync + Send + Clone + Debug>(
+    res: &World,
+    entity: Entity,
+    data: Option<&T>,
+) {
+    if res.has_value::<ComponentCensus>() {
+        res.fetch_mut::<ComponentCensus>().component_added(T::ID);
+    }
+
+    if ComponentPolicy::<T>::ignores_data(res) || !should_store_received(res, entity, T::ID) {
+        return;
+    }
+
+    #[cfg(feature = "chaos")]
+    {
+        if ChaosMonkey::inject(res, ChaosFault::InsertFailure) {
+            let failure = InsertFailed {
+                entity,
+                entity_id: EntityIds::fetch(res).get_entity_id(entity),
+                component_id: T::ID,
+                reason: "the failure was injected by the ChaosMonkey".to_string(),
+            };
+            return report_insert_failure(res, failure);
+        }
+    }
+
+    if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+        let data = match data {
+            Some(data) => data.clone(),
+            None => return log_deserialization_failure(res, T::ID, "data"),
+        };
+
+        if !insert_received(res, &mut storage, entity, data) {
+            return;
+        }
+    }
+
+    received_value::<T>(res, entity, true);
+}
+
+fn apply_received_update<T: 'static + WorkerComponent + Sync + Send + Clone + Debug>(
+    res: &World,
+    entity: Entity,
+    update: Option<&T::Update>,
+) {
+    if res.has_value::<ArchetypeStats>() {
+        res.fetch_mut::<ArchetypeStats>().update_received(entity);
+    }
+
+    if ComponentPolicy::<T>::ignores_data(res) || !should_store_received(res, entity, T::ID) {
+        return;
+    }
+
+    if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+        let update = match update {
+            Some(update) => update.clone(),
+            None => return log_deserialization_failure(res, T::ID, "update"),
+        };
+
+        if !storage.contains(entity) {
+            drop(storage);
+            return report_update_without_data(res, entity, T::ID);
+        }
+
+        let watched = if res.has_value::<FieldWatcher<T>>() {
+            let fields = res.fetch::<FieldWatcher<T>>().watched_fields(&update);
+            match storage.get(entity) {
+                Some(component) if !fields.is_empty() => Some((fields, (**component).clone())),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some(component) = storage.get_mut(entity) {
+            let previous = component.last_update_instant();
+            let now = clock::now(res);
+
+            if res.has_value::<TickStamping>() {
+                res.fetch_mut::<TickStamping>().update_received(
+                    entity,
+                    T::ID,
+                    &update as &Any,
+                    now,
+                );
+            }
+
+            if res.has_value::<UpdateLatency>() {
+                UpdateLatency::update_received(res, T::ID, &update as &Any, previous, now);
+            }
+
+            if res.has_value::<ReplicationAudit>() {
+                let mut audit = res.fetch_mut::<ReplicationAudit>();
+                if let (true, Some(entity_id)) = (
+                    audit.is_enabled(),
+                    EntityIds::fetch(res).get_entity_id(entity),
+                ) {
+                    audit.record(ReplicationRecord {
+                        entity_id,
+                        component_id: T::ID,
+                        reason: ReplicationReason::Received,
+                        update: format!("{:?}", update),
+                    });
+                }
+            }
+
+            component.apply_received_update(update, now);
+
+            if let Some((fields, old)) = watched {
+                res.fetch::<FieldWatcher<T>>()
+                    .notify(entity, &fields, &old, &**component);
+            }
+        }
+    }
+
+    received_value::<T>(res, entity, false);
+}
+
 #[derive(Clone)]
 struct ComponentDispatcher<T: 'static + WorkerComponent + Sync + Send + Clone + Debug> {
     _phantom: PhantomData<T>,
 }
 
 pub(crate) trait ComponentDispatcherInterface {
-    fn add_component<'b>(&self, res: &Resources, entity: Entity, add_component: AddComponentOp);
-    fn remove_component<'b>(&self, res: &Resources, entity: Entity);
+    fn component_id(&self) -> ComponentId;
+    fn add_component<'b>(&self, res: &World, entity: Entity, add_component: AddComponentOp);
+    fn remove_component<'b>(&self, res: &World, entity: Entity);
     fn apply_component_update<'b>(
         &self,
-        res: &Resources,
+        res: &World,
         entity: Entity,
         component_update: ComponentUpdateOp,
     );
     fn apply_authority_change<'b>(
         &self,
-        res: &Resources,
+        res: &World,
         entity: Entity,
         authority_change: AuthorityChangeOp,
     );
     fn on_command_request<'b>(
         &self,
-        res: &Resources,
+        res: &World,
         entity: Entity,
         command_request: CommandRequestOp,
     );
-    fn on_command_response<'b>(&self, res: &Resources, command_response: CommandResponseOp);
-    fn replicate(&self, res: &Resources, connection: &mut WorkerConnection);
+    fn on_command_response<'b>(&self, res: &World, command_response: CommandResponseOp);
+    // Calls the callbacks of commands which completed without a response from SpatialOS.
+    fn complete_local_commands(&self, res: &World);
+    fn refresh_responder_budget(&self, res: &World);
+    // Returns the number of updates, command requests and incoming command requests which
+    // are waiting to be sent or responded to.
+    fn unfinished_sends(&self, res: &World) -> usize;
+    // Sends updates starting after the `resume` entity index, returning the number of
+    // messages sent and the index of the last entity an update was sent for.
+    fn replicate(
+        &self,
+        res: &World,
+        connection: &mut SpatialConnectionHandle,
+        resume: Option<Index>,
+    ) -> (usize, Option<Index>);
+    fn publish_snapshot(&self, res: &World);
+    fn verify_checksums(&self, res: &World);
+    fn mark_full_resends(
+        &self,
+        res: &World,
+        after: Option<Index>,
+        limit: usize,
+    ) -> (usize, Option<Index>);
+    fn dump_component(&self, res: &World, entity: Entity) -> Option<ComponentDump>;
+    fn reset(&self, res: &World);
+    fn evict_data(&self, res: &World, entity: Entity) -> bool;
+    fn insert_from_snapshot(&self, res: &World, entity: Entity, snapshot: &WorkerEntity);
+    fn serialize_component(&self, res: &World, entity: Entity) -> Option<Result<Vec<u8>, String>>;
+    fn serialize_from_snapshot(&self, snapshot: &WorkerEntity) -> Option<Result<Vec<u8>, String>>;
+    fn view_data(&self, add_component: &AddComponentOp) -> Option<Box<Any + Send + Sync>>;
+    // Returns whether the update could be applied.
+    fn view_update(&self, value: &mut Any, component_update: &ComponentUpdateOp) -> bool;
 }
 
 impl<T: 'static + WorkerComponent + Sync + Send + Clone + Debug> ComponentDispatcherInterface
     for ComponentDispatcher<T>
 {
-    fn add_component<'b>(&self, res: &Resources, entity: Entity, add_component: AddComponentOp) {
-        if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
-            let data = add_component.get::<T>().unwrap().clone();
+    fn component_id(&self) -> ComponentId {
+        T::ID
+    }
 
-            storage.insert(entity, SpatialComponent::new(data)).unwrap();
-        }
+    fn add_component<'b>(&self, res: &World, entity: Entity, add_component: AddComponentOp) {
+        add_received_component::<T>(res, entity, add_component.get::<T>());
     }
 
-    fn remove_component<'b>(&self, res: &Resources, entity: Entity) {
-        if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
-            storage.remove(entity);
+    fn remove_component<'b>(&self, res: &World, entity: Entity) {
+        component_removing(res, entity, T::ID);
+
+        let removed = match SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            Some(mut storage) => storage.remove(entity),
+            None => None,
+        };
+
+        if let Some(component) = removed {
+            if component.has_pending_update() {
+                let entity_id = EntityIds::fetch(res).get_entity_id(entity);
+                if let Some(entity_id) = entity_id {
+                    report_dropped_update(res, entity, entity_id, T::ID);
+                }
+            }
+
+            if res.has_value::<ComponentRemovingEvents<T>>() {
+                let entity_id = EntityIds::fetch(res).get_entity_id(entity);
+                if let Some(entity_id) = entity_id {
+                    res.fetch_mut::<ComponentRemovingEvents<T>>()
+                        .single_write(ComponentRemoving {
+                            entity,
+                            entity_id,
+                            value: component.into_value(),
+                        });
+                }
+            }
         }
     }
 
     fn apply_component_update<'b>(
         &self,
-        res: &Resources,
+        res: &World,
         entity: Entity,
         component_update: ComponentUpdateOp,
     ) {
-        if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
-            let update = component_update.get::<T>().unwrap().clone();
-
-            storage
-                .get_mut(entity)
-                .unwrap()
-                .apply_update_to_value(update);
-        }
+        apply_received_update::<T>(res, entity, component_update.get::<T>());
     }
 
     fn apply_authority_change<'b>(
         &self,
-        res: &Resources,
+        res: &World,
         entity: Entity,
         authority_change: AuthorityChangeOp,
     ) {
-        if res.has_value::<AuthorityBitSet<T>>() {
-            res.fetch_mut::<AuthorityBitSet<T>>()
+        record_authority_change(res, entity, T::ID, authority_change.authority);
+
+        if res.has_value::<ComponentAuthority<T>>() {
+            res.fetch_mut::<ComponentAuthority<T>>()
                 .set_authority(entity, authority_change.authority);
         }
+
+        if authority_change.authority == Authority::NotAuthoritative
+            && res.has_value::<MaskedStorage<CommandRequestsComp<T>>>()
+        {
+            CommandRequests::<T>::fetch(res).remove(entity);
+        }
     }
 
     fn on_command_request<'b>(
         &self,
-        res: &Resources,
+        res: &World,
         entity: Entity,
         command_request: CommandRequestOp,
     ) {
+        if !admit_command_request(res, entity, T::ID, &command_request) {
+            return;
+        }
+
         if res.has_value::<MaskedStorage<CommandRequestsComp<T>>>() {
             let mut command_requests = CommandRequests::<T>::fetch(res);
-            let request = command_request.get::<T>().unwrap().clone();
+            let request = match command_request.get::<T>() {
+                Some(request) => request.clone(),
+                None => return log_deserialization_failure(res, T::ID, "command request"),
+            };
+
+            let command_index = T::get_request_command_index(&request);
+            if !authorize_command_request(res, T::ID, command_index, &command_request) {
+                return;
+            }
+
+            let budget = responder_budget(res, T::ID);
 
             match command_requests.get_mut(entity) {
                 Some(requests) => {
+                    requests.set_budget(budget);
                     requests.on_request(
                         command_request.request_id,
                         request,
@@ -163,46 +933,708 @@ impl<T: 'static + WorkerComponent + Sync + Send + Clone + Debug> ComponentDispat
                 }
                 None => {
                     let mut requests: CommandRequestsComp<T> = Default::default();
+                    requests.set_budget(budget);
                     requests.on_request(
                         command_request.request_id,
                         request,
                         command_request.caller_worker_id,
                         command_request.caller_attribute_set,
                     );
-                    command_requests
-                        .insert(entity, requests)
-                        .expect("Error inserting new command request object.");
+                    let result = command_requests.insert(entity, requests);
+                    handle_insert_result(res, entity, T::ID, result);
                 }
             }
         }
     }
 
-    fn on_command_response<'b>(&self, res: &Resources, command_response: CommandResponseOp) {
+    fn on_command_response<'b>(&self, res: &World, command_response: CommandResponseOp) {
         if res.has_value::<CommandSenderRes<T>>() {
             CommandSenderRes::<T>::got_command_response(res, command_response);
         }
     }
 
-    fn replicate(&self, res: &Resources, connection: &mut WorkerConnection) {
+    fn replicate(
+        &self,
+        res: &World,
+        connection: &mut SpatialConnectionHandle,
+        resume: Option<Index>,
+    ) -> (usize, Option<Index>) {
+        let mut last_sent = None;
+        let mut updates_sent = 0;
+        let mut requests_sent = 0;
+        let mut responses_sent = 0;
+
         if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            let entities = res.entities();
             let entity_ids = EntityIds::fetch(res);
 
-            for (entity_id, component) in (&entity_ids, &mut storage).join() {
-                component.replicate(connection, *entity_id);
+            let auditing =
+                res.has_value::<ReplicationAudit>() && res.fetch::<ReplicationAudit>().is_enabled();
+            let mut archetype_stats = if res.has_value::<ArchetypeStats>() {
+                Some(res.fetch_mut::<ArchetypeStats>())
+            } else {
+                None
+            };
+            let stamping = if res.has_value::<TickStamping>() {
+                Some(res.fetch::<TickStamping>()).filter(|stamping| stamping.stamps(T::ID))
+            } else {
+                None
+            };
+
+            let mut network_stats = if res.has_value::<NetworkStats>() {
+                Some(res.fetch_mut::<NetworkStats>())
+            } else {
+                None
+            };
+            let mut persistence = if res.has_value::<Persistence>() {
+                Some(res.fetch_mut::<Persistence>())
+                    .filter(|persistence| persistence.is_persisted(T::ID))
+            } else {
+                None
+            };
+            let mut spatial_hash = if res.has_value::<SpatialHash>() {
+                Some(res.fetch_mut::<SpatialHash>())
+                    .filter(|spatial_hash| spatial_hash.is_position_component(T::ID))
+            } else {
+                None
+            };
+
+            // Updates over the `ConnectionCalls` cap stay pending until a later frame, which
+            // resumes after the last entity sent, so the cap isn't always spent on the same
+            // entities.
+            let mut pending: Vec<(Entity, EntityId)> = (&entities, &entity_ids, &storage)
+                .join()
+                .filter(|(_, _, component)| component.has_pending_update())
+                .map(|(entity, entity_id, _)| (entity, *entity_id))
+                .collect();
+            if let Some(resume) = resume {
+                rotate_from(&mut pending, |(entity, _)| entity.id() > resume);
+            }
+
+            for (entity, entity_id) in pending {
+                if connection.capacity(SdkCall::ComponentUpdate) == 0 {
+                    if !auditing {
+                        break;
+                    }
+
+                    res.fetch_mut::<ReplicationAudit>()
+                        .record(ReplicationRecord {
+                            entity_id,
+                            component_id: T::ID,
+                            reason: ReplicationReason::Deferred,
+                            update: String::new(),
+                        });
+                    continue;
+                }
+
+                let component = match storage.get_mut(entity) {
+                    Some(component) => component,
+                    None => continue,
+                };
+                let logical_updates = component.logical_updates();
+                let stamp = stamping.as_ref().map(|stamping| {
+                    move |update: &mut T::Update| stamping.stamp_update(T::ID, update as &mut Any)
+                });
+                let sent = component.replicate(connection, entity_id, auditing, stamp);
+                if sent.is_some() {
+                    last_sent = Some(entity.id());
+                    updates_sent += 1;
+                    if let Some(stats) = archetype_stats.as_mut() {
+                        stats.update_sent(entity);
+                    }
+                    // A repair resend alone carries no logical update.
+                    if let (Some(stats), true) = (network_stats.as_mut(), logical_updates > 0) {
+                        stats.record_sent(T::ID, logical_updates);
+                    }
+                    if let Some(persistence) = persistence.as_mut() {
+                        persistence.component_changed(entity, T::ID);
+                    }
+                    if let Some(spatial_hash) = spatial_hash.as_mut() {
+                        spatial_hash.position_changed(entity, &**component as &Any);
+                    }
+                }
+
+                if let (true, Some((reason, update))) = (auditing, sent) {
+                    res.fetch_mut::<ReplicationAudit>()
+                        .record(ReplicationRecord {
+                            entity_id,
+                            component_id: T::ID,
+                            reason,
+                            update: update.unwrap_or_default(),
+                        });
+                }
             }
         }
 
         if res.has_value::<CommandSenderRes<T>>() {
-            CommandSender::<T>::fetch(res).flush_requests(connection);
+            if res.has_value::<CommandValidation>() {
+                validate_command_requests::<T>(res);
+            }
+            requests_sent += CommandSender::<T>::fetch(res).flush_requests(connection);
         }
 
         if res.has_value::<MaskedStorage<CommandRequestsComp<T>>>() {
             let mut responses = CommandRequests::<T>::fetch(res);
             for entity in (&mut responses).join() {
-                entity.flush_responses(connection);
+                responses_sent += entity.flush_responses(connection);
+            }
+
+            responses.remove_empty_queues(res);
+        }
+
+        if res.has_value::<FrameReport>() {
+            res.fetch_mut::<FrameReport>()
+                .record_sent(updates_sent, requests_sent, responses_sent);
+        }
+
+        (updates_sent + requests_sent + responses_sent, last_sent)
+    }
+
+    fn publish_snapshot(&self, res: &World) {
+        if !res.has_value::<DoubleBuffered<T>>() {
+            return;
+        }
+
+        if let Some(storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            let entity_ids = EntityIds::fetch(res);
+
+            res.fetch_mut::<DoubleBuffered<T>>().publish(
+                (&entity_ids, &storage)
+                    .join()
+                    .map(|(entity_id, component)| (*entity_id, &**component)),
+            );
+        }
+    }
+
+    fn verify_checksums(&self, res: &World) {
+        let results: Vec<(EntityId, Result<(u64, u64), String>)> =
+            match SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+                Some(storage) => (&EntityIds::fetch(res), &storage)
+                    .join()
+                    .map(|(entity_id, component)| (*entity_id, component.checksums()))
+                    .collect(),
+                None => return,
+            };
+
+        for (entity_id, result) in results {
+            match result {
+                Ok((local_checksum, sent_checksum)) if local_checksum != sent_checksum => {
+                    ChecksumVerification::report(
+                        res,
+                        ChecksumMismatch {
+                            entity_id,
+                            component_id: T::ID,
+                            local_checksum,
+                            sent_checksum,
+                        },
+                    );
+                }
+                Ok(_) => {}
+                Err(error) => ChecksumVerification::report_error(res, entity_id, T::ID, &error),
+            }
+        }
+    }
+
+    fn mark_full_resends(
+        &self,
+        res: &World,
+        after: Option<Index>,
+        limit: usize,
+    ) -> (usize, Option<Index>) {
+        if !res.has_value::<MaskedStorage<SpatialComponent<T>>>()
+            || !res.has_value::<ComponentAuthority<T>>()
+        {
+            return (0, None);
+        }
+
+        let entities = res.entities();
+        let mut storage = SpatialWriteStorage::<T>::authoritative(res);
+
+        let mut marked = 0;
+        let mut last = None;
+        for (entity, component) in (&entities, &mut storage).join() {
+            if marked == limit {
+                break;
+            }
+            if after.map_or(false, |after| entity.id() <= after) || component.is_dirty() {
+                continue;
+            }
+
+            component.mark_repair_resend();
+            marked += 1;
+            last = Some(entity.id());
+        }
+
+        (marked, last)
+    }
+
+    fn dump_component(&self, res: &World, entity: Entity) -> Option<ComponentDump> {
+        let storage = SpatialWriteStorage::<T>::try_fetch_component_storage(res)?;
+        let component = storage.get(entity)?;
+
+        let authoritative = res.has_value::<ComponentAuthority<T>>()
+            && res
+                .fetch::<ComponentAuthority<T>>()
+                .is_authoritative(entity);
+
+        Some(ComponentDump {
+            component_id: T::ID,
+            value: format!("{:?}", **component),
+            authoritative,
+            pending_update: component.pending_update_description(),
+        })
+    }
+
+    fn complete_local_commands(&self, res: &World) {
+        if res.has_value::<CommandSenderRes<T>>() {
+            CommandSenderRes::<T>::fail_rejected_requests(res);
+            CommandSenderRes::<T>::finish_empty_broadcasts(res);
+        }
+    }
+
+    fn refresh_responder_budget(&self, res: &World) {
+        if !res.has_value::<MaskedStorage<CommandRequestsComp<T>>>() {
+            return;
+        }
+
+        let budget = responder_budget(res, T::ID);
+        for requests in (&mut CommandRequests::<T>::fetch(res)).join() {
+            requests.refresh_budget(budget);
+        }
+    }
+
+    fn unfinished_sends(&self, res: &World) -> usize {
+        let mut unfinished = 0;
+
+        if let Some(storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            unfinished += (&storage)
+                .join()
+                .filter(|component| component.has_pending_update())
+                .count();
+        }
+
+        if res.has_value::<CommandSenderRes<T>>() {
+            unfinished += res.fetch::<CommandSenderRes<T>>().buffered_requests();
+        }
+
+        if res.has_value::<MaskedStorage<CommandRequestsComp<T>>>() {
+            unfinished += (&CommandRequests::<T>::fetch(res))
+                .join()
+                .map(|requests| requests.unanswered())
+                .sum::<usize>();
+        }
+
+        unfinished
+    }
+
+    fn reset(&self, res: &World) {
+        if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            storage.clear();
+        }
+
+        if res.has_value::<ComponentAuthority<T>>() {
+            res.fetch_mut::<ComponentAuthority<T>>().clear();
+        }
+
+        if res.has_value::<MaskedStorage<CommandRequestsComp<T>>>() {
+            CommandRequests::<T>::fetch(res).clear();
+        }
+
+        if res.has_value::<CommandSenderRes<T>>() {
+            CommandSenderRes::<T>::fail_sent_requests(res, CONNECTION_LOST);
+        }
+    }
+
+    fn evict_data(&self, res: &World, entity: Entity) -> bool {
+        // Evicting data this worker is authoritative over would lose local changes.
+        if res.has_value::<ComponentAuthority<T>>()
+            && res
+                .fetch::<ComponentAuthority<T>>()
+                .is_authoritative(entity)
+        {
+            return false;
+        }
+
+        if res.has_value::<Persistence>() {
+            Persistence::component_removing(res, entity, T::ID);
+        }
+
+        match SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            Some(mut storage) => storage.remove(entity).is_some(),
+            None => false,
+        }
+    }
+
+    fn insert_from_snapshot(&self, res: &World, entity: Entity, snapshot: &WorkerEntity) {
+        if let Some(mut storage) = SpatialWriteStorage::<T>::try_fetch_component_storage(res) {
+            if let Some(data) = snapshot.get::<T>() {
+                insert_received(res, &mut storage, entity, data.clone());
             }
+        }
+    }
+
+    fn serialize_component(&self, res: &World, entity: Entity) -> Option<Result<Vec<u8>, String>> {
+        let storage = SpatialWriteStorage::<T>::try_fetch_component_storage(res)?;
+        storage
+            .get(entity)
+            .map(|component| sdk::serialize_data::<T>(&**component))
+    }
+
+    fn serialize_from_snapshot(&self, snapshot: &WorkerEntity) -> Option<Result<Vec<u8>, String>> {
+        snapshot.get::<T>().map(sdk::serialize_data::<T>)
+    }
 
-            responses.clear_empty_request_objects(res);
+    fn view_data(&self, add_component: &AddComponentOp) -> Option<Box<Any + Send + Sync>> {
+        add_component
+            .get::<T>()
+            .map(|data| Box::new(data.clone()) as Box<Any + Send + Sync>)
+    }
+
+    fn view_update(&self, value: &mut Any, component_update: &ComponentUpdateOp) -> bool {
+        match (value.downcast_mut::<T>(), component_update.get::<T>()) {
+            (Some(value), Some(update)) => {
+                value.merge(update.clone());
+                true
+            }
+            _ => false,
         }
     }
 }
+
+#[test]
+fn component_names_should_resolve_both_ways() {
+    assert_eq!(Some("improbable.Position"), component_name(54));
+    assert_eq!(Some(54), component_id("improbable.Position"));
+    assert_eq!("54 (improbable.Position)", describe_component(54));
+
+    assert_eq!(None, component_name(4321));
+    assert_eq!("4321", describe_component(4321));
+
+    register_component_name(4322, "example.Registered");
+    assert_eq!(Some(4322), component_id("example.Registered"));
+}
+
+#[test]
+fn component_commands_should_be_published() {
+    use crate::generated_test::*;
+
+    assert!(component_commands(4323).is_empty());
+
+    register_component_commands::<Position>();
+    assert!(components_with_commands().contains(&Position::ID));
+    assert_eq!(
+        "improbable.UpdateCoordsRequest",
+        component_commands(Position::ID)[0].request_type
+    );
+}
+
+#[test]
+fn insert_failures_should_be_reported_rather_than_panicking() {
+    use crate::generated_test::*;
+    use specs::prelude::Builder;
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    world.insert(InsertFailedEvents::new());
+    let mut reader_id = world.fetch_mut::<InsertFailedEvents>().register_reader();
+
+    // The entity is added and removed in the same op list, so is already dead when its
+    // component data is applied.
+    let entity = world.create_entity().build();
+    world.delete_entity(entity).unwrap();
+
+    let position = Position {
+        coords: Coordinates {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        },
+    };
+
+    let inserted = {
+        let mut storage = WriteStorage::<SpatialComponent<Position>>::fetch(&world);
+        insert_received(&world, &mut storage, entity, position.clone())
+    };
+    assert!(!inserted);
+
+    let failures: Vec<_> = world
+        .fetch::<InsertFailedEvents>()
+        .read(&mut reader_id)
+        .cloned()
+        .collect();
+    assert_eq!(1, failures.len());
+    assert_eq!(entity, failures[0].entity);
+    assert_eq!(Position::ID, failures[0].component_id);
+
+    world.insert(InsertFailurePolicy::Panic);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut storage = WriteStorage::<SpatialComponent<Position>>::fetch(&world);
+        insert_received(&world, &mut storage, entity, position)
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn updates_for_components_which_were_not_inserted_should_be_dropped() {
+    use crate::generated_test::*;
+    use specs::prelude::Builder;
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    world.insert(InsertFailedEvents::new());
+    let mut reader_id = world.fetch_mut::<InsertFailedEvents>().register_reader();
+    let interface = ComponentRegistry::get_interface(Position::ID).unwrap();
+
+    let coords = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    let position = Position {
+        coords: coords(1.0),
+    };
+    let update = PositionUpdate {
+        coords: Some(coords(2.0)),
+    };
+
+    // SDK ops can't be built without a connection, so the ops of each op list are replayed
+    // through the functions their dispatchers call. First AddComponent, RemoveComponent
+    // and ComponentUpdate for an entity.
+    let removed = world.create_entity().build();
+    add_received_component(&world, removed, Some(&position));
+    interface.remove_component(&world, removed);
+    apply_received_update::<Position>(&world, removed, Some(&update));
+
+    // Then AddComponent and ComponentUpdate for an entity deleted earlier in the frame.
+    let deleted = world.create_entity().build();
+    world.delete_entity(deleted).unwrap();
+    add_received_component(&world, deleted, Some(&position));
+    apply_received_update::<Position>(&world, deleted, Some(&update));
+
+    let failures: Vec<(Entity, String)> = world
+        .fetch::<InsertFailedEvents>()
+        .read(&mut reader_id)
+        .map(|failure| (failure.entity, failure.reason.clone()))
+        .collect();
+    assert_eq!(
+        vec![removed, deleted, deleted],
+        failures
+            .iter()
+            .map(|(entity, _)| *entity)
+            .collect::<Vec<_>>()
+    );
+    assert!(failures[0].1.contains("update was dropped"));
+    assert!(failures[2].1.contains("update was dropped"));
+    assert!(SpatialWriteStorage::<Position>::unrestricted(&world)
+        .get(removed)
+        .is_none());
+}
+
+#[test]
+fn command_validation_should_accept_components_which_are_not_stored() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::op::StatusCode;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use std::sync::{Arc, Mutex};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    CommandSender::<Position>::setup(&mut world);
+    world.insert(ComponentAuthority::<Position>::default());
+    world.insert(CommandValidation::new());
+
+    let authoritative = EntityId(WorkerEntityId::new(1));
+    let missing = EntityId(WorkerEntityId::new(2));
+    for entity_id in &[authoritative, missing] {
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(&world, *entity_id);
+    }
+
+    // The authoritative component's data was never stored, as if it had been evicted.
+    let entity = EntityIds::fetch(&world).get_entity(authoritative).unwrap();
+    world
+        .fetch_mut::<ComponentAuthority<Position>>()
+        .set_authority(entity, Authority::Authoritative);
+
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    for entity_id in &[authoritative, missing] {
+        let failures = failures.clone();
+        let entity_id = *entity_id;
+        CommandSender::<Position>::fetch(&world).send_command(
+            entity_id,
+            PositionCommandRequest::UpdateCoords,
+            move |result, _| {
+                if let Err(StatusCode::NotFound(_)) = result {
+                    failures.lock().unwrap().push(entity_id);
+                }
+            },
+        );
+    }
+
+    validate_command_requests::<Position>(&world);
+    assert!(failures.lock().unwrap().is_empty());
+
+    CommandSenderRes::<Position>::fail_rejected_requests(&world);
+    assert_eq!(vec![missing], *failures.lock().unwrap());
+}
+
+#[test]
+fn mark_full_resends_should_resend_without_logical_updates() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    world.insert(ComponentAuthority::<Position>::default());
+
+    let mut entities = Vec::new();
+    for id in 1..=4 {
+        let entity_id = EntityId(WorkerEntityId::new(id));
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(&world, entity_id);
+        let entity = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+        let position = Position {
+            coords: Coordinates {
+                x: id as f64,
+                y: 0.0,
+                z: 0.0,
+            },
+        };
+        SpatialWriteStorage::<Position>::unrestricted(&world)
+            .insert(entity, SpatialComponent::new(position))
+            .unwrap();
+        entities.push(entity);
+    }
+
+    // The last entity isn't authoritative, and the first is already being sent in full.
+    for entity in &entities[..3] {
+        world
+            .fetch_mut::<ComponentAuthority<Position>>()
+            .set_authority(*entity, Authority::Authoritative);
+    }
+    SpatialWriteStorage::<Position>::fetch(&world)
+        .get_mut(entities[0])
+        .unwrap()
+        .coords
+        .x = 10.0;
+
+    let dispatcher = ComponentDispatcher::<Position> {
+        _phantom: PhantomData,
+    };
+    assert_eq!(
+        (1, Some(entities[1].id())),
+        dispatcher.mark_full_resends(&world, None, 1)
+    );
+    assert_eq!(
+        (1, Some(entities[2].id())),
+        dispatcher.mark_full_resends(&world, Some(entities[1].id()), 5)
+    );
+
+    let storage = SpatialWriteStorage::<Position>::unrestricted(&world);
+    let component = |index: usize| storage.get(entities[index]).unwrap();
+    assert_eq!(1, component(0).logical_updates());
+    for index in 1..3 {
+        assert!(component(index).is_dirty());
+        assert_eq!(0, component(index).logical_updates());
+    }
+    assert!(!component(3).is_dirty());
+}
+
+#[test]
+fn data_ignore_policy_should_skip_storing_component_data() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, ReadStorage};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    WriteStorage::<SpatialComponent<Counter>>::setup(&mut world);
+    world.insert(InsertFailedEvents::new());
+    let mut reader_id = world.fetch_mut::<InsertFailedEvents>().register_reader();
+    world.insert(ComponentPolicy::<Position>::DataIgnore);
+
+    let coords = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    let entity = world.create_entity().build();
+    add_received_component(
+        &world,
+        entity,
+        Some(&Position {
+            coords: coords(1.0),
+        }),
+    );
+    apply_received_update::<Position>(
+        &world,
+        entity,
+        Some(&PositionUpdate {
+            coords: Some(coords(2.0)),
+        }),
+    );
+    add_received_component(
+        &world,
+        entity,
+        Some(&Counter {
+            total: 1,
+            moves: Vec::new(),
+        }),
+    );
+
+    // The ignored update isn't reported as one for data which was never stored.
+    assert_eq!(
+        0,
+        world
+            .fetch::<InsertFailedEvents>()
+            .read(&mut reader_id)
+            .count()
+    );
+    assert!(!ReadStorage::<SpatialComponent<Position>>::fetch(&world).contains(entity));
+    assert!(ReadStorage::<SpatialComponent<Counter>>::fetch(&world).contains(entity));
+}
+
+#[test]
+fn replication_audit_should_record_received_updates() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    let mut audit = ReplicationAudit::new(2);
+    audit.enable();
+    world.insert(audit);
+
+    let entity_id = EntityId(WorkerEntityId::new(5));
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+    let entity = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+
+    let coords = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    add_received_component(
+        &world,
+        entity,
+        Some(&Position {
+            coords: coords(1.0),
+        }),
+    );
+    apply_received_update::<Position>(
+        &world,
+        entity,
+        Some(&PositionUpdate {
+            coords: Some(coords(2.0)),
+        }),
+    );
+
+    let audit = world.fetch::<ReplicationAudit>();
+    let records: Vec<&ReplicationRecord> = audit
+        .find(entity_id, Position::ID)
+        .map(|(_, record)| record)
+        .collect();
+    assert_eq!(1, records.len());
+    assert_eq!(ReplicationReason::Received, records[0].reason);
+    assert!(records[0].update.contains("2.0"));
+}
@@ -0,0 +1,336 @@
+//! A single choke point for everything this crate sends through the `WorkerConnection`,
+//! for counting outgoing calls and capping how many are made each frame.
+//!
+//! Every component update, command request, command response, command failure and system
+//! command sent by the crate goes through a `SpatialConnectionHandle`, which wraps the
+//! connection for the duration of a send. Adding a `ConnectionCalls` resource enables
+//! counting and timestamping the calls:
+//!
+//! ```ignore
+//! let mut calls = ConnectionCalls::default();
+//! calls.set_update_cap(Some(500));
+//! world.insert(calls);
+//!
+//! // Later, for example once a minute:
+//! for (call, stat) in world.fetch::<ConnectionCalls>().iter() {
+//!     println!("{:?}: {} total, {} last frame", call, stat.count, stat.last_frame);
+//! }
+//! ```
+//!
+//! Caps are a safety limit rather than a scheduler. Only component updates and command
+//! requests can be capped, as they can wait: updates over the cap stay pending on their
+//! component and requests stay buffered, and both are sent in a later frame. Once the
+//! update cap is reached, the next frame's updates continue round-robin from the component
+//! and entity where it was reached, so that no entity's updates wait indefinitely.
+//! Responses and system commands are always sent.
+use crate::clock;
+use crate::sdk::SdkConnection;
+use spatialos_sdk::worker::commands::{
+    CreateEntityRequest, DeleteEntityRequest, EntityQueryRequest, IncomingCommandRequest,
+    OutgoingCommandRequest, ReserveEntityIdsRequest,
+};
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::connection::WorkerConnection;
+use spatialos_sdk::worker::entity::Entity as WorkerEntity;
+use spatialos_sdk::worker::op::OpList;
+use spatialos_sdk::worker::query::EntityQuery;
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use spatialos_sdk::worker::RequestId;
+use specs::prelude::World;
+use specs::world::Index;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// A kind of outgoing call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SdkCall {
+    ComponentUpdate,
+    CommandRequest,
+    CommandResponse,
+    CommandFailure,
+    /// Reserving entity IDs, creating and deleting entities, and entity queries.
+    SystemCommand,
+}
+
+/// The calls of one kind.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CallStat {
+    /// The total number of calls.
+    pub count: u64,
+    /// The number of calls during the most recently finished frame.
+    pub last_frame: u64,
+    /// When the most recent call was made, according to the `SpatialClock`.
+    pub last_call: Option<Instant>,
+}
+
+/// A resource which enables counting of outgoing calls, and optionally caps them.
+#[derive(Debug, Default)]
+pub struct ConnectionCalls {
+    stats: HashMap<SdkCall, CallStat>,
+    this_frame: HashMap<SdkCall, u64>,
+    update_cap: Option<usize>,
+    command_request_cap: Option<usize>,
+    // The component and entity index at which the update cap was last reached.
+    update_cursor: Option<(ComponentId, Option<Index>)>,
+}
+
+impl ConnectionCalls {
+    /// Sets the maximum number of component updates sent each frame.
+    pub fn set_update_cap(&mut self, cap: Option<usize>) {
+        self.update_cap = cap;
+    }
+
+    /// Sets the maximum number of command requests sent each frame.
+    pub fn set_command_request_cap(&mut self, cap: Option<usize>) {
+        self.command_request_cap = cap;
+    }
+
+    pub fn get(&self, call: SdkCall) -> CallStat {
+        self.stats.get(&call).cloned().unwrap_or_default()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (SdkCall, CallStat)> + 'a {
+        self.stats.iter().map(|(call, stat)| (*call, *stat))
+    }
+
+    /// Returns how long ago the most recent call of any kind was made.
+    pub fn since_last_call(&self, now: Instant) -> Option<Duration> {
+        self.stats
+            .values()
+            .filter_map(|stat| stat.last_call)
+            .max()
+            .map(|last_call| now.duration_since(last_call))
+    }
+
+    // The number of further calls of the kind which can be made this frame.
+    fn capacity(&self, call: SdkCall) -> usize {
+        let cap = match call {
+            SdkCall::ComponentUpdate => self.update_cap,
+            SdkCall::CommandRequest => self.command_request_cap,
+            _ => None,
+        };
+
+        match cap {
+            Some(cap) => {
+                let made = self.this_frame.get(&call).cloned().unwrap_or(0);
+                cap.saturating_sub(made as usize)
+            }
+            None => usize::max_value(),
+        }
+    }
+
+    fn record(&mut self, call: SdkCall, now: Instant) {
+        *self.this_frame.entry(call).or_insert(0) += 1;
+
+        let stat = self.stats.entry(call).or_insert_with(CallStat::default);
+        stat.count += 1;
+        stat.last_call = Some(now);
+    }
+
+    pub(crate) fn update_cursor(res: &World) -> Option<(ComponentId, Option<Index>)> {
+        if res.has_value::<ConnectionCalls>() {
+            res.fetch::<ConnectionCalls>().update_cursor
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn set_update_cursor(res: &World, cursor: Option<(ComponentId, Option<Index>)>) {
+        if res.has_value::<ConnectionCalls>() {
+            res.fetch_mut::<ConnectionCalls>().update_cursor = cursor;
+        }
+    }
+
+    pub(crate) fn finish_frame(&mut self) {
+        for (call, stat) in self.stats.iter_mut() {
+            stat.last_frame = self.this_frame.get(call).cloned().unwrap_or(0);
+        }
+        self.this_frame.clear();
+    }
+}
+
+// Rotates `items` to start from the first for which `starts` is true, so that capped sends
+// continue round-robin from where they stopped.
+pub(crate) fn rotate_from<T, F: Fn(&T) -> bool>(items: &mut [T], starts: F) {
+    if let Some(first) = items.iter().position(starts) {
+        items.rotate_left(first);
+    }
+}
+
+/// The `WorkerConnection`, borrowed for sending.
+///
+/// The `ConnectionCalls` are only fetched while a call is counted, so code which runs while
+/// a handle is alive can still read them.
+pub struct SpatialConnectionHandle<'a> {
+    connection: &'a mut WorkerConnection,
+    res: &'a World,
+    now: Instant,
+}
+
+impl<'a> SpatialConnectionHandle<'a> {
+    pub(crate) fn new(res: &'a World, connection: &'a mut WorkerConnection) -> Self {
+        SpatialConnectionHandle {
+            connection,
+            res,
+            now: clock::now(res),
+        }
+    }
+
+    /// The number of further calls of the kind which can be made this frame.
+    pub(crate) fn capacity(&self, call: SdkCall) -> usize {
+        if self.res.has_value::<ConnectionCalls>() {
+            self.res.fetch::<ConnectionCalls>().capacity(call)
+        } else {
+            usize::max_value()
+        }
+    }
+
+    fn record(&mut self, call: SdkCall) {
+        if self.res.has_value::<ConnectionCalls>() {
+            self.res
+                .fetch_mut::<ConnectionCalls>()
+                .record(call, self.now);
+        }
+    }
+}
+
+impl<'a> SdkConnection for SpatialConnectionHandle<'a> {
+    fn poll_ops(&mut self) -> OpList {
+        self.connection.poll_ops()
+    }
+
+    fn send_update<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        update: T::Update,
+    ) {
+        self.record(SdkCall::ComponentUpdate);
+        self.connection.send_update::<T>(entity_id, update);
+    }
+
+    fn send_request<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        request: T::CommandRequest,
+    ) -> RequestId<OutgoingCommandRequest> {
+        self.record(SdkCall::CommandRequest);
+        self.connection.send_request::<T>(entity_id, request)
+    }
+
+    fn send_response<T: 'static + WorkerComponent>(
+        &mut self,
+        request_id: RequestId<IncomingCommandRequest>,
+        response: T::CommandResponse,
+    ) {
+        self.record(SdkCall::CommandResponse);
+        self.connection.send_response::<T>(request_id, response);
+    }
+
+    fn send_failure(&mut self, request_id: RequestId<IncomingCommandRequest>, message: &str) {
+        self.record(SdkCall::CommandFailure);
+        self.connection.send_failure(request_id, message);
+    }
+
+    fn send_reserve_entity_ids(
+        &mut self,
+        number: u32,
+        timeout: Option<Duration>,
+    ) -> RequestId<ReserveEntityIdsRequest> {
+        self.record(SdkCall::SystemCommand);
+        self.connection.send_reserve_entity_ids(number, timeout)
+    }
+
+    fn send_create_entity(
+        &mut self,
+        entity: WorkerEntity,
+        entity_id: Option<WorkerEntityId>,
+        timeout: Option<Duration>,
+    ) -> RequestId<CreateEntityRequest> {
+        self.record(SdkCall::SystemCommand);
+        self.connection
+            .send_create_entity(entity, entity_id, timeout)
+    }
+
+    fn send_delete_entity(
+        &mut self,
+        entity_id: WorkerEntityId,
+        timeout: Option<Duration>,
+    ) -> RequestId<DeleteEntityRequest> {
+        self.record(SdkCall::SystemCommand);
+        self.connection.send_delete_entity(entity_id, timeout)
+    }
+
+    fn send_entity_query(
+        &mut self,
+        query: EntityQuery,
+        timeout: Option<Duration>,
+    ) -> RequestId<EntityQueryRequest> {
+        self.record(SdkCall::SystemCommand);
+        self.connection.send_entity_query(query, timeout)
+    }
+
+    #[cfg(feature = "partitions")]
+    fn send_claim_partition(
+        &mut self,
+        partition_id: WorkerEntityId,
+    ) -> RequestId<OutgoingCommandRequest> {
+        self.record(SdkCall::CommandRequest);
+        self.connection.send_claim_partition(partition_id)
+    }
+
+    fn worker_flag(&self, name: &str) -> Option<String> {
+        self.connection.worker_flag(name)
+    }
+
+    fn connected(&self) -> bool {
+        self.connection.connected()
+    }
+}
+
+#[test]
+fn connection_calls_should_count_calls_and_enforce_caps_per_frame() {
+    let mut calls = ConnectionCalls::default();
+    calls.set_update_cap(Some(2));
+    let now = Instant::now();
+
+    assert_eq!(2, calls.capacity(SdkCall::ComponentUpdate));
+    calls.record(SdkCall::ComponentUpdate, now);
+    calls.record(SdkCall::ComponentUpdate, now);
+    calls.record(SdkCall::CommandResponse, now);
+    assert_eq!(0, calls.capacity(SdkCall::ComponentUpdate));
+    assert_eq!(usize::max_value(), calls.capacity(SdkCall::CommandResponse));
+
+    calls.finish_frame();
+    assert_eq!(2, calls.capacity(SdkCall::ComponentUpdate));
+    let updates = calls.get(SdkCall::ComponentUpdate);
+    assert_eq!(
+        (2, 2, Some(now)),
+        (updates.count, updates.last_frame, updates.last_call)
+    );
+
+    calls.record(SdkCall::ComponentUpdate, now);
+    calls.finish_frame();
+    assert_eq!(3, calls.get(SdkCall::ComponentUpdate).count);
+    assert_eq!(1, calls.get(SdkCall::ComponentUpdate).last_frame);
+    assert_eq!(0, calls.get(SdkCall::CommandResponse).last_frame);
+    assert_eq!(
+        Some(Duration::from_secs(1)),
+        calls.since_last_call(now + Duration::from_secs(1))
+    );
+}
+
+#[test]
+fn rotate_from_should_continue_round_robin() {
+    let mut indices = vec![1, 3, 5, 7];
+    rotate_from(&mut indices, |index| *index > 3);
+    assert_eq!(vec![5, 7, 1, 3], indices);
+
+    // Nothing after the cursor leaves the order as it was.
+    rotate_from(&mut indices, |index| *index > 7);
+    assert_eq!(vec![5, 7, 1, 3], indices);
+
+    let mut empty: Vec<u32> = Vec::new();
+    rotate_from(&mut empty, |_| true);
+    assert!(empty.is_empty());
+}
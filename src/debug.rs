@@ -0,0 +1,189 @@
+//! Utilities for inspecting the local view of the SpatialOS world.
+//!
+//! These are useful for diagnosing divergence between the worker's local state and
+//! what the Inspector shows.
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::entities::{EntityId, SpatialEntitiesRes};
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Entity, World};
+use std::fmt::Write;
+
+/// A snapshot of a single component on a single entity.
+#[derive(Debug, Clone)]
+pub(crate) struct ComponentDump {
+    pub(crate) component_id: ComponentId,
+    pub(crate) value: String,
+    pub(crate) authoritative: bool,
+    pub(crate) pending_update: Option<String>,
+}
+
+struct EntityDump {
+    entity_id: EntityId,
+    entity: Entity,
+    components: Vec<ComponentDump>,
+}
+
+fn collect(world: &World) -> Vec<EntityDump> {
+    let res = world;
+
+    let mut entities: Vec<(EntityId, Entity)> = res.fetch::<SpatialEntitiesRes>().iter().collect();
+    entities.sort_by_key(|(entity_id, _)| *entity_id);
+
+    let mut interfaces: Vec<_> = ComponentRegistry::interfaces_iter().collect();
+    interfaces.sort_by_key(|interface| interface.component_id());
+
+    entities
+        .into_iter()
+        .map(|(entity_id, entity)| EntityDump {
+            entity_id,
+            entity,
+            components: interfaces
+                .iter()
+                .filter_map(|interface| interface.dump_component(res, entity))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Renders a human readable description of every spatial entity in the world.
+///
+/// For each entity, this lists its `EntityId`, the components it has checked out, whether
+/// this worker is authoritative over them, and any updates waiting to be sent.
+pub fn dump_world(world: &World) -> String {
+    let mut output = String::new();
+
+    for entity in collect(world) {
+        writeln!(
+            output,
+            "Entity {} (specs entity {})",
+            entity.entity_id.id().id,
+            entity.entity.id()
+        )
+        .unwrap();
+
+        for component in entity.components {
+            writeln!(
+                output,
+                "  Component {}{}",
+                describe_component(component.component_id),
+                if component.authoritative {
+                    " [authoritative]"
+                } else {
+                    ""
+                }
+            )
+            .unwrap();
+            writeln!(output, "    value: {}", component.value).unwrap();
+
+            if let Some(pending_update) = component.pending_update {
+                writeln!(output, "    pending update: {}", pending_update).unwrap();
+            }
+        }
+    }
+
+    output
+}
+
+/// Renders the same information as [`dump_world`](fn.dump_world.html) as a JSON array.
+///
+/// Component values and updates are included as their `Debug` representation.
+pub fn dump_world_json(world: &World) -> String {
+    let entities: Vec<String> = collect(world)
+        .into_iter()
+        .map(|entity| {
+            let components: Vec<String> = entity
+                .components
+                .into_iter()
+                .map(|component| {
+                    format!(
+                        "{{\"component_id\":{},\"authoritative\":{},\"value\":{},\"pending_update\":{}}}",
+                        component.component_id,
+                        component.authoritative,
+                        json_string(&component.value),
+                        component
+                            .pending_update
+                            .map(|update| json_string(&update))
+                            .unwrap_or_else(|| "null".to_string())
+                    )
+                })
+                .collect();
+
+            format!(
+                "{{\"entity_id\":{},\"entity\":{},\"components\":[{}]}}",
+                entity.entity_id.id().id,
+                entity.entity.id(),
+                components.join(",")
+            )
+        })
+        .collect();
+
+    format!("[{}]", entities.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::with_capacity(value.len() + 2);
+    output.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(output, "\\u{:04x}", c as u32).unwrap(),
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+    output
+}
+
+#[test]
+fn dump_should_list_entities_and_components() {
+    use crate::generated_test::*;
+    use crate::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::*;
+
+    let mut world = World::new();
+
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+
+    let entity_id = EntityId(WorkerEntityId::new(7));
+
+    let entity = {
+        let mut entities_res = world.fetch_mut::<SpatialEntitiesRes>();
+        entities_res.got_new_entity(&world, entity_id);
+        entities_res.get_entity(entity_id).unwrap()
+    };
+
+    WriteStorage::<SpatialComponent<Position>>::fetch(&world)
+        .insert(
+            entity,
+            SpatialComponent::new(Position {
+                coords: Coordinates {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 3.0,
+                },
+            }),
+        )
+        .unwrap();
+
+    let dump = dump_world(&world);
+    assert!(dump.contains("Entity 7"));
+    assert!(dump.contains("Component 54 (improbable.Position)"));
+    assert!(!dump.contains("pending update"));
+
+    let json = dump_world_json(&world);
+    assert!(json.starts_with("[{\"entity_id\":7,"));
+    assert!(json.contains("\"component_id\":54,\"authoritative\":false"));
+}
+
+#[test]
+fn json_string_should_escape() {
+    assert_eq!("\"a\\\"b\\\\c\\n\"", json_string("a\"b\\c\n"));
+}
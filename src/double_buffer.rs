@@ -0,0 +1,159 @@
+//! Double-buffered component state, for reading SpatialOS components from threads
+//! outside of the specs dispatcher, such as a render thread.
+use crate::entities::EntityId;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// An immutable copy of every checked out component of type `T`, as it was at the
+/// end of a frame.
+#[derive(Debug)]
+pub struct ComponentSnapshot<T> {
+    frame: u64,
+    values: HashMap<EntityId, T>,
+}
+
+impl<T> ComponentSnapshot<T> {
+    /// The number of frames which had been published when this snapshot was taken.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn get(&self, entity_id: EntityId) -> Option<&T> {
+        self.values.get(&entity_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.values
+            .iter()
+            .map(|(entity_id, value)| (*entity_id, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+type FrontBuffer<T> = Arc<RwLock<Arc<ComponentSnapshot<T>>>>;
+
+/// A read-only handle to the most recently published `ComponentSnapshot`.
+///
+/// Handles can be cloned and sent to other threads. A loaded snapshot never changes,
+/// so code holding one never observes a partially simulated frame.
+pub struct SnapshotHandle<T> {
+    front: FrontBuffer<T>,
+}
+
+impl<T> SnapshotHandle<T> {
+    /// Returns the most recently published snapshot.
+    pub fn load(&self) -> Arc<ComponentSnapshot<T>> {
+        self.front.read().unwrap().clone()
+    }
+}
+
+impl<T> Clone for SnapshotHandle<T> {
+    fn clone(&self) -> Self {
+        SnapshotHandle {
+            front: self.front.clone(),
+        }
+    }
+}
+
+/// A resource which enables double buffering of a component.
+///
+/// When present, the `SpatialWriterSystem` publishes a copy of every checked out
+/// `T` once it has replicated the frame's changes.
+///
+/// ```ignore
+/// let buffer = DoubleBuffered::<Position>::new();
+/// let handle = buffer.handle();
+/// world.insert(buffer);
+///
+/// std::thread::spawn(move || loop {
+///     let positions = handle.load();
+///     // render positions
+/// });
+/// ```
+pub struct DoubleBuffered<T: WorkerComponent> {
+    front: FrontBuffer<T>,
+    frame: u64,
+}
+
+impl<T: WorkerComponent> DoubleBuffered<T> {
+    pub fn new() -> DoubleBuffered<T> {
+        DoubleBuffered {
+            front: Arc::new(RwLock::new(Arc::new(ComponentSnapshot {
+                frame: 0,
+                values: HashMap::new(),
+            }))),
+            frame: 0,
+        }
+    }
+
+    /// Returns a handle which can be used to read published snapshots from any thread.
+    pub fn handle(&self) -> SnapshotHandle<T> {
+        SnapshotHandle {
+            front: self.front.clone(),
+        }
+    }
+
+    /// Replaces the published snapshot. Readers holding the previous snapshot
+    /// keep it until they next load.
+    pub(crate) fn publish<'a, I>(&mut self, values: I)
+    where
+        I: Iterator<Item = (EntityId, &'a T)>,
+    {
+        self.frame += 1;
+
+        let snapshot = Arc::new(ComponentSnapshot {
+            frame: self.frame,
+            values: values
+                .map(|(entity_id, value)| (entity_id, value.clone()))
+                .collect(),
+        });
+
+        *self.front.write().unwrap() = snapshot;
+    }
+}
+
+impl<T: WorkerComponent> Default for DoubleBuffered<T> {
+    fn default() -> Self {
+        DoubleBuffered::new()
+    }
+}
+
+#[test]
+fn snapshots_should_not_change_after_loading() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let entity_id = EntityId::new(WorkerEntityId::new(1));
+    let at = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    let mut buffer = DoubleBuffered::<Position>::new();
+    let handle = buffer.handle();
+    assert!(handle.load().is_empty());
+
+    buffer.publish(vec![(entity_id, &at(1.0))].into_iter());
+    let first = handle.load();
+
+    let reader = handle.clone();
+    std::thread::spawn(move || {
+        assert_eq!(1.0, reader.load().get(entity_id).unwrap().coords.x);
+    })
+    .join()
+    .unwrap();
+
+    buffer.publish(vec![(entity_id, &at(2.0))].into_iter());
+
+    assert_eq!(1, first.frame());
+    assert_eq!(1.0, first.get(entity_id).unwrap().coords.x);
+    assert_eq!(2, handle.load().frame());
+    assert_eq!(2.0, handle.load().get(entity_id).unwrap().coords.x);
+}
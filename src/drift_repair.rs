@@ -0,0 +1,175 @@
+//! Slow, continuous re-sending of the full state of authoritative components, to repair
+//! divergence between the local value and SpatialOS which nothing else would notice, such
+//! as that caused by a lost update or an incorrect `merge`.
+//!
+//! Adding a `DriftRepair` resource enables it. Every `interval`, the `SpatialWriterSystem`
+//! marks up to `budget` authoritative components for a full resend, continuing round-robin
+//! from where the previous pass stopped, across entities and then components:
+//!
+//! ```ignore
+//! // Re-send 20 components every second.
+//! world.insert(DriftRepair::new(Duration::from_secs(1), 20));
+//! ```
+//!
+//! The budget bounds the extra bandwidth: each pass sends at most `budget` full components,
+//! so every authoritative component is repaired once every `count / budget` passes.
+//! Components which are already being sent in full that frame are skipped.
+use crate::clock;
+use crate::component_registry::ComponentRegistry;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::World;
+use specs::world::Index;
+use std::time::{Duration, Instant};
+
+/// A resource which enables background full resends.
+pub struct DriftRepair {
+    interval: Duration,
+    budget: usize,
+    last_pass: Option<Instant>,
+    // The component and entity index of the last component marked.
+    cursor: Option<(ComponentId, Option<Index>)>,
+    resent: u64,
+}
+
+impl DriftRepair {
+    /// Marks up to `budget` components for a full resend every `interval`.
+    pub fn new(interval: Duration, budget: usize) -> DriftRepair {
+        DriftRepair {
+            interval,
+            budget,
+            last_pass: None,
+            cursor: None,
+            resent: 0,
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+    }
+
+    /// The total number of components marked for a full resend.
+    pub fn resent(&self) -> u64 {
+        self.resent
+    }
+
+    fn is_due(&mut self, now: Instant) -> bool {
+        match self.last_pass {
+            Some(last_pass) if now.duration_since(last_pass) < self.interval => false,
+            _ => {
+                self.last_pass = Some(now);
+                true
+            }
+        }
+    }
+
+    pub(crate) fn update(res: &World) {
+        let now = clock::now(res);
+        let (budget, cursor) = {
+            let mut repair = res.fetch_mut::<DriftRepair>();
+            if !repair.is_due(now) {
+                return;
+            }
+            (repair.budget, repair.cursor)
+        };
+
+        let mut component_ids: Vec<ComponentId> = ComponentRegistry::interfaces_iter()
+            .map(|interface| interface.component_id())
+            .collect();
+        component_ids.sort();
+
+        let (resent, cursor) = Self::pass(&component_ids, cursor, budget, |id, after, limit| {
+            ComponentRegistry::get_interface(id)
+                .map(|interface| interface.mark_full_resends(res, after, limit))
+                .unwrap_or((0, None))
+        });
+
+        let mut repair = res.fetch_mut::<DriftRepair>();
+        repair.resent += resent as u64;
+        repair.cursor = cursor;
+    }
+
+    // Visits each component once, starting from the cursor, until the budget is spent.
+    // `mark` marks up to `limit` components after the given entity index, returning how
+    // many were marked and the index of the last.
+    fn pass<F>(
+        component_ids: &[ComponentId],
+        cursor: Option<(ComponentId, Option<Index>)>,
+        budget: usize,
+        mut mark: F,
+    ) -> (usize, Option<(ComponentId, Option<Index>)>)
+    where
+        F: FnMut(ComponentId, Option<Index>, usize) -> (usize, Option<Index>),
+    {
+        if component_ids.is_empty() || budget == 0 {
+            return (0, cursor);
+        }
+
+        let (mut position, mut after) = match cursor {
+            Some((component_id, after)) => match component_ids.binary_search(&component_id) {
+                Ok(position) => (position, after),
+                Err(position) => (position % component_ids.len(), None),
+            },
+            None => (0, None),
+        };
+
+        let mut remaining = budget;
+        for _ in 0..component_ids.len() {
+            let component_id = component_ids[position];
+            let (marked, last) = mark(component_id, after, remaining);
+            remaining -= marked.min(remaining);
+
+            if remaining == 0 {
+                return (budget, Some((component_id, last)));
+            }
+
+            position = (position + 1) % component_ids.len();
+            after = None;
+        }
+
+        (budget - remaining, Some((component_ids[position], None)))
+    }
+}
+
+#[test]
+fn drift_repair_should_continue_round_robin_from_cursor() {
+    // Component 1 has entities 0 to 4, component 2 has entities 0 and 1.
+    let entities = |id: ComponentId| if id == 1 { 5 } else { 2 };
+    let mut marked = Vec::new();
+    let mut mark = |id, after: Option<Index>, limit| {
+        let first = after.map_or(0, |after| after + 1);
+        let indices: Vec<Index> = (first..entities(id)).take(limit).collect();
+        marked.extend(indices.iter().map(|index| (id, *index)));
+        (indices.len(), indices.last().cloned())
+    };
+
+    let (resent, cursor) = DriftRepair::pass(&[1, 2], None, 3, &mut mark);
+    assert_eq!((3, Some((1, Some(2)))), (resent, cursor));
+
+    let (resent, cursor) = DriftRepair::pass(&[1, 2], cursor, 3, &mut mark);
+    assert_eq!((3, Some((2, Some(0)))), (resent, cursor));
+
+    let (resent, cursor) = DriftRepair::pass(&[1, 2], cursor, 10, &mut mark);
+    assert_eq!((6, Some((2, None))), (resent, cursor));
+
+    assert_eq!(
+        vec![
+            (1, 0),
+            (1, 1),
+            (1, 2),
+            (1, 3),
+            (1, 4),
+            (2, 0),
+            (2, 1),
+            (1, 0),
+            (1, 1),
+            (1, 2),
+            (1, 3),
+            (1, 4)
+        ],
+        marked
+    );
+}
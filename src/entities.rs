@@ -1,18 +1,47 @@
+use crate::checkout_group::CheckoutGroups;
+use crate::clock;
+use crate::logging::{self, LogKind, LogLevel};
+use spatialos_sdk::worker::component::ComponentId;
 use spatialos_sdk::worker::EntityId as WorkerEntityId;
 use specs::prelude::{
-    Component, Entities, Entity, Join, Read, ReadStorage, Resources, SystemData, VecStorage,
+    Component, Entities, Entity, Join, Read, ReadStorage, SystemData, VecStorage, World, Write,
     WriteStorage,
 };
 use specs::shred::{Fetch, ResourceId};
+use specs::shrev::EventChannel;
 use specs::storage::MaskedStorage;
 use specs::world::Index;
 use std::collections::HashMap;
-use std::ops::Deref;
-
+use std::fmt;
+use std::ops::{Add, Deref};
+use std::time::{Duration, Instant};
+
+/// The SpatialOS entity ID of a specs entity.
+///
+/// This is also the component which maps specs entities to SpatialOS entities. It is
+/// inserted on a new specs entity when the SpatialOS entity is added to this worker's view,
+/// before any of its SpatialOS components, and is removed, along with the specs entity,
+/// when the SpatialOS entity leaves the view. Specs entities created by user code never
+/// have it, so joining on `ReadStorage<EntityId>` visits exactly the SpatialOS entities
+/// currently checked out:
+///
+/// ```ignore
+/// for (entity_id, position) in (&entity_ids, &positions).join() {
+///     println!("{} is at {:?}", entity_id, position.coords);
+/// }
+/// ```
+///
+/// The mapping is never changed by user code, so `EntityId` storage is read only. Use
+/// `EntityIds::get_entity` to map in the other direction.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct EntityId(pub(crate) WorkerEntityId);
 
 impl EntityId {
+    pub fn new(id: WorkerEntityId) -> EntityId {
+        EntityId(id)
+    }
+
+    /// The underlying SDK entity ID.
     pub fn id(self) -> WorkerEntityId {
         self.0
     }
@@ -26,38 +55,281 @@ impl Deref for EntityId {
     }
 }
 
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.id)
+    }
+}
+
+impl From<WorkerEntityId> for EntityId {
+    fn from(id: WorkerEntityId) -> Self {
+        EntityId(id)
+    }
+}
+
+impl Add<i64> for EntityId {
+    type Output = EntityId;
+
+    fn add(self, offset: i64) -> EntityId {
+        EntityId(WorkerEntityId::new(self.0.id + offset))
+    }
+}
+
 impl Component for EntityId {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Debug, Default)]
+/// Allocates sequential entity IDs, for building snapshots offline.
+///
+/// ```ignore
+/// let mut allocator = SnapshotIdAllocator::new(EntityId::new(WorkerEntityId::new(1)));
+/// let spawner = allocator.allocate();
+/// let players = allocator.allocate_many(10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SnapshotIdAllocator {
+    next: EntityId,
+}
+
+impl SnapshotIdAllocator {
+    /// Creates an allocator whose first ID is `base`.
+    pub fn new(base: EntityId) -> SnapshotIdAllocator {
+        SnapshotIdAllocator { next: base }
+    }
+
+    /// The ID which will be returned by the next call to `allocate`.
+    pub fn peek(&self) -> EntityId {
+        self.next
+    }
+
+    pub fn allocate(&mut self) -> EntityId {
+        let id = self.next;
+        self.next = self.next + 1;
+        id
+    }
+
+    pub fn allocate_many(&mut self, count: usize) -> Vec<EntityId> {
+        (0..count).map(|_| self.allocate()).collect()
+    }
+}
+
+/// An event emitted when a SpatialOS entity enters or leaves the local world.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpatialEntityEvent {
+    Added(EntityId, Entity),
+    Removed(EntityId, Entity),
+}
+
+/// An event channel which receives a `SpatialEntityEvent` whenever a SpatialOS entity
+/// is added to or removed from the local world.
+pub type SpatialEntityEvents = EventChannel<SpatialEntityEvent>;
+
+/// Whether a SpatialOS entity is in this worker's view.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EntityLiveness {
+    /// The entity is checked out, as the given specs entity.
+    CheckedOut(Entity),
+    /// The entity left this worker's view, because it was deleted or is no longer
+    /// in its interest, within the tombstone window.
+    Removed,
+    /// The entity is not checked out and has not been removed recently.
+    Unknown,
+}
+
+/// What to do when an entity is added which is already checked out, which can happen
+/// across checkout boundaries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DuplicateEntityPolicy {
+    /// Keep the existing specs entity. Its SpatialOS components are removed by the reader,
+    /// as if they had left the view, and those of the new checkout are added to it as they
+    /// arrive. No `SpatialEntityEvent` is emitted.
+    Reuse,
+    /// Remove the existing specs entity, as if it had left the view, emitting
+    /// `SpatialEntityEvent::Removed`, and create a new one.
+    Replace,
+}
+
+#[derive(Debug)]
 pub struct SpatialEntitiesRes {
     entities: HashMap<EntityId, Entity>,
+    tombstones: HashMap<EntityId, Instant>,
+    tombstone_window: Duration,
+    duplicate_policy: DuplicateEntityPolicy,
+    bindings: HashMap<EntityId, Entity>,
+    // The components of each checked out entity, including those whose data isn't stored.
+    components: HashMap<EntityId, Vec<ComponentId>>,
 }
 
 impl SpatialEntitiesRes {
-    pub(crate) fn got_new_entity(&mut self, res: &Resources, entity_id: EntityId) {
-        let specs_entity = Entities::fetch(res).create();
+    /// Sets how long removed entities are reported as `EntityLiveness::Removed`.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn set_tombstone_window(&mut self, tombstone_window: Duration) {
+        self.tombstone_window = tombstone_window;
+    }
+
+    /// Sets what to do when an entity is added which is already checked out.
+    ///
+    /// Defaults to `DuplicateEntityPolicy::Reuse`.
+    pub fn set_duplicate_policy(&mut self, duplicate_policy: DuplicateEntityPolicy) {
+        self.duplicate_policy = duplicate_policy;
+    }
+
+    pub(crate) fn duplicate_policy(&self) -> DuplicateEntityPolicy {
+        self.duplicate_policy
+    }
+
+    /// Makes the SpatialOS entity use an existing specs entity when it is checked out,
+    /// rather than creating a new one.
+    ///
+    /// This lets a placeholder entity, such as one a client creates while waiting for its
+    /// entity creation request to complete, become the checked out entity. Its components
+    /// are kept, and those of the SpatialOS entity are added to it. The binding is used
+    /// once; if the specs entity has been deleted by then, a new one is created instead.
+    pub fn bind(&mut self, entity_id: EntityId, entity: Entity) {
+        self.bindings.insert(entity_id, entity);
+    }
+
+    /// Removes a binding which hasn't been used yet, returning its specs entity.
+    pub fn unbind(&mut self, entity_id: EntityId) -> Option<Entity> {
+        self.bindings.remove(&entity_id)
+    }
+
+    pub(crate) fn got_new_entity(&mut self, res: &World, entity_id: EntityId) {
+        if self.entities.contains_key(&entity_id) {
+            logging::log(
+                res,
+                LogLevel::Warn,
+                LogKind::UnexpectedEntity,
+                &format!(
+                    "Entity {} was added while already checked out, applying {:?}.",
+                    entity_id, self.duplicate_policy
+                ),
+            );
+
+            match self.duplicate_policy {
+                DuplicateEntityPolicy::Reuse => return,
+                DuplicateEntityPolicy::Replace => self.remove_entity(res, entity_id),
+            }
+        }
+
+        self.tombstones.remove(&entity_id);
+
+        let specs_entity = {
+            let entities = Entities::fetch(res);
+            match self.bindings.remove(&entity_id) {
+                Some(entity) if entities.is_alive(entity) => entity,
+                _ => entities.create(),
+            }
+        };
 
         self.entities.insert(entity_id, specs_entity);
         WriteStorage::<EntityId>::fetch(res)
             .insert(specs_entity, entity_id)
             .expect("Error inserting new EntityId object.");
+
+        Self::emit(res, SpatialEntityEvent::Added(entity_id, specs_entity));
+    }
+
+    pub(crate) fn component_added(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        let components = self.components.entry(entity_id).or_insert_with(Vec::new);
+        if !components.contains(&component_id) {
+            components.push(component_id);
+        }
     }
 
-    pub(crate) fn remove_entity(&mut self, res: &Resources, entity_id: EntityId) {
+    pub(crate) fn component_removed(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        if let Some(components) = self.components.get_mut(&entity_id) {
+            components.retain(|other| *other != component_id);
+        }
+    }
+
+    /// Forgets the components of the entity, returning them so that they can be removed.
+    pub(crate) fn take_components(&mut self, entity_id: EntityId) -> Vec<ComponentId> {
+        self.components.remove(&entity_id).unwrap_or_default()
+    }
+
+    pub(crate) fn remove_entity(&mut self, res: &World, entity_id: EntityId) {
         let entity = self.entities.remove(&entity_id).unwrap();
+        self.components.remove(&entity_id);
         WriteStorage::<EntityId>::fetch(res).remove(entity);
         Entities::fetch(res)
             .delete(entity)
             .expect("Error deleting specs entity.");
+
+        self.tombstones.insert(entity_id, clock::now(res));
+
+        Self::emit(res, SpatialEntityEvent::Removed(entity_id, entity));
+    }
+
+    pub(crate) fn prune_tombstones(&mut self, now: Instant) {
+        let tombstone_window = self.tombstone_window;
+        self.tombstones
+            .retain(|_, removed_at| now.duration_since(*removed_at) < tombstone_window);
+    }
+
+    pub(crate) fn remove_all_entities(&mut self, res: &World) {
+        let entity_ids: Vec<EntityId> = self.entities.keys().cloned().collect();
+        for entity_id in entity_ids {
+            self.remove_entity(res, entity_id);
+        }
     }
 
+    fn emit(res: &World, event: SpatialEntityEvent) {
+        if res.has_value::<CheckoutGroups>() {
+            let entity_id = match event {
+                SpatialEntityEvent::Added(entity_id, _) => entity_id,
+                SpatialEntityEvent::Removed(entity_id, _) => entity_id,
+            };
+            res.fetch_mut::<CheckoutGroups>().entity_changed(entity_id);
+        }
+
+        if res.has_value::<SpatialEntityEvents>() {
+            res.fetch_mut::<SpatialEntityEvents>().single_write(event);
+        }
+    }
+
+    /// Returns the specs entity of the SpatialOS entity, or `None` if it is not
+    /// currently checked out.
     pub fn get_entity(&self, entity_id: EntityId) -> Option<Entity> {
         self.entities.get(&entity_id).cloned()
     }
+
+    /// Returns whether the entity is checked out or was recently removed.
+    ///
+    /// Callbacks which resolve after the entity they refer to may have left the view,
+    /// such as command responses, can use this to bail out.
+    pub fn liveness(&self, entity_id: EntityId) -> EntityLiveness {
+        match self.entities.get(&entity_id) {
+            Some(entity) => EntityLiveness::CheckedOut(*entity),
+            None if self.tombstones.contains_key(&entity_id) => EntityLiveness::Removed,
+            None => EntityLiveness::Unknown,
+        }
+    }
+
+    pub(crate) fn iter<'a>(&'a self) -> impl Iterator<Item = (EntityId, Entity)> + 'a {
+        self.entities
+            .iter()
+            .map(|(entity_id, entity)| (*entity_id, *entity))
+    }
 }
 
+impl Default for SpatialEntitiesRes {
+    fn default() -> Self {
+        SpatialEntitiesRes {
+            entities: HashMap::new(),
+            tombstones: HashMap::new(),
+            tombstone_window: Duration::from_secs(30),
+            duplicate_policy: DuplicateEntityPolicy::Reuse,
+            bindings: HashMap::new(),
+            components: HashMap::new(),
+        }
+    }
+}
+
+/// SystemData giving access to the mapping between specs entities and SpatialOS entities.
+///
+/// It can be joined on like a `ReadStorage<EntityId>`.
 pub type EntityIds<'a> = EntityIdsSystemData<'a>;
 
 #[doc(hidden)]
@@ -66,6 +338,14 @@ pub struct EntityIdsSystemData<'a> {
     entity_id_storage: ReadStorage<'a, EntityId>,
 }
 
+impl<'a> EntityIdsSystemData<'a> {
+    /// Returns the SpatialOS entity ID of the entity, or `None` if it is not a
+    /// SpatialOS entity.
+    pub fn get_entity_id(&self, entity: Entity) -> Option<EntityId> {
+        self.entity_id_storage.get(entity).cloned()
+    }
+}
+
 impl<'a> Deref for EntityIdsSystemData<'a> {
     type Target = Fetch<'a, SpatialEntitiesRes>;
 
@@ -75,12 +355,13 @@ impl<'a> Deref for EntityIdsSystemData<'a> {
 }
 
 impl<'a> SystemData<'a> for EntityIdsSystemData<'a> {
-    fn setup(res: &mut Resources) {
+    fn setup(res: &mut World) {
         Read::<SpatialEntitiesRes>::setup(res);
         ReadStorage::<EntityId>::setup(res);
+        Write::<SpatialEntityEvents>::setup(res);
     }
 
-    fn fetch(res: &'a Resources) -> Self {
+    fn fetch(res: &'a World) -> Self {
         EntityIdsSystemData {
             spatial_entities_res: res.fetch(),
             entity_id_storage: ReadStorage::<'a, EntityId>::fetch(res),
@@ -118,26 +399,25 @@ unsafe impl<'a> ParJoin for EntityIdsSystemData<'a> {}
 
 #[test]
 fn entities_should_be_added_and_removed_successfully() {
-    use specs::prelude::World;
+    use specs::prelude::{World, WorldExt};
 
     let mut world = World::new();
 
     type SystemData<'a> = (Entities<'a>, EntityIds<'a>);
 
-    SystemData::setup(&mut world.res);
+    SystemData::setup(&mut world);
 
     {
-        let (entities, entity_ids) = SystemData::fetch(&world.res);
+        let (entities, entity_ids) = SystemData::fetch(&world);
         assert!((&entities, &entity_ids).join().next().is_none());
     }
 
     world
-        .res
         .fetch_mut::<SpatialEntitiesRes>()
-        .got_new_entity(&world.res, EntityId(WorkerEntityId::new(5)));
+        .got_new_entity(&world, EntityId(WorkerEntityId::new(5)));
 
     {
-        let (entities, entity_ids) = SystemData::fetch(&world.res);
+        let (entities, entity_ids) = SystemData::fetch(&world);
         let (entity, entity_id) = (&entities, &entity_ids).join().next().unwrap();
         assert_eq!(5, entity_id.id().id);
 
@@ -148,15 +428,187 @@ fn entities_should_be_added_and_removed_successfully() {
     }
 
     world
-        .res
         .fetch_mut::<SpatialEntitiesRes>()
-        .remove_entity(&world.res, EntityId(WorkerEntityId::new(5)));
+        .remove_entity(&world, EntityId(WorkerEntityId::new(5)));
 
     {
-        let (entities, entity_ids) = SystemData::fetch(&world.res);
+        let (entities, entity_ids) = SystemData::fetch(&world);
         assert!((&entities, &entity_ids).join().next().is_none());
         assert!(entity_ids
             .get_entity(EntityId(WorkerEntityId::new(5)))
             .is_none());
     }
 }
+
+#[test]
+fn entity_events_should_be_emitted() {
+    use specs::prelude::{World, WorldExt};
+
+    let mut world = World::new();
+
+    EntityIds::setup(&mut world);
+
+    let mut reader_id = world.fetch_mut::<SpatialEntityEvents>().register_reader();
+
+    let entity_id = EntityId(WorkerEntityId::new(5));
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .remove_all_entities(&world);
+
+    let events: Vec<SpatialEntityEvent> = world
+        .fetch::<SpatialEntityEvents>()
+        .read(&mut reader_id)
+        .cloned()
+        .collect();
+
+    match events.as_slice() {
+        [SpatialEntityEvent::Added(added, _), SpatialEntityEvent::Removed(removed, _)] => {
+            assert_eq!(entity_id, *added);
+            assert_eq!(entity_id, *removed);
+        }
+        other => panic!("Unexpected events: {:?}", other),
+    }
+
+    assert!(world
+        .fetch::<SpatialEntitiesRes>()
+        .get_entity(entity_id)
+        .is_none());
+}
+
+#[test]
+fn snapshot_id_allocator_should_allocate_sequentially() {
+    let mut allocator = SnapshotIdAllocator::new(EntityId::new(WorkerEntityId::new(10)));
+
+    assert_eq!(EntityId::new(WorkerEntityId::new(10)), allocator.allocate());
+
+    let ids = allocator.allocate_many(3);
+    assert_eq!(
+        vec!["11", "12", "13"],
+        ids.iter().map(ToString::to_string).collect::<Vec<_>>()
+    );
+    assert!(ids[0] < ids[2]);
+    assert_eq!(EntityId::new(WorkerEntityId::new(14)), allocator.peek());
+}
+
+#[test]
+fn liveness_should_report_tombstones_within_window() {
+    use specs::prelude::{World, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+
+    let entity_id = EntityId(WorkerEntityId::new(5));
+    let liveness = |world: &World| world.fetch::<SpatialEntitiesRes>().liveness(entity_id);
+
+    assert_eq!(EntityLiveness::Unknown, liveness(&world));
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+    match liveness(&world) {
+        EntityLiveness::CheckedOut(_) => {}
+        other => panic!("Unexpected liveness: {:?}", other),
+    }
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .remove_entity(&world, entity_id);
+    assert_eq!(EntityLiveness::Removed, liveness(&world));
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .prune_tombstones(Instant::now() + Duration::from_secs(30));
+    assert_eq!(EntityLiveness::Unknown, liveness(&world));
+}
+
+#[test]
+fn duplicate_entities_should_follow_policy() {
+    use specs::prelude::{World, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+
+    let mut reader_id = world.fetch_mut::<SpatialEntityEvents>().register_reader();
+    let entity_id = EntityId(WorkerEntityId::new(5));
+    let add = |world: &World| {
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(world, entity_id);
+        world
+            .fetch::<SpatialEntitiesRes>()
+            .get_entity(entity_id)
+            .unwrap()
+    };
+    let checked_out = |world: &World| {
+        let (entities, entity_ids) = <(Entities, EntityIds)>::fetch(world);
+        (&entities, &entity_ids).join().count()
+    };
+
+    let first = add(&world);
+    assert_eq!(first, add(&world));
+    assert_eq!(1, checked_out(&world));
+    assert_eq!(
+        1,
+        world
+            .fetch::<SpatialEntityEvents>()
+            .read(&mut reader_id)
+            .count()
+    );
+
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .set_duplicate_policy(DuplicateEntityPolicy::Replace);
+    let second = add(&world);
+    assert_ne!(first, second);
+    world.maintain();
+    assert!(!world.is_alive(first));
+    assert_eq!(1, checked_out(&world));
+
+    let events: Vec<SpatialEntityEvent> = world
+        .fetch::<SpatialEntityEvents>()
+        .read(&mut reader_id)
+        .cloned()
+        .collect();
+    assert_eq!(
+        vec![
+            SpatialEntityEvent::Removed(entity_id, first),
+            SpatialEntityEvent::Added(entity_id, second)
+        ],
+        events
+    );
+}
+
+#[test]
+fn bound_entities_should_be_used_on_checkout() {
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+
+    let placeholder = world.create_entity().build();
+    let deleted = world.create_entity().build();
+    world.delete_entity(deleted).unwrap();
+
+    let bound = EntityId(WorkerEntityId::new(5));
+    let stale = EntityId(WorkerEntityId::new(6));
+    {
+        let mut entities = world.fetch_mut::<SpatialEntitiesRes>();
+        entities.bind(bound, placeholder);
+        entities.bind(stale, deleted);
+        entities.got_new_entity(&world, bound);
+        entities.got_new_entity(&world, stale);
+    }
+
+    let entity_ids = EntityIds::fetch(&world);
+    assert_eq!(Some(placeholder), entity_ids.get_entity(bound));
+    assert_eq!(Some(bound), entity_ids.get_entity_id(placeholder));
+
+    let created = entity_ids.get_entity(stale).unwrap();
+    assert_ne!(deleted, created);
+    assert!(world.is_alive(created));
+}
@@ -0,0 +1,529 @@
+//! An optional policy for client workers with large checkouts, which drops the locally
+//! cached data of entities outside a relevance radius, and optionally keeps the number of
+//! entities whose data is cached within a budget.
+//!
+//! The entity itself and its position component are always kept. When an entity comes back
+//! into range, its evicted components are re-requested with an entity query.
+use crate::clock;
+use crate::component_registry::ComponentRegistry;
+use crate::entities::{EntityId, EntityIds};
+use crate::logging::{self, LogKind, LogLevel};
+use crate::system_commands::SystemCommandSender;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::op::{EntityQueryResponseOp, QueryResponse, StatusCode};
+use spatialos_sdk::worker::query::{EntityQuery, QueryConstraint, ResultType, SnapshotResultType};
+use specs::prelude::{Entity, SystemData, World};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+type PositionExtractor = Box<Fn(&Any) -> Option<[f64; 3]> + Send + Sync>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RelevanceChange {
+    Entered,
+    Left,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Relevance {
+    // Whether the entity's data is kept. Only changes once the grace period has passed.
+    relevant: bool,
+    out_of_range_since: Option<Instant>,
+}
+
+/// A resource which evicts the data components of entities outside a relevance radius
+/// around a center point, such as the camera.
+///
+/// Entities are only evicted once they have been out of range for the grace period, so
+/// entities moving along the edge of the radius are not repeatedly evicted and re-requested.
+/// The grace period is checked every frame by the `SpatialReaderSystem`, so entities which
+/// stop moving outside the radius, or are left behind when the center moves, are still
+/// evicted. Entities first seen outside the radius are evicted straight away.
+///
+/// With an entity budget, once more entities than the budget have their data kept, the
+/// entities which left the radius longest ago are evicted before their grace period runs
+/// out, until the budget is met. Entities inside the radius are never evicted, so the budget
+/// can still be exceeded if more entities than that are in range.
+///
+/// ```ignore
+/// let mut eviction = ProxyEviction::new(200.0, |position: &Position| {
+///     [position.coords.x, position.coords.y, position.coords.z]
+/// });
+/// eviction.set_entity_budget(Some(5000));
+/// world.insert(eviction);
+/// ```
+pub struct ProxyEviction {
+    center: [f64; 3],
+    radius: f64,
+    grace_period: Duration,
+    entity_budget: Option<usize>,
+    position_component: ComponentId,
+    position_extractor: PositionExtractor,
+    kept_components: HashSet<ComponentId>,
+    positions: HashMap<Entity, [f64; 3]>,
+    relevance: HashMap<Entity, Relevance>,
+    evicted: HashMap<Entity, HashSet<ComponentId>>,
+    pending_refresh: HashSet<Entity>,
+}
+
+impl ProxyEviction {
+    pub fn new<P, F>(radius: f64, position: F) -> ProxyEviction
+    where
+        P: 'static + WorkerComponent,
+        F: 'static + Fn(&P) -> [f64; 3] + Send + Sync,
+    {
+        ProxyEviction {
+            center: [0.0, 0.0, 0.0],
+            radius,
+            grace_period: Duration::from_secs(5),
+            entity_budget: None,
+            position_component: P::ID,
+            position_extractor: Box::new(move |value| value.downcast_ref::<P>().map(&position)),
+            kept_components: HashSet::new(),
+            positions: HashMap::new(),
+            relevance: HashMap::new(),
+            evicted: HashMap::new(),
+            pending_refresh: HashSet::new(),
+        }
+    }
+
+    pub fn set_center(&mut self, center: [f64; 3]) {
+        self.center = center;
+    }
+
+    pub fn set_radius(&mut self, radius: f64) {
+        self.radius = radius;
+    }
+
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /// Sets the maximum number of entities whose data is kept, or `None` for no limit,
+    /// which is the default.
+    pub fn set_entity_budget(&mut self, entity_budget: Option<usize>) {
+        self.entity_budget = entity_budget;
+    }
+
+    /// Never evicts the data of the given component.
+    pub fn keep<T: WorkerComponent>(&mut self) {
+        self.kept_components.insert(T::ID);
+    }
+
+    /// Returns whether the given component of the entity is currently evicted.
+    pub fn is_evicted(&self, entity: Entity, component_id: ComponentId) -> bool {
+        self.evicted
+            .get(&entity)
+            .map(|components| components.contains(&component_id))
+            .unwrap_or(false)
+    }
+
+    fn in_range(&self, position: [f64; 3]) -> bool {
+        let distance_squared: f64 = (0..3).map(|i| (position[i] - self.center[i]).powi(2)).sum();
+        distance_squared <= self.radius * self.radius
+    }
+
+    // Entities without a known position are relevant until it arrives.
+    fn is_relevant(&self, entity: Entity) -> bool {
+        self.relevance
+            .get(&entity)
+            .map_or(true, |relevance| relevance.relevant)
+    }
+
+    // Updates the stored relevance of the entity from its position, returning any change.
+    fn refresh_relevance(&mut self, entity: Entity, now: Instant) -> Option<RelevanceChange> {
+        let position = *self.positions.get(&entity)?;
+        let in_range = self.in_range(position);
+        let grace_period = self.grace_period;
+
+        let first_seen = !self.relevance.contains_key(&entity);
+        let relevance = self.relevance.entry(entity).or_insert(Relevance {
+            relevant: true,
+            out_of_range_since: None,
+        });
+
+        let change = if in_range {
+            relevance.out_of_range_since = None;
+            if relevance.relevant {
+                None
+            } else {
+                relevance.relevant = true;
+                Some(RelevanceChange::Entered)
+            }
+        } else {
+            let out_of_range_since = *relevance.out_of_range_since.get_or_insert(now);
+            if relevance.relevant
+                && (first_seen || now.duration_since(out_of_range_since) >= grace_period)
+            {
+                relevance.relevant = false;
+                Some(RelevanceChange::Left)
+            } else {
+                None
+            }
+        };
+
+        if change == Some(RelevanceChange::Entered) && self.evicted.contains_key(&entity) {
+            self.pending_refresh.insert(entity);
+        }
+
+        change
+    }
+
+    // Returns the entities whose grace period has run out since they were last checked, or
+    // which are evicted early to meet the entity budget.
+    fn expire(&mut self, now: Instant) -> Vec<Entity> {
+        let entities: Vec<Entity> = self.positions.keys().cloned().collect();
+        let mut left: Vec<Entity> = entities
+            .into_iter()
+            .filter(|entity| self.refresh_relevance(*entity, now) == Some(RelevanceChange::Left))
+            .collect();
+        left.extend(self.enforce_budget());
+        left
+    }
+
+    // Evicts the relevant entities which have been out of range the longest, until no more
+    // than the budget are relevant or only entities in range are left.
+    fn enforce_budget(&mut self) -> Vec<Entity> {
+        let budget = match self.entity_budget {
+            Some(budget) => budget,
+            None => return Vec::new(),
+        };
+
+        let relevant = self
+            .relevance
+            .values()
+            .filter(|relevance| relevance.relevant)
+            .count();
+        if relevant <= budget {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<(Instant, Entity)> = self
+            .relevance
+            .iter()
+            .filter(|(_, relevance)| relevance.relevant)
+            .filter_map(|(entity, relevance)| Some((relevance.out_of_range_since?, *entity)))
+            .collect();
+        candidates.sort();
+        candidates.truncate(relevant - budget);
+
+        candidates
+            .into_iter()
+            .map(|(_, entity)| {
+                self.relevance.get_mut(&entity).unwrap().relevant = false;
+                entity
+            })
+            .collect()
+    }
+
+    // Returns whether component data for the entity should be stored, recording an
+    // eviction if not.
+    pub(crate) fn should_store(&mut self, entity: Entity, component_id: ComponentId) -> bool {
+        if component_id == self.position_component || self.kept_components.contains(&component_id) {
+            return true;
+        }
+
+        if self.is_evicted(entity, component_id) {
+            return false;
+        }
+
+        if self.is_relevant(entity) {
+            true
+        } else {
+            self.evicted
+                .entry(entity)
+                .or_insert_with(HashSet::new)
+                .insert(component_id);
+            false
+        }
+    }
+
+    pub(crate) fn is_position_component(&self, component_id: ComponentId) -> bool {
+        component_id == self.position_component
+    }
+
+    pub(crate) fn position_changed(
+        &mut self,
+        entity: Entity,
+        value: &Any,
+        now: Instant,
+    ) -> Option<RelevanceChange> {
+        let position = (self.position_extractor)(value)?;
+        self.positions.insert(entity, position);
+        self.refresh_relevance(entity, now)
+    }
+
+    pub(crate) fn entity_removed(&mut self, entity: Entity) {
+        self.positions.remove(&entity);
+        self.relevance.remove(&entity);
+        self.evicted.remove(&entity);
+        self.pending_refresh.remove(&entity);
+    }
+
+    // Evicts every evictable component of an entity which has left the relevance radius.
+    pub(crate) fn evict_entity(res: &World, entity: Entity) {
+        let mut evicted = HashSet::new();
+
+        {
+            let eviction = res.fetch::<ProxyEviction>();
+            for interface in ComponentRegistry::interfaces_iter() {
+                let component_id = interface.component_id();
+                if component_id != eviction.position_component
+                    && !eviction.kept_components.contains(&component_id)
+                    && interface.evict_data(res, entity)
+                {
+                    evicted.insert(component_id);
+                }
+            }
+        }
+
+        res.fetch_mut::<ProxyEviction>()
+            .evicted
+            .entry(entity)
+            .or_insert_with(HashSet::new)
+            .extend(evicted);
+    }
+
+    // Evicts the entities whose grace period has run out, and requests the evicted data of
+    // those which have come back into range.
+    pub(crate) fn update(res: &World) {
+        let left = res.fetch_mut::<ProxyEviction>().expire(clock::now(res));
+        for entity in left {
+            ProxyEviction::evict_entity(res, entity);
+        }
+
+        ProxyEviction::request_refreshes(res);
+    }
+
+    // Sends an entity query for every entity which has come back into range.
+    fn request_refreshes(res: &World) {
+        let entities: Vec<Entity> = res
+            .fetch_mut::<ProxyEviction>()
+            .pending_refresh
+            .drain()
+            .collect();
+
+        if entities.is_empty() {
+            return;
+        }
+
+        let entity_ids = EntityIds::fetch(res);
+        let mut sender = SystemCommandSender::fetch(res);
+
+        for entity in entities {
+            let entity_id = match entity_ids.get_entity_id(entity) {
+                Some(entity_id) => entity_id,
+                None => continue,
+            };
+
+            let query = EntityQuery {
+                constraint: QueryConstraint::EntityId(entity_id.id()),
+                result_type: ResultType::Snapshot(SnapshotResultType::FullSnapshot),
+            };
+
+            sender.entity_query_internal(
+                query,
+                Box::new(move |res, response_op| {
+                    ProxyEviction::on_refresh(res, entity_id, response_op)
+                }),
+            );
+        }
+    }
+
+    fn on_refresh(res: &World, entity_id: EntityId, response_op: EntityQueryResponseOp) {
+        let entity = match EntityIds::fetch(res).get_entity(entity_id) {
+            Some(entity) => entity,
+            None => return,
+        };
+
+        let snapshot = match response_op.status_code {
+            StatusCode::Success(QueryResponse::Snapshot(snapshot)) => snapshot,
+            other => {
+                // Try again next frame.
+                res.fetch_mut::<ProxyEviction>()
+                    .pending_refresh
+                    .insert(entity);
+                return logging::log(
+                    res,
+                    LogLevel::Warn,
+                    LogKind::Eviction,
+                    &format!(
+                        "Failed to refresh evicted components of entity {:?}: {:?}",
+                        entity_id, other
+                    ),
+                );
+            }
+        };
+
+        let worker_entity = match snapshot.get(&entity_id.id()) {
+            Some(worker_entity) => worker_entity,
+            None => return,
+        };
+
+        let components = {
+            let mut eviction = res.fetch_mut::<ProxyEviction>();
+            if !eviction.is_relevant(entity) {
+                return;
+            }
+            eviction.evicted.remove(&entity).unwrap_or_default()
+        };
+
+        for interface in ComponentRegistry::interfaces_iter() {
+            if components.contains(&interface.component_id()) {
+                interface.insert_from_snapshot(res, entity, worker_entity);
+            }
+        }
+    }
+}
+
+#[test]
+fn proxy_eviction_should_evict_entities_once_the_grace_period_expires() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+    let mut eviction = ProxyEviction::new(10.0, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    });
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+    let start = Instant::now();
+
+    assert_eq!(
+        None,
+        eviction.position_changed(entity, &position(1.0), start)
+    );
+    assert_eq!(
+        None,
+        eviction.position_changed(entity, &position(20.0), start)
+    );
+
+    // The entity isn't evicted by later updates or checks within the grace period.
+    let later = start + Duration::from_secs(4);
+    assert_eq!(
+        None,
+        eviction.position_changed(entity, &position(21.0), later)
+    );
+    assert!(eviction.expire(later).is_empty());
+    assert!(eviction.should_store(entity, Blob::ID));
+
+    // Nor does the grace period restart while it stays out of range.
+    let expired = start + Duration::from_secs(5);
+    assert_eq!(vec![entity], eviction.expire(expired));
+    assert!(eviction.expire(expired).is_empty());
+    assert!(!eviction.should_store(entity, Blob::ID));
+    assert!(eviction.is_evicted(entity, Blob::ID));
+}
+
+#[test]
+fn proxy_eviction_should_report_leaving_immediately_without_a_grace_period() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let near = world.create_entity().build();
+    let far = world.create_entity().build();
+    let mut eviction = ProxyEviction::new(10.0, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    });
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+    let now = Instant::now();
+
+    // Entities first seen out of range are never relevant.
+    assert_eq!(
+        Some(RelevanceChange::Left),
+        eviction.position_changed(far, &position(50.0), now)
+    );
+
+    eviction.set_grace_period(Duration::from_secs(0));
+    assert_eq!(None, eviction.position_changed(near, &position(1.0), now));
+    assert_eq!(
+        Some(RelevanceChange::Left),
+        eviction.position_changed(near, &position(11.0), now)
+    );
+    assert_eq!(None, eviction.position_changed(near, &position(12.0), now));
+}
+
+#[test]
+fn proxy_eviction_should_refresh_entities_which_come_back_into_range() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+    let mut eviction = ProxyEviction::new(10.0, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    });
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+    let start = Instant::now();
+    let at = |seconds| start + Duration::from_secs(seconds);
+
+    // Coming back within the grace period keeps the data, and restarts the grace period.
+    eviction.position_changed(entity, &position(1.0), at(0));
+    eviction.position_changed(entity, &position(20.0), at(0));
+    assert_eq!(
+        None,
+        eviction.position_changed(entity, &position(1.0), at(3))
+    );
+    eviction.position_changed(entity, &position(20.0), at(6));
+    assert!(eviction.expire(at(10)).is_empty());
+    assert!(eviction.pending_refresh.is_empty());
+
+    assert_eq!(vec![entity], eviction.expire(at(11)));
+    assert!(!eviction.should_store(entity, Blob::ID));
+
+    // The evicted data is requested again once it returns.
+    assert_eq!(
+        Some(RelevanceChange::Entered),
+        eviction.position_changed(entity, &position(1.0), at(12))
+    );
+    assert!(eviction.pending_refresh.contains(&entity));
+    assert!(eviction.is_evicted(entity, Blob::ID));
+    assert!(!eviction.should_store(entity, Blob::ID));
+}
+
+#[test]
+fn proxy_eviction_should_evict_the_least_recently_relevant_entities_over_budget() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let near = world.create_entity().build();
+    let first = world.create_entity().build();
+    let second = world.create_entity().build();
+    let mut eviction = ProxyEviction::new(10.0, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    });
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+    let start = Instant::now();
+    let at = |seconds| start + Duration::from_secs(seconds);
+
+    for entity in &[near, first, second] {
+        eviction.position_changed(*entity, &position(1.0), at(0));
+    }
+    eviction.position_changed(first, &position(20.0), at(1));
+    eviction.position_changed(second, &position(20.0), at(2));
+    assert!(eviction.expire(at(3)).is_empty());
+
+    // Within the grace period, the entity which left the radius first is evicted first.
+    eviction.set_entity_budget(Some(2));
+    assert_eq!(vec![first], eviction.expire(at(3)));
+    assert!(!eviction.should_store(first, Blob::ID));
+    assert!(eviction.should_store(second, Blob::ID));
+
+    // Entities in range are kept even if the budget is still exceeded.
+    eviction.set_entity_budget(Some(0));
+    assert_eq!(vec![second], eviction.expire(at(3)));
+    assert!(eviction.expire(at(3)).is_empty());
+    assert!(eviction.should_store(near, Blob::ID));
+}
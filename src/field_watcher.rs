@@ -0,0 +1,125 @@
+//! Callbacks for changes to individual fields of a component, so that UI and audio can
+//! react to exactly the changes they care about without polling or diffing.
+//!
+//! A `FieldWatcher` resource holds the callbacks for a component, which must implement
+//! `ComponentFields` by hand. Each callback is registered for a field ID with a closure
+//! which reads the field's value, as nothing is generated to access fields by ID:
+//!
+//! ```ignore
+//! let mut watcher = FieldWatcher::<Player>::new();
+//! watcher.on_change(Player::HEALTH_FIELD_ID, |player| &player.health, |entity, old, new| {
+//!     if new < old {
+//!         play_hurt_sound(entity);
+//!     }
+//! });
+//! world.insert(watcher);
+//! ```
+//!
+//! Callbacks are called by the `SpatialReaderSystem` after an update received from
+//! SpatialOS has been applied, if the update sets the field and the field's value changed.
+//! Changes made locally by this worker are not reported.
+use crate::fields::{ComponentFields, FieldId};
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use specs::prelude::Entity;
+
+type FieldCallback<T> = Box<Fn(Entity, &T, &T) + Send + Sync>;
+
+/// A resource holding callbacks for field changes of the component `T`.
+pub struct FieldWatcher<T: WorkerComponent> {
+    update_sets_field: fn(&T::Update, FieldId) -> bool,
+    callbacks: Vec<(FieldId, FieldCallback<T>)>,
+}
+
+impl<T: ComponentFields> FieldWatcher<T> {
+    pub fn new() -> FieldWatcher<T> {
+        FieldWatcher {
+            update_sets_field: T::update_sets_field,
+            callbacks: Vec::new(),
+        }
+    }
+}
+
+impl<T: ComponentFields> Default for FieldWatcher<T> {
+    fn default() -> Self {
+        FieldWatcher::new()
+    }
+}
+
+impl<T: WorkerComponent> FieldWatcher<T> {
+    /// Calls `callback` with the entity and the old and new value of the field whenever a
+    /// received update changes it. `field` returns the field's value from the component.
+    pub fn on_change<V, A, F>(&mut self, field_id: FieldId, field: A, callback: F)
+    where
+        V: PartialEq,
+        A: 'static + Fn(&T) -> &V + Send + Sync,
+        F: 'static + Fn(Entity, &V, &V) + Send + Sync,
+    {
+        self.callbacks.push((
+            field_id,
+            Box::new(move |entity, old, new| {
+                let (old, new) = (field(old), field(new));
+                if old != new {
+                    callback(entity, old, new);
+                }
+            }),
+        ));
+    }
+
+    /// Returns the watched fields which the update sets.
+    pub(crate) fn watched_fields(&self, update: &T::Update) -> Vec<FieldId> {
+        let mut fields: Vec<FieldId> = self
+            .callbacks
+            .iter()
+            .map(|(field_id, _)| *field_id)
+            .filter(|field_id| (self.update_sets_field)(update, *field_id))
+            .collect();
+        fields.dedup();
+        fields
+    }
+
+    pub(crate) fn notify(&self, entity: Entity, fields: &[FieldId], old: &T, new: &T) {
+        for (field_id, callback) in &self.callbacks {
+            if fields.contains(field_id) {
+                callback(entity, old, new);
+            }
+        }
+    }
+}
+
+#[test]
+fn field_watcher_should_only_report_changed_watched_fields() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, World, WorldExt};
+    use std::sync::{Arc, Mutex};
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let mut watcher = FieldWatcher::<Position>::new();
+    {
+        let changes = changes.clone();
+        watcher.on_change(
+            Position::COORDS_FIELD_ID,
+            |position| &position.coords.x,
+            move |entity, old, new| changes.lock().unwrap().push((entity, *old, *new)),
+        );
+    }
+
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    assert!(watcher
+        .watched_fields(&PositionUpdate { coords: None })
+        .is_empty());
+
+    let fields = watcher.watched_fields(&PositionUpdate {
+        coords: Some(position(2.0).coords),
+    });
+    assert_eq!(vec![Position::COORDS_FIELD_ID], fields);
+
+    watcher.notify(entity, &fields, &position(1.0), &position(1.0));
+    watcher.notify(entity, &fields, &position(1.0), &position(2.0));
+    assert_eq!(vec![(entity, 1.0, 2.0)], *changes.lock().unwrap());
+}
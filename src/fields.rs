@@ -0,0 +1,88 @@
+//! Numeric identification of schema fields, so that middleware and diffing features can
+//! refer to fields without string lookups.
+//!
+//! `ComponentFields` and the `FieldId` constants are not generated, so they are implemented
+//! by hand for each component whose fields should be described, using the field numbers and
+//! the `Option` fields of the update type from the schema:
+//!
+//! ```ignore
+//! impl Player {
+//!     pub const NAME_FIELD_ID: FieldId = 1;
+//!     pub const HEALTH_FIELD_ID: FieldId = 2;
+//! }
+//!
+//! impl ComponentFields for Player {
+//!     const FIELDS: &'static [FieldInfo] = &[
+//!         FieldInfo { id: 1, name: "name" },
+//!         FieldInfo { id: 2, name: "health" },
+//!     ];
+//!
+//!     fn update_sets_field(update: &PlayerUpdate, field_id: FieldId) -> bool {
+//!         match field_id {
+//!             1 => update.name.is_some(),
+//!             2 => update.health.is_some(),
+//!             _ => false,
+//!         }
+//!     }
+//! }
+//! ```
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+
+/// The schema field number of a field.
+pub type FieldId = u32;
+
+/// A field of a component.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub id: FieldId,
+    /// The name of the field in schema. This is only intended for diagnostics.
+    pub name: &'static str,
+}
+
+/// A table describing the fields of a component.
+pub trait ComponentFields: WorkerComponent {
+    /// Every field of the component, in ascending order of `FieldId`.
+    const FIELDS: &'static [FieldInfo];
+
+    /// Returns whether the update sets the given field.
+    fn update_sets_field(update: &Self::Update, field_id: FieldId) -> bool;
+
+    fn field_info(field_id: FieldId) -> Option<&'static FieldInfo> {
+        Self::FIELDS
+            .binary_search_by_key(&field_id, |field| field.id)
+            .ok()
+            .map(|index| &Self::FIELDS[index])
+    }
+
+    /// Returns the ID of every field set by the update.
+    fn updated_fields(update: &Self::Update) -> Vec<FieldId> {
+        Self::FIELDS
+            .iter()
+            .map(|field| field.id)
+            .filter(|field_id| Self::update_sets_field(update, *field_id))
+            .collect()
+    }
+}
+
+#[test]
+fn updated_fields_should_list_set_fields() {
+    use crate::generated_test::*;
+
+    let update = SchemaShapesUpdate {
+        anchors: Some(Default::default()),
+        blob: Some(vec![1]),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        vec![SchemaShapes::ANCHORS_FIELD_ID, SchemaShapes::BLOB_FIELD_ID],
+        SchemaShapes::updated_fields(&update)
+    );
+    assert_eq!(
+        "loadout",
+        SchemaShapes::field_info(SchemaShapes::LOADOUT_FIELD_ID)
+            .unwrap()
+            .name
+    );
+    assert!(Position::field_info(2).is_none());
+}
@@ -0,0 +1,288 @@
+//! Typed configuration from worker flags, selected with the `worker-flags` feature.
+//!
+//! Worker flags are received as ops, so the `SpatialReaderSystem` keeps every flag in the
+//! `WorkerFlags` resource and emits a `WorkerFlagEvent` whenever one changes. A
+//! configuration struct is parsed from the flags with serde, with each flag parsed from
+//! its string value into the type of the field of the same name:
+//!
+//! ```ignore
+//! #[derive(Deserialize, Default)]
+//! #[serde(default)]
+//! struct GameConfig {
+//!     max_players: u32,
+//!     spawn_radius: f64,
+//!     #[serde(rename = "game_mode")]
+//!     mode: GameMode,
+//! }
+//!
+//! struct ConfigSys(WorkerConfig<GameConfig>);
+//!
+//! impl<'a> System<'a> for ConfigSys {
+//!     type SystemData = Read<'a, WorkerFlags>;
+//!
+//!     fn run(&mut self, flags: Self::SystemData) {
+//!         match self.0.update(&flags) {
+//!             Ok(true) => println!("Max players is now {}", self.0.get().max_players),
+//!             Ok(false) => {}
+//!             Err(error) => println!("Invalid worker flags: {}", error),
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Fields without a flag take their serde default, so `#[serde(default)]` makes every flag
+//! optional. An empty flag value is `None` for `Option` fields.
+use serde::de::value::{Error as ValueError, MapDeserializer, StrDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use specs::prelude::World;
+use specs::shrev::EventChannel;
+use std::collections::HashMap;
+
+/// An event emitted when a worker flag is set, changed or removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerFlagEvent {
+    pub name: String,
+    /// The new value, or `None` if the flag was removed.
+    pub value: Option<String>,
+}
+
+/// An event channel which receives a `WorkerFlagEvent` whenever a worker flag changes.
+pub type WorkerFlagEvents = EventChannel<WorkerFlagEvent>;
+
+/// A resource holding the current value of every worker flag.
+#[derive(Debug, Default)]
+pub struct WorkerFlags {
+    values: HashMap<String, String>,
+    generation: u64,
+}
+
+impl WorkerFlags {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// A counter which is incremented whenever any flag changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Parses the flags into a configuration struct.
+    pub fn parse<C: DeserializeOwned>(&self) -> Result<C, String> {
+        let values = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.as_str(), FlagValue(value)));
+        C::deserialize(MapDeserializer::<_, ValueError>::new(values)).map_err(|e| e.to_string())
+    }
+
+    // Returns whether the flag changed.
+    fn set(&mut self, name: &str, value: Option<String>) -> bool {
+        let changed = match value {
+            Some(value) => self.values.insert(name.to_string(), value.clone()) != Some(value),
+            None => self.values.remove(name).is_some(),
+        };
+
+        if changed {
+            self.generation += 1;
+        }
+        changed
+    }
+
+    pub(crate) fn got_flag_update(res: &World, name: &str, value: Option<String>) {
+        let changed = res.fetch_mut::<WorkerFlags>().set(name, value.clone());
+
+        if changed && res.has_value::<WorkerFlagEvents>() {
+            res.fetch_mut::<WorkerFlagEvents>()
+                .single_write(WorkerFlagEvent {
+                    name: name.to_string(),
+                    value,
+                });
+        }
+    }
+}
+
+/// A configuration struct parsed from the `WorkerFlags`, which is parsed again whenever
+/// the flags change.
+pub struct WorkerConfig<C> {
+    config: C,
+    generation: Option<u64>,
+}
+
+impl<C: DeserializeOwned + Default> WorkerConfig<C> {
+    /// Starts with the default configuration, until `update` is first called.
+    pub fn new() -> WorkerConfig<C> {
+        WorkerConfig {
+            config: C::default(),
+            generation: None,
+        }
+    }
+
+    pub fn get(&self) -> &C {
+        &self.config
+    }
+
+    /// Parses the flags if they have changed since the last call, returning whether the
+    /// configuration was replaced. If the flags cannot be parsed, the previous
+    /// configuration is kept and the error is returned.
+    pub fn update(&mut self, flags: &WorkerFlags) -> Result<bool, String> {
+        if self.generation == Some(flags.generation()) {
+            return Ok(false);
+        }
+
+        self.generation = Some(flags.generation());
+        self.config = flags.parse()?;
+        Ok(true)
+    }
+}
+
+impl<C: DeserializeOwned + Default> Default for WorkerConfig<C> {
+    fn default() -> Self {
+        WorkerConfig::new()
+    }
+}
+
+// Deserializes a flag's string value into whichever type the field has.
+struct FlagValue<'a>(&'a str);
+
+impl<'a> FlagValue<'a> {
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, ValueError> {
+        self.0.trim().parse().map_err(|_| {
+            de::Error::custom(format!("expected {} but found \"{}\"", expected, self.0))
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident: $type:ty),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                visitor.$visit(self.parse::<$type>(stringify!($type))?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for FlagValue<'a> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_str(self.0)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool: bool,
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        let variant: StrDeserializer<ValueError> = self.0.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, ValueError> for FlagValue<'a> {
+    type Deserializer = FlagValue<'a>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+#[test]
+fn worker_flags_should_parse_into_typed_config() {
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Mode {
+        Casual,
+        Ranked,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(default)]
+    struct Config {
+        max_players: u32,
+        radius: f64,
+        debug: bool,
+        mode: Mode,
+        motd: Option<String>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                max_players: 8,
+                radius: 1.5,
+                debug: false,
+                mode: Mode::Casual,
+                motd: None,
+            }
+        }
+    }
+
+    let mut flags = WorkerFlags::default();
+    let mut config = WorkerConfig::<Config>::new();
+    assert_eq!(Ok(true), config.update(&flags));
+    assert_eq!(&Config::default(), config.get());
+    assert_eq!(Ok(false), config.update(&flags));
+
+    assert!(flags.set("max_players", Some("16".to_string())));
+    assert!(!flags.set("max_players", Some("16".to_string())));
+    flags.set("debug", Some("true".to_string()));
+    flags.set("mode", Some("Ranked".to_string()));
+    flags.set("motd", Some("hello".to_string()));
+    flags.set("unrelated", Some("x".to_string()));
+
+    assert_eq!(Ok(true), config.update(&flags));
+    assert_eq!(
+        &Config {
+            max_players: 16,
+            radius: 1.5,
+            debug: true,
+            mode: Mode::Ranked,
+            motd: Some("hello".to_string()),
+        },
+        config.get()
+    );
+
+    flags.set("radius", Some("far".to_string()));
+    assert!(config.update(&flags).unwrap_err().contains("far"));
+    assert_eq!(16, config.get().max_players);
+
+    assert!(flags.set("radius", None));
+    assert!(config.update(&flags).unwrap());
+}
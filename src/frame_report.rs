@@ -0,0 +1,182 @@
+//! A summary of the traffic handled each frame, logged at the end of the frame for
+//! production triage.
+//!
+//! Adding a `FrameReport` resource enables the summary, which is logged through the
+//! `SpatialLogger` under `LogKind::FrameReport`:
+//!
+//! ```ignore
+//! let mut report = FrameReport::new(LogLevel::Info);
+//! report.set_interval(60);
+//! world.insert(report);
+//! ```
+//!
+//! With an interval greater than one, the counts are summed over the interval and the
+//! times are the totals for the interval.
+use crate::logging::{self, LogKind, LogLevel};
+use spatialos_sdk::worker::op::WorkerOp;
+use specs::prelude::World;
+use std::time::Duration;
+
+/// The traffic handled over one or more frames.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameCounts {
+    pub frames: u32,
+    pub entities_added: usize,
+    pub entities_removed: usize,
+    pub updates_received: usize,
+    pub updates_sent: usize,
+    pub command_requests_received: usize,
+    pub command_responses_received: usize,
+    pub command_requests_sent: usize,
+    pub command_responses_sent: usize,
+    pub system_commands_sent: usize,
+    pub reader_time: Duration,
+    pub writer_time: Duration,
+}
+
+/// A resource which enables the end of frame summary.
+pub struct FrameReport {
+    level: LogLevel,
+    interval: u32,
+    current: FrameCounts,
+    last: Option<FrameCounts>,
+}
+
+impl FrameReport {
+    /// Logs a summary every frame at the given level. The summary is only shown if the
+    /// `SpatialLogger`'s minimum level is at or below it, so `LogLevel::Debug` keeps the
+    /// report quiet until verbose logging is enabled.
+    pub fn new(level: LogLevel) -> FrameReport {
+        FrameReport {
+            level,
+            interval: 1,
+            current: FrameCounts::default(),
+            last: None,
+        }
+    }
+
+    pub fn set_level(&mut self, level: LogLevel) {
+        self.level = level;
+    }
+
+    /// Logs a single summary of every `frames` frames.
+    pub fn set_interval(&mut self, frames: u32) {
+        self.interval = frames.max(1);
+    }
+
+    /// The counts of the most recently logged summary.
+    pub fn last(&self) -> Option<&FrameCounts> {
+        self.last.as_ref()
+    }
+
+    pub(crate) fn record_op(&mut self, op: &WorkerOp) {
+        let counts = &mut self.current;
+        match op {
+            WorkerOp::AddEntity(_) => counts.entities_added += 1,
+            WorkerOp::RemoveEntity(_) => counts.entities_removed += 1,
+            WorkerOp::ComponentUpdate(_) => counts.updates_received += 1,
+            WorkerOp::CommandRequest(_) => counts.command_requests_received += 1,
+            WorkerOp::CommandResponse(_) => counts.command_responses_received += 1,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn record_sent(
+        &mut self,
+        updates: usize,
+        command_requests: usize,
+        command_responses: usize,
+    ) {
+        self.current.updates_sent += updates;
+        self.current.command_requests_sent += command_requests;
+        self.current.command_responses_sent += command_responses;
+    }
+
+    pub(crate) fn record_system_commands_sent(&mut self, count: usize) {
+        self.current.system_commands_sent += count;
+    }
+
+    pub(crate) fn record_reader_time(&mut self, time: Duration) {
+        self.current.reader_time += time;
+    }
+
+    pub(crate) fn record_writer_time(&mut self, time: Duration) {
+        self.current.writer_time += time;
+    }
+
+    // Returns the counts to log if the interval has elapsed.
+    fn end_frame(&mut self) -> Option<FrameCounts> {
+        self.current.frames += 1;
+        if self.current.frames < self.interval {
+            return None;
+        }
+
+        let counts = self.current;
+        self.current = FrameCounts::default();
+        self.last = Some(counts);
+        Some(counts)
+    }
+
+    pub(crate) fn finish_frame(res: &World) {
+        let (level, counts) = {
+            let mut report = res.fetch_mut::<FrameReport>();
+            match report.end_frame() {
+                Some(counts) => (report.level, counts),
+                None => return,
+            }
+        };
+
+        logging::log(
+            res,
+            level,
+            LogKind::FrameReport,
+            &format!(
+                "Frame report ({} frames): entities +{} -{}, updates in {} out {}, command requests in {} out {}, command responses in {} out {}, system commands out {}, reader {:?}, writer {:?}.",
+                counts.frames,
+                counts.entities_added,
+                counts.entities_removed,
+                counts.updates_received,
+                counts.updates_sent,
+                counts.command_requests_received,
+                counts.command_requests_sent,
+                counts.command_responses_received,
+                counts.command_responses_sent,
+                counts.system_commands_sent,
+                counts.reader_time,
+                counts.writer_time,
+            ),
+        );
+    }
+}
+
+impl Default for FrameReport {
+    fn default() -> Self {
+        FrameReport::new(LogLevel::Debug)
+    }
+}
+
+#[test]
+fn frame_report_should_sum_counts_over_interval() {
+    let mut report = FrameReport::new(LogLevel::Info);
+    report.set_interval(2);
+
+    report.record_sent(3, 1, 0);
+    report.record_writer_time(Duration::from_millis(2));
+    assert!(report.end_frame().is_none());
+
+    report.record_sent(1, 0, 2);
+    report.record_system_commands_sent(4);
+    report.record_writer_time(Duration::from_millis(3));
+    let counts = report.end_frame().unwrap();
+
+    assert_eq!(2, counts.frames);
+    assert_eq!(4, counts.updates_sent);
+    assert_eq!(1, counts.command_requests_sent);
+    assert_eq!(2, counts.command_responses_sent);
+    assert_eq!(4, counts.system_commands_sent);
+    assert_eq!(Duration::from_millis(5), counts.writer_time);
+    assert_eq!(Some(&counts), report.last());
+
+    report.record_sent(1, 0, 0);
+    assert!(report.end_frame().is_none());
+}
@@ -0,0 +1,376 @@
+//! Fuzzing of command handlers with random requests, selected with the `fuzzing` feature.
+//!
+//! Commands can be sent by any worker or client with the right attributes, so responders
+//! must cope with whatever they are given. A `CommandFuzzer` generates requests of the
+//! shape described by the schema, passes them through the same serialization as requests
+//! received from SpatialOS, with some bytes corrupted, and hands the requests which still
+//! deserialize to a responder against a world:
+//!
+//! ```ignore
+//! let mut world = World::new();
+//! CommandRequests::<Inventory>::setup(&mut world);
+//! let entity = world.create_entity().build();
+//!
+//! let mut fuzzer = CommandFuzzer::<Inventory>::new(seed);
+//! let report = fuzzer.run(&world, entity, 10_000, |world| {
+//!     InventorySys.run_now(world);
+//! });
+//! assert!(report.failures.is_empty(), "{:?}", report.failures);
+//! ```
+//!
+//! The code generator does not emit `FuzzCommand`, so it is implemented by hand for each
+//! fuzzed component, building a request with random field values using a `FuzzRng`. A
+//! panic anywhere between generating the request and serializing the responses is recorded
+//! as a `FuzzFailure`, and the same seed reproduces the same requests.
+use crate::command_info::{CommandInfo, ComponentCommands};
+use crate::commands::{CommandRequests, CommandRequestsComp};
+use crate::sdk;
+use spatialos_sdk::worker::RequestId;
+use specs::prelude::{Entity, SystemData, World};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// A seeded source of random values for generating requests.
+pub struct FuzzRng {
+    state: u64,
+}
+
+impl FuzzRng {
+    pub fn new(seed: u64) -> FuzzRng {
+        FuzzRng { state: seed }
+    }
+
+    // SplitMix64, so that a seed gives the same requests on every platform.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value between 0 and 1.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a value below `bound`, or 0 if `bound` is 0.
+    pub fn below(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            0
+        } else {
+            (self.next_u64() % bound as u64) as usize
+        }
+    }
+
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    /// Returns a value of any bit pattern, including NaN and the infinities.
+    pub fn any_f64(&mut self) -> f64 {
+        f64::from_bits(self.next_u64())
+    }
+
+    /// Returns a string of up to `max_len` characters, which may be empty and may contain
+    /// any character.
+    pub fn string(&mut self, max_len: usize) -> String {
+        let len = self.below(max_len + 1);
+        (0..len)
+            .map(|_| std::char::from_u32(self.below(0x11_0000) as u32).unwrap_or('\u{FFFD}'))
+            .collect()
+    }
+
+    /// Returns a list of up to `max_len` elements.
+    pub fn list<V, F: FnMut(&mut FuzzRng) -> V>(
+        &mut self,
+        max_len: usize,
+        mut element: F,
+    ) -> Vec<V> {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| element(self)).collect()
+    }
+}
+
+/// Generates random requests for the commands of a component. Implementations should fill
+/// each field of the request type with a random value of the field's type.
+pub trait FuzzCommand: ComponentCommands {
+    fn arbitrary_request(command: &CommandInfo, rng: &mut FuzzRng) -> Self::CommandRequest;
+}
+
+/// The point at which a fuzzed request caused a panic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FuzzStage {
+    Generate,
+    Serialize,
+    Deserialize,
+    Respond,
+    SerializeResponse,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuzzFailure {
+    /// The iteration of the run which failed.
+    pub iteration: usize,
+    pub command: &'static CommandInfo,
+    pub stage: FuzzStage,
+    /// The message of the panic.
+    pub message: String,
+}
+
+/// The outcome of a run of a `CommandFuzzer`.
+#[derive(Debug, Clone, Default)]
+pub struct FuzzReport {
+    pub iterations: usize,
+    /// The number of corrupted requests which failed to deserialize, as SpatialOS would
+    /// have rejected them.
+    pub rejected: usize,
+    /// The number of requests which the responder responded to.
+    pub responded: usize,
+    pub failures: Vec<FuzzFailure>,
+}
+
+/// Generates random requests for the commands of `T` and feeds them to a responder.
+pub struct CommandFuzzer<T: FuzzCommand> {
+    rng: FuzzRng,
+    corruption_probability: f64,
+    next_request_id: u32,
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: 'static + FuzzCommand> CommandFuzzer<T> {
+    pub fn new(seed: u64) -> CommandFuzzer<T> {
+        CommandFuzzer {
+            rng: FuzzRng::new(seed),
+            corruption_probability: 0.25,
+            next_request_id: 0,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Sets the probability, between 0 and 1, that the serialized bytes of a request are
+    /// corrupted before being deserialized. The default is 0.25.
+    pub fn set_corruption_probability(&mut self, probability: f64) {
+        self.corruption_probability = probability;
+    }
+
+    /// Generates `iterations` requests, each of which is queued on `entity` in the world's
+    /// `CommandRequests<T>` before `responder` is called. `responder` is expected to
+    /// respond through the storage, usually by running the system under test.
+    pub fn run<F: FnMut(&World)>(
+        &mut self,
+        world: &World,
+        entity: Entity,
+        iterations: usize,
+        mut responder: F,
+    ) -> FuzzReport {
+        let mut report = FuzzReport::default();
+        if T::COMMANDS.is_empty() {
+            return report;
+        }
+
+        for iteration in 0..iterations {
+            report.iterations += 1;
+            let command = &T::COMMANDS[self.rng.below(T::COMMANDS.len())];
+
+            if let Err((stage, message)) =
+                self.run_once(world, entity, command, &mut responder, &mut report)
+            {
+                report.failures.push(FuzzFailure {
+                    iteration,
+                    command,
+                    stage,
+                    message,
+                });
+            }
+        }
+
+        report
+    }
+
+    fn run_once<F: FnMut(&World)>(
+        &mut self,
+        world: &World,
+        entity: Entity,
+        command: &'static CommandInfo,
+        responder: &mut F,
+        report: &mut FuzzReport,
+    ) -> Result<(), (FuzzStage, String)> {
+        let rng = &mut self.rng;
+        let request = catch(FuzzStage::Generate, || T::arbitrary_request(command, rng))?;
+        let (command_index, mut bytes) = catch(FuzzStage::Serialize, || {
+            sdk::serialize_request::<T>(&request)
+        })?
+        .map_err(|e| (FuzzStage::Serialize, e))?;
+
+        if self.rng.next_f64() < self.corruption_probability {
+            corrupt(&mut self.rng, &mut bytes);
+        }
+
+        let request = match catch(FuzzStage::Deserialize, || {
+            sdk::deserialize_request::<T>(command_index, &bytes)
+        })? {
+            Ok(request) => request,
+            Err(_) => {
+                report.rejected += 1;
+                return Ok(());
+            }
+        };
+
+        self.next_request_id += 1;
+        let caller_worker_id = self.rng.string(16);
+        let caller_attribute_set = self.rng.list(3, |rng| rng.string(8));
+        {
+            let mut requests = CommandRequests::<T>::fetch(world);
+            if !requests.contains(entity) {
+                requests
+                    .insert(entity, CommandRequestsComp::default())
+                    .map_err(|e| (FuzzStage::Respond, e.to_string()))?;
+            }
+            requests.get_mut(entity).unwrap().on_request(
+                RequestId::new(self.next_request_id),
+                request,
+                caller_worker_id,
+                caller_attribute_set,
+            );
+        }
+
+        catch(FuzzStage::Respond, || responder(world))?;
+
+        let responses = match CommandRequests::<T>::fetch(world).get_mut(entity) {
+            Some(requests) => requests.take_responses(),
+            None => Vec::new(),
+        };
+        report.responded += responses.len();
+
+        for (_, response) in responses {
+            catch(FuzzStage::SerializeResponse, || {
+                sdk::serialize_response::<T>(&response)
+            })?
+            .map_err(|e| (FuzzStage::SerializeResponse, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Flips, truncates or extends the bytes, so that they are no longer what was serialized.
+fn corrupt(rng: &mut FuzzRng, bytes: &mut Vec<u8>) {
+    match rng.below(3) {
+        0 if !bytes.is_empty() => {
+            let index = rng.below(bytes.len());
+            bytes[index] ^= 1 << rng.below(8);
+        }
+        1 => {
+            let len = rng.below(bytes.len());
+            bytes.truncate(len);
+        }
+        _ => {
+            let extra = rng.list(8, |rng| rng.next_u64() as u8);
+            bytes.extend(extra);
+        }
+    }
+}
+
+fn catch<R, F: FnOnce() -> R>(stage: FuzzStage, f: F) -> Result<R, (FuzzStage, String)> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| (stage, panic_message(payload)))
+}
+
+fn panic_message(payload: Box<Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+#[test]
+fn command_fuzzer_should_record_panics() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    impl FuzzCommand for Position {
+        fn arbitrary_request(_: &CommandInfo, _: &mut FuzzRng) -> PositionCommandRequest {
+            PositionCommandRequest::UpdateCoords
+        }
+    }
+
+    let mut world = World::new();
+    CommandRequests::<Position>::setup(&mut world);
+    let entity = world.create_entity().build();
+
+    let mut fuzzer = CommandFuzzer::<Position>::new(1);
+    let report = fuzzer.run(&world, entity, 3, |_| {});
+
+    assert_eq!(3, report.iterations);
+    assert_eq!(3, report.failures.len());
+    assert_eq!(FuzzStage::Serialize, report.failures[0].stage);
+    assert_eq!("update_coords", report.failures[0].command.name);
+    assert!(report.failures[0].message.contains("not implemented"));
+}
+
+#[test]
+fn command_fuzzer_should_exercise_every_stage() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, Join, WorldExt};
+
+    impl FuzzCommand for Counter {
+        fn arbitrary_request(_: &CommandInfo, rng: &mut FuzzRng) -> CounterCommandRequest {
+            let amount = rng.below(20) as u32;
+            assert!(amount != 0, "generated a zero amount");
+            CounterCommandRequest::Increment(IncrementRequest { amount })
+        }
+    }
+
+    let respond = |world: &World| {
+        let mut requests = CommandRequests::<Counter>::fetch(world);
+        for requests in (&mut requests).join() {
+            requests.respond(|request, _, _| match request {
+                CounterCommandRequest::Increment(request) => {
+                    assert!(request.amount < 15, "amount too large");
+                    Some(CounterCommandResponse::Increment(IncrementResponse {
+                        total: request.amount,
+                    }))
+                }
+            });
+        }
+    };
+
+    let mut world = World::new();
+    CommandRequests::<Counter>::setup(&mut world);
+    let entity = world.create_entity().build();
+
+    let mut fuzzer = CommandFuzzer::<Counter>::new(7);
+    fuzzer.set_corruption_probability(0.0);
+    let report = fuzzer.run(&world, entity, 200, respond);
+    let generate_failures = failed_at(&report, FuzzStage::Generate);
+    let respond_failures = failed_at(&report, FuzzStage::Respond);
+
+    // Every valid request is serialized, deserialized and responded to, and each response
+    // is serialized.
+    assert_eq!(0, report.rejected);
+    assert!(report.responded > 0);
+    assert!(generate_failures > 0);
+    assert!(respond_failures > 0);
+    assert_eq!(200, report.responded + generate_failures + respond_failures);
+    assert_eq!("increment", report.failures[0].command.name);
+
+    // Corrupted requests which fail to deserialize are rejected rather than responded to.
+    let mut fuzzer = CommandFuzzer::<Counter>::new(7);
+    fuzzer.set_corruption_probability(1.0);
+    let report = fuzzer.run(&world, entity, 200, respond);
+    assert!(report.rejected > 0);
+    assert_eq!(0, failed_at(&report, FuzzStage::Deserialize));
+    assert_eq!(0, failed_at(&report, FuzzStage::SerializeResponse));
+}
+
+#[cfg(test)]
+fn failed_at(report: &FuzzReport, stage: FuzzStage) -> usize {
+    report
+        .failures
+        .iter()
+        .filter(|failure| failure.stage == stage)
+        .count()
+}
@@ -6,6 +6,10 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_mut)]
 
+use crate::command_info::*;
+use crate::fields::*;
+use crate::merge::*;
+use crate::shared_bytes::*;
 use spatialos_sdk::worker::component::*;
 use spatialos_sdk::worker::internal::schema::*;
 use std::collections::BTreeMap;
@@ -17,6 +21,22 @@ pub struct Coordinates {
     pub z: f64,
 }
 
+impl TypeConversion for Coordinates {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            x: input.field::<SchemaDouble>(1).get_or_default(),
+            y: input.field::<SchemaDouble>(2).get_or_default(),
+            z: input.field::<SchemaDouble>(3).get_or_default(),
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        output.field::<SchemaDouble>(1).add(input.x);
+        output.field::<SchemaDouble>(2).add(input.y);
+        output.field::<SchemaDouble>(3).add(input.z);
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Position {
     pub coords: Coordinates,
@@ -24,15 +44,18 @@ pub struct Position {
 
 impl TypeConversion for Position {
     fn from_type(input: &SchemaObject) -> Result<Self, String> {
-        unimplemented!()
+        Ok(Self {
+            coords: <Coordinates as TypeConversion>::from_type(&input.field::<SchemaObject>(1).get_or_default())?,
+        })
     }
     fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
-        unimplemented!()
+        <Coordinates as TypeConversion>::to_type(&&input.coords, &mut output.field::<SchemaObject>(1).add())?;
+        Ok(())
     }
 }
 impl ComponentData<Position> for Position {
     fn merge(&mut self, update: PositionUpdate) {
-        unimplemented!()
+        if let Some(value) = update.coords { self.coords = value; }
     }
 }
 
@@ -42,17 +65,48 @@ pub struct PositionUpdate {
 }
 impl TypeConversion for PositionUpdate {
     fn from_type(input: &SchemaObject) -> Result<Self, String> {
-        unimplemented!()
+        let mut output = Self {
+            coords: None,
+        };
+        let _field_coords = input.field::<SchemaObject>(1);
+        if _field_coords.count() > 0 {
+            let field = &_field_coords;
+            output.coords = Some(<Coordinates as TypeConversion>::from_type(&field.get_or_default())?);
+        }
+        Ok(output)
     }
     fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
-        unimplemented!()
+        if let Some(ref value) = input.coords {
+            <Coordinates as TypeConversion>::to_type(&value, &mut output.field::<SchemaObject>(1).add())?;
+        }
+        Ok(())
     }
 }
 impl ComponentUpdate<Position> for PositionUpdate {
     fn merge(&mut self, update: PositionUpdate) {
-        unimplemented!()
+        if update.coords.is_some() { self.coords = update.coords; }
     }
 }
+impl Position {
+    pub const COORDS_FIELD_ID: FieldId = 1;
+}
+impl ComponentFields for Position {
+    const FIELDS: &'static [FieldInfo] = &[
+        FieldInfo { id: 1, name: "coords" },
+    ];
+    fn update_sets_field(update: &PositionUpdate, field_id: FieldId) -> bool {
+        match field_id {
+            1 => update.coords.is_some(),
+            _ => false,
+        }
+    }
+}
+
+impl ComponentCommands for Position {
+    const COMMANDS: &'static [CommandInfo] = &[
+        CommandInfo { index: 1, name: "update_coords", request_type: "improbable.UpdateCoordsRequest", response_type: "improbable.UpdateCoordsResponse" },
+    ];
+}
 
 #[derive(Debug, Clone)]
 pub enum PositionCommandRequest {
@@ -72,11 +126,11 @@ impl Component for Position {
     const ID: ComponentId = 54;
 
     fn from_data(data: &SchemaComponentData) -> Result<Position, String> {
-        unimplemented!()
+        <Position as TypeConversion>::from_type(&data.fields())
     }
 
     fn from_update(update: &SchemaComponentUpdate) -> Result<PositionUpdate, String> {
-        unimplemented!()
+        <PositionUpdate as TypeConversion>::from_type(&update.fields())
     }
 
     fn from_request(command_index: CommandIndex, request: &SchemaCommandRequest) -> Result<PositionCommandRequest, String> {
@@ -88,11 +142,15 @@ impl Component for Position {
     }
 
     fn to_data(data: &Position) -> Result<SchemaComponentData, String> {
-        unimplemented!()
+        let mut serialized_data = SchemaComponentData::new();
+        <Position as TypeConversion>::to_type(data, &mut serialized_data.fields_mut())?;
+        Ok(serialized_data)
     }
 
     fn to_update(update: &PositionUpdate) -> Result<SchemaComponentUpdate, String> {
-        unimplemented!()
+        let mut serialized_update = SchemaComponentUpdate::new();
+        <PositionUpdate as TypeConversion>::to_type(update, &mut serialized_update.fields_mut())?;
+        Ok(serialized_update)
     }
 
     fn to_request(request: &PositionCommandRequest) -> Result<SchemaCommandRequest, String> {
@@ -111,3 +169,742 @@ impl Component for Position {
         unimplemented!()
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct Inventory {
+    pub items: Vec<String>,
+    pub slots: BTreeMap<u32, String>,
+}
+
+impl TypeConversion for Inventory {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        unimplemented!()
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        unimplemented!()
+    }
+}
+impl ComponentData<Inventory> for Inventory {
+    fn merge(&mut self, update: InventoryUpdate) {
+        if let Some(value) = update.items { self.items = value; }
+        if let Some(value) = update.slots { self.slots = value; }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InventoryUpdate {
+    pub items: Option<Vec<String>>,
+    pub slots: Option<BTreeMap<u32, String>>,
+}
+impl InventoryUpdate {
+    pub const ITEMS_MERGE_STRATEGY: MergeStrategy = MergeStrategy::Append;
+    pub const SLOTS_MERGE_STRATEGY: MergeStrategy = MergeStrategy::Replace;
+}
+impl TypeConversion for InventoryUpdate {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        unimplemented!()
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        unimplemented!()
+    }
+}
+impl ComponentUpdate<Inventory> for InventoryUpdate {
+    fn merge(&mut self, update: InventoryUpdate) {
+        merge_list(Self::ITEMS_MERGE_STRATEGY, &mut self.items, update.items);
+        merge_map(Self::SLOTS_MERGE_STRATEGY, &mut self.slots, update.slots);
+    }
+}
+impl Inventory {
+    pub const ITEMS_FIELD_ID: FieldId = 1;
+    pub const SLOTS_FIELD_ID: FieldId = 2;
+}
+impl ComponentFields for Inventory {
+    const FIELDS: &'static [FieldInfo] = &[
+        FieldInfo { id: 1, name: "items" },
+        FieldInfo { id: 2, name: "slots" },
+    ];
+    fn update_sets_field(update: &InventoryUpdate, field_id: FieldId) -> bool {
+        match field_id {
+            1 => update.items.is_some(),
+            2 => update.slots.is_some(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum InventoryCommandRequest {
+}
+
+#[derive(Debug, Clone)]
+pub enum InventoryCommandResponse {
+}
+
+impl Component for Inventory {
+    type Update = InventoryUpdate;
+    type CommandRequest = InventoryCommandRequest;
+    type CommandResponse = InventoryCommandResponse;
+
+    const ID: ComponentId = 1100;
+
+    fn from_data(data: &SchemaComponentData) -> Result<Inventory, String> {
+        <Inventory as TypeConversion>::from_type(&data.fields())
+    }
+
+    fn from_update(update: &SchemaComponentUpdate) -> Result<InventoryUpdate, String> {
+        <InventoryUpdate as TypeConversion>::from_type(&update.fields())
+    }
+
+    fn from_request(command_index: CommandIndex, request: &SchemaCommandRequest) -> Result<InventoryCommandRequest, String> {
+        match command_index {
+            _ => Err(format!("Attempted to deserialize an unrecognised command request with index {} in component Inventory.", command_index))
+        }
+    }
+
+    fn from_response(command_index: CommandIndex, response: &SchemaCommandResponse) -> Result<InventoryCommandResponse, String> {
+        match command_index {
+            _ => Err(format!("Attempted to deserialize an unrecognised command response with index {} in component Inventory.", command_index))
+        }
+    }
+
+    fn to_data(data: &Inventory) -> Result<SchemaComponentData, String> {
+        let mut serialized_data = SchemaComponentData::new();
+        <Inventory as TypeConversion>::to_type(data, &mut serialized_data.fields_mut())?;
+        Ok(serialized_data)
+    }
+
+    fn to_update(update: &InventoryUpdate) -> Result<SchemaComponentUpdate, String> {
+        let mut serialized_update = SchemaComponentUpdate::new();
+        <InventoryUpdate as TypeConversion>::to_type(update, &mut serialized_update.fields_mut())?;
+        Ok(serialized_update)
+    }
+
+    fn to_request(request: &InventoryCommandRequest) -> Result<SchemaCommandRequest, String> {
+        match request {
+            _ => unreachable!()
+        }
+    }
+
+    fn to_response(response: &InventoryCommandResponse) -> Result<SchemaCommandResponse, String> {
+        match response {
+            _ => unreachable!()
+        }
+    }
+
+    fn get_request_command_index(request: &InventoryCommandRequest) -> u32 {
+        match request {
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_response_command_index(response: &InventoryCommandResponse) -> u32 {
+        match response {
+            _ => unreachable!(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    pub entity_id_constraint: Option<i64>,
+    pub and_constraint: Vec<Constraint>,
+    pub or_constraint: Vec<Constraint>,
+}
+impl TypeConversion for Constraint {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            entity_id_constraint: if let Some(data) = input.field::<SchemaInt64>(1).get() { Some(data) } else { None },
+            and_constraint: { let size = input.field::<SchemaObject>(2).count(); let mut l = Vec::with_capacity(size); for i in 0..size { l.push(<Constraint as TypeConversion>::from_type(&input.field::<SchemaObject>(2).index(i))?); }; l },
+            or_constraint: { let size = input.field::<SchemaObject>(3).count(); let mut l = Vec::with_capacity(size); for i in 0..size { l.push(<Constraint as TypeConversion>::from_type(&input.field::<SchemaObject>(3).index(i))?); }; l },
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        if let Some(data) = input.entity_id_constraint { output.field::<SchemaInt64>(1).add(data); };
+        for element in (&input.and_constraint).iter() { <Constraint as TypeConversion>::to_type(&element, &mut output.field::<SchemaObject>(2).add())?; };
+        for element in (&input.or_constraint).iter() { <Constraint as TypeConversion>::to_type(&element, &mut output.field::<SchemaObject>(3).add())?; };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub offset: Option<Coordinates>,
+    pub payload: Vec<u8>,
+}
+impl TypeConversion for Attachment {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            offset: if let Some(data) = input.field::<SchemaObject>(1).get() { Some(<Coordinates as TypeConversion>::from_type(&data)?) } else { None },
+            payload: input.field::<SchemaBytes>(2).get_or_default(),
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        if let Some(ref data) = &input.offset { <Coordinates as TypeConversion>::to_type(&data, &mut output.field::<SchemaObject>(1).add())?; };
+        output.field::<SchemaBytes>(2).add(&&input.payload);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Loadout {
+    pub primary: Option<Attachment>,
+}
+impl TypeConversion for Loadout {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            primary: if let Some(data) = input.field::<SchemaObject>(1).get() { Some(<Attachment as TypeConversion>::from_type(&data)?) } else { None },
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        if let Some(ref data) = &input.primary { <Attachment as TypeConversion>::to_type(&data, &mut output.field::<SchemaObject>(1).add())?; };
+        Ok(())
+    }
+}
+
+/// Exercises the schema shapes which are awkward to store and dispatch: a recursive
+/// type, nested options of objects, maps with struct values and bytes.
+#[derive(Debug, Clone)]
+pub struct SchemaShapes {
+    pub constraint: Constraint,
+    pub loadout: Loadout,
+    pub anchors: BTreeMap<u32, Coordinates>,
+    pub blob: Vec<u8>,
+}
+impl TypeConversion for SchemaShapes {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            constraint: <Constraint as TypeConversion>::from_type(&input.field::<SchemaObject>(1).get_or_default())?,
+            loadout: <Loadout as TypeConversion>::from_type(&input.field::<SchemaObject>(2).get_or_default())?,
+            anchors: { let size = input.field::<SchemaObject>(3).count(); let mut m = BTreeMap::new(); for i in 0..size { let kv = input.field::<SchemaObject>(3).index(i); m.insert(kv.field::<SchemaUint32>(1).get_or_default(), <Coordinates as TypeConversion>::from_type(&kv.field::<SchemaObject>(2).get_or_default())?); }; m },
+            blob: input.field::<SchemaBytes>(4).get_or_default(),
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        <Constraint as TypeConversion>::to_type(&&input.constraint, &mut output.field::<SchemaObject>(1).add())?;
+        <Loadout as TypeConversion>::to_type(&&input.loadout, &mut output.field::<SchemaObject>(2).add())?;
+        for (k, v) in &input.anchors { let object = output.field::<SchemaObject>(3).add(); object.field::<SchemaUint32>(1).add(*k); <Coordinates as TypeConversion>::to_type(&v, &mut object.field::<SchemaObject>(2).add())?; };
+        output.field::<SchemaBytes>(4).add(&&input.blob);
+        Ok(())
+    }
+}
+impl ComponentData<SchemaShapes> for SchemaShapes {
+    fn merge(&mut self, update: SchemaShapesUpdate) {
+        if let Some(value) = update.constraint { self.constraint = value; }
+        if let Some(value) = update.loadout { self.loadout = value; }
+        if let Some(value) = update.anchors { self.anchors = value; }
+        if let Some(value) = update.blob { self.blob = value; }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SchemaShapesUpdate {
+    pub constraint: Option<Constraint>,
+    pub loadout: Option<Loadout>,
+    pub anchors: Option<BTreeMap<u32, Coordinates>>,
+    pub blob: Option<Vec<u8>>,
+}
+impl TypeConversion for SchemaShapesUpdate {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        let mut output = Self {
+            constraint: None,
+            loadout: None,
+            anchors: None,
+            blob: None,
+        };
+        let _field_constraint = input.field::<SchemaObject>(1);
+        if _field_constraint.count() > 0 {
+            let field = &_field_constraint;
+            output.constraint = Some(<Constraint as TypeConversion>::from_type(&field.get_or_default())?);
+        }
+        let _field_loadout = input.field::<SchemaObject>(2);
+        if _field_loadout.count() > 0 {
+            let field = &_field_loadout;
+            output.loadout = Some(<Loadout as TypeConversion>::from_type(&field.get_or_default())?);
+        }
+        let _field_anchors = input.field::<SchemaObject>(3);
+        if _field_anchors.count() > 0 {
+            let field = &_field_anchors;
+            output.anchors = Some({ let size = field.count(); let mut m = BTreeMap::new(); for i in 0..size { let kv = field.index(i); m.insert(kv.field::<SchemaUint32>(1).get_or_default(), <Coordinates as TypeConversion>::from_type(&kv.field::<SchemaObject>(2).get_or_default())?); }; m });
+        }
+        let _field_blob = input.field::<SchemaBytes>(4);
+        if _field_blob.count() > 0 {
+            let field = &_field_blob;
+            output.blob = Some(field.get_or_default());
+        }
+        Ok(output)
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        if let Some(ref value) = input.constraint {
+            <Constraint as TypeConversion>::to_type(&value, &mut output.field::<SchemaObject>(1).add())?;
+        }
+        if let Some(ref value) = input.loadout {
+            <Loadout as TypeConversion>::to_type(&value, &mut output.field::<SchemaObject>(2).add())?;
+        }
+        if let Some(ref value) = input.anchors {
+            for (k, v) in value { let object = output.field::<SchemaObject>(3).add(); object.field::<SchemaUint32>(1).add(*k); <Coordinates as TypeConversion>::to_type(&v, &mut object.field::<SchemaObject>(2).add())?; };
+        }
+        if let Some(ref value) = input.blob {
+            output.field::<SchemaBytes>(4).add(value);
+        }
+        Ok(())
+    }
+}
+impl ComponentUpdate<SchemaShapes> for SchemaShapesUpdate {
+    fn merge(&mut self, update: SchemaShapesUpdate) {
+        if update.constraint.is_some() { self.constraint = update.constraint; }
+        if update.loadout.is_some() { self.loadout = update.loadout; }
+        if update.anchors.is_some() { self.anchors = update.anchors; }
+        if update.blob.is_some() { self.blob = update.blob; }
+    }
+}
+impl SchemaShapes {
+    pub const CONSTRAINT_FIELD_ID: FieldId = 1;
+    pub const LOADOUT_FIELD_ID: FieldId = 2;
+    pub const ANCHORS_FIELD_ID: FieldId = 3;
+    pub const BLOB_FIELD_ID: FieldId = 4;
+}
+impl ComponentFields for SchemaShapes {
+    const FIELDS: &'static [FieldInfo] = &[
+        FieldInfo { id: 1, name: "constraint" },
+        FieldInfo { id: 2, name: "loadout" },
+        FieldInfo { id: 3, name: "anchors" },
+        FieldInfo { id: 4, name: "blob" },
+    ];
+    fn update_sets_field(update: &SchemaShapesUpdate, field_id: FieldId) -> bool {
+        match field_id {
+            1 => update.constraint.is_some(),
+            2 => update.loadout.is_some(),
+            3 => update.anchors.is_some(),
+            4 => update.blob.is_some(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SchemaShapesCommandRequest {
+}
+
+#[derive(Debug, Clone)]
+pub enum SchemaShapesCommandResponse {
+}
+
+impl Component for SchemaShapes {
+    type Update = SchemaShapesUpdate;
+    type CommandRequest = SchemaShapesCommandRequest;
+    type CommandResponse = SchemaShapesCommandResponse;
+
+    const ID: ComponentId = 1101;
+
+    fn from_data(data: &SchemaComponentData) -> Result<SchemaShapes, String> {
+        <SchemaShapes as TypeConversion>::from_type(&data.fields())
+    }
+
+    fn from_update(update: &SchemaComponentUpdate) -> Result<SchemaShapesUpdate, String> {
+        <SchemaShapesUpdate as TypeConversion>::from_type(&update.fields())
+    }
+
+    fn from_request(command_index: CommandIndex, request: &SchemaCommandRequest) -> Result<SchemaShapesCommandRequest, String> {
+        match command_index {
+            _ => Err(format!("Attempted to deserialize an unrecognised command request with index {} in component SchemaShapes.", command_index))
+        }
+    }
+
+    fn from_response(command_index: CommandIndex, response: &SchemaCommandResponse) -> Result<SchemaShapesCommandResponse, String> {
+        match command_index {
+            _ => Err(format!("Attempted to deserialize an unrecognised command response with index {} in component SchemaShapes.", command_index))
+        }
+    }
+
+    fn to_data(data: &SchemaShapes) -> Result<SchemaComponentData, String> {
+        let mut serialized_data = SchemaComponentData::new();
+        <SchemaShapes as TypeConversion>::to_type(data, &mut serialized_data.fields_mut())?;
+        Ok(serialized_data)
+    }
+
+    fn to_update(update: &SchemaShapesUpdate) -> Result<SchemaComponentUpdate, String> {
+        let mut serialized_update = SchemaComponentUpdate::new();
+        <SchemaShapesUpdate as TypeConversion>::to_type(update, &mut serialized_update.fields_mut())?;
+        Ok(serialized_update)
+    }
+
+    fn to_request(request: &SchemaShapesCommandRequest) -> Result<SchemaCommandRequest, String> {
+        match request {
+            _ => unreachable!()
+        }
+    }
+
+    fn to_response(response: &SchemaShapesCommandResponse) -> Result<SchemaCommandResponse, String> {
+        match response {
+            _ => unreachable!()
+        }
+    }
+
+    fn get_request_command_index(request: &SchemaShapesCommandRequest) -> u32 {
+        match request {
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_response_command_index(response: &SchemaShapesCommandResponse) -> u32 {
+        match response {
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Uses `SharedBytes` for its payload, so that it is shared rather than copied between the
+/// op, storage and any snapshots.
+#[derive(Debug, Clone)]
+pub struct Blob {
+    pub data: SharedBytes,
+}
+impl TypeConversion for Blob {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            data: SharedBytes::from(input.field::<SchemaBytes>(1).get_or_default()),
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        output.field::<SchemaBytes>(1).add(&input.data);
+        Ok(())
+    }
+}
+impl ComponentData<Blob> for Blob {
+    fn merge(&mut self, update: BlobUpdate) {
+        if let Some(value) = update.data { self.data = value; }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlobUpdate {
+    pub data: Option<SharedBytes>,
+}
+impl TypeConversion for BlobUpdate {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        let mut output = Self {
+            data: None,
+        };
+        let _field_data = input.field::<SchemaBytes>(1);
+        if _field_data.count() > 0 {
+            let field = &_field_data;
+            output.data = Some(SharedBytes::from(field.get_or_default()));
+        }
+        Ok(output)
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        if let Some(ref value) = input.data {
+            output.field::<SchemaBytes>(1).add(value);
+        }
+        Ok(())
+    }
+}
+impl ComponentUpdate<Blob> for BlobUpdate {
+    fn merge(&mut self, update: BlobUpdate) {
+        if update.data.is_some() { self.data = update.data; }
+    }
+}
+impl Blob {
+    pub const DATA_FIELD_ID: FieldId = 1;
+}
+impl ComponentFields for Blob {
+    const FIELDS: &'static [FieldInfo] = &[
+        FieldInfo { id: 1, name: "data" },
+    ];
+    fn update_sets_field(update: &BlobUpdate, field_id: FieldId) -> bool {
+        match field_id {
+            1 => update.data.is_some(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BlobCommandRequest {
+}
+
+#[derive(Debug, Clone)]
+pub enum BlobCommandResponse {
+}
+
+impl Component for Blob {
+    type Update = BlobUpdate;
+    type CommandRequest = BlobCommandRequest;
+    type CommandResponse = BlobCommandResponse;
+
+    const ID: ComponentId = 1102;
+
+    fn from_data(data: &SchemaComponentData) -> Result<Blob, String> {
+        <Blob as TypeConversion>::from_type(&data.fields())
+    }
+
+    fn from_update(update: &SchemaComponentUpdate) -> Result<BlobUpdate, String> {
+        <BlobUpdate as TypeConversion>::from_type(&update.fields())
+    }
+
+    fn from_request(command_index: CommandIndex, request: &SchemaCommandRequest) -> Result<BlobCommandRequest, String> {
+        match command_index {
+            _ => Err(format!("Attempted to deserialize an unrecognised command request with index {} in component Blob.", command_index))
+        }
+    }
+
+    fn from_response(command_index: CommandIndex, response: &SchemaCommandResponse) -> Result<BlobCommandResponse, String> {
+        match command_index {
+            _ => Err(format!("Attempted to deserialize an unrecognised command response with index {} in component Blob.", command_index))
+        }
+    }
+
+    fn to_data(data: &Blob) -> Result<SchemaComponentData, String> {
+        let mut serialized_data = SchemaComponentData::new();
+        <Blob as TypeConversion>::to_type(data, &mut serialized_data.fields_mut())?;
+        Ok(serialized_data)
+    }
+
+    fn to_update(update: &BlobUpdate) -> Result<SchemaComponentUpdate, String> {
+        let mut serialized_update = SchemaComponentUpdate::new();
+        <BlobUpdate as TypeConversion>::to_type(update, &mut serialized_update.fields_mut())?;
+        Ok(serialized_update)
+    }
+
+    fn to_request(request: &BlobCommandRequest) -> Result<SchemaCommandRequest, String> {
+        match request {
+            _ => unreachable!()
+        }
+    }
+
+    fn to_response(response: &BlobCommandResponse) -> Result<SchemaCommandResponse, String> {
+        match response {
+            _ => unreachable!()
+        }
+    }
+
+    fn get_request_command_index(request: &BlobCommandRequest) -> u32 {
+        match request {
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_response_command_index(response: &BlobCommandResponse) -> u32 {
+        match response {
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Counter {
+    pub total: u32,
+    pub moves: Vec<Coordinates>,
+}
+impl TypeConversion for Counter {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            total: input.field::<SchemaUint32>(1).get_or_default(),
+            moves: { let size = input.field::<SchemaObject>(2).count(); let mut l = Vec::with_capacity(size); for i in 0..size { l.push(<Coordinates as TypeConversion>::from_type(&input.field::<SchemaObject>(2).index(i))?); }; l },
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        output.field::<SchemaUint32>(1).add(input.total);
+        for element in (&input.moves).iter() { <Coordinates as TypeConversion>::to_type(&element, &mut output.field::<SchemaObject>(2).add())?; };
+        Ok(())
+    }
+}
+impl ComponentData<Counter> for Counter {
+    fn merge(&mut self, update: CounterUpdate) {
+        if let Some(value) = update.total { self.total = value; }
+        if let Some(value) = update.moves { self.moves = value; }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CounterUpdate {
+    pub total: Option<u32>,
+    pub moves: Option<Vec<Coordinates>>,
+}
+impl CounterUpdate {
+    pub const MOVES_MERGE_STRATEGY: MergeStrategy = MergeStrategy::Append;
+}
+impl TypeConversion for CounterUpdate {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        let mut output = Self {
+            total: None,
+            moves: None,
+        };
+        let _field_total = input.field::<SchemaUint32>(1);
+        if _field_total.count() > 0 {
+            let field = &_field_total;
+            output.total = Some(field.get_or_default());
+        }
+        let _field_moves = input.field::<SchemaObject>(2);
+        if _field_moves.count() > 0 {
+            let field = &_field_moves;
+            output.moves = Some({ let size = field.count(); let mut l = Vec::with_capacity(size); for i in 0..size { l.push(<Coordinates as TypeConversion>::from_type(&field.index(i))?); }; l });
+        }
+        Ok(output)
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        if let Some(value) = input.total {
+            output.field::<SchemaUint32>(1).add(value);
+        }
+        if let Some(ref value) = input.moves {
+            for element in value.iter() { <Coordinates as TypeConversion>::to_type(&element, &mut output.field::<SchemaObject>(2).add())?; };
+        }
+        Ok(())
+    }
+}
+impl ComponentUpdate<Counter> for CounterUpdate {
+    fn merge(&mut self, update: CounterUpdate) {
+        if update.total.is_some() { self.total = update.total; }
+        merge_list(Self::MOVES_MERGE_STRATEGY, &mut self.moves, update.moves);
+    }
+}
+impl Counter {
+    pub const TOTAL_FIELD_ID: FieldId = 1;
+    pub const MOVES_FIELD_ID: FieldId = 2;
+}
+impl ComponentFields for Counter {
+    const FIELDS: &'static [FieldInfo] = &[
+        FieldInfo { id: 1, name: "total" },
+        FieldInfo { id: 2, name: "moves" },
+    ];
+    fn update_sets_field(update: &CounterUpdate, field_id: FieldId) -> bool {
+        match field_id {
+            1 => update.total.is_some(),
+            2 => update.moves.is_some(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IncrementRequest {
+    pub amount: u32,
+}
+impl TypeConversion for IncrementRequest {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            amount: input.field::<SchemaUint32>(1).get_or_default(),
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        output.field::<SchemaUint32>(1).add(input.amount);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IncrementResponse {
+    pub total: u32,
+}
+impl TypeConversion for IncrementResponse {
+    fn from_type(input: &SchemaObject) -> Result<Self, String> {
+        Ok(Self {
+            total: input.field::<SchemaUint32>(1).get_or_default(),
+        })
+    }
+    fn to_type(input: &Self, output: &mut SchemaObject) -> Result<(), String> {
+        output.field::<SchemaUint32>(1).add(input.total);
+        Ok(())
+    }
+}
+
+impl ComponentCommands for Counter {
+    const COMMANDS: &'static [CommandInfo] = &[
+        CommandInfo { index: 1, name: "increment", request_type: "test.IncrementRequest", response_type: "test.IncrementResponse" },
+    ];
+}
+
+#[derive(Debug, Clone)]
+pub enum CounterCommandRequest {
+    Increment(IncrementRequest),
+}
+
+#[derive(Debug, Clone)]
+pub enum CounterCommandResponse {
+    Increment(IncrementResponse),
+}
+
+impl Component for Counter {
+    type Update = CounterUpdate;
+    type CommandRequest = CounterCommandRequest;
+    type CommandResponse = CounterCommandResponse;
+
+    const ID: ComponentId = 1103;
+
+    fn from_data(data: &SchemaComponentData) -> Result<Counter, String> {
+        <Counter as TypeConversion>::from_type(&data.fields())
+    }
+
+    fn from_update(update: &SchemaComponentUpdate) -> Result<CounterUpdate, String> {
+        <CounterUpdate as TypeConversion>::from_type(&update.fields())
+    }
+
+    fn from_request(command_index: CommandIndex, request: &SchemaCommandRequest) -> Result<CounterCommandRequest, String> {
+        match command_index {
+            1 => {
+                let result = <IncrementRequest as TypeConversion>::from_type(&request.object());
+                result.and_then(|deserialized| Ok(CounterCommandRequest::Increment(deserialized)))
+            },
+            _ => Err(format!("Attempted to deserialize an unrecognised command request with index {} in component Counter.", command_index))
+        }
+    }
+
+    fn from_response(command_index: CommandIndex, response: &SchemaCommandResponse) -> Result<CounterCommandResponse, String> {
+        match command_index {
+            1 => {
+                let result = <IncrementResponse as TypeConversion>::from_type(&response.object());
+                result.and_then(|deserialized| Ok(CounterCommandResponse::Increment(deserialized)))
+            },
+            _ => Err(format!("Attempted to deserialize an unrecognised command response with index {} in component Counter.", command_index))
+        }
+    }
+
+    fn to_data(data: &Counter) -> Result<SchemaComponentData, String> {
+        let mut serialized_data = SchemaComponentData::new();
+        <Counter as TypeConversion>::to_type(data, &mut serialized_data.fields_mut())?;
+        Ok(serialized_data)
+    }
+
+    fn to_update(update: &CounterUpdate) -> Result<SchemaComponentUpdate, String> {
+        let mut serialized_update = SchemaComponentUpdate::new();
+        <CounterUpdate as TypeConversion>::to_type(update, &mut serialized_update.fields_mut())?;
+        Ok(serialized_update)
+    }
+
+    fn to_request(request: &CounterCommandRequest) -> Result<SchemaCommandRequest, String> {
+        let mut serialized_request = SchemaCommandRequest::new();
+        match request {
+            CounterCommandRequest::Increment(ref data) => {
+                <IncrementRequest as TypeConversion>::to_type(data, &mut serialized_request.object_mut())?;
+            },
+            _ => unreachable!()
+        }
+        Ok(serialized_request)
+    }
+
+    fn to_response(response: &CounterCommandResponse) -> Result<SchemaCommandResponse, String> {
+        let mut serialized_response = SchemaCommandResponse::new();
+        match response {
+            CounterCommandResponse::Increment(ref data) => {
+                <IncrementResponse as TypeConversion>::to_type(data, &mut serialized_response.object_mut())?;
+            },
+            _ => unreachable!()
+        }
+        Ok(serialized_response)
+    }
+
+    fn get_request_command_index(request: &CounterCommandRequest) -> u32 {
+        match request {
+            CounterCommandRequest::Increment(_) => 1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_response_command_index(response: &CounterCommandResponse) -> u32 {
+        match response {
+            CounterCommandResponse::Increment(_) => 1,
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Diagnostics of the traffic flowing through the connection, so workers can shed load
+//! before the bridge disconnects them.
+use crate::logging::{self, LogKind, LogLevel};
+use specs::prelude::World;
+use specs::shrev::EventChannel;
+
+/// An event emitted when the health of the connection changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionHealthEvent {
+    /// More ops were received in a single frame than the configured threshold.
+    IncomingBacklog(usize),
+    /// More messages were sent in a single frame than the configured threshold.
+    OutgoingBacklog(usize),
+    /// Traffic has returned below both thresholds after a backlog.
+    Recovered,
+    /// The connection is no longer connected.
+    Disconnected,
+}
+
+/// An event channel which receives `ConnectionHealthEvent`s whenever a threshold is crossed.
+pub type ConnectionHealthEvents = EventChannel<ConnectionHealthEvent>;
+
+/// A resource describing the traffic through the connection during the last frame.
+///
+/// The number of ops received is recorded by the `SpatialReaderSystem`, and the number of
+/// messages sent, including component updates, command requests and command responses, is
+/// recorded by the `SpatialWriterSystem`, which then checks both against the thresholds.
+/// Messages sent earlier in the frame by `flush` count towards the frame's total.
+/// Events and warnings are only emitted when a threshold is first crossed.
+///
+/// The SDK does not expose the length of its internal queues, so a sustained high volume
+/// of traffic is used as a proxy for a backlog building up.
+pub struct ConnectionHealth {
+    connected: bool,
+    ops_received: usize,
+    messages_sent: usize,
+    flushed: usize,
+    max_ops_received: usize,
+    max_messages_sent: usize,
+    backlogged: bool,
+}
+
+impl ConnectionHealth {
+    pub fn new(max_ops_received: usize, max_messages_sent: usize) -> ConnectionHealth {
+        ConnectionHealth {
+            connected: true,
+            ops_received: 0,
+            messages_sent: 0,
+            flushed: 0,
+            max_ops_received,
+            max_messages_sent,
+            backlogged: false,
+        }
+    }
+
+    /// Sets the number of ops received and messages sent in a single frame above which
+    /// the connection is considered backlogged.
+    pub fn set_thresholds(&mut self, max_ops_received: usize, max_messages_sent: usize) {
+        self.max_ops_received = max_ops_received;
+        self.max_messages_sent = max_messages_sent;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The number of ops received during the last frame.
+    pub fn ops_received(&self) -> usize {
+        self.ops_received
+    }
+
+    /// The number of messages sent during the last frame.
+    pub fn messages_sent(&self) -> usize {
+        self.messages_sent
+    }
+
+    /// Whether either threshold was exceeded during the last frame.
+    pub fn is_backlogged(&self) -> bool {
+        self.backlogged
+    }
+
+    pub(crate) fn record_ops_received(&mut self, ops_received: usize) {
+        self.ops_received = ops_received;
+    }
+
+    pub(crate) fn record_flushed(&mut self, messages_sent: usize) {
+        self.flushed += messages_sent;
+    }
+
+    pub(crate) fn update(res: &World, connected: bool, messages_sent: usize) {
+        let events = {
+            let mut health = res.fetch_mut::<ConnectionHealth>();
+            health.messages_sent = messages_sent + health.flushed;
+            health.flushed = 0;
+
+            let mut events = Vec::new();
+
+            if health.connected && !connected {
+                events.push(ConnectionHealthEvent::Disconnected);
+            }
+            health.connected = connected;
+
+            let incoming = health.ops_received > health.max_ops_received;
+            let outgoing = health.messages_sent > health.max_messages_sent;
+
+            if !health.backlogged {
+                if incoming {
+                    events.push(ConnectionHealthEvent::IncomingBacklog(health.ops_received));
+                }
+                if outgoing {
+                    events.push(ConnectionHealthEvent::OutgoingBacklog(health.messages_sent));
+                }
+            } else if !incoming && !outgoing {
+                events.push(ConnectionHealthEvent::Recovered);
+            }
+            health.backlogged = incoming || outgoing;
+
+            events
+        };
+
+        for event in &events {
+            if *event != ConnectionHealthEvent::Recovered {
+                logging::log(
+                    res,
+                    LogLevel::Warn,
+                    LogKind::ConnectionHealth,
+                    &format!("Connection health: {:?}", event),
+                );
+            }
+        }
+
+        if res.has_value::<ConnectionHealthEvents>() {
+            res.fetch_mut::<ConnectionHealthEvents>().iter_write(events);
+        }
+    }
+}
+
+impl Default for ConnectionHealth {
+    fn default() -> Self {
+        ConnectionHealth::new(10_000, 10_000)
+    }
+}
+
+#[test]
+fn health_should_emit_events_when_thresholds_are_crossed() {
+    use specs::prelude::{World, WorldExt};
+
+    let mut world = World::new();
+    world.insert(ConnectionHealth::new(10, 10));
+    world.insert(ConnectionHealthEvents::new());
+    world.insert(logging::SpatialLogger::new(
+        |_: LogLevel, _: LogKind, _: &str| {},
+    ));
+
+    let mut reader_id = world
+        .fetch_mut::<ConnectionHealthEvents>()
+        .register_reader();
+
+    let mut frame = |ops_received, messages_sent, connected| {
+        world
+            .fetch_mut::<ConnectionHealth>()
+            .record_ops_received(ops_received);
+        ConnectionHealth::update(&world, connected, messages_sent);
+
+        world
+            .fetch::<ConnectionHealthEvents>()
+            .read(&mut reader_id)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    assert!(frame(5, 5, true).is_empty());
+    assert_eq!(
+        vec![
+            ConnectionHealthEvent::IncomingBacklog(20),
+            ConnectionHealthEvent::OutgoingBacklog(11)
+        ],
+        frame(20, 11, true)
+    );
+    assert!(frame(30, 0, true).is_empty());
+    assert_eq!(vec![ConnectionHealthEvent::Recovered], frame(0, 0, true));
+    assert_eq!(
+        vec![ConnectionHealthEvent::Disconnected],
+        frame(0, 0, false)
+    );
+}
+
+#[test]
+fn health_should_count_flushed_messages_in_the_frame() {
+    use specs::prelude::{World, WorldExt};
+
+    let mut world = World::new();
+    world.insert(ConnectionHealth::new(10, 10));
+
+    world.fetch_mut::<ConnectionHealth>().record_flushed(4);
+    world.fetch_mut::<ConnectionHealth>().record_flushed(3);
+    ConnectionHealth::update(&world, true, 2);
+    assert_eq!(9, world.fetch::<ConnectionHealth>().messages_sent());
+
+    ConnectionHealth::update(&world, true, 2);
+    assert_eq!(2, world.fetch::<ConnectionHealth>().messages_sent());
+}
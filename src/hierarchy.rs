@@ -0,0 +1,284 @@
+//! Integration with `specs-hierarchy`, for schemas which store the `EntityId` of a
+//! parent entity in a component.
+//!
+//! Add a `SpatialParentSystem` for each component which contains a parent reference,
+//! followed by a `SpatialHierarchySystem`:
+//!
+//! ```ignore
+//! let mut dispatcher = DispatcherBuilder::new()
+//!     .with(SpatialReaderSystem, "reader", &[])
+//!     .with_barrier()
+//!     .with(
+//!         SpatialParentSystem::new(|weapon: &Weapon| weapon.owner.map(EntityId::new)),
+//!         "weapon_parent",
+//!         &[],
+//!     )
+//!     .with(SpatialHierarchySystem::new(&mut world), "hierarchy", &["weapon_parent"])
+//!     ...
+//! ```
+use crate::entities::{EntityId, EntityIds};
+use crate::storage::SpatialReadStorage;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{
+    Component, DenseVecStorage, Entities, Entity, FlaggedStorage, Join, System, WriteStorage,
+};
+use specs_hierarchy::{Hierarchy, HierarchySystem, Parent};
+use std::marker::PhantomData;
+
+/// A component which refers to the parent of an entity, resolved from an `EntityId`
+/// stored in a SpatialOS component.
+///
+/// This is maintained by `SpatialParentSystem` and is present whenever the referenced
+/// parent entity is checked out by this worker.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpatialParent {
+    entity: Entity,
+    source: ComponentId,
+}
+
+impl SpatialParent {
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    /// The ID of the component which the parent reference was read from.
+    pub fn source(&self) -> ComponentId {
+        self.source
+    }
+}
+
+impl Component for SpatialParent {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+impl Parent for SpatialParent {
+    fn parent_entity(&self) -> Entity {
+        self.entity
+    }
+}
+
+/// The hierarchy resource built from `SpatialParent` components.
+pub type SpatialHierarchy = Hierarchy<SpatialParent>;
+
+/// The system which maintains the `SpatialHierarchy` resource.
+pub type SpatialHierarchySystem = HierarchySystem<SpatialParent>;
+
+/// A system which keeps the `SpatialParent` component of every entity with a `T` component
+/// in sync with the parent `EntityId` returned by the extractor.
+///
+/// The parent is updated when the referenced `EntityId` changes, when the parent entity is
+/// checked out or removed, and when the `T` component is removed.
+///
+/// Only parents read from `T` are changed or removed, so several systems can maintain the
+/// parents of an entity with more than one such component. The first parent found is kept
+/// until its reference is cleared, after which the next system to run sets its own.
+pub struct SpatialParentSystem<T, F> {
+    extractor: F,
+    _phantom: PhantomData<T>,
+}
+
+impl<T, F> SpatialParentSystem<T, F>
+where
+    T: 'static + WorkerComponent,
+    F: Fn(&T) -> Option<EntityId>,
+{
+    pub fn new(extractor: F) -> SpatialParentSystem<T, F> {
+        SpatialParentSystem {
+            extractor,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, F> System<'a> for SpatialParentSystem<T, F>
+where
+    T: 'static + WorkerComponent,
+    F: Fn(&T) -> Option<EntityId> + Send + Sync,
+{
+    type SystemData = (
+        Entities<'a>,
+        EntityIds<'a>,
+        SpatialReadStorage<'a, T>,
+        WriteStorage<'a, SpatialParent>,
+    );
+
+    fn run(&mut self, (entities, entity_ids, components, mut parents): Self::SystemData) {
+        for (entity, component) in (&entities, &components).join() {
+            let parent = (self.extractor)(&**component)
+                .and_then(|entity_id| entity_ids.get_entity(entity_id))
+                .filter(|parent| *parent != entity);
+            let current = parents.get(entity).cloned();
+
+            match (parent, current) {
+                (_, Some(current)) if current.source != T::ID => {}
+                (Some(parent), Some(current)) if parent == current.entity => {}
+                (Some(parent), _) => {
+                    parents
+                        .insert(
+                            entity,
+                            SpatialParent {
+                                entity: parent,
+                                source: T::ID,
+                            },
+                        )
+                        .expect("Error inserting SpatialParent.");
+                }
+                (None, Some(_)) => {
+                    parents.remove(entity);
+                }
+                (None, None) => {}
+            }
+        }
+
+        let orphaned: Vec<Entity> = (&entities, &parents, !&components)
+            .join()
+            .filter(|(_, parent, _)| parent.source == T::ID)
+            .map(|(entity, _, _)| entity)
+            .collect();
+
+        for entity in orphaned {
+            parents.remove(entity);
+        }
+    }
+}
+
+#[test]
+fn spatial_parent_system_should_follow_parent_references() {
+    use crate::component_registry::add_received_component;
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use crate::storage::SpatialWriteStorage;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::{ReadStorage, RunNow, SystemData, World, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Counter>>::setup(&mut world);
+
+    // The Counter's total holds the parent's EntityId.
+    let mut system = SpatialParentSystem::new(|counter: &Counter| {
+        Some(EntityId::new(WorkerEntityId::new(i64::from(counter.total))))
+    });
+    System::setup(&mut system, &mut world);
+
+    let entity_id = |id| EntityId::new(WorkerEntityId::new(id));
+    for id in 1..=3 {
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(&world, entity_id(id));
+    }
+    let entity = |id| EntityIds::fetch(&world).get_entity(entity_id(id)).unwrap();
+    let parent = |world: &World| {
+        ReadStorage::<SpatialParent>::fetch(world)
+            .get(entity(1))
+            .map(SpatialParent::entity)
+    };
+
+    let counter = |total| Counter {
+        total,
+        moves: Vec::new(),
+    };
+    add_received_component(&world, entity(1), Some(&counter(2)));
+    system.run_now(&world);
+    assert_eq!(Some(entity(2)), parent(&world));
+
+    SpatialWriteStorage::<Counter>::unrestricted(&world)
+        .get_mut(entity(1))
+        .unwrap()
+        .total = 3;
+    system.run_now(&world);
+    assert_eq!(Some(entity(3)), parent(&world));
+
+    // Parents which aren't checked out, and references to the entity itself, are ignored.
+    for total in &[4, 1] {
+        SpatialWriteStorage::<Counter>::unrestricted(&world)
+            .get_mut(entity(1))
+            .unwrap()
+            .total = *total;
+        system.run_now(&world);
+        assert_eq!(None, parent(&world));
+    }
+
+    SpatialWriteStorage::<Counter>::unrestricted(&world)
+        .get_mut(entity(1))
+        .unwrap()
+        .total = 2;
+    system.run_now(&world);
+    assert_eq!(Some(entity(2)), parent(&world));
+
+    WriteStorage::<SpatialComponent<Counter>>::fetch(&world).remove(entity(1));
+    system.run_now(&world);
+    assert_eq!(None, parent(&world));
+}
+
+#[test]
+fn spatial_parent_systems_should_only_change_their_own_parents() {
+    use crate::component_registry::add_received_component;
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::{ReadStorage, RunNow, SystemData, World, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Counter>>::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+
+    // The Counter's total and the Position's x coordinate hold parent EntityIds.
+    let mut counter_system = SpatialParentSystem::new(|counter: &Counter| {
+        Some(EntityId::new(WorkerEntityId::new(i64::from(counter.total))))
+    });
+    System::setup(&mut counter_system, &mut world);
+    let mut position_system = SpatialParentSystem::new(|position: &Position| {
+        Some(EntityId::new(WorkerEntityId::new(position.coords.x as i64)))
+    });
+    System::setup(&mut position_system, &mut world);
+
+    let entity_id = |id| EntityId::new(WorkerEntityId::new(id));
+    for id in 1..=3 {
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(&world, entity_id(id));
+    }
+    let entity = |id| EntityIds::fetch(&world).get_entity(entity_id(id)).unwrap();
+    let parent = |world: &World| {
+        ReadStorage::<SpatialParent>::fetch(world)
+            .get(entity(1))
+            .map(|parent| (parent.entity(), parent.source()))
+    };
+
+    let counter = |total| Counter {
+        total,
+        moves: Vec::new(),
+    };
+    add_received_component(&world, entity(1), Some(&counter(2)));
+    add_received_component(
+        &world,
+        entity(1),
+        Some(&Position {
+            coords: Coordinates {
+                x: 3.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        }),
+    );
+    counter_system.run_now(&world);
+    position_system.run_now(&world);
+    assert_eq!(Some((entity(2), Counter::ID)), parent(&world));
+
+    // Once the Counter is removed, the Position's parent takes over.
+    WriteStorage::<SpatialComponent<Counter>>::fetch(&world).remove(entity(1));
+    counter_system.run_now(&world);
+    position_system.run_now(&world);
+    assert_eq!(Some((entity(3), Position::ID)), parent(&world));
+
+    // A Counter which refers to no checked out entity doesn't remove the Position's parent.
+    add_received_component(&world, entity(1), Some(&counter(4)));
+    counter_system.run_now(&world);
+    position_system.run_now(&world);
+    assert_eq!(Some((entity(3), Position::ID)), parent(&world));
+}
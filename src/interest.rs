@@ -0,0 +1,277 @@
+//! Incremental changes to the interest of an entity.
+//!
+//! The `Interest` component maps each component ID to the queries whose results are sent
+//! to the worker authoritative over that component. Rather than rebuilding the whole map
+//! whenever a query changes, systems edit an `InterestQueries` component, and once per
+//! frame take the keys which changed since the last update was sent:
+//!
+//! ```ignore
+//! for (queries, interest) in (&mut interest_queries, &mut interests).join() {
+//!     if let Some(diff) = queries.take_diff() {
+//!         let mut component_interest = interest.component_interest.clone();
+//!         diff.apply(&mut component_interest, |queries| ComponentInterest { queries });
+//!         interest.send_update(InterestUpdate {
+//!             component_interest: Some(component_interest),
+//!         });
+//!     }
+//! }
+//! ```
+//!
+//! Changes made several times within a frame are coalesced into a single diff, and changes
+//! which are reverted before the diff is taken produce no update at all.
+//!
+//! The `frequency` of existing queries can be changed without rebuilding them, once the
+//! generated query type implements `InterestQuery`. This suits lowering the update rate of
+//! entities far from the camera, as only the keys whose frequency actually changed are
+//! sent again:
+//!
+//! ```ignore
+//! impl InterestQuery for ComponentInterest_Query {
+//!     fn frequency(&self) -> Option<f32> {
+//!         self.frequency
+//!     }
+//!
+//!     fn set_frequency(&mut self, frequency: Option<f32>) {
+//!         self.frequency = frequency;
+//!     }
+//! }
+//!
+//! let frequency = if distance > 100.0 { Some(2.0) } else { None };
+//! queries.set_frequency(Position::ID, frequency);
+//! ```
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Component, HashMapStorage};
+use std::collections::BTreeMap;
+
+/// The queries of an entity's interest, keyed by the component whose authoritative worker
+/// receives their results.
+///
+/// `Q` is the generated query type, usually `ComponentInterest_Query`.
+#[derive(Debug, Clone)]
+pub struct InterestQueries<Q> {
+    current: BTreeMap<ComponentId, Vec<Q>>,
+    sent: BTreeMap<ComponentId, Vec<Q>>,
+}
+
+impl<Q: 'static + Send + Sync> Component for InterestQueries<Q> {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl<Q: Clone + PartialEq> InterestQueries<Q> {
+    pub fn new() -> InterestQueries<Q> {
+        InterestQueries {
+            current: BTreeMap::new(),
+            sent: BTreeMap::new(),
+        }
+    }
+
+    /// Starts from queries which SpatialOS already has, such as those in the entity's
+    /// `Interest` component when it was checked out.
+    pub fn from_sent(sent: BTreeMap<ComponentId, Vec<Q>>) -> InterestQueries<Q> {
+        InterestQueries {
+            current: sent.clone(),
+            sent,
+        }
+    }
+
+    pub fn queries(&self, component_id: ComponentId) -> &[Q] {
+        self.current
+            .get(&component_id)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    pub fn set_queries(&mut self, component_id: ComponentId, queries: Vec<Q>) {
+        if queries.is_empty() {
+            self.current.remove(&component_id);
+        } else {
+            self.current.insert(component_id, queries);
+        }
+    }
+
+    pub fn add_query(&mut self, component_id: ComponentId, query: Q) {
+        self.current
+            .entry(component_id)
+            .or_insert_with(Vec::new)
+            .push(query);
+    }
+
+    /// Removes every query which matches the predicate.
+    pub fn remove_queries<F: Fn(&Q) -> bool>(&mut self, component_id: ComponentId, predicate: F) {
+        let now_empty = match self.current.get_mut(&component_id) {
+            Some(queries) => {
+                queries.retain(|query| !predicate(query));
+                queries.is_empty()
+            }
+            None => false,
+        };
+
+        if now_empty {
+            self.current.remove(&component_id);
+        }
+    }
+
+    pub fn clear_queries(&mut self, component_id: ComponentId) {
+        self.current.remove(&component_id);
+    }
+
+    /// Returns whether the queries differ from those last sent.
+    pub fn is_dirty(&self) -> bool {
+        self.current != self.sent
+    }
+
+    /// Returns the changes since the diff was last taken, if there are any, and treats the
+    /// current queries as sent.
+    pub fn take_diff(&mut self) -> Option<InterestDiff<Q>> {
+        if !self.is_dirty() {
+            return None;
+        }
+
+        let changed = self
+            .current
+            .iter()
+            .filter(|(component_id, queries)| self.sent.get(*component_id) != Some(*queries))
+            .map(|(component_id, queries)| (*component_id, queries.clone()))
+            .collect();
+
+        let removed = self
+            .sent
+            .keys()
+            .filter(|component_id| !self.current.contains_key(*component_id))
+            .cloned()
+            .collect();
+
+        self.sent = self.current.clone();
+
+        Some(InterestDiff { changed, removed })
+    }
+}
+
+/// Access to the `frequency` of a generated query type, in updates per second. A
+/// frequency of `None` sends updates as they happen.
+pub trait InterestQuery {
+    fn frequency(&self) -> Option<f32>;
+    fn set_frequency(&mut self, frequency: Option<f32>);
+}
+
+impl<Q: Clone + PartialEq + InterestQuery> InterestQueries<Q> {
+    /// Sets the frequency of every query of the component.
+    pub fn set_frequency(&mut self, component_id: ComponentId, frequency: Option<f32>) {
+        self.set_frequency_where(component_id, |_| true, frequency);
+    }
+
+    /// Sets the frequency of every query of the component which matches the predicate.
+    pub fn set_frequency_where<F: Fn(&Q) -> bool>(
+        &mut self,
+        component_id: ComponentId,
+        predicate: F,
+        frequency: Option<f32>,
+    ) {
+        if let Some(queries) = self.current.get_mut(&component_id) {
+            for query in queries.iter_mut().filter(|query| predicate(query)) {
+                query.set_frequency(frequency);
+            }
+        }
+    }
+}
+
+impl<Q: Clone + PartialEq> Default for InterestQueries<Q> {
+    fn default() -> Self {
+        InterestQueries::new()
+    }
+}
+
+/// The keys of an interest map which changed since the last update.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterestDiff<Q> {
+    /// The new queries of every component whose queries were added or changed.
+    pub changed: BTreeMap<ComponentId, Vec<Q>>,
+    /// The components which no longer have any queries.
+    pub removed: Vec<ComponentId>,
+}
+
+impl<Q> InterestDiff<Q> {
+    /// Applies the diff to an interest map, converting the queries of each changed key into
+    /// the map's value type.
+    pub fn apply<V, F: Fn(Vec<Q>) -> V>(self, map: &mut BTreeMap<ComponentId, V>, convert: F) {
+        for component_id in self.removed {
+            map.remove(&component_id);
+        }
+
+        for (component_id, queries) in self.changed {
+            map.insert(component_id, convert(queries));
+        }
+    }
+}
+
+#[test]
+fn interest_queries_should_coalesce_changes_into_minimal_diff() {
+    let mut interest =
+        InterestQueries::from_sent(vec![(1, vec!["a"]), (2, vec!["b"])].into_iter().collect());
+
+    interest.add_query(1, "c");
+    interest.set_queries(3, vec!["d"]);
+    interest.set_queries(3, vec!["e"]);
+    interest.add_query(2, "f");
+    interest.remove_queries(2, |query| *query == "f");
+    interest.clear_queries(2);
+
+    let diff = interest.take_diff().unwrap();
+    assert_eq!(
+        vec![(1, vec!["a", "c"]), (3, vec!["e"])],
+        diff.changed.clone().into_iter().collect::<Vec<_>>()
+    );
+    assert_eq!(vec![2], diff.removed);
+
+    let mut map: BTreeMap<ComponentId, usize> = vec![(1, 1), (2, 1)].into_iter().collect();
+    diff.apply(&mut map, |queries| queries.len());
+    assert_eq!(vec![(1, 2), (3, 1)], map.into_iter().collect::<Vec<_>>());
+
+    interest.add_query(3, "g");
+    interest.remove_queries(3, |query| *query == "g");
+    assert!(interest.take_diff().is_none());
+}
+
+#[test]
+fn frequency_overrides_should_only_resend_changed_queries() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct Query {
+        name: &'static str,
+        frequency: Option<f32>,
+    }
+
+    impl InterestQuery for Query {
+        fn frequency(&self) -> Option<f32> {
+            self.frequency
+        }
+
+        fn set_frequency(&mut self, frequency: Option<f32>) {
+            self.frequency = frequency;
+        }
+    }
+
+    let query = |name| Query {
+        name,
+        frequency: None,
+    };
+    let mut interest = InterestQueries::from_sent(
+        vec![
+            (1, vec![query("near"), query("far")]),
+            (2, vec![query("b")]),
+        ]
+        .into_iter()
+        .collect(),
+    );
+
+    interest.set_frequency(2, None);
+    assert!(interest.take_diff().is_none());
+
+    interest.set_frequency_where(1, |query| query.name == "far", Some(2.0));
+    let diff = interest.take_diff().unwrap();
+    assert_eq!(vec![1], diff.changed.keys().cloned().collect::<Vec<_>>());
+    assert_eq!(None, diff.changed[&1][0].frequency());
+    assert_eq!(Some(2.0), diff.changed[&1][1].frequency());
+
+    interest.set_frequency_where(1, |query| query.name == "far", Some(2.0));
+    assert!(interest.take_diff().is_none());
+}
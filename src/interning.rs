@@ -0,0 +1,149 @@
+//! Interned strings for string fields whose values repeat across many entities, such as
+//! entity types or team names.
+//!
+//! Each distinct value is held once, and every component with that value shares it. The code
+//! generator has no option to emit it, so the field's type, and its conversion with
+//! `InternedStr::from`, are changed by hand in the generated code:
+//!
+//! ```ignore
+//! pub struct Team {
+//!     pub name: InternedStr,
+//! }
+//!
+//! // Comparing and hashing are as for a `String`, and it dereferences to `str`.
+//! if team.name == "red" { ... }
+//! ```
+//!
+//! Values stay in the interner after the last component using them is removed, until
+//! `purge_unused_strings` is called. Workers whose values change over time, such as player
+//! names, should call it periodically.
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    static ref INTERNER: Mutex<HashSet<Arc<str>>> = Mutex::new(HashSet::new());
+}
+
+/// A string shared with every other `InternedStr` of the same value.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InternedStr(Arc<str>);
+
+impl InternedStr {
+    pub fn intern(value: &str) -> InternedStr {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(interned) = interner.get(value) {
+            return InternedStr(interned.clone());
+        }
+
+        let interned: Arc<str> = Arc::from(value);
+        interner.insert(interned.clone());
+        InternedStr(interned)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether both share the same allocation, which is the case for any two
+    /// `InternedStr`s with the same value.
+    pub fn ptr_eq(&self, other: &InternedStr) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Removes values which are no longer used by any `InternedStr`, returning how many
+/// were removed.
+pub fn purge_unused_strings() -> usize {
+    let mut interner = INTERNER.lock().unwrap();
+    let before = interner.len();
+    interner.retain(|interned| Arc::strong_count(interned) > 1);
+    before - interner.len()
+}
+
+/// The number of distinct values held by the interner.
+pub fn interned_string_count() -> usize {
+    INTERNER.lock().unwrap().len()
+}
+
+impl Default for InternedStr {
+    fn default() -> Self {
+        InternedStr::intern("")
+    }
+}
+
+impl Deref for InternedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for InternedStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for InternedStr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a str> for InternedStr {
+    fn from(value: &'a str) -> InternedStr {
+        InternedStr::intern(value)
+    }
+}
+
+impl From<String> for InternedStr {
+    fn from(value: String) -> InternedStr {
+        InternedStr::intern(&value)
+    }
+}
+
+impl<'a> PartialEq<&'a str> for InternedStr {
+    fn eq(&self, other: &&'a str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<str> for InternedStr {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl fmt::Debug for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+#[test]
+fn interned_strings_should_share_one_allocation_per_value() {
+    let red = InternedStr::intern("interning-test-red");
+    let also_red = InternedStr::from("interning-test-red".to_string());
+    let blue = InternedStr::from("interning-test-blue");
+
+    assert!(red.ptr_eq(&also_red));
+    assert!(!red.ptr_eq(&blue));
+    assert_eq!(red, "interning-test-red");
+    assert_eq!("\"interning-test-blue\"", format!("{:?}", blue));
+
+    drop(blue);
+    purge_unused_strings();
+    let interner = INTERNER.lock().unwrap();
+    assert!(interner.contains("interning-test-red"));
+    assert!(!interner.contains("interning-test-blue"));
+}
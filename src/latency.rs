@@ -0,0 +1,215 @@
+//! Exponential moving averages of how late component updates arrive.
+//!
+//! Adding an `UpdateLatency` resource makes the `SpatialReaderSystem` track, for every
+//! component, the interval between successive updates received for the same entity. For
+//! components which carry the time they were written, a timestamp accessor also tracks the
+//! latency from the sending worker to this one, for each update which sets the timestamp:
+//!
+//! ```ignore
+//! let mut latency = UpdateLatency::new();
+//! latency.track_timestamp::<PlayerInput, _>(|update| {
+//!     update
+//!         .sent_at_ms
+//!         .map(|sent_at_ms| UNIX_EPOCH + Duration::from_millis(sent_at_ms))
+//! });
+//! world.insert(latency);
+//!
+//! if let Some(stat) = world.fetch::<UpdateLatency>().get(PlayerInput::ID) {
+//!     println!("Input latency: {:?}", stat.latency);
+//! }
+//! ```
+//!
+//! Latency is measured against this machine's wall clock, so it includes any clock skew
+//! between the two workers.
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::World;
+use std::any::Any;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+type TimestampFn = Box<Fn(&Any) -> Option<SystemTime> + Send + Sync>;
+
+/// The smoothed latency and update interval of a component.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct LatencyStat {
+    /// The moving average of the time between an update being written and received, for
+    /// components with a timestamp accessor.
+    pub latency: Option<Duration>,
+    /// The moving average of the time between updates to the same entity.
+    pub interval: Option<Duration>,
+    /// The number of updates received.
+    pub samples: u64,
+}
+
+/// A resource tracking the moving average latency of component updates.
+pub struct UpdateLatency {
+    smoothing: f64,
+    timestamps: HashMap<ComponentId, TimestampFn>,
+    stats: HashMap<ComponentId, LatencyStat>,
+}
+
+impl UpdateLatency {
+    pub fn new() -> UpdateLatency {
+        UpdateLatency {
+            smoothing: 0.1,
+            timestamps: HashMap::new(),
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Sets the weight given to each new sample, between 0 and 1. Defaults to 0.1.
+    pub fn set_smoothing(&mut self, smoothing: f64) {
+        assert!(
+            smoothing > 0.0 && smoothing <= 1.0,
+            "Smoothing must be in (0, 1], but was {}.",
+            smoothing
+        );
+        self.smoothing = smoothing;
+    }
+
+    /// Measures the latency of the component from the time returned by `timestamp`, which
+    /// is read from each received update and is `None` if the update doesn't set it.
+    pub fn track_timestamp<T, F>(&mut self, timestamp: F)
+    where
+        T: 'static + WorkerComponent,
+        F: 'static + Fn(&T::Update) -> Option<SystemTime> + Send + Sync,
+    {
+        self.timestamps.insert(
+            T::ID,
+            Box::new(move |update| {
+                update
+                    .downcast_ref::<T::Update>()
+                    .and_then(|update| timestamp(update))
+            }),
+        );
+    }
+
+    pub fn get(&self, component_id: ComponentId) -> Option<LatencyStat> {
+        self.stats.get(&component_id).cloned()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (ComponentId, LatencyStat)> + 'a {
+        self.stats.iter().map(|(id, stat)| (*id, *stat))
+    }
+
+    pub fn reset(&mut self) {
+        self.stats.clear();
+    }
+
+    fn average(&self, average: Option<Duration>, sample: Duration) -> Duration {
+        match average {
+            Some(average) => Duration::from_secs_f64(
+                average.as_secs_f64()
+                    + self.smoothing * (sample.as_secs_f64() - average.as_secs_f64()),
+            ),
+            None => sample,
+        }
+    }
+
+    fn record(
+        &mut self,
+        component_id: ComponentId,
+        latency: Option<Duration>,
+        interval: Option<Duration>,
+    ) {
+        let mut stat = self.get(component_id).unwrap_or_default();
+        if let Some(latency) = latency {
+            stat.latency = Some(self.average(stat.latency, latency));
+        }
+        if let Some(interval) = interval {
+            stat.interval = Some(self.average(stat.interval, interval));
+        }
+        stat.samples += 1;
+        self.stats.insert(component_id, stat);
+    }
+
+    /// Records an update received for a component which was last updated at `previous`.
+    pub(crate) fn update_received(
+        res: &World,
+        component_id: ComponentId,
+        update: &Any,
+        previous: Option<Instant>,
+        now: Instant,
+    ) {
+        let mut latency = res.fetch_mut::<UpdateLatency>();
+        let sent = latency
+            .timestamps
+            .get(&component_id)
+            .and_then(|timestamp| timestamp(update));
+
+        // Timestamps in the future are skew between the workers' clocks.
+        let sent_latency = sent.map(|sent| {
+            SystemTime::now()
+                .duration_since(sent)
+                .unwrap_or(Duration::from_secs(0))
+        });
+        let interval = previous.map(|previous| now.duration_since(previous));
+        latency.record(component_id, sent_latency, interval);
+    }
+}
+
+impl Default for UpdateLatency {
+    fn default() -> Self {
+        UpdateLatency::new()
+    }
+}
+
+#[test]
+fn update_latency_should_smooth_samples() {
+    let mut latency = UpdateLatency::new();
+    latency.set_smoothing(0.5);
+
+    latency.record(54, Some(Duration::from_millis(100)), None);
+    latency.record(
+        54,
+        Some(Duration::from_millis(200)),
+        Some(Duration::from_secs(1)),
+    );
+    latency.record(54, None, Some(Duration::from_secs(2)));
+
+    let stat = latency.get(54).unwrap();
+    assert_eq!(Some(Duration::from_millis(150)), stat.latency);
+    assert_eq!(Some(Duration::from_millis(1500)), stat.interval);
+    assert_eq!(3, stat.samples);
+    assert_eq!(None, latency.get(1000));
+}
+
+#[test]
+fn update_latency_should_only_sample_updates_with_a_timestamp() {
+    use crate::generated_test::*;
+    use specs::prelude::WorldExt;
+
+    let mut latency = UpdateLatency::new();
+    latency.track_timestamp::<Position, _>(|update| {
+        update
+            .coords
+            .map(|_| SystemTime::now() - Duration::from_secs(1))
+    });
+    let mut world = World::new();
+    world.insert(latency);
+
+    let now = Instant::now();
+    let update = PositionUpdate { coords: None };
+    UpdateLatency::update_received(&world, Position::ID, &update, None, now);
+    assert_eq!(
+        None,
+        world
+            .fetch::<UpdateLatency>()
+            .get(Position::ID)
+            .unwrap()
+            .latency
+    );
+
+    let update = PositionUpdate {
+        coords: Some(Coordinates {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }),
+    };
+    UpdateLatency::update_received(&world, Position::ID, &update, Some(now), now);
+    let stat = world.fetch::<UpdateLatency>().get(Position::ID).unwrap();
+    assert!(stat.latency.unwrap() >= Duration::from_secs(1));
+    assert_eq!(2, stat.samples);
+}
@@ -1,31 +1,168 @@
 #[macro_use]
 extern crate lazy_static;
 
+// The rest of the crate refers to whichever SDK build was selected as `spatialos_sdk`.
+#[cfg(all(feature = "sdk-13", not(feature = "sdk-14")))]
+extern crate spatialos_sdk_13 as spatialos_sdk;
+#[cfg(feature = "sdk-14")]
+extern crate spatialos_sdk_14 as spatialos_sdk;
+
+pub mod acl;
+pub mod allowlist;
+pub mod archetype;
+pub mod audit;
+pub mod bulk;
+#[cfg(feature = "amethyst")]
+pub mod bundle;
+pub mod census;
+pub mod checkout_group;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod checksum;
+pub mod clock;
+pub mod command_info;
 pub mod commands;
 mod component_registry;
+mod connection_handle;
+pub mod debug;
+pub mod double_buffer;
+pub mod drift_repair;
 pub mod entities;
-#[cfg(test)]
+pub mod eviction;
+pub mod field_watcher;
+pub mod fields;
+#[cfg(feature = "worker-flags")]
+pub mod flags;
+pub mod frame_report;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+pub mod bench_support;
+#[cfg(any(test, feature = "bench-internals"))]
 mod generated_test;
+pub mod health;
+#[cfg(feature = "hierarchy")]
+pub mod hierarchy;
+pub mod interest;
+pub mod interning;
+pub mod latency;
+pub mod local_data;
+pub mod logging;
+pub mod merge;
+pub mod network_stats;
+pub mod op_stats;
+#[cfg(feature = "partitions")]
+pub mod partition;
+pub mod ownership;
+pub mod persistence;
+pub mod player_lifecycle;
+pub mod position_history;
+pub mod prelude;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod query;
+pub mod query_result;
+pub mod resync;
+pub mod rpc;
+pub mod saga;
+pub mod schema_version;
+mod sdk;
+pub mod shared_bytes;
+pub mod shutdown;
+pub mod snapshot_diff;
+pub mod spatial_hash;
+pub mod spawn_queue;
 mod spatial_reader;
 mod spatial_writer;
 mod storage;
 pub mod system_commands;
+pub mod tags;
+pub mod test;
+pub mod tick_rate;
+pub mod tick_stamp;
+mod update_builder;
+pub mod view;
 
-pub use commands::{CommandRequests, CommandSender};
-pub use entities::{EntityId, EntityIds};
-pub use spatial_reader::SpatialReaderSystem;
-pub use spatial_writer::SpatialWriterSystem;
-pub use storage::{SpatialReadStorage, SpatialWriteStorage};
-pub use system_commands::SystemCommandSender;
+pub use acl::{worker_id_from_attribute, Acl, AttributeSet, RequirementSet};
+pub use allowlist::ComponentAllowlist;
+pub use archetype::{ArchetypeStat, ArchetypeStats};
+pub use bulk::{BulkCommand, BulkReceiver, BulkSender, BulkTransfer};
+pub use census::{ComponentCensus, ComponentCount};
+pub use checkout_group::{
+    CheckoutGroupEvent, CheckoutGroupEvents, CheckoutGroupId, CheckoutGroups,
+};
+pub use checksum::{ChecksumMismatch, ChecksumMismatchEvents, ChecksumVerification};
+pub use clock::SpatialClock;
+pub use commands::{
+    BroadcastResults, CallerAttributes, CommandAuthority, CommandAuthorityEvent,
+    CommandAuthorityEvents, CommandAuthorization, CommandClaim, CommandRequests, CommandSender,
+    CommandValidation, RespondWithData, ResponderBudget, SerializedCommandRequest,
+};
+pub use component_registry::{
+    component_commands, component_id, component_name, components_with_commands,
+    register_component_commands, register_component_name,
+};
+pub use connection_handle::{CallStat, ConnectionCalls, SdkCall};
+pub use double_buffer::{ComponentSnapshot, DoubleBuffered, SnapshotHandle};
+pub use drift_repair::DriftRepair;
+pub use entities::{
+    DuplicateEntityPolicy, EntityId, EntityIds, EntityLiveness, SnapshotIdAllocator,
+    SpatialEntityEvent, SpatialEntityEvents,
+};
+pub use field_watcher::FieldWatcher;
+pub use health::{ConnectionHealth, ConnectionHealthEvent, ConnectionHealthEvents};
+pub use interning::{interned_string_count, purge_unused_strings, InternedStr};
+pub use latency::{LatencyStat, UpdateLatency};
+pub use local_data::LocalData;
+pub use logging::SpatialLogger;
+pub use network_stats::{CoalescingStat, NetworkStats};
+pub use op_stats::{OpCategory, OpStats, OpTiming};
+pub use ownership::{entities_owned_by, Owner, OwnerCleanup, Owners, OwnershipConfig};
+pub use persistence::{PersistedComponent, Persistence, PersistenceHook};
+pub use player_lifecycle::{
+    DisconnectPolicy, PlayerEvent, PlayerEvents, PlayerLeftReason, PlayerLifecycle,
+};
+pub use position_history::{PositionHistories, PositionHistory, PositionHistoryConfig};
+pub use query_result::QueryResult;
+pub use resync::{ResyncEvent, ResyncEvents, ResyncInProgress};
+pub use rpc::RpcContext;
+pub use saga::{Saga, SagaEvent, SagaEvents, SagaId, SagaProgress, Sagas, StepHandle};
+pub use schema_version::{SchemaVersion, SchemaVersionEvents, SchemaVersionStatus};
+pub use shared_bytes::SharedBytes;
+pub use shutdown::{ShutdownCoordinator, ShutdownState};
+pub use spatial_hash::SpatialHash;
+pub use spatial_reader::{
+    SpatialOpApplierSystem, SpatialOpCollectorSystem, SpatialReaderSystem, CONNECTION_LOST,
+};
+pub use spatial_writer::{
+    flush, LockingWriterSystem, SpatialFlushSystem, SpatialWriterSystem, WriterStageSystem,
+    WriterStages,
+};
+pub use spatialos_sdk::worker::Authority;
+pub use spawn_queue::{SpawnEvent, SpawnEvents, SpawnQueue};
+pub use storage::{
+    authority, ComponentAuthority, ComponentPolicy, ComponentRemoving, ComponentRemovingEvents,
+    InsertFailed, InsertFailedEvents, InsertFailurePolicy, ReadAuthority, SpatialReadStorage,
+    SpatialReadStorageExt, SpatialWriteStorage, UpdateDropped, UpdateDroppedEvents,
+};
+pub use system_commands::{EntityBatchProgress, SystemCommandResult, SystemCommandSender};
+pub use tags::EntityTags;
+pub use tick_rate::TickRateController;
+pub use tick_stamp::{RemoteTick, TickStamping};
+pub use view::View;
 
+use crate::audit::ReplicationReason;
+use crate::local_data::LocalData;
+use crate::sdk::{self, SdkConnection};
 use crate::storage::SpatialUnprotectedStorage;
 use spatialos_sdk::worker::component::Component as WorkerComponent;
-use spatialos_sdk::worker::component::{ComponentUpdate, TypeConversion, UpdateParameters};
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
-use spatialos_sdk::worker::internal::schema::SchemaComponentUpdate;
-use specs::prelude::{Component, Resources, System, SystemData, VecStorage};
+use spatialos_sdk::worker::component::{ComponentUpdate, TypeConversion};
+use spatialos_sdk::worker::internal::schema::{SchemaComponentData, SchemaComponentUpdate};
+use specs::prelude::{Component, System, SystemData, VecStorage, World};
 use std::fmt::Debug;
 use std::ops::{Deref, DerefMut};
+use std::time::Instant;
 
 /// A wrapper for a SpatialOS component data.
 ///
@@ -44,11 +181,26 @@ use std::ops::{Deref, DerefMut};
 /// * You can use `send_update` to apply and send a partial update to SpatialOS.
 ///   This is more efficient as you can control the exact properties you send.
 ///
+/// Either way, the local value changes immediately, so every system which runs later in the
+/// frame reads the new value without waiting for SpatialOS to acknowledge it. This also
+/// holds when an update is received before a local change has been sent: partial updates
+/// which haven't been sent are applied again on top of the received update, and fields
+/// changed through a mutable dereference keep their local values unless the received
+/// update sets them. An echo of an older update from this worker can still briefly
+/// overwrite a newer value which has already been sent, until the echo of the newer one
+/// arrives.
+///
 #[derive(Debug)]
 pub struct SpatialComponent<T: WorkerComponent + Debug> {
     value: T,
     value_is_dirty: bool,
+    full_resend: bool,
     current_update: Option<T::Update>,
+    // The value before `current_update` was applied to it.
+    unsent_base: Option<T>,
+    logical_updates: u32,
+    last_received: Option<Instant>,
+    local: LocalData,
 }
 
 impl<T: WorkerComponent + TypeConversion + Debug> SpatialComponent<T> {
@@ -56,29 +208,112 @@ impl<T: WorkerComponent + TypeConversion + Debug> SpatialComponent<T> {
         SpatialComponent {
             value,
             value_is_dirty: false,
+            full_resend: false,
             current_update: None,
+            unsent_base: None,
+            logical_updates: 0,
+            last_received: None,
+            local: LocalData::default(),
         }
     }
 
-    pub(crate) fn replicate(&mut self, connection: &mut WorkerConnection, entity_id: EntityId) {
-        let update = {
-            if self.value_is_dirty {
-                self.value_is_dirty = false;
-                Some(self.to_update())
-            } else {
-                self.current_update.take()
+    pub(crate) fn received(value: T, now: Instant) -> SpatialComponent<T> {
+        let mut component = SpatialComponent::new(value);
+        component.last_received = Some(now);
+        component
+    }
+
+    /// The time at which the component data, or the most recent update to it, was
+    /// received from SpatialOS, according to the `SpatialClock`.
+    ///
+    /// This is `None` for components which were inserted locally. Updates sent by this
+    /// worker do not change it.
+    pub fn last_update_instant(&self) -> Option<Instant> {
+        self.last_received
+    }
+
+    /// Non-replicated data attached to this component, which lives as long as it does.
+    pub fn local(&self) -> &LocalData {
+        &self.local
+    }
+
+    /// Mutable access to the attached local data, which doesn't cause an update to be sent.
+    pub fn local_mut(&mut self) -> &mut LocalData {
+        &mut self.local
+    }
+
+    pub(crate) fn apply_received_update(&mut self, update: T::Update, now: Instant) {
+        // The unsent partial updates are applied again to the value from before them, as
+        // SpatialOS will apply them once they're sent. A mutably dereferenced value is
+        // updated in place, as only the fields set by the received update change.
+        match (&mut self.unsent_base, &self.current_update) {
+            (Some(base), Some(unsent)) => {
+                base.merge(update);
+                let mut value = base.clone();
+                value.merge(unsent.clone());
+                self.value = value;
             }
+            _ => self.apply_update_to_value(update),
+        }
+
+        self.last_received = Some(now);
+    }
+
+    /// Sends any pending update, after `stamp` has been applied to it and to the local
+    /// value, returning why it was sent and, if `describe` is set, a description of the
+    /// update.
+    pub(crate) fn replicate<C: SdkConnection, S: Fn(&mut T::Update)>(
+        &mut self,
+        connection: &mut C,
+        entity_id: EntityId,
+        describe: bool,
+        stamp: Option<S>,
+    ) -> Option<(ReplicationReason, Option<String>)> {
+        let (reason, mut update) = self.take_pending_update()?;
+        if let Some(stamp) = stamp {
+            stamp(&mut update);
+            self.stamp_value(&stamp);
+        }
+
+        let description = if describe {
+            Some(format!("{:?}", update))
+        } else {
+            None
         };
 
-        if let Some(update) = update {
-            connection.send_component_update::<T>(
-                entity_id.id(),
-                update,
-                UpdateParameters::default(),
-            );
+        connection.send_update::<T>(entity_id.id(), update);
+
+        Some((reason, description))
+    }
+
+    /// Takes the update which should be sent for this component, if any, clearing
+    /// the component's dirty state.
+    pub(crate) fn take_pending_update(&mut self) -> Option<(ReplicationReason, T::Update)> {
+        self.logical_updates = 0;
+        self.unsent_base = None;
+        if self.full_resend {
+            self.full_resend = false;
+            self.value_is_dirty = false;
+            self.current_update = None;
+            Some((ReplicationReason::FullResend, self.to_update()))
+        } else if self.value_is_dirty {
+            self.value_is_dirty = false;
+            Some((ReplicationReason::MutablyDereferenced, self.to_update()))
+        } else {
+            self.current_update
+                .take()
+                .map(|update| (ReplicationReason::SendUpdate, update))
         }
     }
 
+    // The rest of the sent update is already in the value, so only the stamped fields are
+    // applied, and list fields aren't appended to twice.
+    fn stamp_value<S: Fn(&mut T::Update)>(&mut self, stamp: &S) {
+        let mut stamped = schema_empty_update::<T>();
+        stamp(&mut stamped);
+        self.apply_update_to_value(stamped);
+    }
+
     // TODO - this is really bad as it seriliases then deserialises.
     fn to_update(&self) -> T::Update {
         let schema_update = SchemaComponentUpdate::new();
@@ -88,24 +323,185 @@ impl<T: WorkerComponent + TypeConversion + Debug> SpatialComponent<T> {
         T::Update::from_type(&fields).unwrap()
     }
 
+    /// Returns whether the entire component will be sent at the end of the frame, because
+    /// it was mutably dereferenced or marked for a full resend.
+    pub fn is_dirty(&self) -> bool {
+        self.value_is_dirty || self.full_resend
+    }
+
+    /// Returns whether any update will be sent for this component at the end of the frame.
+    pub fn has_pending_update(&self) -> bool {
+        self.is_dirty() || self.current_update.is_some()
+    }
+
+    /// The number of updates, mutable dereferences and full resends which will be merged
+    /// into the pending update.
+    pub(crate) fn logical_updates(&self) -> u32 {
+        self.logical_updates
+    }
+
+    /// The partial updates given to `send_update` this frame, merged into one, which will
+    /// be sent at the end of the frame.
+    ///
+    /// This is `None` if the component is dirty, as the entire component will be sent
+    /// instead.
+    pub fn pending_update(&self) -> Option<&T::Update> {
+        if self.is_dirty() {
+            None
+        } else {
+            self.current_update.as_ref()
+        }
+    }
+
+    /// Describes the update which will be sent for this component at the end of the frame.
+    pub(crate) fn pending_update_description(&self) -> Option<String> {
+        if self.value_is_dirty || self.full_resend {
+            Some(format!("<full component> {:?}", self.value))
+        } else {
+            self.current_update
+                .as_ref()
+                .map(|update| format!("{:?}", update))
+        }
+    }
+
+    /// Serializes the update which will be sent for this component at the end of the frame,
+    /// if there is one, so that it can be persisted across a graceful restart.
+    pub fn serialize_pending_update(&self) -> Result<Option<Vec<u8>>, String> {
+        self.outgoing_update()
+            .map(|update| sdk::serialize_update::<T>(&update))
+            .transpose()
+    }
+
+    pub(crate) fn outgoing_update(&self) -> Option<T::Update> {
+        if self.value_is_dirty || self.full_resend {
+            Some(self.to_update())
+        } else {
+            self.current_update.clone()
+        }
+    }
+
+    /// Returns the checksums of the local value, and of the value rebuilt from a
+    /// deserialized copy of the update which would be sent.
+    ///
+    /// A pending partial update is applied to the value from before it, as SpatialOS will
+    /// apply it, and a full update to the schema default value.
+    pub(crate) fn checksums(&self) -> Result<(u64, u64), String> {
+        let local = sdk::serialize_data::<T>(&self.value)?;
+
+        let (base, update) = match (&self.unsent_base, &self.current_update) {
+            (Some(base), Some(unsent)) if !self.is_dirty() => {
+                (sdk::serialize_data::<T>(base)?, unsent.clone())
+            }
+            _ => (
+                sdk::serialize_data::<T>(&schema_default::<T>())?,
+                self.to_update(),
+            ),
+        };
+        let update = sdk::deserialize_update::<T>(&sdk::serialize_update::<T>(&update)?)?;
+
+        let mut copy = sdk::deserialize_data::<T>(&base)?;
+        copy.merge(update);
+        let sent = sdk::serialize_data::<T>(&copy)?;
+
+        Ok((checksum::checksum(&local), checksum::checksum(&sent)))
+    }
+
+    /// Applies an update serialized by `serialize_pending_update` and queues it to be sent,
+    /// as with `send_update`.
+    pub fn restore_pending_update(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let update = sdk::deserialize_update::<T>(bytes)?;
+        self.send_update(update);
+        Ok(())
+    }
+
+    pub(crate) fn into_value(self) -> T {
+        self.value
+    }
+
     pub(crate) fn apply_update_to_value(&mut self, update: T::Update) {
         self.value.merge(update);
     }
 
+    /// Queues the complete component data to be sent as an update at the end of the frame.
+    ///
+    /// This is useful after gaining authority, or to repair divergence between the local
+    /// value and the value in SpatialOS. Any pending partial updates are superseded, and
+    /// this can be combined with either way of updating the component.
+    pub fn mark_full_resend(&mut self) {
+        self.full_resend = true;
+        self.logical_updates += 1;
+    }
+
+    // Marks the component for a full resend by `DriftRepair`, which repeats the value
+    // rather than changing it, so isn't a logical update.
+    pub(crate) fn mark_repair_resend(&mut self) {
+        self.full_resend = true;
+    }
+
     pub fn send_update(&mut self, update: T::Update) {
         if self.value_is_dirty {
             panic!("Attempt to send update to component which has already been mutably dereferenced. Id {}", T::ID);
         }
 
-        self.apply_update_to_value(update.clone());
-
+        self.logical_updates += 1;
         match &mut self.current_update {
-            Some(current_update) => current_update.merge(update),
-            None => self.current_update = Some(update),
+            Some(current_update) => {
+                // The merged update is applied to the value from before it, as SpatialOS
+                // will apply it once it's sent. Applying each update in turn would leave
+                // only the last one's elements in fields which the merged update appends to.
+                current_update.merge(update);
+                if let Some(base) = &self.unsent_base {
+                    let mut value = base.clone();
+                    value.merge(current_update.clone());
+                    self.value = value;
+                }
+            }
+            None => {
+                self.unsent_base = Some(self.value.clone());
+                self.apply_update_to_value(update.clone());
+                self.current_update = Some(update);
+            }
         }
     }
 }
 
+/// Constructs an update which sets none of a component's fields, by deserializing an
+/// empty `SchemaObject`.
+pub(crate) fn schema_empty_update<T: WorkerComponent>() -> T::Update {
+    let schema_update = SchemaComponentUpdate::new();
+    T::Update::from_type(&schema_update.fields())
+        .expect("Error deserializing update from an empty SchemaObject.")
+}
+
+/// Constructs the schema default value of a component, by deserializing
+/// an empty `SchemaObject`.
+pub(crate) fn schema_default<T: WorkerComponent>() -> T {
+    let schema_data = SchemaComponentData::new();
+    T::from_type(&schema_data.fields())
+        .expect("Error deserializing component from an empty SchemaObject.")
+}
+
+/// The value every component has when none of its fields are set: zero for numbers, empty
+/// for strings, lists and maps, and `None` for options. Nested types have their own
+/// schema default.
+///
+/// This is a baseline for building entities and for tests, without writing out every field:
+///
+/// ```ignore
+/// let mut player = Player::schema_default();
+/// player.name = "Ada".to_string();
+/// builder.add_component(player, write_acl)?;
+/// ```
+pub trait SchemaDefault: WorkerComponent {
+    fn schema_default() -> Self;
+}
+
+impl<T: WorkerComponent> SchemaDefault for T {
+    fn schema_default() -> T {
+        schema_default::<T>()
+    }
+}
+
 impl<T: WorkerComponent + Debug> Deref for SpatialComponent<T> {
     type Target = T;
 
@@ -120,21 +516,24 @@ impl<T: WorkerComponent + Debug> DerefMut for SpatialComponent<T> {
             panic!("Attempt to mutably dereference a component which has already had an update applied to it. Id {}", T::ID);
         }
 
-        self.value_is_dirty = true;
+        if !self.value_is_dirty {
+            self.value_is_dirty = true;
+            self.logical_updates += 1;
+        }
         &mut self.value
     }
 }
 
 impl<T: 'static + WorkerComponent> Component for SpatialComponent<T> {
-    type Storage = SpatialUnprotectedStorage<T, Self, VecStorage<Self>>;
+    type Storage = SpatialUnprotectedStorage<T, VecStorage<Self>>;
 }
 
 pub struct SystemDataFetch<'a> {
-    res: &'a Resources,
+    res: &'a World,
 }
 
 impl<'a> SystemDataFetch<'a> {
-    pub(crate) fn new(res: &'a Resources) -> SystemDataFetch<'a> {
+    pub(crate) fn new(res: &'a World) -> SystemDataFetch<'a> {
         SystemDataFetch { res }
     }
 
@@ -146,3 +545,344 @@ impl<'a> SystemDataFetch<'a> {
         S::SystemData::fetch(self.res)
     }
 }
+
+#[test]
+fn full_resend_should_supersede_partial_updates() {
+    use crate::generated_test::*;
+
+    let mut component = SpatialComponent::new(Position {
+        coords: Coordinates {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        },
+    });
+    assert!(component.pending_update_description().is_none());
+    assert!(!component.has_pending_update());
+
+    component.send_update(PositionUpdate {
+        coords: Some(Coordinates {
+            x: 4.0,
+            y: 5.0,
+            z: 6.0,
+        }),
+    });
+    assert!(component.has_pending_update());
+    assert!(!component.is_dirty());
+    assert_eq!(
+        5.0,
+        component
+            .pending_update()
+            .unwrap()
+            .coords
+            .as_ref()
+            .unwrap()
+            .y
+    );
+
+    component.mark_full_resend();
+    assert!(component.is_dirty());
+    assert!(component.pending_update().is_none());
+
+    // A full resend does not prevent further partial updates.
+    component.send_update(PositionUpdate { coords: None });
+
+    assert!(component
+        .pending_update_description()
+        .unwrap()
+        .starts_with("<full component>"));
+}
+
+#[test]
+fn full_resend_should_round_trip_all_schema_shapes() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::component::ComponentData;
+    use std::collections::BTreeMap;
+
+    let empty = schema_default::<SchemaShapes>();
+    assert!(empty.constraint.and_constraint.is_empty());
+    assert!(empty.loadout.primary.is_none());
+    assert!(empty.anchors.is_empty());
+    assert!(empty.blob.is_empty());
+
+    let leaf = |id| Constraint {
+        entity_id_constraint: Some(id),
+        and_constraint: vec![],
+        or_constraint: vec![],
+    };
+    let coords = Coordinates {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+    };
+    let mut anchors = BTreeMap::new();
+    anchors.insert(7, coords.clone());
+
+    let mut component = SpatialComponent::new(SchemaShapes {
+        constraint: Constraint {
+            entity_id_constraint: None,
+            and_constraint: vec![leaf(1)],
+            or_constraint: vec![leaf(2), leaf(3)],
+        },
+        loadout: Loadout {
+            primary: Some(Attachment {
+                offset: Some(coords),
+                payload: vec![0, 1, 255],
+            }),
+        },
+        anchors,
+        blob: vec![42; 16],
+    });
+    component.mark_full_resend();
+
+    let (reason, update) = component.take_pending_update().unwrap();
+    assert_eq!(ReplicationReason::FullResend, reason);
+
+    let mut value = empty;
+    value.merge(update);
+
+    assert_eq!(None, value.constraint.entity_id_constraint);
+    assert_eq!(
+        Some(1),
+        value.constraint.and_constraint[0].entity_id_constraint
+    );
+    assert_eq!(2, value.constraint.or_constraint.len());
+    let primary = value.loadout.primary.unwrap();
+    assert_eq!(3.0, primary.offset.unwrap().z);
+    assert_eq!(vec![0, 1, 255], primary.payload);
+    assert_eq!(2.0, value.anchors[&7].y);
+    assert_eq!(vec![42; 16], value.blob);
+}
+
+#[test]
+fn last_update_instant_should_only_track_received_data() {
+    use crate::generated_test::*;
+    use std::time::Duration;
+
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+    let update = |x| PositionUpdate {
+        coords: Some(Coordinates { x, y: 0.0, z: 0.0 }),
+    };
+
+    assert!(SpatialComponent::new(position(0.0))
+        .last_update_instant()
+        .is_none());
+
+    let added = Instant::now();
+    let mut component = SpatialComponent::received(position(0.0), added);
+    assert_eq!(Some(added), component.last_update_instant());
+
+    component.send_update(update(1.0));
+    assert_eq!(Some(added), component.last_update_instant());
+
+    let updated = added + Duration::from_millis(50);
+    component.apply_received_update(update(2.0), updated);
+    assert_eq!(Some(updated), component.last_update_instant());
+    assert_eq!(2.0, component.coords.x);
+}
+
+#[test]
+fn unsent_local_changes_should_survive_received_updates() {
+    use crate::generated_test::*;
+
+    let coords = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    let received = |x| PositionUpdate {
+        coords: Some(coords(x)),
+    };
+    let now = Instant::now();
+
+    let mut component = SpatialComponent::new(Position {
+        coords: coords(0.0),
+    });
+    component.send_update(received(1.0));
+    component.apply_received_update(received(2.0), now);
+    assert_eq!(1.0, component.coords.x);
+    assert_eq!(
+        1.0,
+        component
+            .pending_update()
+            .unwrap()
+            .coords
+            .as_ref()
+            .unwrap()
+            .x
+    );
+
+    component.take_pending_update().unwrap();
+    component.apply_received_update(received(2.0), now);
+    assert_eq!(2.0, component.coords.x);
+}
+
+#[test]
+fn received_updates_should_only_replace_the_fields_they_set() {
+    use crate::generated_test::*;
+    use std::collections::BTreeMap;
+
+    let now = Instant::now();
+    let blob = |blob| SchemaShapesUpdate {
+        blob: Some(blob),
+        ..Default::default()
+    };
+    let anchors = || {
+        let mut anchors = BTreeMap::new();
+        anchors.insert(
+            1,
+            Coordinates {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        SchemaShapesUpdate {
+            anchors: Some(anchors),
+            ..Default::default()
+        }
+    };
+
+    // Another worker changes the blob while this worker changes the anchors.
+    let mut component = SpatialComponent::new(schema_default::<SchemaShapes>());
+    component.send_update(anchors());
+    component.apply_received_update(blob(vec![7]), now);
+    assert_eq!(vec![7], component.blob);
+    assert_eq!(1.0, component.anchors[&1].x);
+    let pending = component.pending_update().unwrap();
+    assert!(pending.anchors.is_some());
+    assert!(pending.blob.is_none());
+
+    let mut component = SpatialComponent::new(schema_default::<SchemaShapes>());
+    component.blob = vec![3];
+    component.apply_received_update(anchors(), now);
+    assert_eq!(vec![3], component.blob);
+    assert_eq!(1.0, component.anchors[&1].x);
+    assert!(component.is_dirty());
+
+    component.take_pending_update().unwrap();
+    component.apply_received_update(blob(vec![9]), now);
+    assert_eq!(vec![9], component.blob);
+    assert_eq!(1, component.anchors.len());
+}
+
+#[test]
+fn unsent_appends_should_be_applied_as_merged() {
+    use crate::generated_test::*;
+    use std::collections::BTreeMap;
+
+    let append = |item: &str| InventoryUpdate {
+        items: Some(vec![item.to_string()]),
+        slots: None,
+    };
+    let mut component = SpatialComponent::new(Inventory {
+        items: vec!["sword".to_string()],
+        slots: BTreeMap::new(),
+    });
+
+    // The merged update appends, but replaces the field when applied to the value.
+    component.send_update(append("shield"));
+    component.send_update(append("bow"));
+    assert_eq!(vec!["shield", "bow"], component.items);
+
+    component.apply_received_update(append("potion"), Instant::now());
+    assert_eq!(vec!["shield", "bow"], component.items);
+
+    let (_, sent) = component.take_pending_update().unwrap();
+    assert_eq!(
+        Some(vec!["shield".to_string(), "bow".to_string()]),
+        sent.items
+    );
+    component.apply_received_update(append("arrow"), Instant::now());
+    assert_eq!(vec!["arrow"], component.items);
+}
+
+#[test]
+fn stamps_should_only_set_the_stamped_fields_of_the_value() {
+    use crate::generated_test::*;
+    use std::collections::BTreeMap;
+
+    let mut component = SpatialComponent::new(schema_default::<SchemaShapes>());
+    component.send_update(SchemaShapesUpdate {
+        blob: Some(vec![1]),
+        ..Default::default()
+    });
+    component.take_pending_update().unwrap();
+
+    component.stamp_value(&|update: &mut SchemaShapesUpdate| {
+        let mut anchors = BTreeMap::new();
+        anchors.insert(
+            42,
+            Coordinates {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        update.anchors = Some(anchors);
+    });
+    assert_eq!(vec![1], component.blob);
+    assert!(component.anchors.contains_key(&42));
+    assert!(!component.has_pending_update());
+}
+
+#[test]
+fn checksums_should_not_append_twice() {
+    use crate::generated_test::*;
+
+    let coordinates = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    let moves = |xs: &[f64]| CounterUpdate {
+        moves: Some(xs.iter().cloned().map(coordinates).collect()),
+        ..Default::default()
+    };
+
+    let mut component = SpatialComponent::new(Counter {
+        total: 1,
+        moves: vec![coordinates(1.0)],
+    });
+    let (local, sent) = component.checksums().unwrap();
+    assert_eq!(local, sent);
+
+    // The pending update is applied to the value from before it.
+    component.send_update(moves(&[2.0]));
+    component.send_update(moves(&[3.0, 4.0]));
+    let (local, sent) = component.checksums().unwrap();
+    assert_eq!(local, sent);
+
+    // A full update is applied to the schema default value.
+    component.total = 5;
+    let (local, sent) = component.checksums().unwrap();
+    assert_eq!(local, sent);
+
+    // A change which isn't in the update is a mismatch.
+    component.take_pending_update().unwrap();
+    component.send_update(moves(&[5.0]));
+    component.value.total = 6;
+    let (local, sent) = component.checksums().unwrap();
+    assert_ne!(local, sent);
+}
+
+#[test]
+fn pending_updates_should_be_restored_from_their_serialization() {
+    use crate::generated_test::*;
+
+    let coordinates = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    let counter = || Counter {
+        total: 1,
+        moves: vec![coordinates(1.0)],
+    };
+
+    let mut component = SpatialComponent::new(counter());
+    assert_eq!(None, component.serialize_pending_update().unwrap());
+    component.send_update(CounterUpdate {
+        total: Some(2),
+        moves: Some(vec![coordinates(2.0)]),
+    });
+    let bytes = component.serialize_pending_update().unwrap().unwrap();
+
+    let mut restored = SpatialComponent::new(counter());
+    restored.restore_pending_update(&bytes).unwrap();
+    assert_eq!(format!("{:?}", *component), format!("{:?}", *restored));
+    assert_eq!(
+        format!("{:?}", component.take_pending_update().unwrap().1),
+        format!("{:?}", restored.take_pending_update().unwrap().1)
+    );
+}
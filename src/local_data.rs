@@ -0,0 +1,117 @@
+//! Local data kept alongside a SpatialOS component, in the same storage slot.
+//!
+//! Data which is derived from a component but never replicated, such as a mesh handle
+//! for a `Renderable`, would otherwise need a parallel local component, added and removed
+//! in step with the SpatialOS one. Instead it can be attached to the `SpatialComponent`:
+//!
+//! ```ignore
+//! for renderable in (&mut renderables).join() {
+//!     let path = renderable.mesh_path.clone();
+//!     let mesh = renderable
+//!         .local_mut()
+//!         .get_or_insert_with(|| MeshHandle::load(&path));
+//!     mesh.draw();
+//! }
+//! ```
+//!
+//! Local data is kept while the component is checked out, including across received
+//! updates, and is dropped with the component. Changing it doesn't send an update.
+use std::any::Any;
+use std::fmt;
+
+/// Values of any type attached to a `SpatialComponent`, holding at most one of each type.
+#[derive(Default)]
+pub struct LocalData {
+    values: Vec<Box<Any + Send + Sync>>,
+}
+
+impl LocalData {
+    pub fn get<L: 'static>(&self) -> Option<&L> {
+        self.values
+            .iter()
+            .find_map(|value| value.downcast_ref::<L>())
+    }
+
+    pub fn get_mut<L: 'static>(&mut self) -> Option<&mut L> {
+        self.values
+            .iter_mut()
+            .find_map(|value| value.downcast_mut::<L>())
+    }
+
+    /// Attaches the value, returning the previous value of the same type.
+    pub fn insert<L: 'static + Send + Sync>(&mut self, value: L) -> Option<L> {
+        let previous = self.remove::<L>();
+        self.values.push(Box::new(value));
+        previous
+    }
+
+    pub fn remove<L: 'static>(&mut self) -> Option<L> {
+        let index = self.values.iter().position(|value| value.is::<L>())?;
+        self.values
+            .swap_remove(index)
+            .downcast::<L>()
+            .ok()
+            .map(|value| *value)
+    }
+
+    pub fn get_or_insert_with<L, F>(&mut self, default: F) -> &mut L
+    where
+        L: 'static + Send + Sync,
+        F: FnOnce() -> L,
+    {
+        if self.get::<L>().is_none() {
+            self.values.push(Box::new(default()));
+        }
+        self.get_mut::<L>().unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for LocalData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LocalData({} values)", self.values.len())
+    }
+}
+
+#[test]
+fn local_data_should_survive_received_updates_without_dirtying() {
+    use crate::generated_test::*;
+    use crate::SpatialComponent;
+    use std::time::Instant;
+
+    #[derive(Debug, PartialEq)]
+    struct MeshHandle(u32);
+
+    let mut component = SpatialComponent::new(Position {
+        coords: Coordinates {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+    });
+
+    assert_eq!(
+        &mut MeshHandle(1),
+        component.local_mut().get_or_insert_with(|| MeshHandle(1))
+    );
+    assert_eq!(None, component.local_mut().insert(7u64));
+    assert!(!component.has_pending_update());
+
+    component.apply_received_update(
+        PositionUpdate {
+            coords: Some(Coordinates {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }),
+        },
+        Instant::now(),
+    );
+
+    assert_eq!(Some(&MeshHandle(1)), component.local().get::<MeshHandle>());
+    assert_eq!(Some(7), component.local_mut().remove::<u64>());
+    assert_eq!(None, component.local().get::<u64>());
+}
@@ -0,0 +1,257 @@
+//! A small logging facility for soft errors encountered while applying ops or
+//! replicating changes.
+//!
+//! Messages are sent to a pluggable `LogSink` and are rate limited per `LogKind`,
+//! so that, for example, a flood of malformed components does not also flood stdout.
+use crate::clock;
+use specs::prelude::World;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// The kind of event being logged. Rate limiting is applied separately to each kind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LogKind {
+    /// A response arrived for a request ID which this worker is not waiting on.
+    UnknownRequestId,
+    /// Component data, an update or a command could not be deserialized.
+    ComponentDeserialization,
+    /// The summary logged by a `FrameReport`.
+    FrameReport,
+    /// The summary logged by an `ArchetypeStats`.
+    ArchetypeReport,
+    /// An op referred to an entity which is not checked out, or added one which already is.
+    UnexpectedEntity,
+    /// Ops for a component outside the `ComponentAllowlist` were dropped.
+    ComponentNotAllowed,
+    /// Received component data could not be inserted into storage.
+    ComponentInsertion,
+    /// A pending update was dropped because its component was removed.
+    DroppedUpdate,
+    /// A command request was dropped, rejected or failed without being sent.
+    CommandRejected,
+    /// The schema hash was checked against the deployment's.
+    SchemaVersion,
+    /// A component could not be serialized for `Persistence`.
+    Persistence,
+    /// A checksum mismatch, or a failure to compute a checksum.
+    Checksum,
+    /// A change in `ConnectionHealth`.
+    ConnectionHealth,
+    /// A partition could not be claimed.
+    Partition,
+    /// Evicted components could not be refreshed.
+    Eviction,
+    /// A step of a saga could not be compensated.
+    Saga,
+    /// A player joined or left, or their entities could not be cleaned up.
+    PlayerLifecycle,
+    /// Progress of a graceful shutdown.
+    Shutdown,
+    /// A fault injected by the `ChaosMonkey`.
+    Chaos,
+    /// Any other soft error.
+    Other,
+}
+
+/// A destination for log messages.
+pub trait LogSink {
+    fn log(&self, level: LogLevel, kind: LogKind, message: &str);
+}
+
+impl<F: Fn(LogLevel, LogKind, &str)> LogSink for F {
+    fn log(&self, level: LogLevel, kind: LogKind, message: &str) {
+        self(level, kind, message)
+    }
+}
+
+/// A `LogSink` which prints every message to stdout.
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn log(&self, level: LogLevel, kind: LogKind, message: &str) {
+        println!("[{:?}] [{:?}] {}", level, kind, message);
+    }
+}
+
+struct RateLimitState {
+    window_start: Instant,
+    logged: u32,
+    suppressed: u32,
+}
+
+/// A resource which receives log messages from this crate and forwards them to a sink.
+///
+/// By default, at most 10 messages of each `LogKind` are logged every 10 seconds, and
+/// messages are printed to stdout. When messages have been suppressed, a summary is logged
+/// once the next window starts.
+pub struct SpatialLogger {
+    sink: Box<LogSink + Send + Sync>,
+    min_level: LogLevel,
+    max_per_window: u32,
+    window: Duration,
+    rate_limits: HashMap<LogKind, RateLimitState>,
+}
+
+impl SpatialLogger {
+    pub fn new<S: 'static + LogSink + Send + Sync>(sink: S) -> SpatialLogger {
+        SpatialLogger {
+            sink: Box::new(sink),
+            min_level: LogLevel::Info,
+            max_per_window: 10,
+            window: Duration::from_secs(10),
+            rate_limits: HashMap::new(),
+        }
+    }
+
+    pub fn set_sink<S: 'static + LogSink + Send + Sync>(&mut self, sink: S) {
+        self.sink = Box::new(sink);
+    }
+
+    /// Messages below this level are discarded.
+    pub fn set_min_level(&mut self, level: LogLevel) {
+        self.min_level = level;
+    }
+
+    /// Sets how many messages of each kind may be logged within each window.
+    pub fn set_rate_limit(&mut self, max_per_window: u32, window: Duration) {
+        self.max_per_window = max_per_window;
+        self.window = window;
+    }
+
+    /// Logs a message, rate limiting against the system clock. Messages logged by this
+    /// crate are rate limited against the `SpatialClock` instead.
+    pub fn log(&mut self, level: LogLevel, kind: LogKind, message: &str) {
+        self.log_at(Instant::now(), level, kind, message);
+    }
+
+    pub(crate) fn log_at(&mut self, now: Instant, level: LogLevel, kind: LogKind, message: &str) {
+        if level < self.min_level {
+            return;
+        }
+
+        let state = self.rate_limits.entry(kind).or_insert(RateLimitState {
+            window_start: now,
+            logged: 0,
+            suppressed: 0,
+        });
+
+        if now.duration_since(state.window_start) >= self.window {
+            if state.suppressed > 0 {
+                self.sink.log(
+                    level,
+                    kind,
+                    &format!(
+                        "{} similar messages were suppressed in the last {:?}.",
+                        state.suppressed, self.window
+                    ),
+                );
+            }
+
+            state.window_start = now;
+            state.logged = 0;
+            state.suppressed = 0;
+        }
+
+        if state.logged < self.max_per_window {
+            state.logged += 1;
+            self.sink.log(level, kind, message);
+        } else {
+            state.suppressed += 1;
+        }
+    }
+}
+
+impl Default for SpatialLogger {
+    fn default() -> Self {
+        SpatialLogger::new(StdoutSink)
+    }
+}
+
+pub(crate) fn log(res: &World, level: LogLevel, kind: LogKind, message: &str) {
+    if res.has_value::<SpatialLogger>() {
+        let now = clock::now(res);
+        res.fetch_mut::<SpatialLogger>()
+            .log_at(now, level, kind, message);
+    } else {
+        StdoutSink.log(level, kind, message);
+    }
+}
+
+pub(crate) fn warn_unknown_request_id<I: Debug>(res: &World, request_id: I) {
+    log(
+        res,
+        LogLevel::Warn,
+        LogKind::UnknownRequestId,
+        &format!("Unknown request ID: {:?}", request_id),
+    );
+}
+
+#[test]
+fn logger_should_rate_limit_per_kind() {
+    use std::sync::{Arc, Mutex};
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let sink_messages = messages.clone();
+
+    let mut logger = SpatialLogger::new(move |_: LogLevel, kind: LogKind, message: &str| {
+        sink_messages
+            .lock()
+            .unwrap()
+            .push((kind, message.to_string()))
+    });
+    logger.set_rate_limit(2, Duration::from_secs(3600));
+
+    for _ in 0..5 {
+        logger.log(LogLevel::Warn, LogKind::UnknownRequestId, "unknown");
+    }
+    logger.log(
+        LogLevel::Warn,
+        LogKind::ComponentDeserialization,
+        "bad data",
+    );
+    logger.log(LogLevel::Debug, LogKind::Other, "discarded");
+
+    let messages = messages.lock().unwrap();
+    assert_eq!(3, messages.len());
+    assert_eq!(LogKind::ComponentDeserialization, messages[2].0);
+}
+
+#[test]
+fn logger_should_summarize_suppressed_messages_in_next_window() {
+    use crate::clock::{ManualClock, SpatialClock};
+    use specs::prelude::{World, WorldExt};
+    use std::sync::{Arc, Mutex};
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let sink_messages = messages.clone();
+
+    let mut logger = SpatialLogger::new(move |_: LogLevel, _: LogKind, message: &str| {
+        sink_messages.lock().unwrap().push(message.to_string())
+    });
+    logger.set_rate_limit(1, Duration::from_secs(10));
+
+    let clock = ManualClock::new();
+    let mut world = World::new();
+    world.insert(logger);
+    world.insert(SpatialClock::new(clock.clone()));
+
+    for _ in 0..3 {
+        log(&world, LogLevel::Warn, LogKind::Other, "first window");
+    }
+    clock.advance(Duration::from_secs(10));
+    log(&world, LogLevel::Warn, LogKind::Other, "second window");
+
+    let messages = messages.lock().unwrap();
+    assert_eq!(3, messages.len());
+    assert!(messages[1].starts_with("2 similar messages"));
+    assert_eq!("second window", messages[2]);
+}
@@ -0,0 +1,164 @@
+//! Helpers for combining collection fields of pending updates in `ComponentUpdate::merge`.
+//!
+//! By default, SpatialOS treats every field of an update as a replacement of the
+//! previous value. For list and map fields which are used as deltas (for example,
+//! a list of events which occurred this frame), merging two pending updates with
+//! last-write-wins semantics would silently drop data. The code generator has no option
+//! for this, so the strategy is chosen by hand: a `MergeStrategy` constant is added for
+//! each such field, and the generated `ComponentUpdate::merge` is changed to call the
+//! functions in this module with it:
+//!
+//! ```ignore
+//! impl InventoryUpdate {
+//!     pub const ITEMS_MERGE_STRATEGY: MergeStrategy = MergeStrategy::Append;
+//! }
+//!
+//! impl ComponentUpdate<Inventory> for InventoryUpdate {
+//!     fn merge(&mut self, update: InventoryUpdate) {
+//!         merge_list(Self::ITEMS_MERGE_STRATEGY, &mut self.items, update.items);
+//!     }
+//! }
+//! ```
+//!
+//! The strategy only applies to merging one update into another. Applying an update to
+//! component data with `ComponentData::merge` still replaces each field which is set, as
+//! SpatialOS does.
+use std::collections::BTreeMap;
+
+/// How a collection field of a pending update is combined with the same field
+/// of a later update.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The later value replaces the earlier value.
+    Replace,
+    /// The elements of the later value are appended to the earlier value. For maps,
+    /// entries of the later value are inserted into the earlier value, overwriting
+    /// entries with the same key.
+    Append,
+}
+
+impl Default for MergeStrategy {
+    fn default() -> Self {
+        MergeStrategy::Replace
+    }
+}
+
+/// Merges an optional list field of an update into the pending value of that field.
+pub fn merge_list<T>(
+    strategy: MergeStrategy,
+    current: &mut Option<Vec<T>>,
+    update: Option<Vec<T>>,
+) {
+    let update = match update {
+        Some(update) => update,
+        None => return,
+    };
+
+    match (strategy, current.as_mut()) {
+        (MergeStrategy::Append, Some(current)) => current.extend(update),
+        _ => *current = Some(update),
+    }
+}
+
+/// Merges an optional map field of an update into the pending value of that field.
+pub fn merge_map<K: Ord, V>(
+    strategy: MergeStrategy,
+    current: &mut Option<BTreeMap<K, V>>,
+    update: Option<BTreeMap<K, V>>,
+) {
+    let update = match update {
+        Some(update) => update,
+        None => return,
+    };
+
+    match (strategy, current.as_mut()) {
+        (MergeStrategy::Append, Some(current)) => current.extend(update),
+        _ => *current = Some(update),
+    }
+}
+
+#[test]
+fn replace_should_keep_latest_list() {
+    let mut current = Some(vec![1, 2]);
+    merge_list(MergeStrategy::Replace, &mut current, Some(vec![3]));
+    assert_eq!(Some(vec![3]), current);
+
+    merge_list(MergeStrategy::Replace, &mut current, None);
+    assert_eq!(Some(vec![3]), current);
+}
+
+#[test]
+fn append_should_concatenate_lists() {
+    let mut current = None;
+    merge_list(MergeStrategy::Append, &mut current, Some(vec![1, 2]));
+    merge_list(MergeStrategy::Append, &mut current, None);
+    merge_list(MergeStrategy::Append, &mut current, Some(vec![3]));
+    assert_eq!(Some(vec![1, 2, 3]), current);
+}
+
+#[test]
+fn append_should_insert_map_entries() {
+    let mut first = BTreeMap::new();
+    first.insert("a", 1);
+    first.insert("b", 2);
+
+    let mut second = BTreeMap::new();
+    second.insert("b", 3);
+    second.insert("c", 4);
+
+    let mut appended = Some(first.clone());
+    merge_map(MergeStrategy::Append, &mut appended, Some(second.clone()));
+    let appended = appended.unwrap();
+    assert_eq!(3, appended.len());
+    assert_eq!(Some(&3), appended.get("b"));
+
+    let mut replaced = Some(first);
+    merge_map(MergeStrategy::Replace, &mut replaced, Some(second.clone()));
+    assert_eq!(Some(second), replaced);
+}
+
+#[test]
+fn generated_update_merge_should_honor_field_strategies() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::component::ComponentUpdate;
+
+    let mut events = BTreeMap::new();
+    events.insert(1, "spawned".to_string());
+
+    let mut update = InventoryUpdate {
+        items: Some(vec!["sword".to_string()]),
+        slots: Some(events),
+    };
+
+    let mut events = BTreeMap::new();
+    events.insert(2, "equipped".to_string());
+
+    update.merge(InventoryUpdate {
+        items: Some(vec!["shield".to_string()]),
+        slots: Some(events),
+    });
+
+    assert_eq!(
+        Some(vec!["sword".to_string(), "shield".to_string()]),
+        update.items
+    );
+    assert_eq!(1, update.slots.unwrap().len());
+}
+
+#[test]
+fn generated_data_merge_should_replace_collections() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::component::ComponentData;
+
+    let mut inventory = Inventory {
+        items: vec!["torch".to_string()],
+        slots: BTreeMap::new(),
+    };
+    inventory.merge(InventoryUpdate {
+        items: Some(vec!["sword".to_string(), "shield".to_string()]),
+        slots: None,
+    });
+
+    assert_eq!(vec!["sword", "shield"], inventory.items);
+    assert!(inventory.slots.is_empty());
+}
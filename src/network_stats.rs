@@ -0,0 +1,153 @@
+//! How many logical updates were merged into each update actually sent, so that the
+//! bandwidth saved by coalescing can be measured and rate limiting policies tuned.
+//!
+//! Every call to `send_update`, mutable dereference and `mark_full_resend` between two
+//! sends of a component is a logical update, and all of them leave the worker as a single
+//! update at the end of the frame. Full resends made by `DriftRepair` repeat the value
+//! rather than change it, so aren't logical updates, and a resend with no other logical
+//! update isn't counted. Adding a `NetworkStats` resource enables counting them:
+//!
+//! ```ignore
+//! world.insert(NetworkStats::default());
+//!
+//! // Later, for example once a minute:
+//! for (component_id, stat) in world.fetch::<NetworkStats>().iter() {
+//!     println!(
+//!         "{}: {} logical updates sent as {} ({} coalesced)",
+//!         component_id, stat.logical_updates, stat.updates_sent, stat.coalesced()
+//!     );
+//! }
+//! world.fetch_mut::<NetworkStats>().reset();
+//! ```
+//!
+//! The counts of the most recent frame are kept separately in `last_frame`.
+use spatialos_sdk::worker::component::ComponentId;
+use std::collections::HashMap;
+
+/// The updates sent for a component and the logical updates merged into them.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CoalescingStat {
+    pub updates_sent: u64,
+    pub logical_updates: u64,
+    /// The most logical updates merged into a single sent update.
+    pub max_coalesced: u64,
+}
+
+impl CoalescingStat {
+    /// The number of updates which didn't have to be sent because they were merged into
+    /// another.
+    pub fn coalesced(&self) -> u64 {
+        self.logical_updates.saturating_sub(self.updates_sent)
+    }
+
+    /// The mean number of logical updates per sent update.
+    pub fn ratio(&self) -> f64 {
+        if self.updates_sent == 0 {
+            0.0
+        } else {
+            self.logical_updates as f64 / self.updates_sent as f64
+        }
+    }
+
+    fn add(&mut self, other: &CoalescingStat) {
+        self.updates_sent += other.updates_sent;
+        self.logical_updates += other.logical_updates;
+        self.max_coalesced = self.max_coalesced.max(other.max_coalesced);
+    }
+}
+
+/// A resource which enables counting of coalesced updates. The totals accumulate until
+/// `reset` is called. Separate totals, which are never reset, are exported as Prometheus
+/// counters, so that they only ever increase.
+#[derive(Debug, Default)]
+pub struct NetworkStats {
+    totals: HashMap<ComponentId, CoalescingStat>,
+    lifetime: HashMap<ComponentId, CoalescingStat>,
+    last_frame: HashMap<ComponentId, CoalescingStat>,
+    frame: HashMap<ComponentId, CoalescingStat>,
+}
+
+impl NetworkStats {
+    pub fn get(&self, component_id: ComponentId) -> CoalescingStat {
+        self.totals.get(&component_id).cloned().unwrap_or_default()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (ComponentId, CoalescingStat)> + 'a {
+        self.totals.iter().map(|(id, stat)| (*id, *stat))
+    }
+
+    /// The counts of the component during the most recently finished frame.
+    pub fn last_frame(&self, component_id: ComponentId) -> CoalescingStat {
+        self.last_frame
+            .get(&component_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Clears the totals returned by `get` and `iter`.
+    pub fn reset(&mut self) {
+        self.totals.clear();
+    }
+
+    // The totals since the resource was added, which `reset` doesn't clear.
+    pub(crate) fn lifetime<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (ComponentId, CoalescingStat)> + 'a {
+        self.lifetime.iter().map(|(id, stat)| (*id, *stat))
+    }
+
+    pub(crate) fn record_sent(&mut self, component_id: ComponentId, logical_updates: u32) {
+        let stat = self
+            .frame
+            .entry(component_id)
+            .or_insert_with(CoalescingStat::default);
+        let logical_updates = u64::from(logical_updates.max(1));
+        stat.updates_sent += 1;
+        stat.logical_updates += logical_updates;
+        stat.max_coalesced = stat.max_coalesced.max(logical_updates);
+    }
+
+    pub(crate) fn finish_frame(&mut self) {
+        for (component_id, stat) in &self.frame {
+            for totals in &mut [&mut self.totals, &mut self.lifetime] {
+                totals
+                    .entry(*component_id)
+                    .or_insert_with(CoalescingStat::default)
+                    .add(stat);
+            }
+        }
+
+        self.last_frame = std::mem::replace(&mut self.frame, HashMap::new());
+    }
+}
+
+#[test]
+fn network_stats_should_count_coalesced_updates_per_frame() {
+    let mut stats = NetworkStats::default();
+
+    stats.record_sent(54, 3);
+    stats.record_sent(54, 1);
+    stats.record_sent(1000, 0);
+    assert_eq!(0, stats.get(54).updates_sent);
+
+    stats.finish_frame();
+    let frame = stats.last_frame(54);
+    assert_eq!(2, frame.updates_sent);
+    assert_eq!(4, frame.logical_updates);
+    assert_eq!(2, frame.coalesced());
+    assert_eq!(3, frame.max_coalesced);
+    assert_eq!(1, stats.last_frame(1000).logical_updates);
+
+    stats.record_sent(54, 2);
+    stats.finish_frame();
+    assert_eq!(1, stats.last_frame(54).coalesced());
+    assert_eq!(0, stats.last_frame(1000).updates_sent);
+    assert_eq!(3, stats.get(54).coalesced());
+    assert_eq!(2.0, stats.get(54).ratio());
+
+    stats.reset();
+    assert_eq!(0, stats.get(54).updates_sent);
+    let lifetime: HashMap<_, _> = stats.lifetime().collect();
+    assert_eq!(3, lifetime[&54].updates_sent);
+    assert_eq!(3, lifetime[&54].coalesced());
+}
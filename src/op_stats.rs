@@ -0,0 +1,166 @@
+//! The time spent applying each kind of op, so that components which are expensive to
+//! deserialize or react to can be found from metrics alone.
+//!
+//! Adding an `OpStats` resource enables the timing, which is done by the
+//! `SpatialReaderSystem` around each op it applies:
+//!
+//! ```ignore
+//! world.insert(OpStats::default());
+//!
+//! // Later, for example once a minute:
+//! for (category, timing) in world.fetch::<OpStats>().slowest(5) {
+//!     println!("{}: {} ops, {:?} total", category, timing.count, timing.total);
+//! }
+//! world.fetch_mut::<OpStats>().reset();
+//! ```
+use crate::component_registry::describe_component;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::op::WorkerOp;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// A kind of op. Ops which concern a component are categorised by the component.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OpCategory {
+    AddEntity,
+    RemoveEntity,
+    AddComponent(ComponentId),
+    RemoveComponent(ComponentId),
+    ComponentUpdate(ComponentId),
+    AuthorityChange(ComponentId),
+    CommandRequest(ComponentId),
+    CommandResponse(ComponentId),
+    /// System command responses, worker flags and every other op.
+    Other,
+}
+
+impl OpCategory {
+    pub fn of(op: &WorkerOp) -> OpCategory {
+        match op {
+            WorkerOp::AddEntity(_) => OpCategory::AddEntity,
+            WorkerOp::RemoveEntity(_) => OpCategory::RemoveEntity,
+            WorkerOp::AddComponent(op) => OpCategory::AddComponent(op.component_id),
+            WorkerOp::RemoveComponent(op) => OpCategory::RemoveComponent(op.component_id),
+            WorkerOp::ComponentUpdate(op) => OpCategory::ComponentUpdate(op.component_id),
+            WorkerOp::AuthorityChange(op) => OpCategory::AuthorityChange(op.component_id),
+            WorkerOp::CommandRequest(op) => OpCategory::CommandRequest(op.component_id),
+            WorkerOp::CommandResponse(op) => OpCategory::CommandResponse(op.component_id),
+            _ => OpCategory::Other,
+        }
+    }
+}
+
+impl fmt::Display for OpCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpCategory::AddEntity => write!(f, "add entity"),
+            OpCategory::RemoveEntity => write!(f, "remove entity"),
+            OpCategory::AddComponent(id) => write!(f, "add component {}", describe_component(*id)),
+            OpCategory::RemoveComponent(id) => {
+                write!(f, "remove component {}", describe_component(*id))
+            }
+            OpCategory::ComponentUpdate(id) => {
+                write!(f, "component update {}", describe_component(*id))
+            }
+            OpCategory::AuthorityChange(id) => {
+                write!(f, "authority change {}", describe_component(*id))
+            }
+            OpCategory::CommandRequest(id) => {
+                write!(f, "command request {}", describe_component(*id))
+            }
+            OpCategory::CommandResponse(id) => {
+                write!(f, "command response {}", describe_component(*id))
+            }
+            OpCategory::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// The time spent applying ops of one category.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct OpTiming {
+    pub count: usize,
+    pub total: Duration,
+    /// The longest time spent applying a single op.
+    pub max: Duration,
+}
+
+impl OpTiming {
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::from_secs(0)
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+/// A resource which enables timing of the ops applied by the `SpatialReaderSystem`. The
+/// timings accumulate until `reset` is called.
+#[derive(Debug, Default)]
+pub struct OpStats {
+    timings: HashMap<OpCategory, OpTiming>,
+}
+
+impl OpStats {
+    pub fn get(&self, category: OpCategory) -> OpTiming {
+        self.timings.get(&category).cloned().unwrap_or_default()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (OpCategory, OpTiming)> + 'a {
+        self.timings
+            .iter()
+            .map(|(category, timing)| (*category, *timing))
+    }
+
+    /// Returns the `count` categories with the most total time, most expensive first.
+    pub fn slowest(&self, count: usize) -> Vec<(OpCategory, OpTiming)> {
+        let mut timings: Vec<(OpCategory, OpTiming)> = self.iter().collect();
+        timings.sort_by(|a, b| b.1.total.cmp(&a.1.total));
+        timings.truncate(count);
+        timings
+    }
+
+    pub fn reset(&mut self) {
+        self.timings.clear();
+    }
+
+    pub(crate) fn record(&mut self, category: OpCategory, elapsed: Duration) {
+        let timing = self
+            .timings
+            .entry(category)
+            .or_insert_with(OpTiming::default);
+        timing.count += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+    }
+}
+
+#[test]
+fn op_stats_should_rank_categories_by_total_time() {
+    let mut stats = OpStats::default();
+    let millis = Duration::from_millis;
+
+    stats.record(OpCategory::ComponentUpdate(54), millis(1));
+    stats.record(OpCategory::ComponentUpdate(54), millis(5));
+    stats.record(OpCategory::AddEntity, millis(2));
+    stats.record(OpCategory::CommandRequest(1000), millis(4));
+
+    let update = stats.get(OpCategory::ComponentUpdate(54));
+    assert_eq!(2, update.count);
+    assert_eq!(millis(5), update.max);
+    assert_eq!(millis(3), update.mean());
+
+    let slowest: Vec<OpCategory> = stats.slowest(2).into_iter().map(|(c, _)| c).collect();
+    assert_eq!(
+        vec![
+            OpCategory::ComponentUpdate(54),
+            OpCategory::CommandRequest(1000)
+        ],
+        slowest
+    );
+
+    stats.reset();
+    assert_eq!(0, stats.get(OpCategory::AddEntity).count);
+}
@@ -0,0 +1,177 @@
+//! Which worker or client owns each entity, derived from a component of the entity.
+//!
+//! Games record ownership in different places: the write ACL of a client-authoritative
+//! component, or a dedicated schema component. An `OwnershipConfig` says where, and the
+//! owner is then kept in the `Owner` component of every entity, updated whenever the
+//! component is received:
+//!
+//! ```ignore
+//! world.register::<Owner>();
+//! // The client which PlayerInput is delegated to, using the `Acl` implementation of
+//! // `EntityAcl`.
+//! world.insert(OwnershipConfig::from_write_acl::<EntityAcl>(PlayerInput::ID));
+//!
+//! let owned = entities_owned_by(&entities, &owners, "UnityClient0");
+//! ```
+//!
+//! With `OwnerCleanup::DeleteWithPlayer`, a player leaving the `PlayerLifecycle` also
+//! deletes every entity they own, in addition to those registered with it.
+use crate::acl::Acl;
+use crate::entities::{EntityId, EntityIds};
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{
+    Component, DenseVecStorage, Entities, Entity, Join, ReadStorage, SystemData, World,
+    WriteStorage,
+};
+use specs::storage::MaskedStorage;
+use std::any::Any;
+
+/// The worker or client which owns an entity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub worker_id: String,
+}
+
+impl Component for Owner {
+    type Storage = DenseVecStorage<Self>;
+}
+
+pub type Owners<'a> = ReadStorage<'a, Owner>;
+
+/// Returns every entity owned by the worker.
+pub fn entities_owned_by(entities: &Entities, owners: &Owners, worker_id: &str) -> Vec<Entity> {
+    (entities, owners)
+        .join()
+        .filter(|(_, owner)| owner.worker_id == worker_id)
+        .map(|(entity, _)| entity)
+        .collect()
+}
+
+/// What happens to the entities of a player who leaves the `PlayerLifecycle`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OwnerCleanup {
+    /// Only entities registered with the `PlayerLifecycle` are cleaned up.
+    None,
+    /// Every entity the player owns is cleaned up with them.
+    DeleteWithPlayer,
+}
+
+type OwnerExtractor = Box<Fn(&Any) -> Option<String> + Send + Sync>;
+
+/// A resource which enables recording of the `Owner` of entities from a component.
+pub struct OwnershipConfig {
+    owner_component: ComponentId,
+    owner_extractor: OwnerExtractor,
+    cleanup: OwnerCleanup,
+}
+
+impl OwnershipConfig {
+    /// Records the owner returned from the component `C`. Entities for which it returns
+    /// `None` have no `Owner`.
+    pub fn new<C, F>(owner: F) -> OwnershipConfig
+    where
+        C: 'static + WorkerComponent,
+        F: 'static + Fn(&C) -> Option<String> + Send + Sync,
+    {
+        OwnershipConfig {
+            owner_component: C::ID,
+            owner_extractor: Box::new(move |value| value.downcast_ref::<C>().and_then(&owner)),
+            cleanup: OwnerCleanup::None,
+        }
+    }
+
+    /// Records the worker which the ACL component `A` delegates the component
+    /// `component_id` to, as returned by `Acl::writer`.
+    pub fn from_write_acl<A>(component_id: ComponentId) -> OwnershipConfig
+    where
+        A: 'static + WorkerComponent + Acl,
+    {
+        OwnershipConfig::new(move |acl: &A| acl.writer(component_id).map(String::from))
+    }
+
+    /// Sets what happens to the entities of players who leave. The default is
+    /// `OwnerCleanup::None`.
+    pub fn set_cleanup(&mut self, cleanup: OwnerCleanup) {
+        self.cleanup = cleanup;
+    }
+
+    pub(crate) fn is_owner_component(&self, component_id: ComponentId) -> bool {
+        component_id == self.owner_component
+    }
+
+    pub(crate) fn record(res: &World, entity: Entity, value: &Any) {
+        let owner = (res.fetch::<OwnershipConfig>().owner_extractor)(value);
+
+        if !res.has_value::<MaskedStorage<Owner>>() {
+            return;
+        }
+
+        let mut owners = WriteStorage::<Owner>::fetch(res);
+        match owner {
+            Some(worker_id) => {
+                if owners.get(entity).map(|owner| &owner.worker_id) != Some(&worker_id) {
+                    owners
+                        .insert(entity, Owner { worker_id })
+                        .expect("Error inserting Owner.");
+                }
+            }
+            None => {
+                owners.remove(entity);
+            }
+        }
+    }
+
+    pub(crate) fn owner_component_removed(res: &World, entity: Entity) {
+        if res.has_value::<MaskedStorage<Owner>>() {
+            WriteStorage::<Owner>::fetch(res).remove(entity);
+        }
+    }
+
+    // The entities to clean up when the player leaves.
+    pub(crate) fn entities_to_clean_up(res: &World, worker_id: &str) -> Vec<EntityId> {
+        if res.fetch::<OwnershipConfig>().cleanup != OwnerCleanup::DeleteWithPlayer
+            || !res.has_value::<MaskedStorage<Owner>>()
+        {
+            return Vec::new();
+        }
+
+        let entity_ids = EntityIds::fetch(res);
+        entities_owned_by(&Entities::fetch(res), &Owners::fetch(res), worker_id)
+            .into_iter()
+            .filter_map(|entity| entity_ids.get_entity_id(entity))
+            .collect()
+    }
+}
+
+#[test]
+fn owners_should_be_recorded_from_component() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    world.register::<Owner>();
+    world.insert(OwnershipConfig::new(|position: &Position| {
+        if position.coords.x > 0.0 {
+            Some("client".to_string())
+        } else {
+            None
+        }
+    }));
+
+    let entities: Vec<Entity> = (0..3).map(|_| world.create_entity().build()).collect();
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    OwnershipConfig::record(&world, entities[0], &position(1.0));
+    OwnershipConfig::record(&world, entities[1], &position(1.0));
+    OwnershipConfig::record(&world, entities[2], &position(1.0));
+    OwnershipConfig::record(&world, entities[1], &position(-1.0));
+    OwnershipConfig::owner_component_removed(&world, entities[2]);
+
+    assert_eq!(
+        vec![entities[0]],
+        entities_owned_by(&world.entities(), &world.read_storage(), "client")
+    );
+}
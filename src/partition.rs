@@ -0,0 +1,185 @@
+//! Authority delegated through partitions, for runtime versions which no longer use
+//! `EntityAcl` write ACLs.
+//!
+//! In partition mode, selected with the `partitions` feature, components are delegated
+//! to partitions rather than to worker attributes, and a worker is authoritative over
+//! whatever is delegated to the partitions it has claimed. Authority changes are then
+//! received and applied as in ACL mode.
+//!
+//! A `PartitionSystem` only runs its inner system while its partition is claimed, so that
+//! each subset of a worker's systems can be mapped to the partition it simulates:
+//!
+//! ```ignore
+//! world.fetch_mut::<Partitions>().claim(physics_partition);
+//!
+//! let mut dispatcher = DispatcherBuilder::new()
+//!     .with(SpatialReaderSystem, "reader", &[])
+//!     .with_barrier()
+//!     .with(PartitionSystem::new(physics_partition, PhysicsSys), "physics", &[])
+//!     .with(PartitionSystem::new(ai_partition, AiSys), "ai", &[])
+//!     .with_barrier()
+//!     .with(SpatialWriterSystem, "writer", &[])
+//!     .build();
+//! ```
+use crate::entities::EntityId;
+use crate::logging::{self, LogKind, LogLevel};
+use crate::sdk::SdkConnection;
+use spatialos_sdk::worker::commands::OutgoingCommandRequest;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::op::{CommandResponseOp, StatusCode};
+use spatialos_sdk::worker::RequestId;
+use specs::prelude::{Read, System, SystemData, World};
+use std::collections::HashMap;
+
+/// The ID of the standard library `Worker` component, whose commands claim partitions.
+pub const WORKER_COMPONENT_ID: ComponentId = 60;
+
+/// The progress of claiming a partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionStatus {
+    Claiming,
+    Claimed,
+    Failed(String),
+}
+
+/// A resource which claims partitions and tracks which have been claimed.
+///
+/// Claims are sent by the `SpatialWriterSystem` and their responses are applied by the
+/// `SpatialReaderSystem`.
+#[derive(Default)]
+pub struct Partitions {
+    statuses: HashMap<EntityId, PartitionStatus>,
+    buffered_claims: Vec<EntityId>,
+    claim_requests: HashMap<RequestId<OutgoingCommandRequest>, EntityId>,
+}
+
+impl Partitions {
+    /// Claims the partition for this worker, so that it becomes authoritative over the
+    /// components delegated to it.
+    pub fn claim(&mut self, partition_id: EntityId) {
+        match self.statuses.get(&partition_id) {
+            Some(PartitionStatus::Claiming) | Some(PartitionStatus::Claimed) => {}
+            _ => {
+                self.statuses
+                    .insert(partition_id, PartitionStatus::Claiming);
+                self.buffered_claims.push(partition_id);
+            }
+        }
+    }
+
+    pub fn status(&self, partition_id: EntityId) -> Option<&PartitionStatus> {
+        self.statuses.get(&partition_id)
+    }
+
+    pub fn is_claimed(&self, partition_id: EntityId) -> bool {
+        self.statuses.get(&partition_id) == Some(&PartitionStatus::Claimed)
+    }
+
+    pub fn claimed<'a>(&'a self) -> impl Iterator<Item = EntityId> + 'a {
+        self.statuses
+            .iter()
+            .filter(|(_, status)| **status == PartitionStatus::Claimed)
+            .map(|(partition_id, _)| *partition_id)
+    }
+
+    pub(crate) fn flush<C: SdkConnection>(&mut self, connection: &mut C) -> usize {
+        let count = self.buffered_claims.len();
+        for partition_id in self.buffered_claims.drain(..) {
+            let request_id = connection.send_claim_partition(partition_id.id());
+            self.claim_requests.insert(request_id, partition_id);
+        }
+        count
+    }
+
+    /// Returns whether the response was to a partition claim.
+    pub(crate) fn got_claim_response(res: &World, response_op: &CommandResponseOp) -> bool {
+        let mut partitions = res.fetch_mut::<Partitions>();
+        let partition_id = match partitions.claim_requests.remove(&response_op.request_id) {
+            Some(partition_id) => partition_id,
+            None => return false,
+        };
+
+        let status = match &response_op.response {
+            StatusCode::Success(_) => PartitionStatus::Claimed,
+            other => {
+                let message = format!("{:?}", other);
+                logging::log(
+                    res,
+                    LogLevel::Error,
+                    LogKind::Partition,
+                    &format!("Failed to claim partition {}: {}", partition_id, message),
+                );
+                PartitionStatus::Failed(message)
+            }
+        };
+
+        partitions.statuses.insert(partition_id, status);
+        true
+    }
+
+    // Claims are made on a connection, so must be made again on a new one.
+    pub(crate) fn reclaim(&mut self) {
+        self.claim_requests.clear();
+        self.buffered_claims = self.statuses.keys().cloned().collect();
+        for status in self.statuses.values_mut() {
+            *status = PartitionStatus::Claiming;
+        }
+    }
+}
+
+/// A system which only runs while this worker has claimed its partition.
+pub struct PartitionSystem<S> {
+    partition_id: EntityId,
+    system: S,
+}
+
+impl<S> PartitionSystem<S> {
+    pub fn new(partition_id: EntityId, system: S) -> PartitionSystem<S> {
+        PartitionSystem {
+            partition_id,
+            system,
+        }
+    }
+}
+
+impl<'a, S: System<'a>> System<'a> for PartitionSystem<S> {
+    type SystemData = (Read<'a, Partitions>, S::SystemData);
+
+    fn setup(&mut self, res: &mut World) {
+        Read::<Partitions>::setup(res);
+        self.system.setup(res);
+    }
+
+    fn run(&mut self, (partitions, data): Self::SystemData) {
+        if partitions.is_claimed(self.partition_id) {
+            self.system.run(data);
+        }
+    }
+}
+
+#[test]
+fn partitions_should_only_claim_once() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let partition_id = EntityId(WorkerEntityId::new(9));
+
+    let mut partitions = Partitions::default();
+    partitions.claim(partition_id);
+    partitions.claim(partition_id);
+    assert_eq!(vec![partition_id], partitions.buffered_claims);
+    assert_eq!(
+        Some(&PartitionStatus::Claiming),
+        partitions.status(partition_id)
+    );
+    assert!(!partitions.is_claimed(partition_id));
+
+    partitions.buffered_claims.clear();
+    partitions
+        .statuses
+        .insert(partition_id, PartitionStatus::Claimed);
+    assert_eq!(vec![partition_id], partitions.claimed().collect::<Vec<_>>());
+
+    partitions.reclaim();
+    assert_eq!(vec![partition_id], partitions.buffered_claims);
+    assert!(!partitions.is_claimed(partition_id));
+}
@@ -0,0 +1,369 @@
+//! Write-behind persistence of designated components, such as a player's inventory, to a
+//! database or other store outside SpatialOS.
+//!
+//! A `Persistence` resource holds a `PersistenceHook` and the components it persists. The
+//! crate keeps the set of those components which changed, whether by an update received
+//! from SpatialOS or one sent by this worker, and at most once every `interval` the
+//! `SpatialWriterSystem` serializes their current values and passes them to the hook in
+//! batches. A component which changes many times between flushes is persisted once.
+//!
+//! The hook is called on the writer's thread, so slow writes should be handed off:
+//!
+//! ```ignore
+//! let (sender, receiver) = std::sync::mpsc::channel();
+//! thread::spawn(move || {
+//!     for batch in receiver {
+//!         database.save(batch);
+//!     }
+//! });
+//!
+//! let sender = Mutex::new(sender);
+//! let persistence = Persistence::new(move |batch: Vec<PersistedComponent>| {
+//!     sender.lock().unwrap().send(batch).unwrap();
+//! })
+//! .with_component::<Inventory>();
+//! world.insert(persistence);
+//! ```
+//!
+//! A changed component which is removed before the next flush, for example because the
+//! player logged out or the entity left this worker's view, is serialized as it is removed,
+//! and its final value is passed to the hook at the next flush.
+//!
+//! Waiting changes are also flushed when the `ShutdownCoordinator` completes, and when the
+//! worker reconnects, before the components checked out over the old connection are
+//! removed. They are lost if the worker exits any other way without calling
+//! `Persistence::flush_now`.
+use crate::clock;
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::entities::{EntityId, EntityIds};
+use crate::logging::{self, LogKind, LogLevel};
+use crate::sdk;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Entity, SystemData, World};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// The serialized value of a persisted component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedComponent {
+    pub entity_id: EntityId,
+    pub component_id: ComponentId,
+    /// The component data, serialized with schema.
+    pub data: Vec<u8>,
+}
+
+impl PersistedComponent {
+    /// Deserializes the data, if it is of the component `T`.
+    pub fn deserialize<T: WorkerComponent>(&self) -> Option<Result<T, String>> {
+        if self.component_id == T::ID {
+            Some(sdk::deserialize_data::<T>(&self.data))
+        } else {
+            None
+        }
+    }
+}
+
+/// Receives batches of changed components to persist.
+pub trait PersistenceHook: Send + Sync {
+    fn persist(&mut self, batch: Vec<PersistedComponent>);
+}
+
+impl<F: FnMut(Vec<PersistedComponent>) + Send + Sync> PersistenceHook for F {
+    fn persist(&mut self, batch: Vec<PersistedComponent>) {
+        self(batch)
+    }
+}
+
+/// A resource which enables persistence of the registered components.
+pub struct Persistence {
+    hook: Box<PersistenceHook>,
+    components: HashSet<ComponentId>,
+    dirty: HashSet<(Entity, ComponentId)>,
+    // Changed components which were serialized as they were removed.
+    removed: Vec<PersistedComponent>,
+    interval: Duration,
+    batch_size: usize,
+    last_flush: Option<Instant>,
+}
+
+impl Persistence {
+    /// Persists changes through `hook` at most once every 5 seconds, in batches of up to
+    /// 100 components.
+    pub fn new<H: 'static + PersistenceHook>(hook: H) -> Persistence {
+        Persistence {
+            hook: Box::new(hook),
+            components: HashSet::new(),
+            dirty: HashSet::new(),
+            removed: Vec::new(),
+            interval: Duration::from_secs(5),
+            batch_size: 100,
+            last_flush: None,
+        }
+    }
+
+    /// Persists the component `T` whenever it changes.
+    pub fn with_component<T: 'static + WorkerComponent>(mut self) -> Self {
+        ComponentRegistry::register_component::<T>();
+        self.components.insert(T::ID);
+        self
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Sets the maximum number of components passed to the hook at once. Changes are also
+    /// flushed before the interval has passed once this many are waiting.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
+
+    /// The number of changed components waiting to be persisted.
+    pub fn pending(&self) -> usize {
+        self.dirty.len() + self.removed.len()
+    }
+
+    /// Persists every waiting change now, regardless of the interval.
+    pub fn flush_now(res: &World) {
+        Self::flush(res, true);
+    }
+
+    pub(crate) fn is_persisted(&self, component_id: ComponentId) -> bool {
+        self.components.contains(&component_id)
+    }
+
+    pub(crate) fn component_changed(&mut self, entity: Entity, component_id: ComponentId) {
+        if self.is_persisted(component_id) {
+            self.dirty.insert((entity, component_id));
+        }
+    }
+
+    // Serializes a changed component which is about to be removed, so that its final value
+    // is persisted at the next flush.
+    pub(crate) fn component_removing(res: &World, entity: Entity, component_id: ComponentId) {
+        if !res
+            .fetch_mut::<Persistence>()
+            .dirty
+            .remove(&(entity, component_id))
+        {
+            return;
+        }
+
+        if let Some(record) = Self::serialize(res, entity, component_id) {
+            res.fetch_mut::<Persistence>().removed.push(record);
+        }
+    }
+
+    fn is_due(&mut self, now: Instant) -> bool {
+        let due = self.pending() >= self.batch_size
+            || self.last_flush.map_or(true, |last_flush| {
+                now.duration_since(last_flush) >= self.interval
+            });
+        if due {
+            self.last_flush = Some(now);
+        }
+        due
+    }
+
+    fn serialize(
+        res: &World,
+        entity: Entity,
+        component_id: ComponentId,
+    ) -> Option<PersistedComponent> {
+        // Changed components are persisted as they are removed, so the entity and component
+        // only go missing if the component was never stored.
+        let entity_id = EntityIds::fetch(res).get_entity_id(entity)?;
+        let serialized = ComponentRegistry::get_interface(component_id)
+            .and_then(|interface| interface.serialize_component(res, entity))?;
+
+        match serialized {
+            Ok(data) => Some(PersistedComponent {
+                entity_id,
+                component_id,
+                data,
+            }),
+            Err(error) => {
+                logging::log(
+                    res,
+                    LogLevel::Error,
+                    LogKind::Persistence,
+                    &format!(
+                        "Failed to serialize component {} of entity {} for persistence: {}",
+                        describe_component(component_id),
+                        entity_id,
+                        error
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    pub(crate) fn flush(res: &World, force: bool) {
+        let (mut dirty, mut records) = {
+            let mut persistence = res.fetch_mut::<Persistence>();
+            if persistence.pending() == 0 || !(force || persistence.is_due(clock::now(res))) {
+                return;
+            }
+            let dirty: Vec<(Entity, ComponentId)> = persistence.dirty.drain().collect();
+            (dirty, persistence.removed.split_off(0))
+        };
+        dirty.sort();
+
+        for (entity, component_id) in dirty {
+            records.extend(Self::serialize(res, entity, component_id));
+        }
+
+        let mut persistence = res.fetch_mut::<Persistence>();
+        let batch_size = persistence.batch_size;
+        while !records.is_empty() {
+            let rest = records.split_off(batch_size.min(records.len()));
+            persistence.hook.persist(records);
+            records = rest;
+        }
+    }
+}
+
+#[test]
+fn persistence_should_batch_changes_of_registered_components() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use crate::storage::SpatialWriteStorage;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+    use std::sync::{Arc, Mutex};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let mut persistence = {
+        let batches = batches.clone();
+        Persistence::new(move |batch: Vec<PersistedComponent>| batches.lock().unwrap().push(batch))
+            .with_component::<Position>()
+    };
+    persistence.set_batch_size(2);
+    world.insert(persistence);
+
+    let mut entities = Vec::new();
+    for id in 1..4 {
+        let entity_id = EntityId(WorkerEntityId::new(id));
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(&world, entity_id);
+        let entity = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+        SpatialWriteStorage::<Position>::unrestricted(&world)
+            .insert(
+                entity,
+                SpatialComponent::new(Position {
+                    coords: Coordinates {
+                        x: id as f64,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                }),
+            )
+            .unwrap();
+        entities.push(entity);
+    }
+
+    {
+        let mut persistence = world.fetch_mut::<Persistence>();
+        persistence.component_changed(entities[0], Position::ID);
+        persistence.component_changed(entities[0], Position::ID);
+        persistence.component_changed(entities[2], Position::ID);
+        persistence.component_changed(entities[1], Blob::ID);
+        assert_eq!(2, persistence.pending());
+    }
+
+    Persistence::flush(&world, false);
+    assert_eq!(0, world.fetch::<Persistence>().pending());
+
+    world
+        .fetch_mut::<Persistence>()
+        .component_changed(entities[1], Position::ID);
+    Persistence::flush(&world, false);
+    assert_eq!(1, world.fetch::<Persistence>().pending());
+    Persistence::flush_now(&world);
+
+    let batches = batches.lock().unwrap();
+    let persisted: Vec<Vec<f64>> = batches
+        .iter()
+        .map(|batch| {
+            batch
+                .iter()
+                .map(|record| record.deserialize::<Position>().unwrap().unwrap().coords.x)
+                .collect()
+        })
+        .collect();
+    assert_eq!(vec![vec![1.0, 3.0], vec![2.0]], persisted);
+}
+
+#[test]
+fn persistence_should_keep_changes_to_removed_components() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use crate::storage::SpatialWriteStorage;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+    use std::sync::{Arc, Mutex};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    world.insert({
+        let batches = batches.clone();
+        Persistence::new(move |batch: Vec<PersistedComponent>| batches.lock().unwrap().push(batch))
+            .with_component::<Position>()
+    });
+
+    let entity_id = EntityId(WorkerEntityId::new(5));
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+    let entity = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+    SpatialWriteStorage::<Position>::unrestricted(&world)
+        .insert(
+            entity,
+            SpatialComponent::new(Position {
+                coords: Coordinates {
+                    x: 7.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            }),
+        )
+        .unwrap();
+    world
+        .fetch_mut::<Persistence>()
+        .component_changed(entity, Position::ID);
+
+    // The player logs out before the next flush.
+    ComponentRegistry::get_interface(Position::ID)
+        .unwrap()
+        .remove_component(&world, entity);
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .remove_entity(&world, entity_id);
+    assert_eq!(1, world.fetch::<Persistence>().pending());
+
+    Persistence::flush_now(&world);
+    let batches = batches.lock().unwrap();
+    assert_eq!(1, batches.len());
+    assert_eq!(entity_id, batches[0][0].entity_id);
+    assert_eq!(
+        7.0,
+        batches[0][0]
+            .deserialize::<Position>()
+            .unwrap()
+            .unwrap()
+            .coords
+            .x
+    );
+}
@@ -0,0 +1,530 @@
+//! The lifecycle of players connected to a server worker: who has joined, whether they
+//! are still connected, and what happens to their entities once they leave.
+//!
+//! A server worker registers each player when it handles their login, along with the
+//! entities the player owns, and adds the `PlayerLifecycle` resource:
+//!
+//! ```ignore
+//! let mut players = PlayerLifecycle::new();
+//! players.set_timeout(Duration::from_secs(15));
+//! players.on_player_left(|worker_id, entities, res| save_progress(res, worker_id, entities));
+//! world.insert(players);
+//!
+//! // In the login command responder, which fetches `Write<PlayerLifecycle>`:
+//! player_lifecycle.player_joined(&caller_worker_id);
+//! player_lifecycle.add_owned_entity(&caller_worker_id, avatar_entity_id);
+//! ```
+//!
+//! Players can instead be tracked through the `improbable.restricted.Worker` component of
+//! the entity SpatialOS creates for each connected worker, given the generated type for it
+//! and a storage for its data. A player joins when the component of a client worker is
+//! added, and leaves when it is removed:
+//!
+//! ```ignore
+//! players.track_workers(|worker: &Worker| {
+//!     if worker.worker_type == "UnityClient" {
+//!         Some(worker.worker_id.clone())
+//!     } else {
+//!         None
+//!     }
+//! });
+//! ```
+//!
+//! Every command request received from a player counts as a heartbeat, so clients send a
+//! lightweight command periodically when they have nothing else to send. Players tracked
+//! through their worker entity are connected for as long as it is checked out, so they
+//! don't need to send heartbeats. A player who has sent nothing for the timeout, whose
+//! worker entity has left, or who is reported with
+//! `player_disconnected`, has left. Once the `SpatialReaderSystem` has applied the ops of
+//! the frame, it calls the `on_player_left` hooks, which can persist the player's state or
+//! hand their entities over to another player, emits a `PlayerEvent::Left`, and deletes
+//! the entities unless the policy is `DisconnectPolicy::Keep`. Nothing is fetched while
+//! the hooks run, so they can fetch any resource, including the `SystemCommandSender`.
+//! With an `OwnershipConfig`, the entities can also include every entity the player owns.
+use crate::entities::EntityId;
+use crate::logging::{self, LogKind, LogLevel};
+use crate::ownership::OwnershipConfig;
+use crate::system_commands::SystemCommandSender;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Entity, SystemData, World};
+use specs::shrev::EventChannel;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Why a player left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayerLeftReason {
+    /// No command request was received from the player within the timeout.
+    HeartbeatTimeout,
+    /// The player was reported with `player_disconnected`, or their worker entity left.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerEvent {
+    /// The worker ID of a player who joined.
+    Joined(String),
+    Left {
+        worker_id: String,
+        reason: PlayerLeftReason,
+        /// The entities the player owned.
+        entities: Vec<EntityId>,
+    },
+}
+
+/// An event channel which receives a `PlayerEvent` whenever a player joins or leaves.
+pub type PlayerEvents = EventChannel<PlayerEvent>;
+
+/// What happens to the entities of a player who has left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// The entities are deleted.
+    Delete,
+    /// The entities are kept, for example because an `on_player_left` hook hands them over.
+    Keep,
+}
+
+type PlayerLeftHook = Arc<Fn(&str, &[EntityId], &World) + Send + Sync>;
+
+type WorkerExtractor = Box<Fn(&Any) -> Option<String> + Send + Sync>;
+
+struct Player {
+    // `None` until the first frame after joining.
+    last_seen: Option<Instant>,
+    entities: Vec<EntityId>,
+}
+
+/// A resource which tracks the players connected to this worker.
+pub struct PlayerLifecycle {
+    timeout: Duration,
+    policy: DisconnectPolicy,
+    players: HashMap<String, Player>,
+    joined: Vec<String>,
+    disconnected: Vec<String>,
+    hooks: Vec<PlayerLeftHook>,
+    worker_component: Option<(ComponentId, WorkerExtractor)>,
+    // The players whose worker entities are checked out.
+    worker_entities: HashMap<Entity, String>,
+}
+
+impl PlayerLifecycle {
+    pub fn new() -> PlayerLifecycle {
+        PlayerLifecycle {
+            timeout: Duration::from_secs(10),
+            policy: DisconnectPolicy::Delete,
+            players: HashMap::new(),
+            joined: Vec::new(),
+            disconnected: Vec::new(),
+            hooks: Vec::new(),
+            worker_component: None,
+            worker_entities: HashMap::new(),
+        }
+    }
+
+    /// Sets how long a player can go without sending a command request before they are
+    /// considered to have left. This doesn't apply to players whose worker entity is checked
+    /// out. The default is 10 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets what happens to the entities of players who leave. The default is to delete
+    /// them.
+    pub fn set_policy(&mut self, policy: DisconnectPolicy) {
+        self.policy = policy;
+    }
+
+    /// Calls `hook` with the worker ID and entities of every player who leaves, before
+    /// their entities are deleted.
+    pub fn on_player_left<F>(&mut self, hook: F)
+    where
+        F: 'static + Fn(&str, &[EntityId], &World) + Send + Sync,
+    {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    /// Tracks players through the worker component `W`, which is
+    /// `improbable.restricted.Worker`. `worker` returns the worker ID of client workers, and
+    /// `None` for workers which aren't players.
+    pub fn track_workers<W, F>(&mut self, worker: F)
+    where
+        W: 'static + WorkerComponent,
+        F: 'static + Fn(&W) -> Option<String> + Send + Sync,
+    {
+        self.worker_component = Some((
+            W::ID,
+            Box::new(move |value| value.downcast_ref::<W>().and_then(&worker)),
+        ));
+    }
+
+    pub(crate) fn is_worker_component(&self, component_id: ComponentId) -> bool {
+        match &self.worker_component {
+            Some((worker_component, _)) => *worker_component == component_id,
+            None => false,
+        }
+    }
+
+    pub(crate) fn worker_added(&mut self, entity: Entity, value: &Any) {
+        let worker_id = match &self.worker_component {
+            Some((_, extractor)) => extractor(value),
+            None => None,
+        };
+
+        if let Some(worker_id) = worker_id {
+            self.player_joined(&worker_id);
+            self.worker_entities.insert(entity, worker_id);
+        }
+    }
+
+    pub(crate) fn worker_removed(&mut self, entity: Entity) {
+        if let Some(worker_id) = self.worker_entities.remove(&entity) {
+            self.player_disconnected(&worker_id);
+        }
+    }
+
+    pub fn player_joined(&mut self, worker_id: &str) {
+        if self.players.contains_key(worker_id) {
+            return;
+        }
+
+        self.players.insert(
+            worker_id.to_string(),
+            Player {
+                last_seen: None,
+                entities: Vec::new(),
+            },
+        );
+        self.joined.push(worker_id.to_string());
+    }
+
+    /// Reports that a player has disconnected, without waiting for the timeout.
+    pub fn player_disconnected(&mut self, worker_id: &str) {
+        if self.players.contains_key(worker_id) {
+            self.disconnected.push(worker_id.to_string());
+        }
+    }
+
+    pub fn add_owned_entity(&mut self, worker_id: &str, entity_id: EntityId) {
+        if let Some(player) = self.players.get_mut(worker_id) {
+            if !player.entities.contains(&entity_id) {
+                player.entities.push(entity_id);
+            }
+        }
+    }
+
+    pub fn remove_owned_entity(&mut self, worker_id: &str, entity_id: EntityId) {
+        if let Some(player) = self.players.get_mut(worker_id) {
+            player.entities.retain(|owned| *owned != entity_id);
+        }
+    }
+
+    pub fn is_connected(&self, worker_id: &str) -> bool {
+        self.players.contains_key(worker_id)
+    }
+
+    /// The worker IDs of every connected player.
+    pub fn players<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
+        self.players.keys().map(String::as_str)
+    }
+
+    pub fn owned_entities(&self, worker_id: &str) -> &[EntityId] {
+        self.players
+            .get(worker_id)
+            .map(|player| player.entities.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub(crate) fn heartbeat(&mut self, worker_id: &str, now: Instant) {
+        if let Some(player) = self.players.get_mut(worker_id) {
+            player.last_seen = Some(now);
+        }
+    }
+
+    // Returns the events for players who joined or left since the last call.
+    fn take_events(&mut self, now: Instant) -> Vec<PlayerEvent> {
+        let mut events: Vec<PlayerEvent> = self.joined.drain(..).map(PlayerEvent::Joined).collect();
+
+        let timeout = self.timeout;
+        let mut left: Vec<(String, PlayerLeftReason)> = self
+            .disconnected
+            .drain(..)
+            .map(|worker_id| (worker_id, PlayerLeftReason::Disconnected))
+            .collect();
+
+        for (worker_id, player) in &mut self.players {
+            // The worker entity of a player is removed when they disconnect.
+            if self
+                .worker_entities
+                .values()
+                .any(|tracked| tracked == worker_id)
+            {
+                player.last_seen = Some(now);
+                continue;
+            }
+
+            let last_seen = *player.last_seen.get_or_insert(now);
+            if now.duration_since(last_seen) > timeout
+                && !left.iter().any(|(left_id, _)| left_id == worker_id)
+            {
+                left.push((worker_id.clone(), PlayerLeftReason::HeartbeatTimeout));
+            }
+        }
+
+        for (worker_id, reason) in left {
+            if let Some(player) = self.players.remove(&worker_id) {
+                events.push(PlayerEvent::Left {
+                    worker_id,
+                    reason,
+                    entities: player.entities,
+                });
+            }
+        }
+
+        events
+    }
+
+    pub(crate) fn update(res: &World, now: Instant) {
+        let (mut events, hooks, policy) = {
+            let mut players = res.fetch_mut::<PlayerLifecycle>();
+            let events = players.take_events(now);
+            (events, players.hooks.clone(), players.policy)
+        };
+
+        if res.has_value::<OwnershipConfig>() {
+            for event in &mut events {
+                if let PlayerEvent::Left {
+                    worker_id,
+                    entities,
+                    ..
+                } = event
+                {
+                    for entity_id in OwnershipConfig::entities_to_clean_up(res, worker_id) {
+                        if !entities.contains(&entity_id) {
+                            entities.push(entity_id);
+                        }
+                    }
+                }
+            }
+        }
+
+        for event in &events {
+            if let PlayerEvent::Left {
+                worker_id,
+                reason,
+                entities,
+            } = event
+            {
+                logging::log(
+                    res,
+                    LogLevel::Info,
+                    LogKind::PlayerLifecycle,
+                    &format!(
+                        "Player {} left ({:?}) owning {} entities.",
+                        worker_id,
+                        reason,
+                        entities.len()
+                    ),
+                );
+
+                for hook in &hooks {
+                    hook(worker_id, entities, res);
+                }
+
+                if policy == DisconnectPolicy::Delete {
+                    let mut sender = SystemCommandSender::fetch(res);
+                    for entity_id in entities {
+                        let entity_id = *entity_id;
+                        sender.delete_entity(entity_id.id(), move |result, fetch| {
+                            if let Err(error) = result {
+                                logging::log(
+                                    fetch.res,
+                                    LogLevel::Warn,
+                                    LogKind::PlayerLifecycle,
+                                    &format!(
+                                        "Failed to delete entity {:?} of a player who left: {:?}",
+                                        entity_id, error
+                                    ),
+                                );
+                            }
+                        });
+                    }
+                }
+            }
+        }
+
+        if res.has_value::<PlayerEvents>() {
+            res.fetch_mut::<PlayerEvents>().iter_write(events);
+        }
+    }
+}
+
+impl Default for PlayerLifecycle {
+    fn default() -> Self {
+        PlayerLifecycle::new()
+    }
+}
+
+#[test]
+fn players_should_leave_after_timeout_or_disconnect() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let start = Instant::now();
+    let entity = EntityId::new(WorkerEntityId::new(7));
+
+    let mut players = PlayerLifecycle::new();
+    players.set_timeout(Duration::from_secs(5));
+    players.player_joined("alice");
+    players.player_joined("bob");
+    players.add_owned_entity("alice", entity);
+
+    let mut events = players.take_events(start);
+    events.sort_by_key(|event| format!("{:?}", event));
+    assert_eq!(
+        vec![
+            PlayerEvent::Joined("alice".to_string()),
+            PlayerEvent::Joined("bob".to_string())
+        ],
+        events
+    );
+
+    players.heartbeat("bob", start + Duration::from_secs(4));
+    players.heartbeat("mallory", start + Duration::from_secs(4));
+    assert_eq!(
+        vec![PlayerEvent::Left {
+            worker_id: "alice".to_string(),
+            reason: PlayerLeftReason::HeartbeatTimeout,
+            entities: vec![entity],
+        }],
+        players.take_events(start + Duration::from_secs(6))
+    );
+
+    players.player_disconnected("bob");
+    assert_eq!(
+        vec![PlayerEvent::Left {
+            worker_id: "bob".to_string(),
+            reason: PlayerLeftReason::Disconnected,
+            entities: vec![],
+        }],
+        players.take_events(start + Duration::from_secs(6))
+    );
+    assert_eq!(0, players.players().count());
+}
+
+#[test]
+fn players_should_follow_their_worker_entities() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    let client = world.create_entity().build();
+    let server = world.create_entity().build();
+
+    // `Position` stands in for the worker component, with the x coordinate as the worker.
+    let mut players = PlayerLifecycle::new();
+    players.track_workers(|position: &Position| {
+        if position.coords.x > 0.0 {
+            Some(format!("client{}", position.coords.x))
+        } else {
+            None
+        }
+    });
+    let worker = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    assert!(players.is_worker_component(Position::ID));
+    players.worker_added(client, &worker(1.0));
+    players.worker_added(server, &worker(0.0));
+    assert_eq!(vec!["client1"], players.players().collect::<Vec<_>>());
+
+    players.worker_removed(server);
+    players.worker_removed(client);
+    let events = players.take_events(Instant::now());
+    assert_eq!(
+        PlayerEvent::Left {
+            worker_id: "client1".to_string(),
+            reason: PlayerLeftReason::Disconnected,
+            entities: vec![],
+        },
+        events[1]
+    );
+    assert!(!players.is_connected("client1"));
+}
+
+#[test]
+fn player_left_hooks_should_be_able_to_send_commands() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut world = World::new();
+    SystemCommandSender::setup(&mut world);
+
+    let deleted = Arc::new(AtomicUsize::new(0));
+    let mut players = PlayerLifecycle::new();
+    {
+        let deleted = deleted.clone();
+        players.on_player_left(move |_, entities, res| {
+            let mut sender = SystemCommandSender::fetch(res);
+            for entity_id in entities {
+                sender.delete_entity(entity_id.id(), |_, _| {});
+                deleted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+    }
+    players.player_joined("alice");
+    players.add_owned_entity("alice", EntityId::new(WorkerEntityId::new(3)));
+    players.player_disconnected("alice");
+    world.insert(players);
+
+    PlayerLifecycle::update(&world, Instant::now());
+    assert_eq!(1, deleted.load(Ordering::SeqCst));
+    assert!(!world.fetch::<PlayerLifecycle>().is_connected("alice"));
+}
+
+#[test]
+fn players_with_worker_entities_should_not_time_out() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let start = Instant::now();
+    let mut world = World::new();
+    let client = world.create_entity().build();
+
+    let mut players = PlayerLifecycle::new();
+    players.set_timeout(Duration::from_secs(5));
+    players.track_workers(|position: &Position| Some(format!("client{}", position.coords.x)));
+    players.worker_added(
+        client,
+        &Position {
+            coords: Coordinates {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        },
+    );
+    assert_eq!(
+        vec![PlayerEvent::Joined("client1".to_string())],
+        players.take_events(start)
+    );
+
+    // The client sends no commands, but its worker entity is still checked out.
+    assert!(players
+        .take_events(start + Duration::from_secs(60))
+        .is_empty());
+    assert!(players.is_connected("client1"));
+
+    players.worker_removed(client);
+    assert_eq!(
+        vec![PlayerEvent::Left {
+            worker_id: "client1".to_string(),
+            reason: PlayerLeftReason::Disconnected,
+            entities: vec![],
+        }],
+        players.take_events(start + Duration::from_secs(61))
+    );
+}
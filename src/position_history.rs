@@ -0,0 +1,205 @@
+//! An opt-in history of recent positions of every entity, for lag compensation such as
+//! server-side hit validation.
+use crate::clock;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Component, Entity, ReadStorage, SystemData, VecStorage, World, WriteStorage};
+use specs::storage::MaskedStorage;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+type PositionExtractor = Box<Fn(&Any) -> Option<[f64; 3]> + Send + Sync>;
+
+/// A resource which enables recording of `PositionHistory` from a position component.
+///
+/// Whenever the position component of an entity is added or updated from SpatialOS, the
+/// new position is recorded in the entity's `PositionHistory` along with the time it was
+/// received. The `PositionHistory` storage must be registered, either with
+/// `world.register` or by a system which fetches `PositionHistories`.
+///
+/// ```ignore
+/// world.insert(PositionHistoryConfig::new(32, |position: &Position| {
+///     [position.coords.x, position.coords.y, position.coords.z]
+/// }));
+/// ```
+pub struct PositionHistoryConfig {
+    capacity: usize,
+    position_component: ComponentId,
+    position_extractor: PositionExtractor,
+}
+
+impl PositionHistoryConfig {
+    /// Records up to `capacity` positions per entity, extracted from the component `P`.
+    pub fn new<P, F>(capacity: usize, position: F) -> PositionHistoryConfig
+    where
+        P: 'static + WorkerComponent,
+        F: 'static + Fn(&P) -> [f64; 3] + Send + Sync,
+    {
+        PositionHistoryConfig {
+            capacity,
+            position_component: P::ID,
+            position_extractor: Box::new(move |value| value.downcast_ref::<P>().map(&position)),
+        }
+    }
+
+    pub(crate) fn is_position_component(&self, component_id: ComponentId) -> bool {
+        component_id == self.position_component
+    }
+
+    pub(crate) fn record(res: &World, entity: Entity, value: &Any) {
+        let (capacity, position) = {
+            let config = res.fetch::<PositionHistoryConfig>();
+            match (config.position_extractor)(value) {
+                Some(position) => (config.capacity, position),
+                None => return,
+            }
+        };
+
+        if !res.has_value::<MaskedStorage<PositionHistory>>() {
+            return;
+        }
+
+        let now = clock::now(res);
+        let mut histories = WriteStorage::<PositionHistory>::fetch(res);
+
+        if !histories.contains(entity) {
+            histories
+                .insert(entity, PositionHistory::new(capacity))
+                .expect("Error inserting PositionHistory.");
+        }
+
+        if let Some(history) = histories.get_mut(entity) {
+            history.push(now, position);
+        }
+    }
+}
+
+/// The most recently received positions of an entity, oldest first.
+#[derive(Debug, Clone)]
+pub struct PositionHistory {
+    capacity: usize,
+    samples: VecDeque<(Instant, [f64; 3])>,
+}
+
+impl Component for PositionHistory {
+    type Storage = VecStorage<Self>;
+}
+
+impl PositionHistory {
+    pub fn new(capacity: usize) -> PositionHistory {
+        PositionHistory {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &(Instant, [f64; 3])> {
+        self.samples.iter()
+    }
+
+    /// Returns the position of the entity at the given time, interpolating linearly between
+    /// the samples either side of it.
+    ///
+    /// Times after the latest sample return the latest position. Times before the oldest
+    /// sample return `None`, as the history no longer covers them.
+    pub fn position_at(&self, time: Instant) -> Option<[f64; 3]> {
+        let (latest_time, latest) = *self.samples.back()?;
+        if time >= latest_time {
+            return Some(latest);
+        }
+
+        let after = self.samples.iter().position(|(t, _)| *t > time)?;
+        if after == 0 {
+            return None;
+        }
+
+        let (t0, p0) = self.samples[after - 1];
+        let (t1, p1) = self.samples[after];
+
+        let span = t1.duration_since(t0);
+        let elapsed = time.duration_since(t0);
+        let alpha = as_nanos(elapsed) / as_nanos(span);
+
+        let lerp = |i: usize| p0[i] + (p1[i] - p0[i]) * alpha;
+        Some([lerp(0), lerp(1), lerp(2)])
+    }
+
+    pub(crate) fn push(&mut self, time: Instant, position: [f64; 3]) {
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((time, position));
+    }
+}
+
+fn as_nanos(duration: std::time::Duration) -> f64 {
+    duration.as_secs() as f64 * 1e9 + f64::from(duration.subsec_nanos())
+}
+
+/// Read access to the `PositionHistory` of every entity.
+pub type PositionHistories<'a> = ReadStorage<'a, PositionHistory>;
+
+/// Returns the position of the entity at the given time, if its history covers it.
+pub fn position_at(
+    histories: &PositionHistories,
+    entity: Entity,
+    time: Instant,
+) -> Option<[f64; 3]> {
+    histories.get(entity)?.position_at(time)
+}
+
+#[test]
+fn position_history_should_interpolate_between_samples() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let at = |millis| start + Duration::from_millis(millis);
+
+    let mut history = PositionHistory::new(2);
+    history.push(at(0), [0.0, 0.0, 0.0]);
+    history.push(at(100), [10.0, 0.0, 0.0]);
+    history.push(at(200), [10.0, 20.0, 0.0]);
+
+    assert_eq!(2, history.samples().count());
+    assert_eq!(None, history.position_at(at(50)));
+    assert_eq!(Some([10.0, 10.0, 0.0]), history.position_at(at(150)));
+    assert_eq!(Some([10.0, 20.0, 0.0]), history.position_at(at(500)));
+}
+
+#[test]
+fn position_history_should_be_recorded_from_position_component() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    world.register::<PositionHistory>();
+    world.insert(PositionHistoryConfig::new(4, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    }));
+
+    let entity = world.create_entity().build();
+    for x in 0..6 {
+        let position = Position {
+            coords: Coordinates {
+                x: f64::from(x),
+                y: 0.0,
+                z: 0.0,
+            },
+        };
+        PositionHistoryConfig::record(&world, entity, &position as &Any);
+    }
+
+    let histories = PositionHistories::fetch(&world);
+    let xs: Vec<f64> = histories
+        .get(entity)
+        .unwrap()
+        .samples()
+        .map(|(_, position)| position[0])
+        .collect();
+    assert_eq!(vec![2.0, 3.0, 4.0, 5.0], xs);
+    assert_eq!(
+        Some([5.0, 0.0, 0.0]),
+        position_at(&histories, entity, Instant::now())
+    );
+}
@@ -0,0 +1,25 @@
+//! The types most workers need, under names which won't change when the crate's modules are
+//! reorganized.
+//!
+//! ```ignore
+//! use spatialos_specs::prelude::*;
+//! use specs::prelude::*;
+//! ```
+//!
+//! Everything here is also exported from the crate root. Optional features, such as the
+//! statistics resources, are left out, and are imported by name when needed.
+pub use crate::commands::{CommandRequests, CommandSender, RespondWithData};
+pub use crate::entities::{EntityId, EntityIds, SpatialEntityEvent, SpatialEntityEvents};
+pub use crate::spatial_reader::{
+    SpatialOpApplierSystem, SpatialOpCollectorSystem, SpatialReaderSystem,
+};
+pub use crate::spatial_writer::{
+    LockingWriterSystem, SpatialFlushSystem, SpatialWriterSystem, WriterStageSystem,
+};
+pub use crate::storage::{
+    ComponentAuthority, ReadAuthority, SpatialReadStorage, SpatialReadStorageExt,
+    SpatialWriteStorage,
+};
+pub use crate::system_commands::{SystemCommandResult, SystemCommandSender};
+pub use crate::{SchemaDefault, SpatialComponent};
+pub use spatialos_sdk::worker::Authority;
@@ -0,0 +1,489 @@
+//! Worker statistics in the Prometheus text exposition format, selected with the
+//! `prometheus` feature.
+//!
+//! `render` writes the statistics of every enabled resource: the traffic of the last
+//! `FrameReport`, the `OpStats` timings, the `ComponentCensus`, the `UpdateLatency`, the
+//! `NetworkStats` and the `ConnectionHealth`.
+//! Adding a `PrometheusExporter` keeps a rendering up to date, which it can also serve
+//! over HTTP for scraping:
+//!
+//! ```ignore
+//! let exporter = PrometheusExporter::new();
+//! exporter.serve("0.0.0.0:9102")?;
+//! world.insert(exporter);
+//! ```
+use crate::census::ComponentCensus;
+use crate::component_registry::component_name;
+use crate::frame_report::FrameReport;
+use crate::health::ConnectionHealth;
+use crate::latency::UpdateLatency;
+use crate::network_stats::NetworkStats;
+use crate::op_stats::{OpCategory, OpStats};
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::World;
+use std::fmt::Write;
+use std::io::{self, Read, Write as IoWrite};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long a scrape may take to send its request or read the response, so that an idle
+// client can't hold up the others.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Renders the statistics of every enabled resource.
+pub fn render(res: &World) -> String {
+    let mut out = String::new();
+
+    if res.has_value::<FrameReport>() {
+        if let Some(counts) = res.fetch::<FrameReport>().last() {
+            let traffic = [
+                ("entities_added", counts.entities_added),
+                ("entities_removed", counts.entities_removed),
+                ("updates_received", counts.updates_received),
+                ("updates_sent", counts.updates_sent),
+                (
+                    "command_requests_received",
+                    counts.command_requests_received,
+                ),
+                ("command_requests_sent", counts.command_requests_sent),
+                (
+                    "command_responses_received",
+                    counts.command_responses_received,
+                ),
+                ("command_responses_sent", counts.command_responses_sent),
+                ("system_commands_sent", counts.system_commands_sent),
+            ];
+
+            header(
+                &mut out,
+                "spatialos_frame_report_frames",
+                "gauge",
+                "Frames in the last frame report.",
+            );
+            sample(&mut out, "spatialos_frame_report_frames", "", counts.frames);
+            for (name, value) in traffic.iter() {
+                let metric = format!("spatialos_frame_report_{}", name);
+                header(
+                    &mut out,
+                    &metric,
+                    "gauge",
+                    "Traffic during the last frame report.",
+                );
+                sample(&mut out, &metric, "", value);
+            }
+            header(
+                &mut out,
+                "spatialos_frame_report_reader_seconds",
+                "gauge",
+                "Time spent in the reader during the last frame report.",
+            );
+            sample(
+                &mut out,
+                "spatialos_frame_report_reader_seconds",
+                "",
+                counts.reader_time.as_secs_f64(),
+            );
+            header(
+                &mut out,
+                "spatialos_frame_report_writer_seconds",
+                "gauge",
+                "Time spent in the writer during the last frame report.",
+            );
+            sample(
+                &mut out,
+                "spatialos_frame_report_writer_seconds",
+                "",
+                counts.writer_time.as_secs_f64(),
+            );
+        }
+    }
+
+    if res.has_value::<OpStats>() {
+        let stats = res.fetch::<OpStats>();
+        let mut timings: Vec<_> = stats
+            .iter()
+            .map(|(category, timing)| (op_labels(category), timing))
+            .collect();
+        timings.sort_by(|a, b| a.0.cmp(&b.0));
+
+        header(
+            &mut out,
+            "spatialos_ops_applied_total",
+            "counter",
+            "Ops applied by the reader.",
+        );
+        for (labels, timing) in &timings {
+            sample(
+                &mut out,
+                "spatialos_ops_applied_total",
+                labels,
+                timing.count,
+            );
+        }
+        header(
+            &mut out,
+            "spatialos_op_apply_seconds_total",
+            "counter",
+            "Time spent applying ops.",
+        );
+        for (labels, timing) in &timings {
+            sample(
+                &mut out,
+                "spatialos_op_apply_seconds_total",
+                labels,
+                timing.total.as_secs_f64(),
+            );
+        }
+        header(
+            &mut out,
+            "spatialos_op_apply_max_seconds",
+            "gauge",
+            "The longest time spent applying a single op.",
+        );
+        for (labels, timing) in &timings {
+            sample(
+                &mut out,
+                "spatialos_op_apply_max_seconds",
+                labels,
+                timing.max.as_secs_f64(),
+            );
+        }
+    }
+
+    if res.has_value::<ComponentCensus>() {
+        let census = res.fetch::<ComponentCensus>();
+        let mut counts: Vec<_> = census.iter().collect();
+        counts.sort_by_key(|(component_id, _)| *component_id);
+
+        header(
+            &mut out,
+            "spatialos_components_checked_out",
+            "gauge",
+            "Entities with the component checked out.",
+        );
+        for (component_id, count) in &counts {
+            sample(
+                &mut out,
+                "spatialos_components_checked_out",
+                &component_labels(*component_id),
+                count.checked_out,
+            );
+        }
+        header(
+            &mut out,
+            "spatialos_components_authoritative",
+            "gauge",
+            "Entities this worker is authoritative over the component for.",
+        );
+        for (component_id, count) in &counts {
+            sample(
+                &mut out,
+                "spatialos_components_authoritative",
+                &component_labels(*component_id),
+                count.authoritative,
+            );
+        }
+    }
+
+    if res.has_value::<UpdateLatency>() {
+        let latency = res.fetch::<UpdateLatency>();
+        let mut stats: Vec<_> = latency.iter().collect();
+        stats.sort_by_key(|(component_id, _)| *component_id);
+
+        header(
+            &mut out,
+            "spatialos_update_latency_seconds",
+            "gauge",
+            "Moving average of the latency of timestamped updates.",
+        );
+        for (component_id, stat) in &stats {
+            if let Some(value) = stat.latency {
+                sample(
+                    &mut out,
+                    "spatialos_update_latency_seconds",
+                    &component_labels(*component_id),
+                    value.as_secs_f64(),
+                );
+            }
+        }
+        header(
+            &mut out,
+            "spatialos_update_interval_seconds",
+            "gauge",
+            "Moving average of the time between updates to the same entity.",
+        );
+        for (component_id, stat) in &stats {
+            if let Some(value) = stat.interval {
+                sample(
+                    &mut out,
+                    "spatialos_update_interval_seconds",
+                    &component_labels(*component_id),
+                    value.as_secs_f64(),
+                );
+            }
+        }
+    }
+
+    if res.has_value::<NetworkStats>() {
+        let network_stats = res.fetch::<NetworkStats>();
+        let mut stats: Vec<_> = network_stats.lifetime().collect();
+        stats.sort_by_key(|(component_id, _)| *component_id);
+
+        header(
+            &mut out,
+            "spatialos_logical_updates_total",
+            "counter",
+            "Updates made locally, before being coalesced.",
+        );
+        for (component_id, stat) in &stats {
+            sample(
+                &mut out,
+                "spatialos_logical_updates_total",
+                &component_labels(*component_id),
+                stat.logical_updates,
+            );
+        }
+        header(
+            &mut out,
+            "spatialos_coalesced_updates_total",
+            "counter",
+            "Updates which were merged into another rather than sent.",
+        );
+        for (component_id, stat) in &stats {
+            sample(
+                &mut out,
+                "spatialos_coalesced_updates_total",
+                &component_labels(*component_id),
+                stat.coalesced(),
+            );
+        }
+    }
+
+    if res.has_value::<ConnectionHealth>() {
+        let health = res.fetch::<ConnectionHealth>();
+        header(
+            &mut out,
+            "spatialos_connected",
+            "gauge",
+            "Whether the connection is connected.",
+        );
+        sample(
+            &mut out,
+            "spatialos_connected",
+            "",
+            health.is_connected() as u8,
+        );
+        header(
+            &mut out,
+            "spatialos_connection_backlogged",
+            "gauge",
+            "Whether traffic exceeded a threshold during the last frame.",
+        );
+        sample(
+            &mut out,
+            "spatialos_connection_backlogged",
+            "",
+            health.is_backlogged() as u8,
+        );
+        header(
+            &mut out,
+            "spatialos_ops_received",
+            "gauge",
+            "Ops received during the last frame.",
+        );
+        sample(
+            &mut out,
+            "spatialos_ops_received",
+            "",
+            health.ops_received(),
+        );
+        header(
+            &mut out,
+            "spatialos_messages_sent",
+            "gauge",
+            "Messages sent during the last frame.",
+        );
+        sample(
+            &mut out,
+            "spatialos_messages_sent",
+            "",
+            health.messages_sent(),
+        );
+    }
+
+    out
+}
+
+fn header(out: &mut String, metric: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", metric, help).unwrap();
+    writeln!(out, "# TYPE {} {}", metric, kind).unwrap();
+}
+
+fn sample<V: std::fmt::Display>(out: &mut String, metric: &str, labels: &str, value: V) {
+    if labels.is_empty() {
+        writeln!(out, "{} {}", metric, value).unwrap();
+    } else {
+        writeln!(out, "{}{{{}}} {}", metric, labels, value).unwrap();
+    }
+}
+
+fn component_labels(component_id: ComponentId) -> String {
+    match component_name(component_id) {
+        Some(name) => format!("component_id=\"{}\",component=\"{}\"", component_id, name),
+        None => format!("component_id=\"{}\"", component_id),
+    }
+}
+
+fn op_labels(category: OpCategory) -> String {
+    let (op, component_id) = match category {
+        OpCategory::AddEntity => ("add_entity", None),
+        OpCategory::RemoveEntity => ("remove_entity", None),
+        OpCategory::AddComponent(id) => ("add_component", Some(id)),
+        OpCategory::RemoveComponent(id) => ("remove_component", Some(id)),
+        OpCategory::ComponentUpdate(id) => ("component_update", Some(id)),
+        OpCategory::AuthorityChange(id) => ("authority_change", Some(id)),
+        OpCategory::CommandRequest(id) => ("command_request", Some(id)),
+        OpCategory::CommandResponse(id) => ("command_response", Some(id)),
+        OpCategory::Other => ("other", None),
+    };
+
+    match component_id {
+        Some(id) => format!("op=\"{}\",{}", op, component_labels(id)),
+        None => format!("op=\"{}\"", op),
+    }
+}
+
+/// A resource which keeps a rendering of the statistics up to date. It is rendered by the
+/// `SpatialWriterSystem` at the end of every `interval` frames.
+pub struct PrometheusExporter {
+    latest: Arc<Mutex<String>>,
+    interval: u32,
+    frames: u32,
+}
+
+impl PrometheusExporter {
+    pub fn new() -> PrometheusExporter {
+        PrometheusExporter {
+            latest: Arc::new(Mutex::new(String::new())),
+            interval: 30,
+            frames: 0,
+        }
+    }
+
+    /// Renders every `frames` frames. The default is 30.
+    pub fn set_interval(&mut self, frames: u32) {
+        self.interval = frames.max(1);
+    }
+
+    /// The most recent rendering.
+    pub fn latest(&self) -> String {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// Serves the most recent rendering to every HTTP request on the address, from a
+    /// background thread. Returns the address bound, which is useful when binding port 0.
+    ///
+    /// Clients are served one at a time, and are disconnected if they don't send their
+    /// request or read the response within a second.
+    pub fn serve<A: ToSocketAddrs>(&self, address: A) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+        let latest = self.latest.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                if stream.set_read_timeout(Some(SCRAPE_TIMEOUT)).is_err()
+                    || stream.set_write_timeout(Some(SCRAPE_TIMEOUT)).is_err()
+                {
+                    continue;
+                }
+
+                // The request is not inspected, as every path serves the metrics.
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+
+                let body = latest.lock().unwrap().clone();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        Ok(local_address)
+    }
+
+    pub(crate) fn finish_frame(res: &World) {
+        {
+            let mut exporter = res.fetch_mut::<PrometheusExporter>();
+            exporter.frames += 1;
+            if exporter.frames < exporter.interval {
+                return;
+            }
+            exporter.frames = 0;
+        }
+
+        let rendered = render(res);
+        *res.fetch::<PrometheusExporter>().latest.lock().unwrap() = rendered;
+    }
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        PrometheusExporter::new()
+    }
+}
+
+#[test]
+fn render_should_write_enabled_statistics() {
+    use specs::prelude::WorldExt;
+    use std::time::Duration;
+
+    let mut world = World::new();
+    assert_eq!("", render(&world));
+
+    let mut census = ComponentCensus::default();
+    census.component_added(54);
+    world.insert(census);
+
+    let mut stats = OpStats::default();
+    stats.record(OpCategory::AddEntity, Duration::from_millis(500));
+    world.insert(stats);
+
+    let rendered = render(&world);
+    assert!(rendered.contains("# TYPE spatialos_components_checked_out gauge\n"));
+    assert!(rendered.contains(
+        "spatialos_components_checked_out{component_id=\"54\",component=\"improbable.Position\"} 1\n"
+    ));
+    assert!(rendered.contains("spatialos_op_apply_seconds_total{op=\"add_entity\"} 0.5\n"));
+    assert!(!rendered.contains("spatialos_connected"));
+}
+
+#[test]
+fn serve_should_not_be_held_up_by_idle_clients() {
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    let exporter = PrometheusExporter::new();
+    *exporter.latest.lock().unwrap() = "spatialos_frames 1\n".to_string();
+    let address = exporter.serve("127.0.0.1:0").unwrap();
+
+    let _idle = TcpStream::connect(address).unwrap();
+
+    let started = Instant::now();
+    let mut scrape = TcpStream::connect(address).unwrap();
+    scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    scrape.read_to_string(&mut response).unwrap();
+
+    assert!(response.ends_with("spatialos_frames 1\n"));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
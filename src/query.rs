@@ -0,0 +1,468 @@
+//! A small textual language for entity queries, for debug consoles and tooling.
+//!
+//! ```ignore
+//! let query = query::parse_query("sphere(10, 0, 10, 50) and component(Player)", |name| {
+//!     match name {
+//!         "Player" => Some(Player::ID),
+//!         _ => None,
+//!     }
+//! })?;
+//!
+//! system_command_sender.entity_query(query, |result, _| println!("{:?}", result));
+//! ```
+//!
+//! The constraints are:
+//!
+//! * `entity(id)`
+//! * `component(id)` or `component(Name)`, with names resolved by the caller
+//! * `sphere(x, y, z, radius)`
+//! * `cylinder(x, y, z, radius)`
+//! * `box(x, y, z, width, height, depth)`
+//!
+//! They can be combined with `and`, `or` and `not`, where `not` binds tightest and `or`
+//! loosest, and grouped with parentheses. The query matches every entity if the text is
+//! `all`.
+//!
+//! Names registered with `register_component_name` can be resolved by passing
+//! `spatialos_specs::component_id` as the resolver.
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::query::{EntityQuery, QueryConstraint, ResultType, SnapshotResultType};
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// An error in the text of a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryParseError {
+    /// The byte offset in the text at which the error was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for QueryParseError {}
+
+/// Parses a query for a full snapshot of every matching entity.
+pub fn parse_query<F>(text: &str, resolve_component: F) -> Result<EntityQuery, QueryParseError>
+where
+    F: Fn(&str) -> Option<ComponentId>,
+{
+    Ok(EntityQuery {
+        constraint: parse_constraint(text, resolve_component)?,
+        result_type: ResultType::Snapshot(SnapshotResultType::FullSnapshot),
+    })
+}
+
+/// Parses a query constraint, resolving component names with `resolve_component`.
+pub fn parse_constraint<F>(
+    text: &str,
+    resolve_component: F,
+) -> Result<QueryConstraint, QueryParseError>
+where
+    F: Fn(&str) -> Option<ComponentId>,
+{
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        index: 0,
+        end: text.len(),
+        resolve_component: &resolve_component,
+    };
+
+    let constraint = parser.or()?;
+    match parser.peek() {
+        None => Ok(constraint),
+        Some((position, token)) => Err(QueryParseError {
+            position,
+            message: format!("Unexpected {}", token),
+        }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Integer(i128),
+    Number(f64),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Integer(integer) => write!(f, "{}", integer),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::LeftParen => write!(f, "'('"),
+            Token::RightParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, QueryParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((position, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            ',' => Token::Comma,
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut number = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    // A sign is only part of the number as the sign of an exponent.
+                    let exponent_sign =
+                        (c == '+' || c == '-') && (number.ends_with('e') || number.ends_with('E'));
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                match number.parse() {
+                    Ok(integer) => Token::Integer(integer),
+                    Err(_) => Token::Number(number.parse().map_err(|_| QueryParseError {
+                        position,
+                        message: format!("Invalid number '{}'", number),
+                    })?),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+            c => {
+                return Err(QueryParseError {
+                    position,
+                    message: format!("Unexpected character '{}'", c),
+                })
+            }
+        };
+        tokens.push((position, token));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    end: usize,
+    resolve_component: &'a Fn(&str) -> Option<ComponentId>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens
+            .get(self.index)
+            .map(|(position, token)| (*position, token))
+    }
+
+    fn position(&self) -> usize {
+        self.peek().map_or(self.end, |(position, _)| position)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, QueryParseError> {
+        Err(QueryParseError {
+            position: self.position(),
+            message,
+        })
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).map(|(_, token)| token.clone());
+        self.index += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some((_, Token::Word(word))) => word.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), QueryParseError> {
+        match self.peek() {
+            Some((_, token)) if *token == expected => {
+                self.index += 1;
+                Ok(())
+            }
+            Some((_, token)) => {
+                let message = format!("Expected {} but found {}", expected, token);
+                self.error(message)
+            }
+            None => self.error(format!("Expected {} but found the end", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<QueryConstraint, QueryParseError> {
+        let mut constraints = vec![self.and()?];
+        while self.is_keyword("or") {
+            self.index += 1;
+            constraints.push(self.and()?);
+        }
+
+        Ok(if constraints.len() == 1 {
+            constraints.remove(0)
+        } else {
+            QueryConstraint::Or(constraints)
+        })
+    }
+
+    fn and(&mut self) -> Result<QueryConstraint, QueryParseError> {
+        let mut constraints = vec![self.unary()?];
+        while self.is_keyword("and") {
+            self.index += 1;
+            constraints.push(self.unary()?);
+        }
+
+        Ok(if constraints.len() == 1 {
+            constraints.remove(0)
+        } else {
+            QueryConstraint::And(constraints)
+        })
+    }
+
+    fn unary(&mut self) -> Result<QueryConstraint, QueryParseError> {
+        if self.is_keyword("not") {
+            self.index += 1;
+            return Ok(QueryConstraint::Not(Box::new(self.unary()?)));
+        }
+
+        if let Some((_, Token::LeftParen)) = self.peek() {
+            self.index += 1;
+            let constraint = self.or()?;
+            self.expect(Token::RightParen)?;
+            return Ok(constraint);
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<QueryConstraint, QueryParseError> {
+        let start = self.position();
+        let name = match self.advance() {
+            Some(Token::Word(name)) => name.to_lowercase(),
+            Some(token) => {
+                self.index -= 1;
+                return self.error(format!("Expected a constraint but found {}", token));
+            }
+            None => return self.error("Expected a constraint but found the end".to_string()),
+        };
+
+        if name == "all" {
+            return Ok(QueryConstraint::And(Vec::new()));
+        }
+
+        self.expect(Token::LeftParen)?;
+        let mut arguments = Vec::new();
+        loop {
+            match self.advance() {
+                Some(token @ Token::Word(_))
+                | Some(token @ Token::Integer(_))
+                | Some(token @ Token::Number(_)) => arguments.push(token),
+                Some(token) => {
+                    self.index -= 1;
+                    return self.error(format!("Expected an argument but found {}", token));
+                }
+                None => return self.error("Expected an argument but found the end".to_string()),
+            }
+
+            match self.peek() {
+                Some((_, Token::Comma)) => self.index += 1,
+                _ => break,
+            }
+        }
+        self.expect(Token::RightParen)?;
+
+        let numbers = |count: usize| -> Result<Vec<f64>, QueryParseError> {
+            if arguments.len() != count {
+                return Err(QueryParseError {
+                    position: start,
+                    message: format!("'{}' takes {} arguments", name, count),
+                });
+            }
+
+            arguments
+                .iter()
+                .map(|argument| match argument {
+                    Token::Integer(integer) => Ok(*integer as f64),
+                    Token::Number(number) => Ok(*number),
+                    other => Err(QueryParseError {
+                        position: start,
+                        message: format!("Expected a number but found {}", other),
+                    }),
+                })
+                .collect()
+        };
+
+        // IDs which don't fit in their type are rejected rather than truncated.
+        match name.as_str() {
+            "entity" => match arguments.as_slice() {
+                [Token::Integer(integer)] => match i64::try_from(*integer) {
+                    Ok(id) => Ok(QueryConstraint::EntityId(WorkerEntityId::new(id))),
+                    Err(_) => Err(QueryParseError {
+                        position: start,
+                        message: format!("{} is not a valid entity ID", integer),
+                    }),
+                },
+                [other] => Err(QueryParseError {
+                    position: start,
+                    message: format!("Expected an entity ID but found {}", other),
+                }),
+                _ => Err(QueryParseError {
+                    position: start,
+                    message: "'entity' takes 1 argument".to_string(),
+                }),
+            },
+            "component" => match arguments.as_slice() {
+                [Token::Integer(integer)] => match ComponentId::try_from(*integer) {
+                    Ok(id) => Ok(QueryConstraint::Component(id)),
+                    Err(_) => Err(QueryParseError {
+                        position: start,
+                        message: format!("{} is not a valid component ID", integer),
+                    }),
+                },
+                [Token::Word(component)] => match (self.resolve_component)(component) {
+                    Some(id) => Ok(QueryConstraint::Component(id)),
+                    None => Err(QueryParseError {
+                        position: start,
+                        message: format!("Unknown component '{}'", component),
+                    }),
+                },
+                [other] => Err(QueryParseError {
+                    position: start,
+                    message: format!("Expected a component ID or name but found {}", other),
+                }),
+                _ => Err(QueryParseError {
+                    position: start,
+                    message: "'component' takes 1 argument".to_string(),
+                }),
+            },
+            "sphere" => {
+                let n = numbers(4)?;
+                Ok(QueryConstraint::Sphere(n[0], n[1], n[2], n[3]))
+            }
+            "cylinder" => {
+                let n = numbers(4)?;
+                Ok(QueryConstraint::Cylinder(n[0], n[1], n[2], n[3]))
+            }
+            "box" => {
+                let n = numbers(6)?;
+                Ok(QueryConstraint::Box(n[0], n[1], n[2], n[3], n[4], n[5]))
+            }
+            _ => Err(QueryParseError {
+                position: start,
+                message: format!("Unknown constraint '{}'", name),
+            }),
+        }
+    }
+}
+
+#[test]
+fn parse_constraint_should_respect_precedence() {
+    let resolve = |name: &str| if name == "Player" { Some(1000) } else { None };
+
+    let constraint = parse_constraint(
+        "sphere(10, 0, 10, 50) and not component(Player) or entity(7)",
+        resolve,
+    )
+    .unwrap();
+
+    assert_eq!(
+        format!(
+            "{:?}",
+            QueryConstraint::Or(vec![
+                QueryConstraint::And(vec![
+                    QueryConstraint::Sphere(10.0, 0.0, 10.0, 50.0),
+                    QueryConstraint::Not(Box::new(QueryConstraint::Component(1000))),
+                ]),
+                QueryConstraint::EntityId(WorkerEntityId::new(7)),
+            ])
+        ),
+        format!("{:?}", constraint)
+    );
+
+    let error = parse_constraint("(component(Npc)", resolve).unwrap_err();
+    assert_eq!(1, error.position);
+
+    let error = parse_constraint("box(1, 2, 3)", resolve).unwrap_err();
+    assert_eq!("'box' takes 6 arguments at position 0", error.to_string());
+
+    let error = parse_constraint("component(54) and", resolve).unwrap_err();
+    assert_eq!(17, error.position);
+}
+
+#[test]
+fn parse_constraint_should_reject_ids_out_of_range() {
+    let resolve = |_: &str| None;
+
+    assert_eq!(
+        format!(
+            "{:?}",
+            QueryConstraint::EntityId(WorkerEntityId::new(9_223_372_036_854_775_807))
+        ),
+        format!(
+            "{:?}",
+            parse_constraint("entity(9223372036854775807)", resolve).unwrap()
+        )
+    );
+
+    let error = parse_constraint("entity(9223372036854775808)", resolve).unwrap_err();
+    assert_eq!(
+        "9223372036854775808 is not a valid entity ID at position 0",
+        error.to_string()
+    );
+
+    let error = parse_constraint("entity(1.5)", resolve).unwrap_err();
+    assert_eq!(
+        "Expected an entity ID but found 1.5 at position 0",
+        error.to_string()
+    );
+
+    let error = parse_constraint("component(-1)", resolve).unwrap_err();
+    assert_eq!(
+        "-1 is not a valid component ID at position 0",
+        error.to_string()
+    );
+
+    let error = parse_constraint("component(4294967296)", resolve).unwrap_err();
+    assert_eq!(
+        "4294967296 is not a valid component ID at position 0",
+        error.to_string()
+    );
+}
+
+#[test]
+fn parse_constraint_should_accept_signed_exponents() {
+    let resolve = |_: &str| None;
+
+    assert_eq!(
+        format!("{:?}", QueryConstraint::Sphere(1e-5, -2.5e+3, 1e3, 0.5)),
+        format!(
+            "{:?}",
+            parse_constraint("sphere(1e-5, -2.5E+3, 1e3, 0.5)", resolve).unwrap()
+        )
+    );
+
+    let error = parse_constraint("sphere(1e-, 0, 0, 1)", resolve).unwrap_err();
+    assert_eq!("Invalid number '1e-' at position 7", error.to_string());
+}
@@ -0,0 +1,129 @@
+//! Entity query results as temporary specs entities, so that they can be processed by the
+//! same joins over spatial storages as checked out entities.
+//!
+//! `SystemCommandSenderRes::entity_query_materialized` sends a snapshot query, and creates
+//! a specs entity for each result, with the data of every registered component and a
+//! `QueryResult` component:
+//!
+//! ```ignore
+//! system_command_sender.entity_query_materialized(query, |result, _| {
+//!     println!("{} results", result.map(|entities| entities.len()).unwrap_or(0));
+//! });
+//!
+//! // In a system running later in the frame:
+//! for (result, position) in (&query_results, &positions).join() {
+//!     println!("{} is at {:?}", result.entity_id(), position.coords);
+//! }
+//! ```
+//!
+//! The entities only exist until the `SpatialReaderSystem` next runs, which deletes them, so
+//! anything to keep must be copied out. They have no `EntityId` component and this worker
+//! has no authority over their components, so nothing about them is ever replicated, and
+//! they are never visited by `SpatialWriteStorage` joins. Joins over `SpatialReadStorage`
+//! do visit them, and can exclude them with `!&query_results`.
+use crate::component_registry::ComponentRegistry;
+use crate::entities::EntityId;
+use spatialos_sdk::worker::entity::Entity as WorkerEntity;
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use specs::prelude::{
+    Component, DenseVecStorage, Entities, Entity, Join, ReadStorage, SystemData, World,
+    WriteStorage,
+};
+use specs::storage::MaskedStorage;
+use std::collections::HashMap;
+
+/// A component marking a temporary entity created from an entity query result.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QueryResult {
+    entity_id: EntityId,
+}
+
+impl QueryResult {
+    /// The ID of the SpatialOS entity the result is a snapshot of.
+    pub fn entity_id(&self) -> EntityId {
+        self.entity_id
+    }
+}
+
+impl Component for QueryResult {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Creates an entity for every result, in entity ID order.
+pub(crate) fn materialize(
+    res: &World,
+    snapshot: &HashMap<WorkerEntityId, WorkerEntity>,
+) -> Vec<Entity> {
+    let mut results: Vec<(&WorkerEntityId, &WorkerEntity)> = snapshot.iter().collect();
+    results.sort_by_key(|(entity_id, _)| entity_id.id);
+
+    let mut entities = Vec::new();
+    for (entity_id, worker_entity) in results {
+        let entity = Entities::fetch(res).create();
+        WriteStorage::<QueryResult>::fetch(res)
+            .insert(
+                entity,
+                QueryResult {
+                    entity_id: EntityId(*entity_id),
+                },
+            )
+            .expect("Error inserting new QueryResult.");
+
+        for interface in ComponentRegistry::interfaces_iter() {
+            interface.insert_from_snapshot(res, entity, worker_entity);
+        }
+
+        entities.push(entity);
+    }
+
+    entities
+}
+
+/// Deletes every entity created for a query result.
+pub(crate) fn remove_results(res: &World) {
+    if !res.has_value::<MaskedStorage<QueryResult>>() {
+        return;
+    }
+
+    let entities = Entities::fetch(res);
+    let results = ReadStorage::<QueryResult>::fetch(res);
+    for (entity, _) in (&entities, &results).join() {
+        entities
+            .delete(entity)
+            .expect("Error deleting query result entity.");
+    }
+}
+
+#[test]
+fn query_results_should_be_removed_by_the_next_reader_run() {
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+    WriteStorage::<QueryResult>::setup(&mut world);
+
+    let mut snapshot = HashMap::new();
+    snapshot.insert(WorkerEntityId::new(8), WorkerEntity::new());
+    snapshot.insert(WorkerEntityId::new(3), WorkerEntity::new());
+
+    let entities = materialize(&world, &snapshot);
+    let ids: Vec<EntityId> = {
+        let results = ReadStorage::<QueryResult>::fetch(&world);
+        entities
+            .iter()
+            .map(|entity| results.get(*entity).unwrap().entity_id())
+            .collect()
+    };
+    assert_eq!(
+        vec![
+            EntityId(WorkerEntityId::new(3)),
+            EntityId(WorkerEntityId::new(8))
+        ],
+        ids
+    );
+
+    remove_results(&world);
+    world.maintain();
+    assert!(entities
+        .iter()
+        .all(|entity| !world.entities().is_alive(*entity)));
+}
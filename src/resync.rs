@@ -0,0 +1,145 @@
+//! Detection of resyncs, where the runtime sends entities which were already checked out
+//! again, for example after a reconnect or a bridge restart.
+//!
+//! During a resync every entity is added again, so systems which play effects or sounds
+//! for `SpatialEntityEvent::Added` can check `ResyncInProgress` to suppress them:
+//!
+//! ```ignore
+//! fn run(&mut self, (resync, events): Self::SystemData) {
+//!     for event in events.read(&mut self.reader_id) {
+//!         if let SpatialEntityEvent::Added(_, entity) = event {
+//!             if !resync.is_in_progress() {
+//!                 play_spawn_effect(*entity);
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! An entity is re-added if it was checked out or was removed within the tombstone window
+//! of `SpatialEntitiesRes`. A resync starts in the frame in which at least `threshold`
+//! entities are re-added, or the first re-add after `SpatialReaderSystem::reconnect`, and
+//! finishes at the end of the first frame in which nothing is re-added.
+use specs::prelude::World;
+use specs::shrev::EventChannel;
+
+/// An event emitted when a resync starts or finishes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResyncEvent {
+    Started,
+    /// The number of entities which were re-added during the resync.
+    Finished(usize),
+}
+
+/// An event channel which receives a `ResyncEvent` whenever a resync starts or finishes.
+pub type ResyncEvents = EventChannel<ResyncEvent>;
+
+/// A resource which tracks whether a resync is in progress. It is updated by the
+/// `SpatialReaderSystem`, so is accurate for every system which runs after it.
+#[derive(Debug)]
+pub struct ResyncInProgress {
+    threshold: usize,
+    in_progress: bool,
+    expecting_resync: bool,
+    readded_this_frame: usize,
+    readded_total: usize,
+}
+
+impl ResyncInProgress {
+    /// Sets the number of entities which must be re-added in a single frame to start a
+    /// resync. The default is 10.
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold.max(1);
+    }
+
+    pub fn is_in_progress(&self) -> bool {
+        self.in_progress
+    }
+
+    pub(crate) fn entity_readded(&mut self) {
+        self.readded_this_frame += 1;
+    }
+
+    pub(crate) fn expect_resync(&mut self) {
+        self.expecting_resync = true;
+    }
+
+    // Returns the event to emit, if the resync started or finished this frame.
+    fn end_frame(&mut self) -> Option<ResyncEvent> {
+        let readded = self.readded_this_frame;
+        self.readded_this_frame = 0;
+
+        if self.in_progress {
+            if readded > 0 {
+                self.readded_total += readded;
+                return None;
+            }
+
+            self.in_progress = false;
+            return Some(ResyncEvent::Finished(self.readded_total));
+        }
+
+        let threshold = if self.expecting_resync {
+            1
+        } else {
+            self.threshold
+        };
+
+        if readded >= threshold {
+            self.in_progress = true;
+            self.expecting_resync = false;
+            self.readded_total = readded;
+            Some(ResyncEvent::Started)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn finish_frame(res: &World) {
+        let event = res.fetch_mut::<ResyncInProgress>().end_frame();
+
+        if let Some(event) = event {
+            if res.has_value::<ResyncEvents>() {
+                res.fetch_mut::<ResyncEvents>().single_write(event);
+            }
+        }
+    }
+}
+
+impl Default for ResyncInProgress {
+    fn default() -> Self {
+        ResyncInProgress {
+            threshold: 10,
+            in_progress: false,
+            expecting_resync: false,
+            readded_this_frame: 0,
+            readded_total: 0,
+        }
+    }
+}
+
+#[test]
+fn resync_should_start_at_threshold_and_finish_when_quiet() {
+    let mut resync = ResyncInProgress::default();
+    resync.set_threshold(3);
+
+    resync.entity_readded();
+    resync.entity_readded();
+    assert_eq!(None, resync.end_frame());
+    assert!(!resync.is_in_progress());
+
+    for _ in 0..3 {
+        resync.entity_readded();
+    }
+    assert_eq!(Some(ResyncEvent::Started), resync.end_frame());
+    assert!(resync.is_in_progress());
+
+    resync.entity_readded();
+    assert_eq!(None, resync.end_frame());
+    assert_eq!(Some(ResyncEvent::Finished(4)), resync.end_frame());
+    assert!(!resync.is_in_progress());
+
+    resync.expect_resync();
+    resync.entity_readded();
+    assert_eq!(Some(ResyncEvent::Started), resync.end_frame());
+}
@@ -0,0 +1,134 @@
+//! RPC-style command handlers.
+//!
+//! `rpc_system!` generates a system which responds to a single command of a component
+//! by calling a function, instead of a bespoke system which joins over the
+//! `CommandRequests` and matches on each request:
+//!
+//! ```ignore
+//! rpc_system!(UpdateHealthSys<'a>: Player,
+//!     PlayerCommandRequest::UpdateHealth => PlayerCommandResponse::UpdateHealth,
+//!     update_health, SpatialWriteStorage<'a, Player>);
+//!
+//! fn update_health(
+//!     request: &UpdateHealthRequest,
+//!     context: &RpcContext,
+//!     players: &mut SpatialWriteStorage<Player>,
+//! ) -> Option<UpdateHealthResponse> {
+//!     let player = players.get_mut(context.entity)?;
+//!     player.health -= request.damage;
+//!     Some(UpdateHealthResponse { new_health: player.health })
+//! }
+//! ```
+//!
+//! The last argument is the `SystemData` the handler needs, which is a tuple if it needs
+//! more than one storage or resource. As with `respond`, returning `None` leaves the
+//! request for other systems or the next frame, and requests for other commands of the
+//! component are left untouched.
+use specs::prelude::Entity;
+
+/// The entity and caller of a command request passed to an `rpc_system!` handler.
+#[derive(Debug)]
+pub struct RpcContext<'a> {
+    pub entity: Entity,
+    pub caller_worker_id: &'a str,
+    pub caller_attribute_set: &'a [String],
+}
+
+#[doc(hidden)]
+pub mod __macro_support {
+    pub use crate::commands::CommandRequests;
+    pub use specs::prelude::{Entities, Join, System};
+}
+
+/// Generates a system which calls `$handler` for every request of the command
+/// `$request`, responding with its result wrapped in `$response`.
+#[macro_export]
+macro_rules! rpc_system {
+    ($system:ident<$lt:lifetime>: $component:ty, $request:path => $response:path, $handler:expr, $data:ty) => {
+        pub struct $system;
+
+        impl<$lt> $crate::rpc::__macro_support::System<$lt> for $system {
+            type SystemData = (
+                $crate::rpc::__macro_support::Entities<$lt>,
+                $crate::rpc::__macro_support::CommandRequests<$lt, $component>,
+                $data,
+            );
+
+            fn run(&mut self, (entities, mut requests, mut data): Self::SystemData) {
+                use $crate::rpc::__macro_support::Join;
+
+                for (entity, requests) in (&entities, &mut requests).join() {
+                    requests.respond(|request, caller_worker_id, caller_attribute_set| {
+                        #[allow(unreachable_patterns)]
+                        match request {
+                            $request(request) => {
+                                let context = $crate::rpc::RpcContext {
+                                    entity,
+                                    caller_worker_id,
+                                    caller_attribute_set,
+                                };
+                                $handler(request, &context, &mut data).map($response)
+                            }
+                            _ => None,
+                        }
+                    });
+                }
+            }
+        }
+    };
+}
+
+#[test]
+fn rpc_system_should_respond_to_its_command() {
+    use crate::commands::{CommandRequests, CommandRequestsComp};
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::RequestId;
+    use specs::prelude::{Builder, RunNow, System, World, WorldExt, Write};
+
+    fn increment(
+        request: &IncrementRequest,
+        context: &RpcContext,
+        total: &mut Write<u32>,
+    ) -> Option<IncrementResponse> {
+        if context.caller_worker_id != "client" {
+            return None;
+        }
+        **total += request.amount;
+        Some(IncrementResponse { total: **total })
+    }
+
+    rpc_system!(IncrementSys<'a>: Counter,
+        CounterCommandRequest::Increment => CounterCommandResponse::Increment,
+        increment, Write<'a, u32>);
+
+    let mut world = World::new();
+    System::setup(&mut IncrementSys, &mut world);
+    let entity = world.create_entity().build();
+
+    {
+        let mut requests = CommandRequests::<Counter>::fetch(&world);
+        let mut comp = CommandRequestsComp::default();
+        for (id, caller) in [(1, "client"), (2, "other"), (3, "client")].iter() {
+            comp.on_request(
+                RequestId::new(*id),
+                CounterCommandRequest::Increment(IncrementRequest { amount: *id }),
+                caller.to_string(),
+                Vec::new(),
+            );
+        }
+        requests.insert(entity, comp).unwrap();
+    }
+
+    IncrementSys.run_now(&world);
+
+    assert_eq!(4, *world.fetch::<u32>());
+    let mut requests = CommandRequests::<Counter>::fetch(&world);
+    let responses = requests.get_mut(entity).unwrap().take_responses();
+    let totals: Vec<_> = responses
+        .into_iter()
+        .map(|(request_id, response)| match response {
+            CounterCommandResponse::Increment(response) => (request_id, response.total),
+        })
+        .collect();
+    assert_eq!(vec![(RequestId::new(1), 1), (RequestId::new(3), 4)], totals);
+}
@@ -0,0 +1,491 @@
+//! Multi-step operations spanning several commands or entities, such as a trade between
+//! two players, in which a failed step undoes the steps before it.
+//!
+//! Each step of a `Saga` is an action which starts some work, usually by sending a
+//! command, and reports its outcome through a `StepHandle`. A step can have a compensation
+//! which undoes it. `Sagas` runs the steps in order, retrying failed steps, and if a step
+//! still fails runs the compensations of every completed step in reverse:
+//!
+//! ```ignore
+//! let trade = Saga::new("trade")
+//!     .command_step::<Inventory, _, _>(
+//!         "take sword",
+//!         seller_id,
+//!         || InventoryCommandRequest::Remove(Item::Sword),
+//!         |_| Ok(()),
+//!     )
+//!     .compensate_with_command::<Inventory, _, _>(
+//!         seller_id,
+//!         || InventoryCommandRequest::Add(Item::Sword),
+//!         |_| Ok(()),
+//!     )
+//!     .command_step::<Wallet, _, _>("pay", buyer_id, || WalletCommandRequest::Pay(10), check_paid)
+//!     .retries(2);
+//!
+//! let saga_id = sagas.start(trade);
+//! ```
+//!
+//! Steps are started by the `SpatialWriterSystem`, before commands are sent, so a step's
+//! command is sent in the same frame it is started. A step or compensation which hasn't
+//! completed within the saga's step timeout, measured with the `SpatialClock`, fails. The
+//! outcome of every saga is emitted as a `SagaEvent`.
+use crate::clock;
+use crate::commands::CommandSender;
+use crate::entities::EntityId;
+use crate::logging::{self, LogKind, LogLevel};
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use specs::prelude::{SystemData, World};
+use specs::shrev::EventChannel;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type SagaId = u64;
+
+/// An event emitted when a saga finishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SagaEvent {
+    /// Every step completed.
+    Completed(SagaId),
+    /// A step failed, and every completed step was compensated.
+    Compensated {
+        saga_id: SagaId,
+        failed_step: &'static str,
+        error: String,
+    },
+    /// A step failed, and then so did the compensation of a completed step, so the saga
+    /// was abandoned partway through.
+    CompensationFailed {
+        saga_id: SagaId,
+        failed_step: &'static str,
+        error: String,
+    },
+}
+
+/// An event channel which receives a `SagaEvent` whenever a saga finishes.
+pub type SagaEvents = EventChannel<SagaEvent>;
+
+/// Reports the outcome of a step or compensation. It can be completed from a command
+/// callback, or immediately for local work.
+#[derive(Clone)]
+pub struct StepHandle {
+    outcome: Arc<Mutex<Option<Result<(), String>>>>,
+}
+
+impl StepHandle {
+    fn new() -> StepHandle {
+        StepHandle {
+            outcome: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn complete(&self, result: Result<(), String>) {
+        *self.outcome.lock().unwrap() = Some(result);
+    }
+
+    fn take_outcome(&self) -> Option<Result<(), String>> {
+        self.outcome.lock().unwrap().take()
+    }
+}
+
+type StepAction = Box<Fn(&World, StepHandle) + Send + Sync>;
+
+struct SagaStep {
+    name: &'static str,
+    action: StepAction,
+    compensation: Option<StepAction>,
+}
+
+/// A sequence of steps to run with `Sagas`.
+pub struct Saga {
+    name: &'static str,
+    steps: Vec<SagaStep>,
+    retries: u32,
+    step_timeout: Duration,
+}
+
+impl Saga {
+    pub fn new(name: &'static str) -> Saga {
+        Saga {
+            name,
+            steps: Vec::new(),
+            retries: 0,
+            step_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Adds a step which runs `action`.
+    pub fn step<F>(mut self, name: &'static str, action: F) -> Saga
+    where
+        F: 'static + Fn(&World, StepHandle) + Send + Sync,
+    {
+        self.steps.push(SagaStep {
+            name,
+            action: Box::new(action),
+            compensation: None,
+        });
+        self
+    }
+
+    /// Sets the compensation of the last step added.
+    pub fn compensate<F>(mut self, compensation: F) -> Saga
+    where
+        F: 'static + Fn(&World, StepHandle) + Send + Sync,
+    {
+        self.steps
+            .last_mut()
+            .expect("A compensation must follow a step.")
+            .compensation = Some(Box::new(compensation));
+        self
+    }
+
+    /// Adds a step which sends a command, and succeeds if the command succeeds and `check`
+    /// accepts its response.
+    pub fn command_step<T, R, C>(
+        self,
+        name: &'static str,
+        entity_id: EntityId,
+        request: R,
+        check: C,
+    ) -> Saga
+    where
+        T: 'static + WorkerComponent,
+        R: 'static + Fn() -> T::CommandRequest + Send + Sync,
+        C: 'static + Fn(&T::CommandResponse) -> Result<(), String> + Send + Sync,
+    {
+        self.step(name, command_action::<T, R, C>(entity_id, request, check))
+    }
+
+    /// Sets the compensation of the last step added to sending a command.
+    pub fn compensate_with_command<T, R, C>(self, entity_id: EntityId, request: R, check: C) -> Saga
+    where
+        T: 'static + WorkerComponent,
+        R: 'static + Fn() -> T::CommandRequest + Send + Sync,
+        C: 'static + Fn(&T::CommandResponse) -> Result<(), String> + Send + Sync,
+    {
+        self.compensate(command_action::<T, R, C>(entity_id, request, check))
+    }
+
+    /// Sets how many times a failed step or compensation is retried. The default is 0.
+    pub fn retries(mut self, retries: u32) -> Saga {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long a step or compensation can run before it fails, for example because
+    /// the work it started will never report its outcome. The default is 30 seconds.
+    pub fn step_timeout(mut self, step_timeout: Duration) -> Saga {
+        self.step_timeout = step_timeout;
+        self
+    }
+}
+
+fn command_action<T, R, C>(entity_id: EntityId, request: R, check: C) -> impl Fn(&World, StepHandle)
+where
+    T: 'static + WorkerComponent,
+    R: 'static + Fn() -> T::CommandRequest + Send + Sync,
+    C: 'static + Fn(&T::CommandResponse) -> Result<(), String> + Send + Sync,
+{
+    let check = Arc::new(check);
+    move |res, handle| {
+        let check = check.clone();
+        CommandSender::<T>::fetch(res).send_command(entity_id, request(), move |response, _| {
+            handle.complete(match response {
+                Ok(response) => check(response),
+                Err(status) => Err(format!("{:?}", status)),
+            });
+        });
+    }
+}
+
+/// How far a running saga has got.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SagaProgress {
+    /// The index of the step being run or compensated.
+    pub step: usize,
+    pub total_steps: usize,
+    pub compensating: bool,
+}
+
+enum Phase {
+    Forward(usize),
+    // The number of completed steps which remain to be compensated.
+    Compensating(usize),
+}
+
+struct RunningSaga {
+    saga: Saga,
+    phase: Phase,
+    attempts: u32,
+    // The current step or compensation, and when it was started.
+    in_flight: Option<(StepHandle, Instant)>,
+    failure: Option<(&'static str, String)>,
+}
+
+impl RunningSaga {
+    // Starts or checks on the current step, returning the event if the saga finished.
+    fn advance(&mut self, saga_id: SagaId, res: &World, now: Instant) -> Option<SagaEvent> {
+        loop {
+            if let Some((handle, started)) = &self.in_flight {
+                let outcome = match handle.take_outcome() {
+                    None if now.duration_since(*started) >= self.saga.step_timeout => {
+                        Some(Err(format!("timed out after {:?}", self.saga.step_timeout)))
+                    }
+                    outcome => outcome,
+                };
+
+                match outcome {
+                    None => return None,
+                    Some(Ok(())) => {
+                        self.in_flight = None;
+                        self.attempts = 0;
+                        self.phase = match self.phase {
+                            Phase::Forward(step) => Phase::Forward(step + 1),
+                            Phase::Compensating(remaining) => Phase::Compensating(remaining - 1),
+                        };
+                    }
+                    Some(Err(error)) => {
+                        self.in_flight = None;
+                        self.attempts += 1;
+                        if self.attempts <= self.saga.retries {
+                            continue;
+                        }
+                        self.attempts = 0;
+
+                        match self.phase {
+                            Phase::Forward(step) => {
+                                self.failure = Some((self.saga.steps[step].name, error));
+                                self.phase = Phase::Compensating(step);
+                            }
+                            Phase::Compensating(remaining) => {
+                                let step = self.saga.steps[remaining - 1].name;
+                                logging::log(
+                                    res,
+                                    LogLevel::Error,
+                                    LogKind::Saga,
+                                    &format!(
+                                        "Saga {} could not compensate step {}: {}",
+                                        self.saga.name, step, error
+                                    ),
+                                );
+                                let (failed_step, error) = self.failure.take().unwrap();
+                                return Some(SagaEvent::CompensationFailed {
+                                    saga_id,
+                                    failed_step,
+                                    error,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+
+            let action = match self.phase {
+                Phase::Forward(step) if step == self.saga.steps.len() => {
+                    return Some(SagaEvent::Completed(saga_id));
+                }
+                Phase::Forward(step) => &self.saga.steps[step].action,
+                Phase::Compensating(0) => {
+                    let (failed_step, error) = self.failure.take().unwrap();
+                    return Some(SagaEvent::Compensated {
+                        saga_id,
+                        failed_step,
+                        error,
+                    });
+                }
+                Phase::Compensating(remaining) => {
+                    match &self.saga.steps[remaining - 1].compensation {
+                        Some(compensation) => compensation,
+                        None => {
+                            self.phase = Phase::Compensating(remaining - 1);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let handle = StepHandle::new();
+            action(res, handle.clone());
+            self.in_flight = Some((handle, now));
+        }
+    }
+
+    fn progress(&self) -> SagaProgress {
+        let (step, compensating) = match self.phase {
+            Phase::Forward(step) => (step, false),
+            Phase::Compensating(remaining) => (remaining.saturating_sub(1), true),
+        };
+        SagaProgress {
+            step,
+            total_steps: self.saga.steps.len(),
+            compensating,
+        }
+    }
+}
+
+/// A resource which runs sagas.
+#[derive(Default)]
+pub struct Sagas {
+    next_id: SagaId,
+    running: HashMap<SagaId, RunningSaga>,
+}
+
+impl Sagas {
+    pub fn start(&mut self, saga: Saga) -> SagaId {
+        self.next_id += 1;
+        self.running.insert(
+            self.next_id,
+            RunningSaga {
+                saga,
+                phase: Phase::Forward(0),
+                attempts: 0,
+                in_flight: None,
+                failure: None,
+            },
+        );
+        self.next_id
+    }
+
+    /// Returns the progress of a saga, or `None` if it has finished.
+    pub fn progress(&self, saga_id: SagaId) -> Option<SagaProgress> {
+        self.running.get(&saga_id).map(RunningSaga::progress)
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    pub(crate) fn drive(res: &World) {
+        // Steps may fetch `Sagas` themselves, for example to start another saga.
+        let mut running = std::mem::replace(&mut res.fetch_mut::<Sagas>().running, HashMap::new());
+        let now = clock::now(res);
+
+        let mut events = Vec::new();
+        running.retain(|saga_id, saga| match saga.advance(*saga_id, res, now) {
+            Some(event) => {
+                events.push(event);
+                false
+            }
+            None => true,
+        });
+
+        res.fetch_mut::<Sagas>().running.extend(running);
+
+        if res.has_value::<SagaEvents>() {
+            res.fetch_mut::<SagaEvents>().iter_write(events);
+        }
+    }
+}
+
+#[test]
+fn failed_step_should_be_retried_then_compensated_in_reverse() {
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+    world.insert(Sagas::default());
+    world.insert(SagaEvents::new());
+    let mut reader = world.fetch_mut::<SagaEvents>().register_reader();
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let record = |entry: &'static str, result: Result<(), String>| {
+        let log = log.clone();
+        move |_: &World, handle: StepHandle| {
+            log.lock().unwrap().push(entry);
+            handle.complete(result.clone());
+        }
+    };
+
+    let saga = Saga::new("trade")
+        .step("reserve", record("reserve", Ok(())))
+        .compensate(record("release", Ok(())))
+        .step("notify", record("notify", Ok(())))
+        .step("pay", record("pay", Err("insufficient funds".to_string())))
+        .compensate(record("refund", Ok(())))
+        .retries(1);
+
+    let saga_id = world.fetch_mut::<Sagas>().start(saga);
+    assert_eq!(
+        Some(SagaProgress {
+            step: 0,
+            total_steps: 3,
+            compensating: false
+        }),
+        world.fetch::<Sagas>().progress(saga_id)
+    );
+
+    Sagas::drive(&world);
+
+    assert_eq!(
+        vec!["reserve", "notify", "pay", "pay", "release"],
+        *log.lock().unwrap()
+    );
+    assert_eq!(None, world.fetch::<Sagas>().progress(saga_id));
+    assert_eq!(
+        vec![SagaEvent::Compensated {
+            saga_id,
+            failed_step: "pay",
+            error: "insufficient funds".to_string(),
+        }],
+        world
+            .fetch::<SagaEvents>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn steps_which_never_complete_should_time_out_and_be_compensated() {
+    use crate::clock::{ManualClock, SpatialClock};
+    use specs::prelude::WorldExt;
+
+    let clock = ManualClock::new();
+    let mut world = World::new();
+    world.insert(SpatialClock::new(clock.clone()));
+    world.insert(Sagas::default());
+    world.insert(SagaEvents::new());
+    let mut reader = world.fetch_mut::<SagaEvents>().register_reader();
+
+    let compensated = Arc::new(Mutex::new(false));
+    let saga = {
+        let compensated = compensated.clone();
+        Saga::new("trade")
+            .step("reserve", |_, handle| handle.complete(Ok(())))
+            .compensate(move |_, handle| {
+                *compensated.lock().unwrap() = true;
+                handle.complete(Ok(()));
+            })
+            // The outcome of this step is never reported, as if its response was lost.
+            .step("pay", |_, _| {})
+            .step_timeout(Duration::from_secs(5))
+    };
+    let saga_id = world.fetch_mut::<Sagas>().start(saga);
+
+    Sagas::drive(&world);
+    clock.advance(Duration::from_secs(4));
+    Sagas::drive(&world);
+    assert_eq!(
+        Some(SagaProgress {
+            step: 1,
+            total_steps: 2,
+            compensating: false
+        }),
+        world.fetch::<Sagas>().progress(saga_id)
+    );
+
+    clock.advance(Duration::from_secs(1));
+    Sagas::drive(&world);
+    assert!(*compensated.lock().unwrap());
+    assert_eq!(
+        vec![SagaEvent::Compensated {
+            saga_id,
+            failed_step: "pay",
+            error: "timed out after 5s".to_string(),
+        }],
+        world
+            .fetch::<SagaEvents>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>()
+    );
+}
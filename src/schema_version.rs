@@ -0,0 +1,231 @@
+//! Detection of workers built against stale schema.
+//!
+//! When a `SchemaVersion` resource is present, the `SpatialReaderSystem` compares the hash of
+//! the schema this worker was built against with the hash in a worker flag once connected, so
+//! a mismatch is reported clearly instead of surfacing later as deserialization failures. The
+//! hash is checked again whenever the flag is updated.
+//!
+//! The code generator doesn't embed a hash of the schema, so the worker has to compute one in
+//! the same way as the tooling which sets the flag. For example, the git tree hash of the
+//! schema directory can be embedded by a build script:
+//!
+//! ```ignore
+//! // build.rs
+//! let output = Command::new("git")
+//!     .args(&["rev-parse", "HEAD:schema"])
+//!     .output()
+//!     .unwrap();
+//! let hash = String::from_utf8(output.stdout).unwrap();
+//! println!("cargo:rustc-env=SCHEMA_HASH={}", hash.trim());
+//! println!("cargo:rerun-if-changed=schema");
+//! ```
+//!
+//! and passed to the deployment with `--worker_flag schema_hash=$(git rev-parse HEAD:schema)`.
+use crate::logging::{self, LogKind, LogLevel};
+use specs::prelude::World;
+use specs::shrev::EventChannel;
+
+/// The result of comparing the schema hash of this worker with the deployment's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaVersionStatus {
+    /// The worker flag has not been checked yet.
+    Unchecked,
+    /// The worker was built against the same schema as the deployment.
+    Matched,
+    /// The worker was built against different schema to the deployment.
+    Mismatched { expected: String, actual: String },
+    /// The deployment does not set the worker flag, so the schema could not be verified.
+    FlagMissing,
+}
+
+/// An event channel which receives the `SchemaVersionStatus` once the schema has been checked,
+/// unless it matched, and again whenever a flag update changes the status.
+pub type SchemaVersionEvents = EventChannel<SchemaVersionStatus>;
+
+/// A resource which enables verification of the schema hash.
+///
+/// ```ignore
+/// world.insert(SchemaVersion::new(env!("SCHEMA_HASH")));
+/// ```
+pub struct SchemaVersion {
+    expected: String,
+    flag_name: String,
+    status: SchemaVersionStatus,
+}
+
+impl SchemaVersion {
+    /// Verifies against the `schema_hash` worker flag.
+    pub fn new(expected: &str) -> SchemaVersion {
+        SchemaVersion::with_flag(expected, "schema_hash")
+    }
+
+    pub fn with_flag(expected: &str, flag_name: &str) -> SchemaVersion {
+        SchemaVersion {
+            expected: expected.to_string(),
+            flag_name: flag_name.to_string(),
+            status: SchemaVersionStatus::Unchecked,
+        }
+    }
+
+    pub fn status(&self) -> &SchemaVersionStatus {
+        &self.status
+    }
+
+    pub(crate) fn flag_name(&self) -> &str {
+        &self.flag_name
+    }
+
+    pub(crate) fn is_checked(&self) -> bool {
+        self.status != SchemaVersionStatus::Unchecked
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.status = SchemaVersionStatus::Unchecked;
+    }
+
+    /// Checks the hash again if its worker flag is updated after it was first checked, such
+    /// as when the flag is set after the worker connected.
+    pub(crate) fn got_flag_update(res: &World, name: &str, value: Option<String>) {
+        let recheck = {
+            let version = res.fetch::<SchemaVersion>();
+            version.is_checked() && version.flag_name == name
+        };
+
+        if recheck {
+            SchemaVersion::verify(res, value);
+        }
+    }
+
+    pub(crate) fn verify(res: &World, flag_value: Option<String>) {
+        let (previous, status) = {
+            let mut version = res.fetch_mut::<SchemaVersion>();
+            let status = match flag_value {
+                Some(ref actual) if *actual == version.expected => SchemaVersionStatus::Matched,
+                Some(actual) => SchemaVersionStatus::Mismatched {
+                    expected: version.expected.clone(),
+                    actual,
+                },
+                None => SchemaVersionStatus::FlagMissing,
+            };
+            let previous = std::mem::replace(&mut version.status, status.clone());
+            (previous, status)
+        };
+
+        if previous == status {
+            return;
+        }
+
+        let message = match status {
+            SchemaVersionStatus::Mismatched {
+                ref expected,
+                ref actual,
+            } => format!(
+                "This worker was built against schema {}, but the deployment uses schema {}. \
+                 Regenerate the schema code and rebuild the worker.",
+                expected, actual
+            ),
+            SchemaVersionStatus::FlagMissing => {
+                "The schema hash worker flag is not set, so the schema could not be verified."
+                    .to_string()
+            }
+            SchemaVersionStatus::Matched if previous != SchemaVersionStatus::Unchecked => {
+                "The schema hash worker flag was updated and now matches this worker's schema."
+                    .to_string()
+            }
+            _ => return,
+        };
+
+        let level = match status {
+            SchemaVersionStatus::Mismatched { .. } => LogLevel::Error,
+            SchemaVersionStatus::Matched => LogLevel::Info,
+            _ => LogLevel::Warn,
+        };
+        logging::log(res, level, LogKind::SchemaVersion, &message);
+
+        if res.has_value::<SchemaVersionEvents>() {
+            res.fetch_mut::<SchemaVersionEvents>().single_write(status);
+        }
+    }
+}
+
+#[test]
+fn schema_version_should_report_mismatch() {
+    use specs::prelude::{World, WorldExt};
+
+    const SCHEMA_HASH: &str = "5f0c2a9e71d4b3a8";
+
+    let mut world = World::new();
+    world.insert(SchemaVersion::new(SCHEMA_HASH));
+    world.insert(SchemaVersionEvents::new());
+    world.insert(logging::SpatialLogger::new(
+        |_: LogLevel, _: LogKind, _: &str| {},
+    ));
+
+    let mut reader_id = world.fetch_mut::<SchemaVersionEvents>().register_reader();
+
+    SchemaVersion::verify(&world, Some(SCHEMA_HASH.to_string()));
+    assert_eq!(
+        SchemaVersionStatus::Matched,
+        *world.fetch::<SchemaVersion>().status()
+    );
+
+    SchemaVersion::verify(&world, Some("stale".to_string()));
+    match world.fetch::<SchemaVersion>().status() {
+        SchemaVersionStatus::Mismatched { actual, .. } => assert_eq!("stale", actual),
+        other => panic!("Unexpected status: {:?}", other),
+    }
+
+    let events: Vec<SchemaVersionStatus> = world
+        .fetch::<SchemaVersionEvents>()
+        .read(&mut reader_id)
+        .cloned()
+        .collect();
+    assert_eq!(1, events.len());
+}
+
+#[test]
+fn schema_version_should_recheck_when_the_flag_arrives() {
+    use specs::prelude::{World, WorldExt};
+
+    const SCHEMA_HASH: &str = "5f0c2a9e71d4b3a8";
+
+    let mut world = World::new();
+    world.insert(SchemaVersion::new(SCHEMA_HASH));
+    world.insert(SchemaVersionEvents::new());
+    world.insert(logging::SpatialLogger::new(
+        |_: LogLevel, _: LogKind, _: &str| {},
+    ));
+
+    let mut reader_id = world.fetch_mut::<SchemaVersionEvents>().register_reader();
+
+    SchemaVersion::verify(&world, None);
+    assert_eq!(
+        SchemaVersionStatus::FlagMissing,
+        *world.fetch::<SchemaVersion>().status()
+    );
+
+    SchemaVersion::got_flag_update(&world, "other_flag", Some(SCHEMA_HASH.to_string()));
+    assert_eq!(
+        SchemaVersionStatus::FlagMissing,
+        *world.fetch::<SchemaVersion>().status()
+    );
+
+    SchemaVersion::got_flag_update(&world, "schema_hash", Some(SCHEMA_HASH.to_string()));
+    assert_eq!(
+        SchemaVersionStatus::Matched,
+        *world.fetch::<SchemaVersion>().status()
+    );
+
+    let events: Vec<SchemaVersionStatus> = world
+        .fetch::<SchemaVersionEvents>()
+        .read(&mut reader_id)
+        .cloned()
+        .collect();
+    assert_eq!(
+        vec![
+            SchemaVersionStatus::FlagMissing,
+            SchemaVersionStatus::Matched
+        ],
+        events
+    );
+}
@@ -0,0 +1,309 @@
+//! The calls this crate makes on a `WorkerConnection`, behind a single trait so that the
+//! differences between Worker SDK versions are confined to this module.
+//!
+//! The SDK version is chosen with the `sdk-13` (the default) or `sdk-14` feature, each of
+//! which builds its own SDK dependency. Enabling both, for example when two crates in a
+//! workspace ask for different versions, selects SDK 14 rather than failing to build. The
+//! rest of the crate only calls `SdkConnection`, and never the `Connection` trait directly.
+//! The same applies to serializing schema objects to bytes.
+use spatialos_sdk::worker::commands::{
+    CreateEntityRequest, DeleteEntityRequest, EntityQueryRequest, IncomingCommandRequest,
+    OutgoingCommandRequest, ReserveEntityIdsRequest,
+};
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::UpdateParameters;
+use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
+use spatialos_sdk::worker::entity::Entity as WorkerEntity;
+use spatialos_sdk::worker::internal::schema::{
+    SchemaCommandRequest, SchemaComponentData, SchemaComponentUpdate,
+};
+use spatialos_sdk::worker::op::OpList;
+use spatialos_sdk::worker::query::EntityQuery;
+use spatialos_sdk::worker::snapshot::SnapshotInputStream;
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use spatialos_sdk::worker::RequestId;
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(not(any(feature = "sdk-13", feature = "sdk-14")))]
+compile_error!("One of the `sdk-13` or `sdk-14` features must be enabled.");
+
+pub(crate) trait SdkConnection {
+    /// Returns the ops received since the last call, without blocking.
+    fn poll_ops(&mut self) -> OpList;
+
+    fn send_update<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        update: T::Update,
+    );
+
+    fn send_request<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        request: T::CommandRequest,
+    ) -> RequestId<OutgoingCommandRequest>;
+
+    fn send_response<T: 'static + WorkerComponent>(
+        &mut self,
+        request_id: RequestId<IncomingCommandRequest>,
+        response: T::CommandResponse,
+    );
+
+    fn send_failure(&mut self, request_id: RequestId<IncomingCommandRequest>, message: &str);
+
+    fn send_reserve_entity_ids(
+        &mut self,
+        number: u32,
+        timeout: Option<Duration>,
+    ) -> RequestId<ReserveEntityIdsRequest>;
+
+    fn send_create_entity(
+        &mut self,
+        entity: WorkerEntity,
+        entity_id: Option<WorkerEntityId>,
+        timeout: Option<Duration>,
+    ) -> RequestId<CreateEntityRequest>;
+
+    fn send_delete_entity(
+        &mut self,
+        entity_id: WorkerEntityId,
+        timeout: Option<Duration>,
+    ) -> RequestId<DeleteEntityRequest>;
+
+    fn send_entity_query(
+        &mut self,
+        query: EntityQuery,
+        timeout: Option<Duration>,
+    ) -> RequestId<EntityQueryRequest>;
+
+    #[cfg(feature = "partitions")]
+    fn send_claim_partition(
+        &mut self,
+        partition_id: WorkerEntityId,
+    ) -> RequestId<OutgoingCommandRequest>;
+
+    fn worker_flag(&self, name: &str) -> Option<String>;
+
+    fn connected(&self) -> bool;
+}
+
+impl SdkConnection for WorkerConnection {
+    #[cfg(not(feature = "sdk-14"))]
+    fn poll_ops(&mut self) -> OpList {
+        self.get_op_list(0)
+    }
+
+    // SDK 14 takes the timeout as a `Duration` rather than milliseconds.
+    #[cfg(feature = "sdk-14")]
+    fn poll_ops(&mut self) -> OpList {
+        self.get_op_list(std::time::Duration::from_millis(0))
+    }
+
+    fn send_update<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        update: T::Update,
+    ) {
+        self.send_component_update::<T>(entity_id, update, UpdateParameters::default());
+    }
+
+    #[cfg(not(feature = "sdk-14"))]
+    fn send_request<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        request: T::CommandRequest,
+    ) -> RequestId<OutgoingCommandRequest> {
+        self.send_command_request::<T>(entity_id, request, None, Default::default())
+    }
+
+    // SDK 14 moves the timeout into the command parameters.
+    #[cfg(feature = "sdk-14")]
+    fn send_request<T: 'static + WorkerComponent>(
+        &mut self,
+        entity_id: WorkerEntityId,
+        request: T::CommandRequest,
+    ) -> RequestId<OutgoingCommandRequest> {
+        self.send_command_request::<T>(entity_id, request, Default::default())
+    }
+
+    fn send_response<T: 'static + WorkerComponent>(
+        &mut self,
+        request_id: RequestId<IncomingCommandRequest>,
+        response: T::CommandResponse,
+    ) {
+        self.send_command_response::<T>(request_id, response);
+    }
+
+    fn send_failure(&mut self, request_id: RequestId<IncomingCommandRequest>, message: &str) {
+        self.send_command_failure(request_id, message);
+    }
+
+    fn send_reserve_entity_ids(
+        &mut self,
+        number: u32,
+        timeout: Option<Duration>,
+    ) -> RequestId<ReserveEntityIdsRequest> {
+        self.send_reserve_entity_ids_request(
+            ReserveEntityIdsRequest(number),
+            timeout_millis(timeout),
+        )
+    }
+
+    fn send_create_entity(
+        &mut self,
+        entity: WorkerEntity,
+        entity_id: Option<WorkerEntityId>,
+        timeout: Option<Duration>,
+    ) -> RequestId<CreateEntityRequest> {
+        self.send_create_entity_request(entity, entity_id, timeout_millis(timeout))
+    }
+
+    fn send_delete_entity(
+        &mut self,
+        entity_id: WorkerEntityId,
+        timeout: Option<Duration>,
+    ) -> RequestId<DeleteEntityRequest> {
+        self.send_delete_entity_request(DeleteEntityRequest(entity_id), timeout_millis(timeout))
+    }
+
+    fn send_entity_query(
+        &mut self,
+        query: EntityQuery,
+        timeout: Option<Duration>,
+    ) -> RequestId<EntityQueryRequest> {
+        self.send_entity_query_request(EntityQueryRequest(query), timeout_millis(timeout))
+    }
+
+    #[cfg(feature = "partitions")]
+    fn send_claim_partition(
+        &mut self,
+        partition_id: WorkerEntityId,
+    ) -> RequestId<OutgoingCommandRequest> {
+        self.send_claim_partition_request(partition_id, Default::default())
+    }
+
+    fn worker_flag(&self, name: &str) -> Option<String> {
+        self.get_worker_flag(name)
+    }
+
+    fn connected(&self) -> bool {
+        self.is_connected()
+    }
+}
+
+// The SDK takes system command timeouts in milliseconds, and uses its own default for `None`.
+fn timeout_millis(timeout: Option<Duration>) -> Option<u32> {
+    timeout.map(|timeout| timeout.as_millis().min(u128::from(u32::MAX)) as u32)
+}
+
+/// Serializes component data to bytes with schema.
+pub(crate) fn serialize_data<T: WorkerComponent>(data: &T) -> Result<Vec<u8>, String> {
+    Ok(T::to_data(data)?.serialize())
+}
+
+/// Reads every entity in a snapshot file.
+pub(crate) fn read_snapshot(path: &Path) -> Result<Vec<(WorkerEntityId, WorkerEntity)>, String> {
+    let mut stream = SnapshotInputStream::new(path)?;
+    let mut entities = Vec::new();
+    while stream.has_next() {
+        entities.push(stream.read_entity()?);
+    }
+    Ok(entities)
+}
+
+pub(crate) fn deserialize_data<T: WorkerComponent>(bytes: &[u8]) -> Result<T, String> {
+    T::from_data(&SchemaComponentData::deserialize(bytes)?)
+}
+
+/// Serializes a component update to bytes with schema.
+pub(crate) fn serialize_update<T: WorkerComponent>(update: &T::Update) -> Result<Vec<u8>, String> {
+    Ok(T::to_update(update)?.serialize())
+}
+
+pub(crate) fn deserialize_update<T: WorkerComponent>(bytes: &[u8]) -> Result<T::Update, String> {
+    T::from_update(&SchemaComponentUpdate::deserialize(bytes)?)
+}
+
+/// Serializes a command request to bytes with schema, returning its command index.
+pub(crate) fn serialize_request<T: WorkerComponent>(
+    request: &T::CommandRequest,
+) -> Result<(u32, Vec<u8>), String> {
+    let command_index = T::get_request_command_index(request);
+    Ok((command_index, T::to_request(request)?.serialize()))
+}
+
+/// Serializes a command response to bytes with schema.
+#[cfg(feature = "fuzzing")]
+pub(crate) fn serialize_response<T: WorkerComponent>(
+    response: &T::CommandResponse,
+) -> Result<Vec<u8>, String> {
+    Ok(T::to_response(response)?.serialize())
+}
+
+pub(crate) fn deserialize_request<T: WorkerComponent>(
+    command_index: u32,
+    bytes: &[u8],
+) -> Result<T::CommandRequest, String> {
+    T::from_request(command_index, &SchemaCommandRequest::deserialize(bytes)?)
+}
+
+#[test]
+fn data_and_updates_should_round_trip_through_schema() {
+    use crate::generated_test::*;
+    use std::collections::BTreeMap;
+
+    let coordinates = |x| Coordinates { x, y: 2.0, z: -3.0 };
+    let mut anchors = BTreeMap::new();
+    anchors.insert(7, coordinates(1.5));
+    let shapes = SchemaShapes {
+        constraint: Constraint {
+            entity_id_constraint: Some(12),
+            and_constraint: vec![Constraint {
+                entity_id_constraint: None,
+                and_constraint: Vec::new(),
+                or_constraint: Vec::new(),
+            }],
+            or_constraint: Vec::new(),
+        },
+        loadout: Loadout {
+            primary: Some(Attachment {
+                offset: Some(coordinates(4.0)),
+                payload: vec![1, 2, 3],
+            }),
+        },
+        anchors: anchors.clone(),
+        blob: vec![0, 255],
+    };
+
+    let bytes = serialize_data::<SchemaShapes>(&shapes).unwrap();
+    let restored = deserialize_data::<SchemaShapes>(&bytes).unwrap();
+    assert_eq!(format!("{:?}", shapes), format!("{:?}", restored));
+    assert_eq!(bytes, serialize_data::<SchemaShapes>(&restored).unwrap());
+
+    let update = SchemaShapesUpdate {
+        anchors: Some(anchors),
+        blob: Some(Vec::new()),
+        ..Default::default()
+    };
+    let restored =
+        deserialize_update::<SchemaShapes>(&serialize_update::<SchemaShapes>(&update).unwrap())
+            .unwrap();
+    assert_eq!(format!("{:?}", update), format!("{:?}", restored));
+
+    let counter = Counter {
+        total: 3,
+        moves: vec![coordinates(1.0), coordinates(2.0)],
+    };
+    let restored =
+        deserialize_data::<Counter>(&serialize_data::<Counter>(&counter).unwrap()).unwrap();
+    assert_eq!(format!("{:?}", counter), format!("{:?}", restored));
+
+    let update = CounterUpdate {
+        total: None,
+        moves: Some(vec![coordinates(5.0)]),
+    };
+    let restored =
+        deserialize_update::<Counter>(&serialize_update::<Counter>(&update).unwrap()).unwrap();
+    assert_eq!(format!("{:?}", update), format!("{:?}", restored));
+}
@@ -0,0 +1,91 @@
+//! A reference-counted buffer for schema `bytes` fields which hold large payloads.
+//!
+//! Received component data is cloned out of the op and into storage, and again into any
+//! snapshot or history which keeps it. Using `SharedBytes` rather than `Vec<u8>` for a
+//! `bytes` field makes those clones share one buffer instead of copying megabytes each
+//! time. The code generator has no option to emit it, so the field's type, and its
+//! conversion with `SharedBytes::from`, are changed by hand in the generated code:
+//!
+//! ```ignore
+//! pub struct Texture {
+//!     pub pixels: SharedBytes,
+//! }
+//!
+//! // Reading is unchanged, as `SharedBytes` dereferences to `[u8]`.
+//! let header = &texture.pixels[..16];
+//! ```
+//!
+//! The buffer is immutable, so changing a payload means building a new one.
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SharedBytes(Arc<Vec<u8>>);
+
+impl SharedBytes {
+    pub fn new() -> SharedBytes {
+        SharedBytes::default()
+    }
+
+    /// Returns whether both share the same buffer, rather than merely being equal.
+    pub fn ptr_eq(&self, other: &SharedBytes) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Returns the bytes, copying them only if the buffer is shared.
+    pub fn into_vec(self) -> Vec<u8> {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl Deref for SharedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for SharedBytes {
+    fn from(bytes: Vec<u8>) -> SharedBytes {
+        SharedBytes(Arc::new(bytes))
+    }
+}
+
+impl<'a> From<&'a [u8]> for SharedBytes {
+    fn from(bytes: &'a [u8]) -> SharedBytes {
+        SharedBytes(Arc::new(bytes.to_vec()))
+    }
+}
+
+// Payloads can be megabytes long, so only their length is shown.
+impl fmt::Debug for SharedBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedBytes({} bytes)", self.0.len())
+    }
+}
+
+#[test]
+fn shared_bytes_should_share_buffer_between_clones() {
+    use crate::generated_test::*;
+    use crate::SpatialComponent;
+
+    let received = Blob {
+        data: SharedBytes::from(vec![7; 1 << 20]),
+    };
+    let stored = SpatialComponent::new(received.clone());
+
+    assert!(stored.data.ptr_eq(&received.data));
+    assert_eq!(7, stored.data[1024]);
+    assert_eq!("SharedBytes(1048576 bytes)", format!("{:?}", received.data));
+
+    drop(stored);
+    assert_eq!(1 << 20, received.data.into_vec().len());
+}
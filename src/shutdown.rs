@@ -0,0 +1,370 @@
+//! Graceful shutdown, so that a worker can be redeployed without dropping work.
+//!
+//! Once shutdown is requested, either with `request_shutdown` or by setting the flag
+//! returned by `shutdown_flag` from a signal handler, the worker stops accepting new
+//! command requests, failing them with "shutting down" so that callers can retry on another
+//! worker. The coordinator then drains: it waits until every request which was already
+//! accepted has been responded to, and every dirty component, buffered command request and
+//! response has been sent, or until the drain timeout elapses. If any handovers are
+//! expected, it then waits for them to be acknowledged, or for the handover timeout to
+//! elapse, before completing. Changes waiting to be persisted by `Persistence` are flushed
+//! before the `on_complete` callbacks are called.
+//!
+//! ```ignore
+//! let mut shutdown = ShutdownCoordinator::new();
+//! shutdown.set_drain_timeout(Duration::from_secs(2));
+//! shutdown.set_handover_timeout(Duration::from_secs(5));
+//! shutdown.on_complete(|_| std::process::exit(0));
+//!
+//! let flag = shutdown.shutdown_flag();
+//! ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))?;
+//!
+//! world.insert(shutdown);
+//! ```
+use crate::component_registry::ComponentRegistry;
+use crate::entities::EntityId;
+use crate::logging::{self, LogKind, LogLevel};
+use crate::persistence::Persistence;
+use specs::prelude::World;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The reason given when failing command requests received during shutdown.
+pub const SHUTTING_DOWN: &str = "shutting down";
+
+/// The progress of a shutdown.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShutdownState {
+    /// Shutdown has not been requested.
+    Running,
+    /// New command requests are failed, and accepted requests are being responded to and
+    /// everything pending sent.
+    Draining,
+    /// Everything has been flushed, and the expected handovers have not all been
+    /// acknowledged.
+    AwaitingHandover,
+    /// The worker can disconnect.
+    Complete,
+}
+
+type ShutdownCallback = Box<FnOnce(&World) + Send + Sync>;
+
+/// A resource which coordinates a graceful shutdown. It is advanced by the
+/// `SpatialWriterSystem` at the end of each frame.
+pub struct ShutdownCoordinator {
+    requested: Arc<AtomicBool>,
+    state: ShutdownState,
+    drain_timeout: Duration,
+    drain_started: Option<Instant>,
+    handover_timeout: Option<Duration>,
+    handover_started: Option<Instant>,
+    pending_handovers: HashSet<EntityId>,
+    callbacks: Vec<ShutdownCallback>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> ShutdownCoordinator {
+        ShutdownCoordinator {
+            requested: Arc::new(AtomicBool::new(false)),
+            state: ShutdownState::Running,
+            drain_timeout: Duration::from_secs(5),
+            drain_started: None,
+            handover_timeout: None,
+            handover_started: None,
+            pending_handovers: HashSet::new(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Returns a flag which requests shutdown when set, and which can be shared with a
+    /// signal handler or another thread.
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        self.requested.clone()
+    }
+
+    pub fn request_shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether shutdown has been requested, in which case new command requests
+    /// are failed.
+    pub fn is_shutting_down(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn state(&self) -> ShutdownState {
+        self.state
+    }
+
+    /// Sets how long to wait for accepted requests to be responded to, and for everything
+    /// pending to be sent, before moving on anyway. Defaults to 5 seconds, so that a
+    /// request which nothing responds to can't delay shutdown indefinitely.
+    pub fn set_drain_timeout(&mut self, timeout: Duration) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Sets how long to wait for expected handovers before completing anyway. By default,
+    /// the coordinator waits until every handover is acknowledged.
+    pub fn set_handover_timeout(&mut self, timeout: Duration) {
+        self.handover_timeout = Some(timeout);
+    }
+
+    /// Delays completion until the handover of the entity, for example to the worker
+    /// which will take over its authority, is acknowledged.
+    pub fn expect_handover(&mut self, entity_id: EntityId) {
+        self.pending_handovers.insert(entity_id);
+    }
+
+    pub fn acknowledge_handover(&mut self, entity_id: EntityId) {
+        self.pending_handovers.remove(&entity_id);
+    }
+
+    /// Calls the callback once shutdown is complete. If it is already complete, the
+    /// callback is called at the end of the next frame.
+    pub fn on_complete<F>(&mut self, callback: F)
+    where
+        F: 'static + FnOnce(&World) + Send + Sync,
+    {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    // Returns whether shutdown has just completed. `unfinished` is the number of requests
+    // and updates still waiting to be responded to or sent.
+    fn advance(&mut self, now: Instant, unfinished: usize) -> bool {
+        match self.state {
+            ShutdownState::Running => {
+                if self.is_shutting_down() {
+                    self.drain_started = Some(now);
+                    self.state = ShutdownState::Draining;
+                }
+                false
+            }
+            ShutdownState::Draining => {
+                let timed_out = self.drain_started.map_or(false, |started| {
+                    now.duration_since(started) >= self.drain_timeout
+                });
+                if unfinished > 0 && !timed_out {
+                    return false;
+                }
+
+                self.handover_started = Some(now);
+                self.state = ShutdownState::AwaitingHandover;
+                self.advance(now, unfinished)
+            }
+            ShutdownState::AwaitingHandover => {
+                let timed_out = match (self.handover_timeout, self.handover_started) {
+                    (Some(timeout), Some(started)) => now.duration_since(started) >= timeout,
+                    _ => false,
+                };
+
+                if self.pending_handovers.is_empty() || timed_out {
+                    self.state = ShutdownState::Complete;
+                    true
+                } else {
+                    false
+                }
+            }
+            ShutdownState::Complete => false,
+        }
+    }
+
+    pub(crate) fn update(res: &World, now: Instant) {
+        let draining = res.fetch::<ShutdownCoordinator>().state == ShutdownState::Draining;
+        let unfinished = if draining {
+            ComponentRegistry::interfaces_iter()
+                .map(|interface| interface.unfinished_sends(res))
+                .sum()
+        } else {
+            0
+        };
+
+        let (completed, callbacks) = {
+            let mut shutdown = res.fetch_mut::<ShutdownCoordinator>();
+            let completed = shutdown.advance(now, unfinished);
+            if draining && shutdown.state != ShutdownState::Draining && unfinished > 0 {
+                logging::log(
+                    res,
+                    LogLevel::Warn,
+                    LogKind::Shutdown,
+                    &format!(
+                        "Shutting down with {} requests and updates unsent after the drain timeout.",
+                        unfinished
+                    ),
+                );
+            }
+
+            if completed && !shutdown.pending_handovers.is_empty() {
+                let pending = shutdown.pending_handovers.len();
+                logging::log(
+                    res,
+                    LogLevel::Warn,
+                    LogKind::Shutdown,
+                    &format!(
+                        "Shutting down with {} handovers unacknowledged after the timeout.",
+                        pending
+                    ),
+                );
+            }
+
+            let callbacks = if shutdown.state == ShutdownState::Complete {
+                shutdown.callbacks.drain(..).collect()
+            } else {
+                Vec::new()
+            };
+            (completed, callbacks)
+        };
+
+        if completed {
+            logging::log(res, LogLevel::Info, LogKind::Shutdown, "Shutdown complete.");
+
+            // The callbacks usually exit the process, so anything not yet persisted would
+            // be lost.
+            if res.has_value::<Persistence>() {
+                Persistence::flush_now(res);
+            }
+        }
+
+        for callback in callbacks {
+            callback(res);
+        }
+    }
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        ShutdownCoordinator::new()
+    }
+}
+
+#[test]
+fn shutdown_coordinator_should_wait_for_handovers() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let now = Instant::now();
+    let entity_id = EntityId(WorkerEntityId::new(4));
+
+    let mut shutdown = ShutdownCoordinator::new();
+    shutdown.set_handover_timeout(Duration::from_secs(5));
+    shutdown.expect_handover(entity_id);
+
+    assert!(!shutdown.advance(now, 0));
+    assert_eq!(ShutdownState::Running, shutdown.state());
+
+    shutdown.shutdown_flag().store(true, Ordering::SeqCst);
+    assert!(shutdown.is_shutting_down());
+    assert!(!shutdown.advance(now, 0));
+    assert_eq!(ShutdownState::Draining, shutdown.state());
+
+    assert!(!shutdown.advance(now, 0));
+    assert_eq!(ShutdownState::AwaitingHandover, shutdown.state());
+
+    shutdown.acknowledge_handover(entity_id);
+    assert!(shutdown.advance(now + Duration::from_secs(1), 0));
+    assert_eq!(ShutdownState::Complete, shutdown.state());
+
+    let mut timed_out = ShutdownCoordinator::new();
+    timed_out.set_handover_timeout(Duration::from_secs(5));
+    timed_out.expect_handover(entity_id);
+    timed_out.request_shutdown();
+    timed_out.advance(now, 0);
+    timed_out.advance(now, 0);
+    assert!(!timed_out.advance(now + Duration::from_secs(4), 0));
+    assert!(timed_out.advance(now + Duration::from_secs(5), 0));
+}
+
+#[test]
+fn shutdown_coordinator_should_drain_before_completing() {
+    let now = Instant::now();
+
+    let mut shutdown = ShutdownCoordinator::new();
+    shutdown.set_drain_timeout(Duration::from_secs(2));
+    shutdown.request_shutdown();
+    assert!(!shutdown.advance(now, 3));
+    assert_eq!(ShutdownState::Draining, shutdown.state());
+
+    assert!(!shutdown.advance(now + Duration::from_secs(1), 1));
+    assert_eq!(ShutdownState::Draining, shutdown.state());
+
+    assert!(shutdown.advance(now + Duration::from_secs(1), 0));
+    assert_eq!(ShutdownState::Complete, shutdown.state());
+
+    let mut timed_out = ShutdownCoordinator::new();
+    timed_out.set_drain_timeout(Duration::from_secs(2));
+    timed_out.request_shutdown();
+    timed_out.advance(now, 1);
+    assert!(!timed_out.advance(now + Duration::from_secs(1), 1));
+    assert!(timed_out.advance(now + Duration::from_secs(2), 1));
+}
+
+#[test]
+fn shutdown_coordinator_should_flush_persistence_before_completing() {
+    use crate::entities::{EntityIds, SpatialEntitiesRes};
+    use crate::generated_test::*;
+    use crate::persistence::PersistedComponent;
+    use crate::storage::SpatialWriteStorage;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::component::Component as WorkerComponent;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+    use std::sync::Mutex;
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+
+    let persisted = Arc::new(Mutex::new(0));
+    world.insert({
+        let persisted = persisted.clone();
+        Persistence::new(move |batch: Vec<PersistedComponent>| {
+            *persisted.lock().unwrap() += batch.len()
+        })
+        .with_component::<Position>()
+    });
+
+    let entity_id = EntityId(WorkerEntityId::new(6));
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+    let entity = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+    SpatialWriteStorage::<Position>::unrestricted(&world)
+        .insert(
+            entity,
+            SpatialComponent::new(Position {
+                coords: Coordinates {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            }),
+        )
+        .unwrap();
+    world
+        .fetch_mut::<Persistence>()
+        .component_changed(entity, Position::ID);
+
+    let persisted_at_completion = Arc::new(Mutex::new(None));
+    let mut shutdown = ShutdownCoordinator::new();
+    {
+        let persisted = persisted.clone();
+        let persisted_at_completion = persisted_at_completion.clone();
+        shutdown.on_complete(move |_| {
+            *persisted_at_completion.lock().unwrap() = Some(*persisted.lock().unwrap());
+        });
+    }
+    shutdown.request_shutdown();
+    world.insert(shutdown);
+
+    let now = Instant::now();
+    for _ in 0..3 {
+        ShutdownCoordinator::update(&world, now);
+    }
+
+    assert_eq!(
+        ShutdownState::Complete,
+        world.fetch::<ShutdownCoordinator>().state()
+    );
+    assert_eq!(Some(1), *persisted_at_completion.lock().unwrap());
+    assert_eq!(0, world.fetch::<Persistence>().pending());
+}
@@ -0,0 +1,268 @@
+//! Differences between two snapshots, or a snapshot and the local view of the world, for
+//! migrating a world between deployments.
+//!
+//! ```ignore
+//! let before = WorldSnapshot::load(Path::new("old.snapshot"))?;
+//! let after = WorldSnapshot::from_world(&world)?;
+//!
+//! let diff = before.diff(&after);
+//! println!("{}", diff);
+//!
+//! for op in diff.operations() {
+//!     match op {
+//!         MigrationOp::UpdateComponent(entity_id, component) if component.component_id == Position::ID => {
+//!             let position = component.deserialize::<Position>()?;
+//!             ...
+//!         }
+//!         ...
+//!     }
+//! }
+//! ```
+//!
+//! Components are compared by their serialized schema data. Only components registered in
+//! the `ComponentRegistry`, by setting up their storages, are compared, so other
+//! components in a snapshot are ignored.
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::entities::{EntityId, SpatialEntitiesRes};
+use crate::sdk;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::World;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+type SerializedEntity = BTreeMap<ComponentId, Vec<u8>>;
+
+/// A component serialized with schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerializedComponent {
+    pub component_id: ComponentId,
+    pub bytes: Vec<u8>,
+}
+
+impl SerializedComponent {
+    pub fn deserialize<T: WorkerComponent>(&self) -> Result<T, String> {
+        sdk::deserialize_data::<T>(&self.bytes)
+    }
+}
+
+/// An operation which turns one world into another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationOp {
+    CreateEntity(EntityId, Vec<SerializedComponent>),
+    DeleteEntity(EntityId),
+    AddComponent(EntityId, SerializedComponent),
+    RemoveComponent(EntityId, ComponentId),
+    /// The component's new data, which replaces the whole component.
+    UpdateComponent(EntityId, SerializedComponent),
+}
+
+/// The serialized components of every entity in a snapshot or world.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorldSnapshot {
+    entities: BTreeMap<EntityId, SerializedEntity>,
+}
+
+impl WorldSnapshot {
+    /// Reads a snapshot file.
+    pub fn load(path: &Path) -> Result<WorldSnapshot, String> {
+        let mut entities = BTreeMap::new();
+        for (entity_id, worker_entity) in sdk::read_snapshot(path)? {
+            let mut components = BTreeMap::new();
+            for interface in ComponentRegistry::interfaces_iter() {
+                if let Some(bytes) = interface.serialize_from_snapshot(&worker_entity) {
+                    components.insert(interface.component_id(), bytes?);
+                }
+            }
+            entities.insert(EntityId(entity_id), components);
+        }
+
+        Ok(WorldSnapshot { entities })
+    }
+
+    /// Takes a snapshot of the components of every entity checked out by this worker.
+    pub fn from_world(world: &World) -> Result<WorldSnapshot, String> {
+        let mut entities = BTreeMap::new();
+        for (entity_id, entity) in world.fetch::<SpatialEntitiesRes>().iter() {
+            let mut components = BTreeMap::new();
+            for interface in ComponentRegistry::interfaces_iter() {
+                if let Some(bytes) = interface.serialize_component(world, entity) {
+                    components.insert(interface.component_id(), bytes?);
+                }
+            }
+            entities.insert(entity_id, components);
+        }
+
+        Ok(WorldSnapshot { entities })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// The operations which turn this snapshot into `after`, ordered by entity ID.
+    pub fn diff(&self, after: &WorldSnapshot) -> SnapshotDiff {
+        let mut operations = Vec::new();
+
+        for (entity_id, before) in &self.entities {
+            match after.entities.get(entity_id) {
+                None => operations.push(MigrationOp::DeleteEntity(*entity_id)),
+                Some(after) => diff_entity(*entity_id, before, after, &mut operations),
+            }
+        }
+
+        for (entity_id, components) in &after.entities {
+            if !self.entities.contains_key(entity_id) {
+                let components = components
+                    .iter()
+                    .map(|(component_id, bytes)| SerializedComponent {
+                        component_id: *component_id,
+                        bytes: bytes.clone(),
+                    })
+                    .collect();
+                operations.push(MigrationOp::CreateEntity(*entity_id, components));
+            }
+        }
+
+        operations.sort_by_key(|op| match op {
+            MigrationOp::CreateEntity(entity_id, _)
+            | MigrationOp::DeleteEntity(entity_id)
+            | MigrationOp::AddComponent(entity_id, _)
+            | MigrationOp::RemoveComponent(entity_id, _)
+            | MigrationOp::UpdateComponent(entity_id, _) => *entity_id,
+        });
+
+        SnapshotDiff { operations }
+    }
+}
+
+fn diff_entity(
+    entity_id: EntityId,
+    before: &SerializedEntity,
+    after: &SerializedEntity,
+    operations: &mut Vec<MigrationOp>,
+) {
+    for component_id in before.keys() {
+        if !after.contains_key(component_id) {
+            operations.push(MigrationOp::RemoveComponent(entity_id, *component_id));
+        }
+    }
+
+    for (component_id, bytes) in after {
+        let component = SerializedComponent {
+            component_id: *component_id,
+            bytes: bytes.clone(),
+        };
+        match before.get(component_id) {
+            None => operations.push(MigrationOp::AddComponent(entity_id, component)),
+            Some(before) if before != bytes => {
+                operations.push(MigrationOp::UpdateComponent(entity_id, component))
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// The differences between two snapshots. Its `Display` implementation is a report with
+/// one line per operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    operations: Vec<MigrationOp>,
+}
+
+impl SnapshotDiff {
+    pub fn operations(&self) -> &[MigrationOp] {
+        &self.operations
+    }
+
+    pub fn into_operations(self) -> Vec<MigrationOp> {
+        self.operations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} differences", self.operations.len())?;
+        for op in &self.operations {
+            match op {
+                MigrationOp::CreateEntity(entity_id, components) => writeln!(
+                    f,
+                    "  + entity {} with {} components",
+                    entity_id,
+                    components.len()
+                )?,
+                MigrationOp::DeleteEntity(entity_id) => writeln!(f, "  - entity {}", entity_id)?,
+                MigrationOp::AddComponent(entity_id, component) => writeln!(
+                    f,
+                    "  + entity {} component {}",
+                    entity_id,
+                    describe_component(component.component_id)
+                )?,
+                MigrationOp::RemoveComponent(entity_id, component_id) => writeln!(
+                    f,
+                    "  - entity {} component {}",
+                    entity_id,
+                    describe_component(*component_id)
+                )?,
+                MigrationOp::UpdateComponent(entity_id, component) => writeln!(
+                    f,
+                    "  ~ entity {} component {}",
+                    entity_id,
+                    describe_component(component.component_id)
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn snapshot_diff_should_produce_operations() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let id = |id| EntityId(WorkerEntityId::new(id));
+    let entity = |components: &[(ComponentId, u8)]| -> SerializedEntity {
+        components
+            .iter()
+            .map(|(component_id, byte)| (*component_id, vec![*byte]))
+            .collect()
+    };
+
+    let mut before = WorldSnapshot::default();
+    before.entities.insert(id(1), entity(&[(54, 1), (53, 1)]));
+    before.entities.insert(id(2), entity(&[(54, 1)]));
+
+    let mut after = WorldSnapshot::default();
+    after.entities.insert(id(1), entity(&[(54, 2), (55, 1)]));
+    after.entities.insert(id(3), entity(&[(54, 3)]));
+
+    assert!(before.diff(&before).is_empty());
+
+    let diff = before.diff(&after);
+    let component = |component_id, byte| SerializedComponent {
+        component_id,
+        bytes: vec![byte],
+    };
+    assert_eq!(
+        &[
+            MigrationOp::RemoveComponent(id(1), 53),
+            MigrationOp::UpdateComponent(id(1), component(54, 2)),
+            MigrationOp::AddComponent(id(1), component(55, 1)),
+            MigrationOp::DeleteEntity(id(2)),
+            MigrationOp::CreateEntity(id(3), vec![component(54, 3)]),
+        ][..],
+        diff.operations()
+    );
+    assert!(diff
+        .to_string()
+        .contains("~ entity 1 component 54 (improbable.Position)"));
+}
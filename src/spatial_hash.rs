@@ -0,0 +1,279 @@
+//! A grid of checked out entities by position, for proximity queries such as "entities
+//! within 10m" without scanning every position component.
+//!
+//! Adding a `SpatialHash` resource enables it, with the size of its cells and a function
+//! returning the position of an entity from its position component:
+//!
+//! ```ignore
+//! world.insert(SpatialHash::new(20.0, |position: &Position| {
+//!     [position.coords.x, position.coords.y, position.coords.z]
+//! }));
+//!
+//! // In a system:
+//! for entity in spatial_hash.within([0.0, 0.0, 0.0], 10.0) {
+//!     ...
+//! }
+//! ```
+//!
+//! The grid is updated whenever the position component is added or updated from SpatialOS,
+//! and when an update to it is sent by this worker, so local changes are indexed at the end
+//! of the frame. Entities leave the grid when their position component is removed.
+//!
+//! Queries are fastest when the cell size is close to the usual query radius. A query
+//! covering more cells than are occupied scans the occupied cells instead, so a huge radius
+//! is no slower than visiting every indexed entity. Positions which aren't finite are not
+//! indexed.
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::Entity;
+use std::any::Any;
+use std::collections::HashMap;
+
+type PositionExtractor = Box<Fn(&Any) -> Option<[f64; 3]> + Send + Sync>;
+
+type Cell = (i64, i64, i64);
+
+/// A resource indexing entities by the position in their position component.
+pub struct SpatialHash {
+    cell_size: f64,
+    position_component: ComponentId,
+    position_extractor: PositionExtractor,
+    cells: HashMap<Cell, Vec<Entity>>,
+    positions: HashMap<Entity, [f64; 3]>,
+}
+
+impl SpatialHash {
+    /// Indexes entities in cubic cells with sides of `cell_size`, by the position extracted
+    /// from the component `P`.
+    pub fn new<P, F>(cell_size: f64, position: F) -> SpatialHash
+    where
+        P: 'static + WorkerComponent,
+        F: 'static + Fn(&P) -> [f64; 3] + Send + Sync,
+    {
+        assert!(cell_size > 0.0, "The cell size must be positive.");
+
+        SpatialHash {
+            cell_size,
+            position_component: P::ID,
+            position_extractor: Box::new(move |value| value.downcast_ref::<P>().map(&position)),
+            cells: HashMap::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    /// The indexed position of the entity.
+    pub fn position(&self, entity: Entity) -> Option<[f64; 3]> {
+        self.positions.get(&entity).cloned()
+    }
+
+    /// The number of indexed entities.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Returns every entity within `radius` of `center`, in no particular order.
+    pub fn within(&self, center: [f64; 3], radius: f64) -> Vec<Entity> {
+        let min = [center[0] - radius, center[1] - radius, center[2] - radius];
+        let max = [center[0] + radius, center[1] + radius, center[2] + radius];
+
+        self.candidates(min, max)
+            .filter(|(_, position)| distance_squared(*position, center) <= radius * radius)
+            .map(|(entity, _)| entity)
+            .collect()
+    }
+
+    /// Returns every entity inside the axis-aligned box between the corners `min` and `max`,
+    /// in no particular order.
+    pub fn in_box(&self, min: [f64; 3], max: [f64; 3]) -> Vec<Entity> {
+        self.candidates(min, max)
+            .filter(|(_, position)| (0..3).all(|i| min[i] <= position[i] && position[i] <= max[i]))
+            .map(|(entity, _)| entity)
+            .collect()
+    }
+
+    // The entities in every cell overlapping the box, with their positions.
+    fn candidates<'a>(
+        &'a self,
+        min: [f64; 3],
+        max: [f64; 3],
+    ) -> Box<Iterator<Item = (Entity, [f64; 3])> + 'a> {
+        let (low, high) = (self.cell(min), self.cell(max));
+        let span = |low: i64, high: i64| (i128::from(high) - i128::from(low) + 1).max(0);
+        let range = span(low.0, high.0)
+            .saturating_mul(span(low.1, high.1))
+            .saturating_mul(span(low.2, high.2));
+
+        let entities: Box<Iterator<Item = &'a Entity> + 'a> = if range > self.cells.len() as i128 {
+            Box::new(
+                self.cells
+                    .iter()
+                    .filter(move |(cell, _)| {
+                        low.0 <= cell.0
+                            && cell.0 <= high.0
+                            && low.1 <= cell.1
+                            && cell.1 <= high.1
+                            && low.2 <= cell.2
+                            && cell.2 <= high.2
+                    })
+                    .flat_map(|(_, entities)| entities.iter()),
+            )
+        } else {
+            Box::new(
+                (low.0..=high.0)
+                    .flat_map(move |x| (low.1..=high.1).map(move |y| (x, y)))
+                    .flat_map(move |(x, y)| (low.2..=high.2).map(move |z| (x, y, z)))
+                    .filter_map(move |cell| self.cells.get(&cell))
+                    .flat_map(|entities| entities.iter()),
+            )
+        };
+
+        Box::new(entities.map(move |entity| (*entity, self.positions[entity])))
+    }
+
+    fn cell(&self, position: [f64; 3]) -> Cell {
+        let coord = |value: f64| cell_coord((value / self.cell_size).floor());
+        (coord(position[0]), coord(position[1]), coord(position[2]))
+    }
+
+    pub(crate) fn is_position_component(&self, component_id: ComponentId) -> bool {
+        component_id == self.position_component
+    }
+
+    // An entity whose position isn't finite is removed, as it can't be placed in a cell.
+    pub(crate) fn position_changed(&mut self, entity: Entity, value: &Any) {
+        match (self.position_extractor)(value) {
+            Some(position) if position.iter().all(|coord| coord.is_finite()) => {
+                self.insert(entity, position)
+            }
+            Some(_) => self.remove(entity),
+            None => {}
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, position: [f64; 3]) {
+        let cell = self.cell(position);
+        if let Some(previous) = self.positions.insert(entity, position) {
+            let previous = self.cell(previous);
+            if previous == cell {
+                return;
+            }
+            self.remove_from_cell(entity, previous);
+        }
+
+        self.cells.entry(cell).or_insert_with(Vec::new).push(entity);
+    }
+
+    pub(crate) fn remove(&mut self, entity: Entity) {
+        if let Some(position) = self.positions.remove(&entity) {
+            let cell = self.cell(position);
+            self.remove_from_cell(entity, cell);
+        }
+    }
+
+    fn remove_from_cell(&mut self, entity: Entity, cell: Cell) {
+        let now_empty = match self.cells.get_mut(&cell) {
+            Some(entities) => {
+                entities.retain(|other| *other != entity);
+                entities.is_empty()
+            }
+            None => false,
+        };
+
+        if now_empty {
+            self.cells.remove(&cell);
+        }
+    }
+}
+
+// Clamps huge query corners to the grid, and places NaN at the origin, from which the
+// distance checks exclude it.
+fn cell_coord(value: f64) -> i64 {
+    if value.is_nan() {
+        0
+    } else if value >= i64::max_value() as f64 {
+        i64::max_value()
+    } else if value <= i64::min_value() as f64 {
+        i64::min_value()
+    } else {
+        value as i64
+    }
+}
+
+fn distance_squared(a: [f64; 3], b: [f64; 3]) -> f64 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+#[test]
+fn spatial_hash_should_track_moves_across_cells() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    let near = world.create_entity().build();
+    let far = world.create_entity().build();
+
+    let mut hash = SpatialHash::new(10.0, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    });
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    assert!(hash.is_position_component(Position::ID));
+    hash.position_changed(near, &position(3.0));
+    hash.position_changed(far, &position(-25.0));
+    assert_eq!(vec![near], hash.within([0.0, 0.0, 0.0], 10.0));
+    assert_eq!(
+        vec![far],
+        hash.in_box([-30.0, -1.0, -1.0], [-20.0, 1.0, 1.0])
+    );
+
+    hash.position_changed(far, &position(8.0));
+    let mut nearby = hash.within([0.0, 0.0, 0.0], 10.0);
+    nearby.sort();
+    assert_eq!(vec![near, far], nearby);
+    assert!(hash
+        .in_box([-30.0, -1.0, -1.0], [-20.0, 1.0, 1.0])
+        .is_empty());
+
+    hash.remove(near);
+    assert_eq!(vec![far], hash.within([0.0, 0.0, 0.0], 10.0));
+    assert_eq!(None, hash.position(near));
+    assert_eq!(1, hash.len());
+}
+
+#[test]
+fn spatial_hash_should_answer_huge_queries_and_ignore_non_finite_positions() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, World, WorldExt};
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+    let lost = world.create_entity().build();
+
+    let mut hash = SpatialHash::new(10.0, |position: &Position| {
+        [position.coords.x, position.coords.y, position.coords.z]
+    });
+    let position = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+
+    hash.position_changed(entity, &position(5.0));
+    hash.position_changed(lost, &position(15.0));
+    hash.position_changed(lost, &position(std::f64::NAN));
+    assert_eq!(None, hash.position(lost));
+    hash.position_changed(lost, &position(std::f64::INFINITY));
+    assert_eq!(1, hash.len());
+
+    // These span far more cells than are occupied, so only the occupied cells are visited.
+    assert_eq!(vec![entity], hash.within([0.0, 0.0, 0.0], 1e6));
+    assert_eq!(
+        vec![entity],
+        hash.in_box([-1e300, -1e300, -1e300], [1e300, 1e300, 1e300])
+    );
+    assert!(hash.within([std::f64::NAN, 0.0, 0.0], 1e6).is_empty());
+}
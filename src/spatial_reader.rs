@@ -1,11 +1,45 @@
+use crate::allowlist::ComponentAllowlist;
+use crate::census::ComponentCensus;
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosFault, ChaosMonkey};
+use crate::checkout_group::CheckoutGroups;
+use crate::clock;
+use crate::commands::{CommandAuthority, CommandAuthorityEvents};
 use crate::component_registry::ComponentRegistry;
-use crate::entities::{EntityId, EntityIds, SpatialEntitiesRes};
+use crate::entities::{
+    DuplicateEntityPolicy, EntityId, EntityIds, EntityLiveness, SpatialEntitiesRes,
+};
+use crate::eviction::ProxyEviction;
+#[cfg(feature = "worker-flags")]
+use crate::flags::WorkerFlags;
+use crate::frame_report::FrameReport;
+use crate::health::ConnectionHealth;
+use crate::logging::{self, LogKind, LogLevel, SpatialLogger};
+use crate::op_stats::{OpCategory, OpStats};
+#[cfg(feature = "partitions")]
+use crate::partition::{Partitions, WORKER_COMPONENT_ID};
+use crate::persistence::Persistence;
+use crate::player_lifecycle::PlayerLifecycle;
+use crate::query_result::{self, QueryResult};
+use crate::resync::ResyncInProgress;
+use crate::schema_version::{SchemaVersion, SchemaVersionEvents};
+use crate::sdk::SdkConnection;
 use crate::system_commands::{SystemCommandSender, SystemCommandSenderRes};
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
-use spatialos_sdk::worker::op::WorkerOp;
-use specs::prelude::{Resources, System, SystemData};
+use crate::tick_rate;
+use crate::view::View;
+use spatialos_sdk::worker::connection::WorkerConnection;
+use spatialos_sdk::worker::op::{OpList, WorkerOp};
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use specs::prelude::{Entity, System, SystemData, World, Write, WriteStorage};
 use specs::shred::ResourceId;
 use specs::world::EntitiesRes;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// The message of the `Timeout` status given to the callbacks of commands which were sent
+/// on a connection replaced by `SpatialReaderSystem::reconnect`.
+pub const CONNECTION_LOST: &str = "connection lost";
 
 /// A system which receives operations from SpatialOS and applies them
 /// to the local world.
@@ -40,135 +74,556 @@ use specs::world::EntitiesRes;
 ///     .with(SpatialWriterSystem, "writer", &[])
 ///     .build();
 ///
-/// dispatcher.setup(&mut world.res);
+/// dispatcher.setup(&mut world);
 /// ```
 pub struct SpatialReaderSystem;
 
 impl<'a> System<'a> for SpatialReaderSystem {
     type SystemData = ResourcesSystemData<'a>;
 
-    fn setup(&mut self, res: &mut Resources) {
+    fn setup(&mut self, res: &mut World) {
         Self::SystemData::setup(res);
 
         SystemCommandSender::setup(res);
         EntityIds::setup(res);
+        Write::<ComponentCensus>::setup(res);
+        Write::<SpatialLogger>::setup(res);
+        Write::<CommandAuthority>::setup(res);
+        Write::<CommandAuthorityEvents>::setup(res);
+        Write::<SchemaVersionEvents>::setup(res);
+        Write::<ResyncInProgress>::setup(res);
+        WriteStorage::<QueryResult>::setup(res);
+        #[cfg(feature = "worker-flags")]
+        Write::<WorkerFlags>::setup(res);
+    }
+
+    fn run(&mut self, res: Self::SystemData) {
+        let res = res.res;
+
+        // Polling counts towards the reader's time.
+        let started = clock::now(res);
+        let ops = res.fetch_mut::<WorkerConnection>().poll_ops();
+
+        apply_ops(res, started, vec![ops]);
+    }
+}
+
+// Op lists received by a `SpatialOpCollectorSystem`, waiting to be applied by its
+// `SpatialOpApplierSystem`.
+#[derive(Default)]
+struct ReceivedOps {
+    op_lists: Vec<OpList>,
+    // When the first of the op lists started being polled for.
+    polled_at: Option<Instant>,
+}
+
+impl ReceivedOps {
+    fn take(&mut self) -> (Option<Instant>, Vec<OpList>) {
+        (
+            self.polled_at.take(),
+            std::mem::replace(&mut self.op_lists, Vec::new()),
+        )
+    }
+}
+
+/// A system which receives operations from SpatialOS, without applying them. Together with
+/// the `SpatialOpApplierSystem` returned by `applier`, this replaces the
+/// `SpatialReaderSystem`.
+///
+/// The two systems share the received op lists, which the Worker SDK doesn't allow to be
+/// sent between threads, so both must be added as thread-local systems. These run one at a
+/// time on the thread which dispatches, after all of the other systems, so the applier
+/// never runs in parallel with systems which fetch spatial storages, such as setup systems,
+/// and no barrier is needed. The ops are applied at the end of the frame, ready for the
+/// next one:
+///
+/// ```ignore
+/// let collector = SpatialOpCollectorSystem::new();
+/// let applier = collector.applier();
+///
+/// let mut dispatcher = DispatcherBuilder::new()
+///     .with(LoadLevelSys, "load_level", &[])
+///     .with(MovePlayerSys, "move_player", &[])
+///     .with_barrier()
+///     .with(SpatialWriterSystem, "writer", &[])
+///     .with_thread_local(collector)
+///     .with_thread_local(applier)
+///     .build();
+/// ```
+///
+/// Other thread-local systems added between the two run after the ops have been received,
+/// but before they are applied.
+pub struct SpatialOpCollectorSystem {
+    received: Rc<RefCell<ReceivedOps>>,
+}
+
+impl SpatialOpCollectorSystem {
+    pub fn new() -> SpatialOpCollectorSystem {
+        SpatialOpCollectorSystem {
+            received: Rc::new(RefCell::new(ReceivedOps::default())),
+        }
+    }
+
+    /// Returns a system which applies the ops received by this one.
+    pub fn applier(&self) -> SpatialOpApplierSystem {
+        SpatialOpApplierSystem {
+            received: self.received.clone(),
+        }
+    }
+}
+
+impl Default for SpatialOpCollectorSystem {
+    fn default() -> Self {
+        SpatialOpCollectorSystem::new()
+    }
+}
+
+impl<'a> System<'a> for SpatialOpCollectorSystem {
+    type SystemData = ResourcesSystemData<'a>;
+
+    fn run(&mut self, res: Self::SystemData) {
+        let res = res.res;
+        let now = clock::now(res);
+        let ops = res.fetch_mut::<WorkerConnection>().poll_ops();
+
+        let mut received = self.received.borrow_mut();
+        received.polled_at = received.polled_at.or(Some(now));
+        received.op_lists.push(ops);
+    }
+}
+
+/// A system which applies the operations received by a `SpatialOpCollectorSystem` to the
+/// local world, in the order they were received.
+///
+/// It is created by `SpatialOpCollectorSystem::applier`, and must be added as a
+/// thread-local system after the collector.
+pub struct SpatialOpApplierSystem {
+    received: Rc<RefCell<ReceivedOps>>,
+}
+
+impl SpatialOpApplierSystem {
+    /// Returns whether there are ops waiting to be applied.
+    pub fn has_received_ops(&self) -> bool {
+        !self.received.borrow().op_lists.is_empty()
+    }
+}
+
+impl<'a> System<'a> for SpatialOpApplierSystem {
+    type SystemData = ResourcesSystemData<'a>;
+
+    fn setup(&mut self, res: &mut World) {
+        SpatialReaderSystem.setup(res);
     }
 
     fn run(&mut self, res: Self::SystemData) {
         let res = res.res;
+        let (polled_at, op_lists) = self.received.borrow_mut().take();
+        let started = polled_at.unwrap_or_else(|| clock::now(res));
+        apply_ops(res, started, op_lists);
+    }
+}
+
+// Returns the entity an op is for. Ops for entities which aren't checked out are logged, and
+// should be skipped.
+fn op_entity(res: &World, entity_id: WorkerEntityId, kind: &str) -> Option<Entity> {
+    let entity = EntityIds::fetch(res).get_entity(EntityId(entity_id));
+    if entity.is_none() {
+        logging::log(
+            res,
+            LogLevel::Warn,
+            LogKind::UnexpectedEntity,
+            &format!(
+                "Received {} for entity {}, which is not checked out.",
+                kind,
+                EntityId(entity_id)
+            ),
+        );
+    }
+    entity
+}
+
+fn add_entity(res: &World, entity_id: EntityId) {
+    let liveness = res.fetch::<SpatialEntitiesRes>().liveness(entity_id);
+    if liveness != EntityLiveness::Unknown && res.has_value::<ResyncInProgress>() {
+        res.fetch_mut::<ResyncInProgress>().entity_readded();
+    }
+
+    // Nothing from the first checkout of a duplicate entity is kept, as none of its
+    // components may be added again.
+    if let EntityLiveness::CheckedOut(entity) = liveness {
+        remove_components(res, entity_id, entity);
+
+        let policy = res.fetch::<SpatialEntitiesRes>().duplicate_policy();
+        if policy == DuplicateEntityPolicy::Replace && res.has_value::<ProxyEviction>() {
+            res.fetch_mut::<ProxyEviction>().entity_removed(entity);
+        }
+    }
+
+    res.fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(res, entity_id);
+}
+
+// SpatialOS removes the components of an entity before the entity itself, but any which
+// are left are removed here, so that everything which tracks them is told.
+fn remove_entity(res: &World, entity_id: EntityId, entity: Entity) {
+    remove_components(res, entity_id, entity);
+
+    if res.has_value::<ProxyEviction>() {
+        res.fetch_mut::<ProxyEviction>().entity_removed(entity);
+    }
+
+    res.fetch_mut::<SpatialEntitiesRes>()
+        .remove_entity(res, entity_id);
+}
+
+fn remove_components(res: &World, entity_id: EntityId, entity: Entity) {
+    let component_ids = res
+        .fetch_mut::<SpatialEntitiesRes>()
+        .take_components(entity_id);
 
-        let ops = {
-            let mut connection = res.fetch_mut::<WorkerConnection>();
-            connection.get_op_list(0)
+    for component_id in component_ids {
+        if let Some(interface) = ComponentRegistry::get_interface(component_id) {
+            interface.remove_component(res, entity);
+        }
+    }
+}
+
+// `started` is when polling for the ops began.
+fn apply_ops(res: &World, started: Instant, op_lists: Vec<OpList>) {
+    let now = clock::now(res);
+    tick_rate::with_controller(res, |controller| controller.reader_started(started));
+
+    res.fetch_mut::<SpatialEntitiesRes>().prune_tombstones(now);
+    query_result::remove_results(res);
+
+    #[cfg(feature = "chaos")]
+    {
+        for authority_change in ChaosMonkey::due_authority_changes(res) {
+            let entity = EntityIds::fetch(res).get_entity(EntityId(authority_change.entity_id));
+            let interface = ComponentRegistry::get_interface(authority_change.component_id);
+            if let (Some(entity), Some(interface)) = (entity, interface) {
+                interface.apply_authority_change(res, entity, authority_change);
+            }
+        }
+    }
+
+    for interface in ComponentRegistry::interfaces_iter() {
+        interface.complete_local_commands(res);
+        interface.refresh_responder_budget(res);
+    }
+
+    let mut ops_received = 0;
+    for op in op_lists.iter().flat_map(|ops| ops) {
+        ops_received += 1;
+
+        if res.has_value::<View>() {
+            res.fetch_mut::<View>().apply(&op);
+        }
+
+        if res.has_value::<FrameReport>() {
+            res.fetch_mut::<FrameReport>().record_op(&op);
+        }
+
+        let timing = if res.has_value::<OpStats>() {
+            Some((OpCategory::of(&op), clock::now(res)))
+        } else {
+            None
         };
 
-        for op in &ops {
-            match op {
-                WorkerOp::AddEntity(add_entity_op) => {
-                    res.fetch_mut::<SpatialEntitiesRes>()
-                        .got_new_entity(res, EntityId(add_entity_op.entity_id));
-                }
-                WorkerOp::RemoveEntity(remove_entity_op) => {
-                    res.fetch_mut::<SpatialEntitiesRes>()
-                        .remove_entity(res, EntityId(remove_entity_op.entity_id));
-                }
-                WorkerOp::AddComponent(add_component) => {
-                    match ComponentRegistry::get_interface(add_component.component_id) {
-                        None => {}
-                        Some(interface) => {
-                            let entity = EntityIds::fetch(res)
-                                .get_entity(EntityId(add_component.entity_id))
-                                .unwrap();
-                            interface.add_component(res, entity, add_component);
-                        }
+        match op {
+            WorkerOp::AddEntity(add_entity_op) => {
+                add_entity(res, EntityId(add_entity_op.entity_id));
+            }
+            WorkerOp::RemoveEntity(remove_entity_op) => {
+                let entity = match op_entity(res, remove_entity_op.entity_id, "an entity removal") {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                remove_entity(res, EntityId(remove_entity_op.entity_id), entity);
+            }
+            WorkerOp::AddComponent(add_component) => {
+                if !ComponentAllowlist::admits(res, add_component.component_id) {
+                    continue;
+                }
+
+                match ComponentRegistry::get_interface(add_component.component_id) {
+                    None => {}
+                    Some(interface) => {
+                        let entity = match op_entity(res, add_component.entity_id, "component data")
+                        {
+                            Some(entity) => entity,
+                            None => continue,
+                        };
+                        res.fetch_mut::<SpatialEntitiesRes>().component_added(
+                            EntityId(add_component.entity_id),
+                            add_component.component_id,
+                        );
+                        interface.add_component(res, entity, add_component);
                     }
                 }
-                WorkerOp::RemoveComponent(remove_component) => {
-                    match ComponentRegistry::get_interface(remove_component.component_id) {
-                        None => {}
-                        Some(interface) => {
-                            let entity = EntityIds::fetch(res)
-                                .get_entity(EntityId(remove_component.entity_id))
-                                .unwrap();
-                            interface.remove_component(res, entity);
-                        }
+            }
+            WorkerOp::RemoveComponent(remove_component) => {
+                if !ComponentAllowlist::admits(res, remove_component.component_id) {
+                    continue;
+                }
+
+                match ComponentRegistry::get_interface(remove_component.component_id) {
+                    None => {}
+                    Some(interface) => {
+                        let entity =
+                            match op_entity(res, remove_component.entity_id, "a component removal")
+                            {
+                                Some(entity) => entity,
+                                None => continue,
+                            };
+                        res.fetch_mut::<SpatialEntitiesRes>().component_removed(
+                            EntityId(remove_component.entity_id),
+                            remove_component.component_id,
+                        );
+                        interface.remove_component(res, entity);
                     }
                 }
-                WorkerOp::ComponentUpdate(update) => {
-                    match ComponentRegistry::get_interface(update.component_id) {
-                        None => {}
-                        Some(interface) => {
-                            let entity = EntityIds::fetch(res)
-                                .get_entity(EntityId(update.entity_id))
-                                .unwrap();
-                            interface.apply_component_update(res, entity, update);
-                        }
+            }
+            WorkerOp::ComponentUpdate(update) => {
+                if !ComponentAllowlist::admits(res, update.component_id) {
+                    continue;
+                }
+
+                match ComponentRegistry::get_interface(update.component_id) {
+                    None => {}
+                    Some(interface) => {
+                        let entity = match op_entity(res, update.entity_id, "a component update") {
+                            Some(entity) => entity,
+                            None => continue,
+                        };
+                        interface.apply_component_update(res, entity, update);
                     }
                 }
-                WorkerOp::AuthorityChange(authority_change) => {
-                    match ComponentRegistry::get_interface(authority_change.component_id) {
-                        None => {}
-                        Some(interface) => {
-                            let entity = EntityIds::fetch(res)
-                                .get_entity(EntityId(authority_change.entity_id))
-                                .unwrap();
-                            interface.apply_authority_change(res, entity, authority_change);
-                        }
+            }
+            WorkerOp::AuthorityChange(authority_change) => {
+                #[cfg(feature = "chaos")]
+                {
+                    if ChaosMonkey::delay_authority_change(res, &authority_change) {
+                        continue;
                     }
                 }
-                WorkerOp::CommandRequest(command_request) => {
-                    match ComponentRegistry::get_interface(command_request.component_id) {
-                        None => {}
-                        Some(interface) => {
-                            let entity = EntityIds::fetch(res)
-                                .get_entity(EntityId(command_request.entity_id))
-                                .unwrap();
-                            interface.on_command_request(res, entity, command_request);
-                        }
+
+                if !ComponentAllowlist::admits(res, authority_change.component_id) {
+                    continue;
+                }
+
+                match ComponentRegistry::get_interface(authority_change.component_id) {
+                    None => {}
+                    Some(interface) => {
+                        let entity =
+                            match op_entity(res, authority_change.entity_id, "an authority change")
+                            {
+                                Some(entity) => entity,
+                                None => continue,
+                            };
+                        interface.apply_authority_change(res, entity, authority_change);
                     }
                 }
-                WorkerOp::CommandResponse(command_response) => {
-                    match ComponentRegistry::get_interface(command_response.component_id) {
-                        None => {}
-                        Some(interface) => {
-                            interface.on_command_response(res, command_response);
-                        }
+            }
+            WorkerOp::CommandRequest(command_request) => {
+                if !ComponentAllowlist::admits(res, command_request.component_id) {
+                    ComponentAllowlist::reject_request(res, command_request);
+                    continue;
+                }
+
+                match ComponentRegistry::get_interface(command_request.component_id) {
+                    None => {}
+                    Some(interface) => {
+                        let entity =
+                            match op_entity(res, command_request.entity_id, "a command request") {
+                                Some(entity) => entity,
+                                None => continue,
+                            };
+                        interface.on_command_request(res, entity, command_request);
                     }
                 }
-                WorkerOp::ReserveEntityIdsResponse(reserve_entity_ids_response) => {
-                    SystemCommandSenderRes::got_reserve_entity_ids_response(
-                        res,
-                        reserve_entity_ids_response,
-                    );
+            }
+            WorkerOp::CommandResponse(command_response) => {
+                #[cfg(feature = "chaos")]
+                {
+                    if ChaosMonkey::inject(res, ChaosFault::DroppedResponse) {
+                        continue;
+                    }
                 }
-                WorkerOp::CreateEntityResponse(create_entity_response) => {
-                    SystemCommandSenderRes::got_create_entity_response(res, create_entity_response);
+
+                #[cfg(feature = "partitions")]
+                {
+                    if command_response.component_id == WORKER_COMPONENT_ID
+                        && res.has_value::<Partitions>()
+                        && Partitions::got_claim_response(res, &command_response)
+                    {
+                        continue;
+                    }
                 }
-                WorkerOp::DeleteEntityResponse(delete_entity_response) => {
-                    SystemCommandSenderRes::got_delete_entity_response(res, delete_entity_response);
+
+                if !ComponentAllowlist::admits(res, command_response.component_id) {
+                    continue;
                 }
-                WorkerOp::EntityQueryResponse(entity_query_response) => {
-                    SystemCommandSenderRes::got_entity_query_response(res, entity_query_response);
+
+                match ComponentRegistry::get_interface(command_response.component_id) {
+                    None => {}
+                    Some(interface) => {
+                        interface.on_command_response(res, command_response);
+                    }
                 }
-                _ => {}
             }
+            WorkerOp::ReserveEntityIdsResponse(reserve_entity_ids_response) => {
+                SystemCommandSenderRes::got_reserve_entity_ids_response(
+                    res,
+                    reserve_entity_ids_response,
+                );
+            }
+            WorkerOp::CreateEntityResponse(create_entity_response) => {
+                SystemCommandSenderRes::got_create_entity_response(res, create_entity_response);
+            }
+            WorkerOp::DeleteEntityResponse(delete_entity_response) => {
+                SystemCommandSenderRes::got_delete_entity_response(res, delete_entity_response);
+            }
+            WorkerOp::EntityQueryResponse(entity_query_response) => {
+                SystemCommandSenderRes::got_entity_query_response(res, entity_query_response);
+            }
+            WorkerOp::FlagUpdate(flag_update) => {
+                #[cfg(feature = "worker-flags")]
+                {
+                    WorkerFlags::got_flag_update(res, &flag_update.name, flag_update.value.clone());
+                }
+
+                if res.has_value::<SchemaVersion>() {
+                    SchemaVersion::got_flag_update(
+                        res,
+                        &flag_update.name,
+                        flag_update.value.clone(),
+                    );
+                }
+            }
+            _ => {}
         }
+
+        if let Some((category, started)) = timing {
+            let elapsed = clock::now(res).duration_since(started);
+            res.fetch_mut::<OpStats>().record(category, elapsed);
+        }
+    }
+
+    if res.has_value::<ProxyEviction>() {
+        ProxyEviction::update(res);
+    }
+
+    if res.has_value::<CheckoutGroups>() {
+        CheckoutGroups::update(res);
     }
+
+    if res.has_value::<PlayerLifecycle>() {
+        PlayerLifecycle::update(res, clock::now(res));
+    }
+
+    // Worker flags are received as ops, so are only available once ops have been processed.
+    if res.has_value::<SchemaVersion>() && !res.fetch::<SchemaVersion>().is_checked() {
+        let flag_name = res.fetch::<SchemaVersion>().flag_name().to_string();
+        let flag_value = res.fetch::<WorkerConnection>().worker_flag(&flag_name);
+        SchemaVersion::verify(res, flag_value);
+    }
+
+    if res.has_value::<ConnectionHealth>() {
+        res.fetch_mut::<ConnectionHealth>()
+            .record_ops_received(ops_received);
+    }
+
+    if res.has_value::<FrameReport>() {
+        let elapsed = clock::now(res).duration_since(started);
+        res.fetch_mut::<FrameReport>().record_reader_time(elapsed);
+    }
+
+    if res.has_value::<ResyncInProgress>() {
+        ResyncInProgress::finish_frame(res);
+    }
+
+    tick_rate::with_controller(res, |controller| controller.reader_finished(ops_received));
 }
 
-/// A SystemData which gives a reference to Resources.
+impl SpatialReaderSystem {
+    /// Replaces the current connection with a new one, for example after the previous
+    /// connection has dropped.
+    ///
+    /// All SpatialOS entities are removed from the local world as if they had left the view,
+    /// emitting the same events as a removal received from SpatialOS, and all authority state
+    /// is reset. The entities will be checked out again through the new connection when the
+    /// reader next runs.
+    ///
+    /// Callbacks for commands which were sent on the previous connection are called with a
+    /// `Timeout` status containing `CONNECTION_LOST`. Commands which have not been sent yet
+    /// will be sent on the new connection.
+    pub fn reconnect(res: &World, connection: WorkerConnection) {
+        SpatialReaderSystem::disconnect(res);
+        *res.fetch_mut::<WorkerConnection>() = connection;
+    }
+
+    // Removes everything received through the current connection.
+    fn disconnect(res: &World) {
+        let checked_out: Vec<(EntityId, Entity)> =
+            res.fetch::<SpatialEntitiesRes>().iter().collect();
+        for (entity_id, entity) in checked_out {
+            remove_entity(res, entity_id, entity);
+        }
+
+        // Persist the final values of the removed components before anything else is reset.
+        if res.has_value::<Persistence>() {
+            Persistence::flush_now(res);
+        }
+
+        for interface in ComponentRegistry::interfaces_iter() {
+            interface.reset(res);
+        }
+
+        if res.has_value::<ComponentCensus>() {
+            *res.fetch_mut::<ComponentCensus>() = Default::default();
+        }
+
+        if res.has_value::<CommandAuthority>() {
+            res.fetch_mut::<CommandAuthority>().clear();
+        }
+
+        if res.has_value::<SchemaVersion>() {
+            res.fetch_mut::<SchemaVersion>().reset();
+        }
+
+        if res.has_value::<View>() {
+            res.fetch_mut::<View>().clear();
+        }
+
+        if res.has_value::<ResyncInProgress>() {
+            res.fetch_mut::<ResyncInProgress>().expect_resync();
+        }
+
+        SystemCommandSenderRes::fail_sent_requests(res, CONNECTION_LOST);
+
+        #[cfg(feature = "partitions")]
+        {
+            if res.has_value::<Partitions>() {
+                res.fetch_mut::<Partitions>().reclaim();
+            }
+        }
+    }
+}
+
+/// A SystemData which gives a reference to World.
 ///
 /// This allows arbitrary fetches. This can cause runtime panics if a fetched
 /// resource has been fetched by another system running in parallel.
 #[doc(hidden)]
 pub struct ResourcesSystemData<'a> {
-    pub(crate) res: &'a Resources,
+    pub(crate) res: &'a World,
 }
 
 impl<'a> SystemData<'a> for ResourcesSystemData<'a> {
-    fn setup(_: &mut Resources) {}
+    fn setup(_: &mut World) {}
 
-    fn fetch(res: &'a Resources) -> Self {
+    fn fetch(res: &'a World) -> Self {
         ResourcesSystemData { res }
     }
 
@@ -183,3 +638,152 @@ impl<'a> SystemData<'a> for ResourcesSystemData<'a> {
         ]
     }
 }
+
+#[test]
+fn op_applier_should_apply_the_ops_its_collector_receives() {
+    use specs::prelude::{RunNow, WorldExt};
+
+    let collector = SpatialOpCollectorSystem::new();
+    let mut applier = collector.applier();
+    assert!(Rc::ptr_eq(&collector.received, &applier.received));
+
+    let mut world = World::new();
+    System::setup(&mut applier, &mut world);
+    assert!(!applier.has_received_ops());
+    applier.run_now(&world);
+}
+
+#[test]
+fn duplicate_entities_should_not_keep_components_of_their_first_checkout() {
+    use crate::generated_test::*;
+    use crate::SpatialComponent;
+    use specs::prelude::{ReadStorage, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    WriteStorage::<SpatialComponent<Position>>::setup(&mut world);
+    world.insert(ComponentCensus::default());
+
+    let entity_id = EntityId(WorkerEntityId::new(4));
+    let add_position = |world: &World| {
+        let entity = EntityIds::fetch(world).get_entity(entity_id).unwrap();
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .component_added(entity_id, Position::ID);
+        world
+            .fetch_mut::<ComponentCensus>()
+            .component_added(Position::ID);
+        let position = Position {
+            coords: Coordinates {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+        };
+        WriteStorage::<SpatialComponent<Position>>::fetch(world)
+            .insert(entity, SpatialComponent::new(position))
+            .unwrap();
+        entity
+    };
+    let has_position = |world: &World, entity| {
+        ReadStorage::<SpatialComponent<Position>>::fetch(world)
+            .get(entity)
+            .is_some()
+    };
+    let checked_out = |world: &World| {
+        world
+            .fetch::<ComponentCensus>()
+            .get(Position::ID)
+            .checked_out
+    };
+
+    add_entity(&world, entity_id);
+    let first = add_position(&world);
+    assert_eq!(1, checked_out(&world));
+
+    add_entity(&world, entity_id);
+    assert_eq!(Some(first), EntityIds::fetch(&world).get_entity(entity_id));
+    assert!(!has_position(&world, first));
+    assert_eq!(0, checked_out(&world));
+
+    add_position(&world);
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .set_duplicate_policy(DuplicateEntityPolicy::Replace);
+    add_entity(&world, entity_id);
+    let second = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+    assert_ne!(first, second);
+    assert!(!has_position(&world, first));
+    assert_eq!(0, checked_out(&world));
+
+    add_position(&world);
+    remove_entity(&world, entity_id, second);
+    assert_eq!(None, EntityIds::fetch(&world).get_entity(entity_id));
+    assert_eq!(0, checked_out(&world));
+}
+
+#[test]
+fn reconnecting_should_remove_entities_and_fail_sent_commands() {
+    use crate::commands::CommandSender;
+    use crate::component_registry::add_received_component;
+    use crate::generated_test::*;
+    use crate::storage::ComponentRemovingEvents;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::op::StatusCode;
+    use specs::prelude::WorldExt;
+    use std::sync::{Arc, Mutex};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SystemCommandSender::setup(&mut world);
+    CommandSender::<Counter>::setup(&mut world);
+    WriteStorage::<SpatialComponent<Counter>>::setup(&mut world);
+    world.insert(ComponentRemovingEvents::<Counter>::new());
+    let mut reader_id = world
+        .fetch_mut::<ComponentRemovingEvents<Counter>>()
+        .register_reader();
+
+    let entity_id = EntityId(WorkerEntityId::new(7));
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+    let entity = EntityIds::fetch(&world).get_entity(entity_id).unwrap();
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .component_added(entity_id, Counter::ID);
+    add_received_component(
+        &world,
+        entity,
+        Some(&Counter {
+            total: 3,
+            moves: Vec::new(),
+        }),
+    );
+
+    let failure = Arc::new(Mutex::new(None));
+    {
+        let failure = failure.clone();
+        let mut sender = CommandSender::<Counter>::fetch(&world);
+        sender.send_command(
+            entity_id,
+            CounterCommandRequest::Increment(IncrementRequest { amount: 1 }),
+            move |response, _| {
+                if let Err(StatusCode::Timeout(message)) = response {
+                    *failure.lock().unwrap() = Some(message);
+                }
+            },
+        );
+        sender.assign_request_ids_without_sending();
+    }
+
+    SpatialReaderSystem::disconnect(&world);
+
+    assert_eq!(None, EntityIds::fetch(&world).get_entity(entity_id));
+    let removed: Vec<u32> = world
+        .fetch::<ComponentRemovingEvents<Counter>>()
+        .read(&mut reader_id)
+        .map(|removing| removing.value.total)
+        .collect();
+    assert_eq!(vec![3], removed);
+    assert_eq!(Some(CONNECTION_LOST.to_string()), *failure.lock().unwrap());
+}
@@ -1,8 +1,41 @@
-use crate::component_registry::ComponentRegistry;
+use crate::archetype::ArchetypeStats;
+use crate::audit::ReplicationAudit;
+use crate::checksum::ChecksumVerification;
+use crate::clock::{self, SpatialClock};
+use crate::commands::{CommandRequestsComp, CommandSenderRes, CommandValidation};
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::connection_handle::{rotate_from, ConnectionCalls, SdkCall, SpatialConnectionHandle};
+use crate::drift_repair::DriftRepair;
+use crate::entities::EntityIds;
+use crate::eviction::ProxyEviction;
+use crate::frame_report::FrameReport;
+use crate::health::{ConnectionHealth, ConnectionHealthEvents};
+use crate::logging::SpatialLogger;
+use crate::network_stats::NetworkStats;
+#[cfg(feature = "partitions")]
+use crate::partition::Partitions;
+use crate::persistence::Persistence;
+#[cfg(feature = "prometheus")]
+use crate::prometheus::PrometheusExporter;
+use crate::saga::Sagas;
+use crate::sdk::SdkConnection;
+use crate::shutdown::ShutdownCoordinator;
+use crate::spatial_hash::SpatialHash;
 use crate::spatial_reader::ResourcesSystemData;
-use crate::system_commands::SystemCommandSender;
+use crate::spawn_queue::SpawnQueue;
+use crate::storage::{ComponentAuthority, ComponentPolicy};
+use crate::system_commands::{SystemCommandSender, SystemCommandSenderRes};
+use crate::tick_rate;
+use crate::tick_stamp::TickStamping;
+use crate::SpatialComponent;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
 use spatialos_sdk::worker::connection::WorkerConnection;
-use specs::prelude::{Resources, System, SystemData, WriteExpect};
+use specs::prelude::{Read, System, SystemData, World, Write, WriteExpect};
+use specs::shred::{Accessor, AccessorCow, DynamicSystemData, ResourceId};
+use specs::storage::MaskedStorage;
+use specs::world::EntitiesRes;
+use std::collections::HashMap;
 
 /// A system which replicates changes in the local world to SpatialOS.
 ///
@@ -36,8 +69,12 @@ use specs::prelude::{Resources, System, SystemData, WriteExpect};
 ///     .with(SpatialWriterSystem, "writer", &[])
 ///     .build();
 ///
-/// dispatcher.setup(&mut world.res);
+/// dispatcher.setup(&mut world);
 /// ```
+///
+/// Components which have been assigned to a `WriterStageSystem` are replicated by that
+/// system instead. Projects which can't afford the barrier can use the
+/// `LockingWriterSystem`.
 pub struct SpatialWriterSystem;
 
 impl<'a> System<'a> for SpatialWriterSystem {
@@ -47,15 +84,553 @@ impl<'a> System<'a> for SpatialWriterSystem {
         ResourcesSystemData<'a>,
     );
 
-    fn setup(&mut self, res: &mut Resources) {
+    fn setup(&mut self, res: &mut World) {
         Self::SystemData::setup(res);
+
+        Write::<ConnectionHealth>::setup(res);
+        Write::<ConnectionHealthEvents>::setup(res);
+        Read::<WriterStages>::setup(res);
     }
 
     fn run(&mut self, (mut connection, mut system_command_sender, res): Self::SystemData) {
+        let started = clock::now(&res.res);
+
+        if res.res.has_value::<ChecksumVerification>() && ChecksumVerification::is_due(&res.res) {
+            for interface in ComponentRegistry::interfaces_iter() {
+                interface.verify_checksums(&res.res);
+            }
+        }
+
         for interface in ComponentRegistry::interfaces_iter() {
-            interface.replicate(&res.res, &mut connection);
+            interface.publish_snapshot(&res.res);
+        }
+
+        if res.res.has_value::<SpawnQueue>() {
+            let now = clock::now(&res.res);
+            res.res
+                .fetch_mut::<SpawnQueue>()
+                .flush(&mut system_command_sender, now);
+        }
+
+        if res.res.has_value::<Sagas>() {
+            Sagas::drive(&res.res);
+        }
+
+        if res.res.has_value::<DriftRepair>() {
+            DriftRepair::update(&res.res);
+        }
+
+        let messages_sent = {
+            let stages = res.res.fetch::<WriterStages>();
+            replicate(
+                &res.res,
+                &mut connection,
+                &mut system_command_sender,
+                |component_id| stages.stage(component_id).is_none(),
+            )
+        };
+
+        if res.res.has_value::<TickStamping>() {
+            TickStamping::finish_frame(&res.res);
+        }
+
+        if res.res.has_value::<NetworkStats>() {
+            res.res.fetch_mut::<NetworkStats>().finish_frame();
+        }
+
+        if res.res.has_value::<ConnectionCalls>() {
+            res.res.fetch_mut::<ConnectionCalls>().finish_frame();
+        }
+
+        if res.res.has_value::<Persistence>() {
+            Persistence::flush(&res.res, false);
+        }
+
+        if res.res.has_value::<ConnectionHealth>() {
+            ConnectionHealth::update(&res.res, connection.connected(), messages_sent);
+        }
+
+        let now = clock::now(&res.res);
+
+        if res.res.has_value::<ShutdownCoordinator>() {
+            ShutdownCoordinator::update(&res.res, now);
+        }
+
+        if res.res.has_value::<ArchetypeStats>() {
+            ArchetypeStats::finish_frame(&res.res, now);
+        }
+
+        if res.res.has_value::<ReplicationAudit>() {
+            let mut audit = res.res.fetch_mut::<ReplicationAudit>();
+            if audit.is_enabled() {
+                audit.finish_frame();
+            }
+        }
+
+        if res.res.has_value::<FrameReport>() {
+            res.res
+                .fetch_mut::<FrameReport>()
+                .record_writer_time(now.duration_since(started));
+            FrameReport::finish_frame(&res.res);
+        }
+
+        #[cfg(feature = "prometheus")]
+        {
+            if res.res.has_value::<PrometheusExporter>() {
+                PrometheusExporter::finish_frame(&res.res);
+            }
+        }
+
+        tick_rate::with_controller(&res.res, |controller| controller.writer_finished(now));
+    }
+}
+
+/// Sends every pending component update, command request and command response now, rather
+/// than waiting for the `SpatialWriterSystem` at the end of the frame. Returns the number
+/// of messages sent.
+///
+/// Anything sent is no longer pending, so the `SpatialWriterSystem` will not send it again,
+/// and only changes made after this call are sent at the end of the frame. What is sent is
+/// counted in the `FrameReport` and `ConnectionHealth` of the frame.
+///
+/// This fetches the `WorkerConnection` and all component storages mutably, so it can only
+/// be called outside of the dispatcher, or while nothing is fetched. To flush between
+/// systems, add a `SpatialFlushSystem` instead.
+pub fn flush(world: &World) -> usize {
+    let messages_sent = {
+        let mut connection = world.fetch_mut::<WorkerConnection>();
+        let mut system_command_sender = SystemCommandSender::fetch(world);
+        replicate(world, &mut connection, &mut system_command_sender, |_| true)
+    };
+
+    if world.has_value::<ConnectionHealth>() {
+        world
+            .fetch_mut::<ConnectionHealth>()
+            .record_flushed(messages_sent);
+    }
+
+    messages_sent
+}
+
+/// A system which sends every pending component update, command request and command
+/// response when it runs, as `flush` does, so that they are sent mid-frame.
+///
+/// ```ignore
+/// let mut dispatcher = DispatcherBuilder::new()
+///     .with(SpatialReaderSystem, "reader", &[])
+///     .with_barrier()
+///     .with(SpawnPlayersSys, "spawn_players", &[])
+///     .with_barrier()
+///     .with(SpatialFlushSystem, "flush", &[])
+///     .with_barrier()
+///     .with(MovePlayerSys, "move_player", &[])
+///     .with_barrier()
+///     .with(SpatialWriterSystem, "writer", &[])
+///     .build();
+/// ```
+///
+/// Like the `SpatialWriterSystem`, this system **must not run in parallel with other
+/// systems**.
+pub struct SpatialFlushSystem;
+
+impl<'a> System<'a> for SpatialFlushSystem {
+    type SystemData = ResourcesSystemData<'a>;
+
+    fn setup(&mut self, res: &mut World) {
+        SystemCommandSender::setup(res);
+    }
+
+    fn run(&mut self, res: Self::SystemData) {
+        flush(res.res);
+    }
+}
+
+fn replicate<F: Fn(ComponentId) -> bool>(
+    res: &World,
+    connection: &mut WorkerConnection,
+    system_command_sender: &mut SystemCommandSenderRes,
+    include_component: F,
+) -> usize {
+    let mut connection = SpatialConnectionHandle::new(res, connection);
+
+    // Components are visited in order of ID, round-robin from where the update cap was last
+    // reached.
+    let mut component_ids: Vec<ComponentId> = ComponentRegistry::interfaces_iter()
+        .map(|interface| interface.component_id())
+        .filter(|component_id| include_component(*component_id))
+        .collect();
+    component_ids.sort();
+
+    let cursor = ConnectionCalls::update_cursor(res);
+    if let Some((cursor_id, _)) = cursor {
+        rotate_from(&mut component_ids, |component_id| {
+            *component_id >= cursor_id
+        });
+    }
+
+    let mut messages_sent = 0;
+    let mut capped_at = None;
+    for component_id in component_ids {
+        let interface = match ComponentRegistry::get_interface(component_id) {
+            Some(interface) => interface,
+            None => continue,
+        };
+        let resume = match cursor {
+            Some((cursor_id, resume)) if cursor_id == component_id => resume,
+            _ => None,
+        };
+
+        let (sent, last_sent) = interface.replicate(res, &mut connection, resume);
+        messages_sent += sent;
+
+        if capped_at.is_none() && connection.capacity(SdkCall::ComponentUpdate) == 0 {
+            capped_at = Some((component_id, last_sent.or(resume)));
+        }
+    }
+    if cursor.is_some() || capped_at.is_some() {
+        ConnectionCalls::set_update_cursor(res, capped_at);
+    }
+
+    let system_commands_sent = system_command_sender.flush_requests(&mut connection);
+    if res.has_value::<FrameReport>() {
+        res.fetch_mut::<FrameReport>()
+            .record_system_commands_sent(system_commands_sent);
+    }
+    messages_sent += system_commands_sent;
+
+    #[cfg(feature = "partitions")]
+    {
+        if res.has_value::<Partitions>() {
+            messages_sent += res.fetch_mut::<Partitions>().flush(&mut connection);
+        }
+    }
+
+    messages_sent
+}
+
+/// A resource recording which `WriterStageSystem` replicates each component. Components
+/// which are not assigned to a stage are replicated by the `SpatialWriterSystem`.
+#[derive(Default)]
+pub struct WriterStages {
+    stages: HashMap<ComponentId, String>,
+}
+
+impl WriterStages {
+    /// The name of the stage the component is assigned to, if any.
+    pub fn stage(&self, component_id: ComponentId) -> Option<&str> {
+        self.stages.get(&component_id).map(String::as_str)
+    }
+
+    // Each component may only be replicated by a single stage, or it could be sent twice
+    // in a frame, in either order.
+    fn assign(&mut self, component_id: ComponentId, stage: &str) {
+        if let Some(existing) = self.stages.get(&component_id) {
+            if existing != stage {
+                panic!(
+                    "Component {} is assigned to both the '{}' and '{}' writer stages.",
+                    describe_component(component_id),
+                    existing,
+                    stage
+                );
+            }
+        }
+
+        self.stages.insert(component_id, stage.to_string());
+    }
+}
+
+/// A system which replicates a declared subset of components, so that they can be sent
+/// mid-frame rather than at the end of the frame.
+///
+/// Updates, command requests and command responses of the stage's components are sent by
+/// this system, and never by the `SpatialWriterSystem`. Setting up two stages which share
+/// a component panics.
+///
+/// ```ignore
+/// let mut dispatcher = DispatcherBuilder::new()
+///     .with(SpatialReaderSystem, "reader", &[])
+///     .with_barrier()
+///     .with(PhysicsSys, "physics", &[])
+///     .with_barrier()
+///     .with(WriterStageSystem::new("physics").with_component::<Position>(), "physics_writer", &[])
+///     .with_barrier()
+///     .with(GameplaySys, "gameplay", &[])
+///     .with_barrier()
+///     .with(SpatialWriterSystem, "writer", &[])
+///     .build();
+/// ```
+///
+/// Like the `SpatialWriterSystem`, this system **must not run in parallel with other
+/// systems**.
+pub struct WriterStageSystem {
+    name: String,
+    component_ids: Vec<ComponentId>,
+}
+
+impl WriterStageSystem {
+    pub fn new(name: &str) -> WriterStageSystem {
+        WriterStageSystem {
+            name: name.to_string(),
+            component_ids: Vec::new(),
+        }
+    }
+
+    pub fn with_component<T: 'static + WorkerComponent>(mut self) -> WriterStageSystem {
+        ComponentRegistry::register_component::<T>();
+        self.component_ids.push(T::ID);
+        self
+    }
+}
+
+impl<'a> System<'a> for WriterStageSystem {
+    type SystemData = (WriteExpect<'a, WorkerConnection>, ResourcesSystemData<'a>);
+
+    fn setup(&mut self, res: &mut World) {
+        Self::SystemData::setup(res);
+        Write::<WriterStages>::setup(res);
+
+        let mut stages = res.fetch_mut::<WriterStages>();
+        for component_id in &self.component_ids {
+            stages.assign(*component_id, &self.name);
+        }
+    }
+
+    fn run(&mut self, (mut connection, res): Self::SystemData) {
+        let mut connection = SpatialConnectionHandle::new(&res.res, &mut connection);
+        for component_id in &self.component_ids {
+            if let Some(interface) = ComponentRegistry::get_interface(*component_id) {
+                interface.replicate(&res.res, &mut connection, None);
+            }
+        }
+    }
+}
+
+/// A writer which declares exactly the resources it fetches, so that it can be scheduled
+/// by the dispatcher like any other system rather than behind a barrier.
+///
+/// The dispatcher treats the writer as holding every resource it may fetch for the whole
+/// of its run: the storages and command queues of the declared components, the entity ID
+/// mapping, and the writer's own bookkeeping resources. Systems which touch none of these
+/// may run in parallel with it. The writer sends updates, command requests and command
+/// responses of the declared components, and pending system commands.
+///
+/// ```ignore
+/// let writer = LockingWriterSystem::new()
+///     .with_component::<Position>()
+///     .with_component::<Player>();
+///
+/// let mut dispatcher = DispatcherBuilder::new()
+///     .with(SpatialReaderSystem, "reader", &[])
+///     .with_barrier()
+///     .with(MovePlayerSys, "move_player", &[])
+///     .with(AudioSys, "audio", &[])
+///     .with(writer, "writer", &["move_player"])
+///     .build();
+/// ```
+///
+/// Declared components are assigned to the `"locking"` writer stage, so they can't also be
+/// replicated by a `WriterStageSystem`. Components which aren't declared are not sent.
+///
+/// End of frame work which may fetch arbitrary resources, such as the spawn queue, sagas,
+/// connection health and frame report, is only done by the `SpatialWriterSystem`.
+pub struct LockingWriterSystem {
+    component_ids: Vec<ComponentId>,
+    access: ReplicationAccess,
+}
+
+impl LockingWriterSystem {
+    pub fn new() -> LockingWriterSystem {
+        #[allow(unused_mut)]
+        let mut writes = vec![
+            ResourceId::new::<WorkerConnection>(),
+            ResourceId::new::<SystemCommandSenderRes>(),
+            ResourceId::new::<ReplicationAudit>(),
+            ResourceId::new::<ArchetypeStats>(),
+            ResourceId::new::<FrameReport>(),
+            ResourceId::new::<NetworkStats>(),
+            ResourceId::new::<ConnectionCalls>(),
+            ResourceId::new::<Persistence>(),
+            ResourceId::new::<SpatialHash>(),
+            ResourceId::new::<SpatialLogger>(),
+            ResourceId::new::<ProxyEviction>(),
+        ];
+        #[cfg(feature = "partitions")]
+        writes.push(ResourceId::new::<Partitions>());
+
+        let mut reads = EntityIds::reads();
+        reads.extend(vec![
+            ResourceId::new::<EntitiesRes>(),
+            ResourceId::new::<TickStamping>(),
+            ResourceId::new::<CommandValidation>(),
+            ResourceId::new::<SpatialClock>(),
+        ]);
+
+        LockingWriterSystem {
+            component_ids: Vec::new(),
+            access: ReplicationAccess { reads, writes },
+        }
+    }
+
+    pub fn with_component<T: 'static + WorkerComponent>(mut self) -> LockingWriterSystem {
+        ComponentRegistry::register_component::<T>();
+        self.component_ids.push(T::ID);
+        self.access.writes.extend(vec![
+            ResourceId::new::<MaskedStorage<SpatialComponent<T>>>(),
+            ResourceId::new::<CommandSenderRes<T>>(),
+            ResourceId::new::<MaskedStorage<CommandRequestsComp<T>>>(),
+        ]);
+        self.access.reads.extend(vec![
+            ResourceId::new::<ComponentAuthority<T>>(),
+            ResourceId::new::<ComponentPolicy<T>>(),
+        ]);
+        self
+    }
+}
+
+impl Default for LockingWriterSystem {
+    fn default() -> Self {
+        LockingWriterSystem::new()
+    }
+}
+
+impl<'a> System<'a> for LockingWriterSystem {
+    type SystemData = ReplicationResources<'a>;
+
+    fn accessor<'b>(&'b self) -> AccessorCow<'a, 'b, Self> {
+        AccessorCow::Ref(&self.access)
+    }
+
+    fn setup(&mut self, res: &mut World) {
+        SystemCommandSender::setup(res);
+        Write::<WriterStages>::setup(res);
+
+        let mut stages = res.fetch_mut::<WriterStages>();
+        for component_id in &self.component_ids {
+            stages.assign(*component_id, "locking");
         }
+    }
 
-        system_command_sender.flush_requests(&mut connection);
+    fn run(&mut self, resources: Self::SystemData) {
+        let res = resources.res;
+        let mut connection = res.fetch_mut::<WorkerConnection>();
+        let mut system_command_sender = SystemCommandSender::fetch(res);
+        let component_ids = &self.component_ids;
+        replicate(
+            res,
+            &mut connection,
+            &mut system_command_sender,
+            |component_id| component_ids.contains(&component_id),
+        );
     }
 }
+
+/// The resources a `LockingWriterSystem` may fetch, which depend on its components.
+#[doc(hidden)]
+pub struct ReplicationAccess {
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+impl Accessor for ReplicationAccess {
+    fn try_new() -> Option<Self> {
+        None
+    }
+
+    fn reads(&self) -> Vec<ResourceId> {
+        self.reads.clone()
+    }
+
+    fn writes(&self) -> Vec<ResourceId> {
+        self.writes.clone()
+    }
+}
+
+/// Gives the `LockingWriterSystem` a reference to the world, from which it fetches only
+/// the resources declared by its `ReplicationAccess`.
+#[doc(hidden)]
+pub struct ReplicationResources<'a> {
+    res: &'a World,
+}
+
+impl<'a> DynamicSystemData<'a> for ReplicationResources<'a> {
+    type Accessor = ReplicationAccess;
+
+    fn setup(_: &ReplicationAccess, _: &mut World) {}
+
+    fn fetch(_: &ReplicationAccess, res: &'a World) -> Self {
+        ReplicationResources { res }
+    }
+}
+
+#[test]
+fn writer_stages_should_assign_each_component_once() {
+    let mut stages = WriterStages::default();
+    stages.assign(54, "physics");
+    stages.assign(54, "physics");
+    assert_eq!(Some("physics"), stages.stage(54));
+    assert_eq!(None, stages.stage(1000));
+
+    let result = std::panic::catch_unwind(move || stages.assign(54, "gameplay"));
+    assert!(result.is_err());
+}
+
+#[test]
+fn locking_writer_should_only_lock_its_own_components() {
+    use crate::generated_test::*;
+
+    let position_writer = LockingWriterSystem::new().with_component::<Position>();
+    let blob_writer = LockingWriterSystem::new().with_component::<Blob>();
+
+    let position_storage = ResourceId::new::<MaskedStorage<SpatialComponent<Position>>>();
+    let blob_storage = ResourceId::new::<MaskedStorage<SpatialComponent<Blob>>>();
+
+    let writes = position_writer.access.writes();
+    assert!(writes.contains(&position_storage));
+    assert!(writes.contains(&ResourceId::new::<WorkerConnection>()));
+    assert!(!writes.contains(&blob_storage));
+    assert!(blob_writer.access.writes().contains(&blob_storage));
+    assert!(position_writer
+        .access
+        .reads()
+        .contains(&ResourceId::new::<EntitiesRes>()));
+}
+
+#[test]
+fn locking_writer_should_conflict_with_systems_writing_entity_ids() {
+    use crate::entities::{EntityId, SpatialEntitiesRes};
+    use crate::generated_test::*;
+    use specs::prelude::WriteStorage;
+
+    let writer = LockingWriterSystem::new().with_component::<Position>();
+    let reads = writer.access.reads();
+    let writes = writer.access.writes();
+
+    // The dispatcher only runs two systems in parallel if neither writes anything the
+    // other reads or writes.
+    let conflicts = |other_writes: Vec<ResourceId>| {
+        other_writes
+            .iter()
+            .any(|resource| reads.contains(resource) || writes.contains(resource))
+    };
+
+    assert!(conflicts(Write::<SpatialEntitiesRes>::writes()));
+    assert!(conflicts(WriteStorage::<EntityId>::writes()));
+    assert!(conflicts(Write::<CommandValidation>::writes()));
+    assert!(conflicts(
+        WriteStorage::<SpatialComponent<Position>>::writes()
+    ));
+    assert!(!conflicts(WriteStorage::<SpatialComponent<Blob>>::writes()));
+}
+
+#[test]
+fn flush_system_should_conflict_with_systems_using_the_connection() {
+    use specs::prelude::WorldExt;
+
+    type FlushData<'a> = <SpatialFlushSystem as System<'a>>::SystemData;
+    assert!(FlushData::writes().contains(&ResourceId::new::<WorkerConnection>()));
+    assert!(FlushData::writes().contains(&ResourceId::new::<EntitiesRes>()));
+
+    let mut world = World::new();
+    System::setup(&mut SpatialFlushSystem, &mut world);
+    assert!(world.has_value::<SystemCommandSenderRes>());
+}
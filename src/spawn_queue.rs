@@ -0,0 +1,257 @@
+use crate::clock;
+use crate::spatial_reader::CONNECTION_LOST;
+use crate::system_commands::SystemCommandSenderRes;
+use spatialos_sdk::worker::entity::Entity as WorkerEntity;
+use spatialos_sdk::worker::op::{CreateEntityResponseOp, StatusCode};
+use spatialos_sdk::worker::EntityId as WorkerEntityId;
+use specs::prelude::World;
+use specs::shrev::EventChannel;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Identifies an entity spawn queued in a `SpawnQueue`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SpawnId(u64);
+
+/// An event emitted when an entity spawned through a `SpawnQueue` is either created
+/// or has permanently failed to be created.
+#[derive(Debug, Clone)]
+pub enum SpawnEvent {
+    Created(SpawnId, WorkerEntityId),
+    Failed(SpawnId, String),
+}
+
+/// An event channel which receives `SpawnEvent`s from the `SpawnQueue`.
+pub type SpawnEvents = EventChannel<SpawnEvent>;
+
+type SpawnTemplate = Box<Fn() -> WorkerEntity + Send + Sync>;
+
+struct QueuedSpawn {
+    id: SpawnId,
+    template: SpawnTemplate,
+    reserved_entity_id: Option<WorkerEntityId>,
+    attempts: u32,
+    not_before: Option<Instant>,
+}
+
+/// A resource which throttles entity creation.
+///
+/// At most `max_in_flight` create entity requests are sent at any one time, with the rest
+/// queued until earlier requests complete. Requests which time out are retried up to
+/// `max_attempts` times with exponential backoff. Any other failure, or running out of
+/// attempts, is a permanent failure and is emitted as a `SpawnEvent::Failed`. Requests
+/// which were in flight when `SpatialReaderSystem::reconnect` was called are sent again
+/// on the new connection, without counting as an attempt.
+///
+/// The queue is flushed by the `SpatialWriterSystem` if it has been added to the world.
+///
+/// ```ignore
+/// world.insert(SpawnQueue::new(16, 3, Duration::from_millis(500)));
+/// ```
+pub struct SpawnQueue {
+    max_in_flight: usize,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    next_id: u64,
+    queued: VecDeque<QueuedSpawn>,
+    in_flight: HashMap<SpawnId, QueuedSpawn>,
+}
+
+impl SpawnQueue {
+    pub fn new(max_in_flight: usize, max_attempts: u32, initial_backoff: Duration) -> SpawnQueue {
+        SpawnQueue {
+            max_in_flight,
+            max_attempts,
+            initial_backoff,
+            next_id: 0,
+            queued: VecDeque::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Queues an entity to be created.
+    ///
+    /// The template is called every time a create entity request is sent, including retries.
+    pub fn spawn<F>(&mut self, reserved_entity_id: Option<WorkerEntityId>, template: F) -> SpawnId
+    where
+        F: 'static + Fn() -> WorkerEntity + Send + Sync,
+    {
+        let id = SpawnId(self.next_id);
+        self.next_id += 1;
+
+        self.queued.push_back(QueuedSpawn {
+            id,
+            template: Box::new(template),
+            reserved_entity_id,
+            attempts: 0,
+            not_before: None,
+        });
+
+        id
+    }
+
+    /// The number of spawns waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// The number of create entity requests awaiting a response.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub(crate) fn flush(&mut self, sender: &mut SystemCommandSenderRes, now: Instant) {
+        while let Some(mut spawn) = self.next_ready(now) {
+            spawn.attempts += 1;
+
+            let id = spawn.id;
+            sender.create_entity_internal(
+                (spawn.template)(),
+                spawn.reserved_entity_id,
+                Box::new(move |res, response_op| SpawnQueue::on_response(res, id, response_op)),
+            );
+
+            self.in_flight.insert(id, spawn);
+        }
+    }
+
+    fn next_ready(&mut self, now: Instant) -> Option<QueuedSpawn> {
+        if self.in_flight.len() >= self.max_in_flight {
+            return None;
+        }
+
+        let index = self.queued.iter().position(|spawn| {
+            spawn
+                .not_before
+                .map_or(true, |not_before| not_before <= now)
+        })?;
+        self.queued.remove(index)
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.initial_backoff * 2u32.pow(attempts.saturating_sub(1).min(16))
+    }
+
+    fn on_response(res: &World, id: SpawnId, response_op: CreateEntityResponseOp) {
+        let now = clock::now(res);
+        let event = {
+            let mut queue = res.fetch_mut::<SpawnQueue>();
+            let mut spawn = match queue.in_flight.remove(&id) {
+                Some(spawn) => spawn,
+                None => return,
+            };
+
+            match response_op.status_code {
+                StatusCode::Success(entity_id) => SpawnEvent::Created(id, entity_id),
+                StatusCode::Timeout(ref message) if message == CONNECTION_LOST => {
+                    spawn.attempts -= 1;
+                    queue.queued.push_front(spawn);
+                    return;
+                }
+                StatusCode::Timeout(_) if spawn.attempts < queue.max_attempts => {
+                    spawn.not_before = Some(now + queue.backoff(spawn.attempts));
+                    queue.queued.push_back(spawn);
+                    return;
+                }
+                other => SpawnEvent::Failed(id, format!("{:?}", other)),
+            }
+        };
+
+        if res.has_value::<SpawnEvents>() {
+            res.fetch_mut::<SpawnEvents>().single_write(event);
+        }
+    }
+}
+
+impl Default for SpawnQueue {
+    fn default() -> Self {
+        SpawnQueue::new(16, 3, Duration::from_millis(500))
+    }
+}
+
+#[test]
+fn spawn_queue_should_retry_timeouts_then_fail() {
+    use spatialos_sdk::worker::RequestId;
+    use specs::prelude::{World, WorldExt};
+
+    let mut world = World::new();
+    world.insert(SpawnQueue::new(1, 2, Duration::from_millis(0)));
+    world.insert(SpawnEvents::new());
+
+    let mut reader_id = world.fetch_mut::<SpawnEvents>().register_reader();
+
+    let (first, second) = {
+        let mut queue = world.fetch_mut::<SpawnQueue>();
+        (
+            queue.spawn(None, || unreachable!()),
+            queue.spawn(None, || unreachable!()),
+        )
+    };
+
+    let timeout = || CreateEntityResponseOp {
+        request_id: RequestId::new(1),
+        status_code: StatusCode::Timeout(String::from("Timeout")),
+    };
+
+    for _ in 0..2 {
+        {
+            let mut queue = world.fetch_mut::<SpawnQueue>();
+            let mut spawn = queue.next_ready(Instant::now()).unwrap();
+            assert_eq!(first, spawn.id);
+            spawn.attempts += 1;
+            queue.in_flight.insert(spawn.id, spawn);
+
+            assert!(queue.next_ready(Instant::now()).is_none());
+        }
+
+        SpawnQueue::on_response(&world, first, timeout());
+    }
+
+    let queue = world.fetch::<SpawnQueue>();
+    assert_eq!(0, queue.in_flight());
+    assert_eq!(1, queue.queued());
+    assert_eq!(second, queue.queued[0].id);
+
+    let events: Vec<SpawnEvent> = world
+        .fetch::<SpawnEvents>()
+        .read(&mut reader_id)
+        .cloned()
+        .collect();
+
+    match events.as_slice() {
+        [SpawnEvent::Failed(id, _)] => assert_eq!(first, *id),
+        other => panic!("Unexpected events: {:?}", other),
+    }
+}
+
+#[test]
+fn spawn_queue_should_resend_spawns_in_flight_when_reconnecting() {
+    use crate::system_commands::SystemCommandSender;
+    use specs::prelude::{SystemData, World, WorldExt};
+
+    let mut world = World::new();
+    SystemCommandSender::setup(&mut world);
+    world.insert(SpawnQueue::new(1, 1, Duration::from_millis(0)));
+
+    let flush = |world: &World| {
+        let mut queue = world.fetch_mut::<SpawnQueue>();
+        let mut sender = SystemCommandSender::fetch(world);
+        queue.flush(&mut sender, Instant::now());
+        sender.assign_request_ids_without_sending();
+    };
+
+    world
+        .fetch_mut::<SpawnQueue>()
+        .spawn(None, WorkerEntity::new);
+    flush(&world);
+    assert_eq!(1, world.fetch::<SpawnQueue>().in_flight());
+
+    SystemCommandSenderRes::fail_sent_requests(&world, CONNECTION_LOST);
+    assert_eq!(0, world.fetch::<SpawnQueue>().in_flight());
+    assert_eq!(1, world.fetch::<SpawnQueue>().queued());
+
+    // The lost request didn't use up the only attempt, so the spawn is sent again.
+    flush(&world);
+    assert_eq!(1, world.fetch::<SpawnQueue>().in_flight());
+    assert_eq!(0, world.fetch::<SpawnQueue>().queued());
+}
@@ -1,15 +1,17 @@
-use crate::component_registry::ComponentRegistry;
-use crate::SpatialComponent;
+use crate::component_registry::{describe_component, ComponentRegistry};
+use crate::entities::{EntityId, EntityIds};
+use crate::{schema_default, SpatialComponent};
 use hibitset::{BitSet, BitSetAnd, BitSetLike};
 use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
 use spatialos_sdk::worker::Authority;
 use specs::join::BitAnd;
-use specs::prelude::{
-    Component, Entity, Join, Read, ReadStorage, Resources, SystemData, WriteStorage,
-};
+use specs::prelude::{Component, Entity, Join, Read, ReadStorage, SystemData, World, WriteStorage};
 use specs::shred::{Fetch, ResourceId};
+use specs::shrev::EventChannel;
 use specs::storage::{DistinctStorage, MaskedStorage, UnprotectedStorage};
 use specs::world::Index;
+use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
@@ -18,18 +20,155 @@ use std::ops::{Deref, DerefMut};
 /// Analagous to `ReadStorage`.
 pub type SpatialReadStorage<'a, T> = ReadStorage<'a, SpatialComponent<T>>;
 
+/// Additional read methods for a `SpatialReadStorage`.
+pub trait SpatialReadStorageExt<T: 'static + WorkerComponent> {
+    /// Returns the component data for the given entity or, if the entity does not
+    /// have the component checked out, the schema default value of the component.
+    ///
+    /// The default value is synthesized by deserializing an empty `SchemaObject`.
+    fn get_or_schema_default(&self, entity: Entity) -> Cow<T>;
+
+    /// Returns the component of the entity with the given SpatialOS entity ID, if it is
+    /// checked out and has the component.
+    ///
+    /// This is useful when an `EntityId` is read from a schema field:
+    ///
+    /// ```ignore
+    /// if let Some(target) = positions.get_by_entity_id(&entity_ids, weapon.target) {
+    ///     // aim at target.coords
+    /// }
+    /// ```
+    fn get_by_entity_id(
+        &self,
+        entity_ids: &EntityIds,
+        entity_id: EntityId,
+    ) -> Option<&SpatialComponent<T>>;
+
+    /// Iterates over every component with the SpatialOS entity ID of its entity:
+    ///
+    /// ```ignore
+    /// for (entity_id, position) in positions.join_with_ids(&entity_ids) {
+    ///     println!("{} is at {:?}", entity_id, position.coords);
+    /// }
+    /// ```
+    fn join_with_ids<'b>(
+        &'b self,
+        entity_ids: &'b EntityIds<'b>,
+    ) -> Box<Iterator<Item = (EntityId, &'b SpatialComponent<T>)> + 'b>;
+}
+
+impl<'a, T: 'static + WorkerComponent> SpatialReadStorageExt<T> for SpatialReadStorage<'a, T> {
+    fn get_or_schema_default(&self, entity: Entity) -> Cow<T> {
+        match self.get(entity) {
+            Some(component) => Cow::Borrowed(&**component),
+            None => Cow::Owned(schema_default::<T>()),
+        }
+    }
+
+    fn get_by_entity_id(
+        &self,
+        entity_ids: &EntityIds,
+        entity_id: EntityId,
+    ) -> Option<&SpatialComponent<T>> {
+        self.get(entity_ids.get_entity(entity_id)?)
+    }
+
+    fn join_with_ids<'b>(
+        &'b self,
+        entity_ids: &'b EntityIds<'b>,
+    ) -> Box<Iterator<Item = (EntityId, &'b SpatialComponent<T>)> + 'b> {
+        Box::new(
+            (entity_ids, self)
+                .join()
+                .map(|(entity_id, component)| (*entity_id, component)),
+        )
+    }
+}
+
 /// Retrieves write access to any component of this type which this worker has
 /// authority over.
 ///
+/// Joins and `get_mut` only visit components this worker is authoritative over, so
+/// changes which SpatialOS would reject cannot be made by accident. Use
+/// [`unrestricted`](#method.unrestricted) to fetch a storage without this restriction.
+///
 /// Analagous to `WriteStorage`.
 pub struct SpatialWriteStorage<'a, T: 'static + WorkerComponent> {
     data: WriteStorage<'a, SpatialComponent<T>>,
-    authority: Fetch<'a, AuthorityBitSet<T>>,
+    authority: Fetch<'a, ComponentAuthority<T>>,
+    authoritative_only: bool,
 }
 
 impl<'a, T: 'static + WorkerComponent> SpatialWriteStorage<'a, T> {
+    /// Fetches a storage whose joins and `get_mut` only visit components this worker is
+    /// authoritative over. This is equivalent to fetching it as `SystemData`.
+    pub fn authoritative(res: &'a World) -> Self {
+        Self::fetch(res)
+    }
+
+    /// Fetches a storage whose joins and `get_mut` visit every checked out component,
+    /// regardless of authority.
+    ///
+    /// Changes to components this worker is not authoritative over are still sent at the
+    /// end of the frame, and will be rejected by SpatialOS.
+    pub fn unrestricted(res: &'a World) -> Self {
+        SpatialWriteStorage {
+            authoritative_only: false,
+            ..Self::fetch(res)
+        }
+    }
+
+    /// Iterates over the components this storage visits with the SpatialOS entity ID of
+    /// their entity, as a mutable join with `EntityIds` would:
+    ///
+    /// ```ignore
+    /// for (entity_id, position) in positions.join_with_ids(&entity_ids) {
+    ///     position.coords.y += 1.0;
+    /// }
+    /// ```
+    pub fn join_with_ids<'b>(
+        &'b mut self,
+        entity_ids: &'b EntityIds<'b>,
+    ) -> impl Iterator<Item = (EntityId, &'b mut SpatialComponent<T>)> + 'b {
+        (entity_ids, self)
+            .join()
+            .map(|(entity_id, component)| (*entity_id, component))
+    }
+
+    /// Inserts the schema default value of the component into the entity, returning the
+    /// component it replaces, if any.
+    ///
+    /// Like other local inserts, this doesn't send anything to SpatialOS.
+    pub fn insert_default(
+        &mut self,
+        entity: Entity,
+    ) -> Result<Option<SpatialComponent<T>>, specs::error::Error> {
+        self.data
+            .insert(entity, SpatialComponent::new(schema_default::<T>()))
+    }
+
+    /// Returns whether this worker is authoritative over the component of the entity.
+    pub fn is_authoritative(&self, entity: Entity) -> bool {
+        self.authority.is_authoritative(entity)
+    }
+
+    /// This worker's authority over the component of the entity.
+    pub fn authority(&self, entity: Entity) -> Authority {
+        self.authority.get(entity)
+    }
+
+    /// Mutably borrows the component of the entity, if it exists and, unless this storage is
+    /// unrestricted, this worker is authoritative over it.
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut SpatialComponent<T>> {
+        if self.authoritative_only && !self.authority.is_authoritative(entity) {
+            return None;
+        }
+
+        self.data.get_mut(entity)
+    }
+
     pub(crate) fn try_fetch_component_storage(
-        res: &'a Resources,
+        res: &'a World,
     ) -> Option<WriteStorage<'a, SpatialComponent<T>>> {
         if res.has_value::<MaskedStorage<SpatialComponent<T>>>() {
             Some(WriteStorage::<SpatialComponent<T>>::fetch(res))
@@ -57,20 +196,28 @@ impl<'a, T: 'static + WorkerComponent> SystemData<'a> for SpatialWriteStorage<'a
 where
     T: 'static + WorkerComponent,
 {
-    fn setup(res: &mut Resources) {
-        Read::<AuthorityBitSet<T>>::setup(res);
+    fn setup(res: &mut World) {
+        ComponentRegistry::register_component::<T>();
+        Read::<ComponentAuthority<T>>::setup(res);
         WriteStorage::<SpatialComponent<T>>::setup(res);
     }
 
-    fn fetch(res: &'a Resources) -> Self {
+    fn fetch(res: &'a World) -> Self {
+        if !res.has_value::<MaskedStorage<SpatialComponent<T>>>()
+            || !res.has_value::<ComponentAuthority<T>>()
+        {
+            panic_not_set_up::<T>();
+        }
+
         SpatialWriteStorage {
             data: WriteStorage::<SpatialComponent<T>>::fetch(res),
             authority: res.fetch(),
+            authoritative_only: true,
         }
     }
 
     fn reads() -> Vec<ResourceId> {
-        vec![ResourceId::new::<AuthorityBitSet<T>>()]
+        vec![ResourceId::new::<ComponentAuthority<T>>()]
     }
 
     fn writes() -> Vec<ResourceId> {
@@ -78,6 +225,19 @@ where
     }
 }
 
+// Fetching a resource which doesn't exist panics with only its type name, which doesn't
+// say how to fix it. The Rust type is included, as only components with a registered name
+// can be described by more than their ID.
+fn panic_not_set_up<T: WorkerComponent>() -> ! {
+    panic!(
+        "Tried to fetch the SpatialWriteStorage of component {} ({}) before it was set up. \
+         Set up the dispatcher containing the systems which use it, or call \
+         `SpatialWriteStorage::<T>::setup(&mut world)`, before fetching it.",
+        describe_component(T::ID),
+        std::any::type_name::<T>()
+    );
+}
+
 impl<'a, 'e, T> Join for &'a mut SpatialWriteStorage<'e, T>
 where
     T: 'static + WorkerComponent,
@@ -89,7 +249,12 @@ where
     unsafe fn open(self) -> (Self::Mask, Self::Value) {
         let storage = &mut self.data;
         let (mask, value) = storage.open();
-        ((&self.authority.mask, mask).and(), value)
+        let authority_mask = if self.authoritative_only {
+            &self.authority.mask
+        } else {
+            mask
+        };
+        ((authority_mask, mask).and(), value)
     }
 
     unsafe fn get(v: &mut Self::Value, i: Index) -> &'a mut SpatialComponent<T> {
@@ -107,18 +272,121 @@ where
 {
 }
 
-/// A wrapper around an arbitrary `UnprotectedStorage` which registers
-/// the SpatialOS component in the `ComponentRegistry`.
+/// A resource which controls how the data of a SpatialOS component is stored locally.
+///
+/// Add this to the world to change the policy for a component:
+///
+/// ```ignore
+/// world.insert(ComponentPolicy::<HeavyComponent>::DataIgnore);
+/// ```
+pub enum ComponentPolicy<T: WorkerComponent> {
+    /// The component data is stored in the `SpatialComponent<T>` storage. This is the default.
+    Materialize,
+    /// The component data is never stored in the `SpatialComponent<T>` storage. This saves
+    /// memory for components which are only needed by other worker types.
+    ///
+    /// Authority is still tracked and command requests for the component are still received.
+    DataIgnore,
+    #[doc(hidden)]
+    __Phantom(PhantomData<T>),
+}
+
+impl<T: WorkerComponent> ComponentPolicy<T> {
+    pub(crate) fn ignores_data(res: &World) -> bool {
+        res.has_value::<ComponentPolicy<T>>()
+            && match *res.fetch::<ComponentPolicy<T>>() {
+                ComponentPolicy::DataIgnore => true,
+                _ => false,
+            }
+    }
+}
+
+impl<T: WorkerComponent> Default for ComponentPolicy<T> {
+    fn default() -> Self {
+        ComponentPolicy::Materialize
+    }
+}
+
+/// An event emitted when a SpatialOS component is removed from an entity, carrying its
+/// last value so that cleanup logic can inspect it.
+///
+/// Components are removed from an entity before the entity itself is removed, so this is
+/// also emitted for every component of an entity which leaves this worker's view. By the
+/// time the event is read, `entity` may no longer be alive.
+#[derive(Debug, Clone)]
+pub struct ComponentRemoving<T> {
+    pub entity: Entity,
+    pub entity_id: EntityId,
+    pub value: T,
+}
+
+/// An event channel which receives a `ComponentRemoving` event whenever a component is
+/// removed. Events are only emitted if this has been added to the world:
+///
+/// ```ignore
+/// world.insert(ComponentRemovingEvents::<Inventory>::new());
+/// ```
+pub type ComponentRemovingEvents<T> = EventChannel<ComponentRemoving<T>>;
+
+/// An event emitted when a component is removed while it has an update which has not
+/// been sent yet, for example because a system sent an update in the same frame in which
+/// the component left this worker's view. The update is dropped, and a warning is logged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UpdateDropped {
+    pub entity: Entity,
+    pub entity_id: EntityId,
+    pub component_id: ComponentId,
+}
+
+/// An event channel which receives an `UpdateDropped` event whenever a pending update is
+/// dropped. Events are only emitted if this has been added to the world.
+pub type UpdateDroppedEvents = EventChannel<UpdateDropped>;
+
+/// An event emitted when data received from SpatialOS can't be inserted into a storage,
+/// for example because the entity was deleted locally earlier in the same frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertFailed {
+    pub entity: Entity,
+    pub entity_id: Option<EntityId>,
+    pub component_id: ComponentId,
+    pub reason: String,
+}
+
+/// An event channel which receives an `InsertFailed` event whenever received data is
+/// dropped because it couldn't be inserted. Events are only emitted if this has been added
+/// to the world.
+pub type InsertFailedEvents = EventChannel<InsertFailed>;
+
+/// A resource deciding what happens when received data can't be inserted into a storage.
+///
+/// Without it, the data is dropped, and an error is logged and emitted as an
+/// `InsertFailed` event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InsertFailurePolicy {
+    /// Drop the data and report the failure.
+    Drop,
+    /// Panic with the failure, which can help catch the cause during development.
+    Panic,
+}
+
+impl Default for InsertFailurePolicy {
+    fn default() -> Self {
+        InsertFailurePolicy::Drop
+    }
+}
+
+/// A wrapper around the `UnprotectedStorage` of the data of a SpatialOS component, which
+/// registers the component in the `ComponentRegistry` when the storage is created.
 #[doc(hidden)]
-pub struct SpatialUnprotectedStorage<T, C, U>(U, PhantomData<T>, PhantomData<C>)
+pub struct SpatialUnprotectedStorage<T, U>(U, PhantomData<T>)
 where
     T: 'static + WorkerComponent,
-    U: UnprotectedStorage<C> + Default;
+    U: UnprotectedStorage<SpatialComponent<T>> + Default;
 
-impl<T, C, U> UnprotectedStorage<C> for SpatialUnprotectedStorage<T, C, U>
+impl<T, U> UnprotectedStorage<SpatialComponent<T>> for SpatialUnprotectedStorage<T, U>
 where
     T: 'static + WorkerComponent,
-    U: UnprotectedStorage<C> + Default,
+    U: UnprotectedStorage<SpatialComponent<T>> + Default,
 {
     unsafe fn clean<B>(&mut self, has: B)
     where
@@ -127,73 +395,153 @@ where
         self.0.clean(has);
     }
 
-    unsafe fn get(&self, id: Index) -> &C {
+    unsafe fn get(&self, id: Index) -> &SpatialComponent<T> {
         self.0.get(id)
     }
 
-    unsafe fn get_mut(&mut self, id: Index) -> &mut C {
+    unsafe fn get_mut(&mut self, id: Index) -> &mut SpatialComponent<T> {
         self.0.get_mut(id)
     }
 
-    unsafe fn insert(&mut self, id: Index, v: C) {
+    unsafe fn insert(&mut self, id: Index, v: SpatialComponent<T>) {
         self.0.insert(id, v);
     }
 
-    unsafe fn remove(&mut self, id: Index) -> C {
+    unsafe fn remove(&mut self, id: Index) -> SpatialComponent<T> {
         self.0.remove(id)
     }
 }
 
-unsafe impl<T, C, U> DistinctStorage for SpatialUnprotectedStorage<T, C, U>
+unsafe impl<T, U> DistinctStorage for SpatialUnprotectedStorage<T, U>
 where
     T: 'static + WorkerComponent,
-    U: UnprotectedStorage<C> + Default + DistinctStorage,
+    U: UnprotectedStorage<SpatialComponent<T>> + Default + DistinctStorage,
 {
 }
 
-impl<T, C, U> Default for SpatialUnprotectedStorage<T, C, U>
+impl<T, U> Default for SpatialUnprotectedStorage<T, U>
 where
     T: 'static + WorkerComponent,
-    U: UnprotectedStorage<C> + Default,
+    U: UnprotectedStorage<SpatialComponent<T>> + Default,
 {
     fn default() -> Self {
         ComponentRegistry::register_component::<T>();
-        SpatialUnprotectedStorage(Default::default(), PhantomData, PhantomData)
+        SpatialUnprotectedStorage(Default::default(), PhantomData)
     }
 }
 
-pub(crate) struct AuthorityBitSet<T: WorkerComponent> {
+/// A resource holding this worker's authority over every checked out instance of a
+/// component, as last reported by SpatialOS.
+///
+/// It can be joined on, visiting every entity this worker is authoritative over, including
+/// those whose authority loss is imminent, with their `Authority`:
+///
+/// ```ignore
+/// for (authority, position) in (&position_authority, &positions).join() {
+///     if authority == Authority::AuthorityLossImminent {
+///         // hand over
+///     }
+/// }
+/// ```
+pub struct ComponentAuthority<T: WorkerComponent> {
+    // Entities which are `Authoritative` or `AuthorityLossImminent`.
     mask: BitSet,
+    loss_imminent: BitSet,
     _phantom: PhantomData<T>,
 }
 
-impl<T: WorkerComponent> AuthorityBitSet<T> {
-    pub(crate) fn set_authority(&mut self, e: Entity, authority: Authority) {
-        if authority == Authority::NotAuthoritative {
-            self.mask.remove(e.id());
+/// Read access to this worker's authority over a component.
+pub type ReadAuthority<'a, T> = Read<'a, ComponentAuthority<T>>;
+
+impl<T: WorkerComponent> ComponentAuthority<T> {
+    /// This worker's authority over the component of the entity.
+    pub fn get(&self, e: Entity) -> Authority {
+        if !self.mask.contains(e.id()) {
+            Authority::NotAuthoritative
+        } else if self.loss_imminent.contains(e.id()) {
+            Authority::AuthorityLossImminent
         } else {
-            self.mask.add(e.id());
+            Authority::Authoritative
+        }
+    }
+
+    /// Returns whether this worker is `Authoritative` or `AuthorityLossImminent` over the
+    /// component of the entity, so may still send updates to it.
+    pub fn is_authoritative(&self, e: Entity) -> bool {
+        self.mask.contains(e.id())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.mask.clear();
+        self.loss_imminent.clear();
+    }
+
+    pub(crate) fn set_authority(&mut self, e: Entity, authority: Authority) {
+        match authority {
+            Authority::NotAuthoritative => {
+                self.mask.remove(e.id());
+                self.loss_imminent.remove(e.id());
+            }
+            Authority::Authoritative => {
+                self.mask.add(e.id());
+                self.loss_imminent.remove(e.id());
+            }
+            Authority::AuthorityLossImminent => {
+                self.mask.add(e.id());
+                self.loss_imminent.add(e.id());
+            }
         }
     }
 }
 
-impl<T: WorkerComponent> Default for AuthorityBitSet<T> {
+impl<T: WorkerComponent> Default for ComponentAuthority<T> {
     fn default() -> Self {
-        AuthorityBitSet {
+        ComponentAuthority {
             mask: BitSet::new(),
+            loss_imminent: BitSet::new(),
             _phantom: PhantomData,
         }
     }
 }
 
+impl<'a, T: WorkerComponent> Join for &'a ComponentAuthority<T> {
+    type Mask = &'a BitSet;
+    type Type = Authority;
+    type Value = &'a BitSet;
+
+    unsafe fn open(self) -> (Self::Mask, Self::Value) {
+        (&self.mask, &self.loss_imminent)
+    }
+
+    unsafe fn get(loss_imminent: &mut Self::Value, i: Index) -> Authority {
+        if loss_imminent.contains(i) {
+            Authority::AuthorityLossImminent
+        } else {
+            Authority::Authoritative
+        }
+    }
+}
+
+/// This worker's authority over the component `T` of the entity.
+///
+/// In systems, prefer fetching a `ReadAuthority<T>`, so that the dispatcher knows about
+/// the access.
+pub fn authority<T: 'static + WorkerComponent>(res: &World, entity: Entity) -> Authority {
+    if res.has_value::<ComponentAuthority<T>>() {
+        res.fetch::<ComponentAuthority<T>>().get(entity)
+    } else {
+        Authority::NotAuthoritative
+    }
+}
+
 #[test]
 fn component_registers_successfully_on_read() {
     use crate::generated_test::*;
-    use specs::prelude::World;
+    use specs::prelude::{World, WorldExt};
 
     let mut world = World::new();
 
-    SpatialReadStorage::<Position>::setup(&mut world.res);
+    SpatialReadStorage::<Position>::setup(&mut world);
 
     assert!(ComponentRegistry::get_interface(Position::ID).is_some());
 }
@@ -201,11 +549,11 @@ fn component_registers_successfully_on_read() {
 #[test]
 fn component_registers_successfully_on_write() {
     use crate::generated_test::*;
-    use specs::prelude::World;
+    use specs::prelude::{World, WorldExt};
 
     let mut world = World::new();
 
-    SpatialWriteStorage::<Position>::setup(&mut world.res);
+    SpatialWriteStorage::<Position>::setup(&mut world);
 
     assert!(ComponentRegistry::get_interface(Position::ID).is_some());
 }
@@ -220,10 +568,10 @@ fn should_only_join_authority() {
 
     let mut world = World::new();
 
-    Entities::setup(&mut world.res);
-    EntityIds::setup(&mut world.res);
-    SpatialReadStorage::<Position>::setup(&mut world.res);
-    SpatialWriteStorage::<Position>::setup(&mut world.res);
+    Entities::setup(&mut world);
+    EntityIds::setup(&mut world);
+    SpatialReadStorage::<Position>::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
 
     let entity_id = EntityId(WorkerEntityId::new(5));
 
@@ -237,61 +585,369 @@ fn should_only_join_authority() {
 
     {
         world
-            .res
             .fetch_mut::<SpatialEntitiesRes>()
-            .got_new_entity(&world.res, entity_id);
+            .got_new_entity(&world, entity_id);
     }
 
     let entity = {
         world
-            .res
             .fetch::<SpatialEntitiesRes>()
             .get_entity(entity_id)
             .unwrap()
     };
 
     {
-        assert!((&SpatialReadStorage::<Position>::fetch(&world.res))
+        assert!((&SpatialReadStorage::<Position>::fetch(&world))
             .join()
             .next()
             .is_none());
     }
 
     {
-        let mut storage = SpatialWriteStorage::<Position>::fetch(&world.res);
+        let mut storage = SpatialWriteStorage::<Position>::fetch(&world);
         storage.insert(entity, SpatialComponent::new(data)).unwrap();
     }
 
     {
-        let mut storage = SpatialReadStorage::<Position>::fetch(&world.res);
+        let mut storage = SpatialReadStorage::<Position>::fetch(&world);
         assert!((&mut storage).join().next().is_some());
     }
 
     {
-        let mut storage = SpatialWriteStorage::<Position>::fetch(&world.res);
+        let mut storage = SpatialWriteStorage::<Position>::fetch(&world);
         assert!(
             (&mut storage).join().next().is_none(),
             "WriteStorage should be empty as the worker is not authoritative."
         );
+        assert!(storage.get_mut(entity).is_none());
+    }
+
+    {
+        let mut storage = SpatialWriteStorage::<Position>::unrestricted(&world);
+        assert!((&mut storage).join().next().is_some());
+        assert!(storage.get_mut(entity).is_some());
     }
 
     {
         world
-            .res
-            .fetch_mut::<AuthorityBitSet<Position>>()
+            .fetch_mut::<ComponentAuthority<Position>>()
             .set_authority(entity, Authority::Authoritative);
     }
 
     {
-        let mut storage = SpatialReadStorage::<Position>::fetch(&world.res);
+        let mut storage = SpatialReadStorage::<Position>::fetch(&world);
         assert!((&mut storage).join().next().is_some());
     }
 
     {
-        let mut storage = SpatialWriteStorage::<Position>::fetch(&world.res);
+        let mut storage = SpatialWriteStorage::<Position>::fetch(&world);
         assert!(
             (&mut storage).join().next().is_some(),
             "WriteStorage should be non-empty as the worker is authoritative."
         );
+        assert!(storage.get_mut(entity).is_some());
     }
 }
+
+#[test]
+fn should_get_schema_default_for_missing_component() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use crate::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::*;
+
+    let mut world = World::new();
+
+    EntityIds::setup(&mut world);
+    SpatialReadStorage::<Position>::setup(&mut world);
+
+    let (first, second) = {
+        let mut entities_res = world.fetch_mut::<SpatialEntitiesRes>();
+        entities_res.got_new_entity(&world, EntityId(WorkerEntityId::new(1)));
+        entities_res.got_new_entity(&world, EntityId(WorkerEntityId::new(2)));
+        (
+            entities_res
+                .get_entity(EntityId(WorkerEntityId::new(1)))
+                .unwrap(),
+            entities_res
+                .get_entity(EntityId(WorkerEntityId::new(2)))
+                .unwrap(),
+        )
+    };
+
+    WriteStorage::<SpatialComponent<Position>>::fetch(&world)
+        .insert(
+            first,
+            SpatialComponent::new(Position {
+                coords: Coordinates {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 3.0,
+                },
+            }),
+        )
+        .unwrap();
+
+    let storage = SpatialReadStorage::<Position>::fetch(&world);
+
+    assert_eq!(1.0, storage.get_or_schema_default(first).coords.x);
+    assert_eq!(0.0, storage.get_or_schema_default(second).coords.x);
+
+    let entity_ids = EntityIds::fetch(&world);
+    let by_entity_id =
+        |id| storage.get_by_entity_id(&entity_ids, EntityId(WorkerEntityId::new(id)));
+
+    assert_eq!(2.0, by_entity_id(1).unwrap().coords.y);
+    assert!(by_entity_id(2).is_none());
+    assert!(by_entity_id(3).is_none());
+}
+
+#[test]
+fn removing_component_should_emit_last_value() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+    world.insert(ComponentRemovingEvents::<Position>::new());
+
+    let mut reader_id = world
+        .fetch_mut::<ComponentRemovingEvents<Position>>()
+        .register_reader();
+
+    let entity_id = EntityId(WorkerEntityId::new(5));
+    world
+        .fetch_mut::<SpatialEntitiesRes>()
+        .got_new_entity(&world, entity_id);
+    let entity = world
+        .fetch::<SpatialEntitiesRes>()
+        .get_entity(entity_id)
+        .unwrap();
+
+    let data = Position {
+        coords: Coordinates {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        },
+    };
+    SpatialWriteStorage::<Position>::unrestricted(&world)
+        .insert(entity, SpatialComponent::new(data))
+        .unwrap();
+
+    ComponentRegistry::get_interface(Position::ID)
+        .unwrap()
+        .remove_component(&world, entity);
+
+    let events = world.fetch::<ComponentRemovingEvents<Position>>();
+    let removed: Vec<_> = events.read(&mut reader_id).collect();
+    assert_eq!(1, removed.len());
+    assert_eq!(entity_id, removed[0].entity_id);
+    assert_eq!(2.0, removed[0].value.coords.y);
+}
+
+#[test]
+fn removing_component_with_pending_update_should_report_dropped_update() {
+    use crate::entities::SpatialEntitiesRes;
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+    world.insert(UpdateDroppedEvents::new());
+
+    let mut reader_id = world.fetch_mut::<UpdateDroppedEvents>().register_reader();
+
+    let entity_ids = [
+        EntityId(WorkerEntityId::new(5)),
+        EntityId(WorkerEntityId::new(6)),
+    ];
+    let mut entities = Vec::new();
+    for entity_id in &entity_ids {
+        world
+            .fetch_mut::<SpatialEntitiesRes>()
+            .got_new_entity(&world, *entity_id);
+        let entity = world
+            .fetch::<SpatialEntitiesRes>()
+            .get_entity(*entity_id)
+            .unwrap();
+
+        let mut component = SpatialComponent::new(Position {
+            coords: Coordinates {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+        });
+        if entities.is_empty() {
+            component.send_update(PositionUpdate { coords: None });
+        }
+        SpatialWriteStorage::<Position>::unrestricted(&world)
+            .insert(entity, component)
+            .unwrap();
+        entities.push(entity);
+    }
+
+    let interface = ComponentRegistry::get_interface(Position::ID).unwrap();
+    for entity in &entities {
+        interface.remove_component(&world, *entity);
+    }
+
+    let events = world.fetch::<UpdateDroppedEvents>();
+    let dropped: Vec<_> = events.read(&mut reader_id).cloned().collect();
+    assert_eq!(
+        vec![UpdateDropped {
+            entity: entities[0],
+            entity_id: entity_ids[0],
+            component_id: Position::ID,
+        }],
+        dropped
+    );
+}
+
+#[test]
+fn authority_should_distinguish_loss_imminent() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    ReadAuthority::<Position>::setup(&mut world);
+
+    let a = world.create_entity().build();
+    let b = world.create_entity().build();
+    let c = world.create_entity().build();
+
+    assert_eq!(
+        Authority::NotAuthoritative,
+        authority::<Position>(&world, a)
+    );
+
+    {
+        let mut component_authority = world.fetch_mut::<ComponentAuthority<Position>>();
+        component_authority.set_authority(a, Authority::Authoritative);
+        component_authority.set_authority(b, Authority::AuthorityLossImminent);
+        component_authority.set_authority(c, Authority::AuthorityLossImminent);
+        component_authority.set_authority(c, Authority::NotAuthoritative);
+    }
+
+    assert_eq!(Authority::Authoritative, authority::<Position>(&world, a));
+    assert_eq!(
+        Authority::AuthorityLossImminent,
+        authority::<Position>(&world, b)
+    );
+    assert_eq!(
+        Authority::NotAuthoritative,
+        authority::<Position>(&world, c)
+    );
+
+    let component_authority = ReadAuthority::<Position>::fetch(&world);
+    let joined: Vec<(Entity, Authority)> =
+        (&world.entities(), &*component_authority).join().collect();
+    assert_eq!(
+        vec![
+            (a, Authority::Authoritative),
+            (b, Authority::AuthorityLossImminent)
+        ],
+        joined
+    );
+}
+
+#[test]
+fn fetching_a_storage_before_setup_should_name_the_component() {
+    use crate::generated_test::*;
+    use specs::prelude::WorldExt;
+
+    let mut world = World::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        SpatialWriteStorage::<Position>::fetch(&world);
+    }));
+    let message = result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("improbable.Position"));
+
+    SpatialWriteStorage::<Position>::setup(&mut world);
+    SpatialWriteStorage::<Position>::fetch(&world);
+    assert!(ComponentRegistry::get_interface(Position::ID).is_some());
+
+    // A component without a registered name is identified by its ID and type.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        SpatialWriteStorage::<Counter>::fetch(&world);
+    }));
+    let message = result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("component 1103 ("));
+    assert!(message.contains("generated_test::Counter"));
+}
+
+#[test]
+fn join_with_ids_should_pair_components_with_entity_ids() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+
+    let entity_id = EntityId(WorkerEntityId::new(7));
+    let entity = world.create_entity().with(entity_id).build();
+    world
+        .create_entity()
+        .with(EntityId(WorkerEntityId::new(8)))
+        .build();
+    SpatialWriteStorage::<Position>::unrestricted(&world)
+        .insert(
+            entity,
+            SpatialComponent::new(Position {
+                coords: Coordinates {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            }),
+        )
+        .unwrap();
+
+    let entity_ids = EntityIds::fetch(&world);
+    {
+        let mut positions = SpatialWriteStorage::<Position>::unrestricted(&world);
+        for (id, position) in positions.join_with_ids(&entity_ids) {
+            assert_eq!(entity_id, id);
+            position.coords.x = 2.0;
+        }
+    }
+
+    let positions = SpatialReadStorage::<Position>::fetch(&world);
+    let joined: Vec<_> = positions
+        .join_with_ids(&entity_ids)
+        .map(|(id, position)| (id, position.coords.x))
+        .collect();
+    assert_eq!(vec![(entity_id, 2.0)], joined);
+}
+
+#[test]
+fn insert_default_should_insert_schema_default_component() {
+    use crate::generated_test::*;
+    use crate::SchemaDefault;
+    use specs::prelude::*;
+
+    let mut world = World::new();
+    SpatialWriteStorage::<Position>::setup(&mut world);
+    let entity = world.create_entity().build();
+
+    assert_eq!(0.0, Position::schema_default().coords.x);
+
+    let mut positions = SpatialWriteStorage::<Position>::unrestricted(&world);
+    assert!(positions.insert_default(entity).unwrap().is_none());
+    positions.get_mut(entity).unwrap().coords.x = 1.0;
+
+    let replaced = positions.insert_default(entity).unwrap().unwrap();
+    assert_eq!(1.0, replaced.coords.x);
+    assert_eq!(0.0, positions.get(entity).unwrap().coords.x);
+}
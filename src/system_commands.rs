@@ -1,53 +1,169 @@
+use crate::logging;
+use crate::query_result;
+use crate::sdk::SdkConnection;
 use crate::SystemDataFetch;
 use spatialos_sdk::worker::commands::{
     CreateEntityRequest, DeleteEntityRequest, EntityQueryRequest, ReserveEntityIdsRequest,
 };
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
 use spatialos_sdk::worker::entity::Entity as WorkerEntity;
 use spatialos_sdk::worker::op::{
     CreateEntityResponseOp, DeleteEntityResponseOp, EntityQueryResponseOp, QueryResponse,
     ReserveEntityIdsResponseOp, ReservedEntityIdRange, StatusCode,
 };
-use spatialos_sdk::worker::query::EntityQuery;
+use spatialos_sdk::worker::query::{EntityQuery, ResultType};
 use spatialos_sdk::worker::EntityId as WorkerEntityId;
 use spatialos_sdk::worker::RequestId;
-use specs::prelude::{Resources, SystemData, Write};
+use specs::prelude::{Entity, SystemData, World, Write};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub type SystemCommandSender<'a> = Write<'a, SystemCommandSenderRes>;
 
-type SystemCommandResult<T> = Result<T, StatusCode<T>>;
+/// The result of a system command. On failure, this holds the status code returned by
+/// SpatialOS, including its message.
+pub type SystemCommandResult<T> = Result<T, StatusCode<T>>;
 
-type IntermediateCallback<O> = Box<FnOnce(&Resources, O) + Send + Sync>;
+type IntermediateCallback<O> = Box<FnOnce(&World, O) + Send + Sync>;
+
+type BatchCallback =
+    Box<FnOnce(Vec<SystemCommandResult<WorkerEntityId>>, SystemDataFetch) + Send + Sync>;
+
+struct EntityBatchState {
+    results: Vec<Option<SystemCommandResult<WorkerEntityId>>>,
+    completed: usize,
+    failed: usize,
+    callback: Option<BatchCallback>,
+}
+
+impl EntityBatchState {
+    fn complete(
+        state: &Mutex<EntityBatchState>,
+        res: &World,
+        index: usize,
+        result: SystemCommandResult<WorkerEntityId>,
+    ) {
+        let finished = {
+            let mut state = state.lock().unwrap();
+            state.completed += 1;
+            if result.is_err() {
+                state.failed += 1;
+            }
+            state.results[index] = Some(result);
+
+            if state.completed == state.results.len() {
+                let results = state.results.drain(..).map(Option::unwrap).collect();
+                state.callback.take().map(|callback| (callback, results))
+            } else {
+                None
+            }
+        };
+
+        if let Some((callback, results)) = finished {
+            callback(results, SystemDataFetch::new(res));
+        }
+    }
+}
+
+/// Tracks the progress of a batch of entity creations started with
+/// [`create_entities`](struct.SystemCommandSenderRes.html#method.create_entities).
+#[derive(Clone)]
+pub struct EntityBatchProgress {
+    total: usize,
+    state: Arc<Mutex<EntityBatchState>>,
+}
+
+impl EntityBatchProgress {
+    /// The number of entities in the batch.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// The number of entities which have either been created or have failed to be created.
+    pub fn completed(&self) -> usize {
+        self.state.lock().unwrap().completed
+    }
+
+    /// The number of entities which have failed to be created.
+    pub fn failed(&self) -> usize {
+        self.state.lock().unwrap().failed
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed() == self.total
+    }
+}
 
 pub struct SystemCommandSenderRes {
+    default_timeout: Option<Duration>,
+    call_timeout: Option<Duration>,
+
     reserve_entity_ids_callbacks: HashMap<
         RequestId<ReserveEntityIdsRequest>,
         IntermediateCallback<ReserveEntityIdsResponseOp>,
     >,
-    buffered_reserve_entity_ids_requests:
-        Vec<(u32, IntermediateCallback<ReserveEntityIdsResponseOp>)>,
+    buffered_reserve_entity_ids_requests: Vec<(
+        u32,
+        Option<Duration>,
+        IntermediateCallback<ReserveEntityIdsResponseOp>,
+    )>,
 
     create_entity_callbacks:
         HashMap<RequestId<CreateEntityRequest>, IntermediateCallback<CreateEntityResponseOp>>,
     buffered_create_entity_requests: Vec<(
         NoAccessContainer<WorkerEntity>,
         Option<WorkerEntityId>,
+        Option<Duration>,
         IntermediateCallback<CreateEntityResponseOp>,
     )>,
 
-    delete_entity_callbacks:
-        HashMap<RequestId<DeleteEntityRequest>, IntermediateCallback<DeleteEntityResponseOp>>,
-    buffered_delete_entity_requests:
-        Vec<(WorkerEntityId, IntermediateCallback<DeleteEntityResponseOp>)>,
+    delete_entity_callbacks: HashMap<
+        RequestId<DeleteEntityRequest>,
+        (WorkerEntityId, IntermediateCallback<DeleteEntityResponseOp>),
+    >,
+    buffered_delete_entity_requests: Vec<(
+        WorkerEntityId,
+        Option<Duration>,
+        IntermediateCallback<DeleteEntityResponseOp>,
+    )>,
 
     entity_query_callbacks:
         HashMap<RequestId<EntityQueryRequest>, IntermediateCallback<EntityQueryResponseOp>>,
-    buffered_entity_query_requests: Vec<(EntityQuery, IntermediateCallback<EntityQueryResponseOp>)>,
+    buffered_entity_query_requests: Vec<(
+        EntityQuery,
+        Option<Duration>,
+        IntermediateCallback<EntityQueryResponseOp>,
+    )>,
 }
 
-// TODO expose parameters like timeout
 impl SystemCommandSenderRes {
+    /// Sets the timeout of every system command sent without an explicit timeout. If this
+    /// is `None`, the default of the Worker SDK is used.
+    pub fn set_default_timeout(&mut self, timeout: Option<Duration>) {
+        self.default_timeout = timeout;
+    }
+
+    /// Sends every system command requested within `send` with the given timeout.
+    ///
+    /// ```ignore
+    /// system_command_sender.with_timeout(Duration::from_secs(2), |sender| {
+    ///     sender.entity_query(query, |result, _| { ... });
+    /// });
+    /// ```
+    pub fn with_timeout<R, F>(&mut self, timeout: Duration, send: F) -> R
+    where
+        F: FnOnce(&mut SystemCommandSenderRes) -> R,
+    {
+        let previous = self.call_timeout.replace(timeout);
+        let result = send(self);
+        self.call_timeout = previous;
+        result
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.call_timeout.or(self.default_timeout)
+    }
+
     pub fn reserve_entity_ids<F>(&mut self, number: u32, callback: F)
     where
         F: 'static
@@ -55,8 +171,10 @@ impl SystemCommandSenderRes {
             + Send
             + Sync,
     {
+        let timeout = self.timeout();
         self.buffered_reserve_entity_ids_requests.push((
             number,
+            timeout,
             Box::new(|res, response_op| {
                 callback(
                     SystemCommandSenderRes::status_code_to_result(response_op.status_code),
@@ -74,9 +192,11 @@ impl SystemCommandSenderRes {
     ) where
         F: 'static + FnOnce(SystemCommandResult<WorkerEntityId>, SystemDataFetch) + Send + Sync,
     {
+        let timeout = self.timeout();
         self.buffered_create_entity_requests.push((
             NoAccessContainer::new(entity),
             reserved_entity_id,
+            timeout,
             Box::new(|res, response_op| {
                 callback(
                     SystemCommandSenderRes::status_code_to_result(response_op.status_code),
@@ -86,12 +206,141 @@ impl SystemCommandSenderRes {
         ));
     }
 
+    pub(crate) fn create_entity_internal(
+        &mut self,
+        entity: WorkerEntity,
+        reserved_entity_id: Option<WorkerEntityId>,
+        callback: IntermediateCallback<CreateEntityResponseOp>,
+    ) {
+        let timeout = self.timeout();
+        self.buffered_create_entity_requests.push((
+            NoAccessContainer::new(entity),
+            reserved_entity_id,
+            timeout,
+            callback,
+        ));
+    }
+
+    pub(crate) fn entity_query_internal(
+        &mut self,
+        query: EntityQuery,
+        callback: IntermediateCallback<EntityQueryResponseOp>,
+    ) {
+        let timeout = self.timeout();
+        self.buffered_entity_query_requests
+            .push((query, timeout, callback));
+    }
+
+    /// Creates a batch of entities.
+    ///
+    /// A single request reserves an entity ID for every entity in the batch, after which
+    /// each entity is created with its reserved ID. The callback is called once every entity
+    /// has either been created or failed, with a result per entity in the same order as
+    /// `entities`. If the reservation fails, every entity fails with the reservation error,
+    /// and if fewer IDs are reserved than requested, the entities left over fail with an
+    /// `InternalError`.
+    ///
+    /// The returned `EntityBatchProgress` can be polled to observe the batch's progress.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `entities` is empty.
+    pub fn create_entities<F>(
+        &mut self,
+        entities: Vec<WorkerEntity>,
+        callback: F,
+    ) -> EntityBatchProgress
+    where
+        F: 'static
+            + FnOnce(Vec<SystemCommandResult<WorkerEntityId>>, SystemDataFetch)
+            + Send
+            + Sync,
+    {
+        assert!(
+            !entities.is_empty(),
+            "Attempt to create an empty batch of entities."
+        );
+
+        let total = entities.len();
+        let state = Arc::new(Mutex::new(EntityBatchState {
+            results: (0..total).map(|_| None).collect(),
+            completed: 0,
+            failed: 0,
+            callback: Some(Box::new(callback)),
+        }));
+
+        let entities = NoAccessContainer::new(entities);
+        let batch_state = state.clone();
+        let timeout = self.timeout();
+
+        self.buffered_reserve_entity_ids_requests.push((
+            total as u32,
+            timeout,
+            Box::new(move |res, response_op| {
+                let entities = entities.get_data();
+
+                match SystemCommandSenderRes::status_code_to_result(response_op.status_code) {
+                    Ok(reserved_ids) => {
+                        let (reserved, unreserved) = pair_reserved_ids(entities, reserved_ids);
+
+                        let mut sender = SystemCommandSender::fetch(res);
+                        for (index, entity, entity_id) in reserved {
+                            let state = batch_state.clone();
+                            sender.buffered_create_entity_requests.push((
+                                NoAccessContainer::new(entity),
+                                Some(entity_id),
+                                timeout,
+                                Box::new(move |res, response_op: CreateEntityResponseOp| {
+                                    EntityBatchState::complete(
+                                        &state,
+                                        res,
+                                        index,
+                                        SystemCommandSenderRes::status_code_to_result(
+                                            response_op.status_code,
+                                        ),
+                                    );
+                                }),
+                            ));
+                        }
+                        drop(sender);
+
+                        for index in unreserved {
+                            EntityBatchState::complete(
+                                &batch_state,
+                                res,
+                                index,
+                                Err(StatusCode::InternalError(format!(
+                                    "No entity ID was reserved for entity {} of the batch of {}.",
+                                    index, total
+                                ))),
+                            );
+                        }
+                    }
+                    Err(status_code) => {
+                        for index in 0..entities.len() {
+                            EntityBatchState::complete(
+                                &batch_state,
+                                res,
+                                index,
+                                Err(map_status_code_error(&status_code)),
+                            );
+                        }
+                    }
+                }
+            }),
+        ));
+
+        EntityBatchProgress { total, state }
+    }
+
     pub fn delete_entity<F>(&mut self, entity_id: WorkerEntityId, callback: F)
     where
         F: 'static + FnOnce(SystemCommandResult<()>, SystemDataFetch) + Send + Sync,
     {
+        let timeout = self.timeout();
         self.buffered_delete_entity_requests.push((
             entity_id,
+            timeout,
             Box::new(|res, response_op| {
                 callback(
                     SystemCommandSenderRes::status_code_to_result(response_op.status_code),
@@ -105,8 +354,10 @@ impl SystemCommandSenderRes {
     where
         F: 'static + FnOnce(SystemCommandResult<QueryResponse>, SystemDataFetch) + Send + Sync,
     {
+        let timeout = self.timeout();
         self.buffered_entity_query_requests.push((
             query,
+            timeout,
             Box::new(|res, response_op| {
                 callback(
                     SystemCommandSenderRes::status_code_to_result(response_op.status_code),
@@ -116,8 +367,44 @@ impl SystemCommandSenderRes {
         ));
     }
 
+    /// Sends a snapshot entity query, and creates a temporary specs entity for each result,
+    /// tagged with a `QueryResult` component and holding the data of every registered
+    /// component. The callback is called with the new entities, in entity ID order.
+    ///
+    /// The entities are deleted when the `SpatialReaderSystem` next runs. See the
+    /// `query_result` module for how they interact with joins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the query's result type is not a snapshot.
+    pub fn entity_query_materialized<F>(&mut self, query: EntityQuery, callback: F)
+    where
+        F: 'static + FnOnce(SystemCommandResult<Vec<Entity>>, SystemDataFetch) + Send + Sync,
+    {
+        match query.result_type {
+            ResultType::Snapshot(_) => {}
+            _ => panic!("Attempt to materialize the results of a query which isn't a snapshot."),
+        }
+
+        let timeout = self.timeout();
+        self.buffered_entity_query_requests.push((
+            query,
+            timeout,
+            Box::new(|res, response_op| {
+                let result = match &response_op.status_code {
+                    StatusCode::Success(QueryResponse::Snapshot(snapshot)) => {
+                        Ok(query_result::materialize(res, snapshot))
+                    }
+                    StatusCode::Success(_) => Ok(Vec::new()),
+                    other => Err(map_status_code_error(other)),
+                };
+                callback(result, SystemDataFetch::new(res));
+            }),
+        ));
+    }
+
     pub(crate) fn got_reserve_entity_ids_response(
-        res: &Resources,
+        res: &World,
         response_op: ReserveEntityIdsResponseOp,
     ) {
         let callback = {
@@ -128,11 +415,11 @@ impl SystemCommandSenderRes {
 
         match callback {
             Some(callback) => callback(res, response_op),
-            None => println!("Unknown request ID: {:?}", response_op.request_id),
+            None => logging::warn_unknown_request_id(res, response_op.request_id),
         }
     }
 
-    pub(crate) fn got_create_entity_response(res: &Resources, response_op: CreateEntityResponseOp) {
+    pub(crate) fn got_create_entity_response(res: &World, response_op: CreateEntityResponseOp) {
         let callback = {
             SystemCommandSender::fetch(res)
                 .create_entity_callbacks
@@ -141,11 +428,11 @@ impl SystemCommandSenderRes {
 
         match callback {
             Some(callback) => callback(res, response_op),
-            None => println!("Unknown request ID: {:?}", response_op.request_id),
+            None => logging::warn_unknown_request_id(res, response_op.request_id),
         }
     }
 
-    pub(crate) fn got_delete_entity_response(res: &Resources, response_op: DeleteEntityResponseOp) {
+    pub(crate) fn got_delete_entity_response(res: &World, response_op: DeleteEntityResponseOp) {
         let callback = {
             SystemCommandSender::fetch(res)
                 .delete_entity_callbacks
@@ -153,12 +440,12 @@ impl SystemCommandSenderRes {
         };
 
         match callback {
-            Some(callback) => callback(res, response_op),
-            None => println!("Unknown request ID: {:?}", response_op.request_id),
+            Some((_, callback)) => callback(res, response_op),
+            None => logging::warn_unknown_request_id(res, response_op.request_id),
         }
     }
 
-    pub(crate) fn got_entity_query_response(res: &Resources, response_op: EntityQueryResponseOp) {
+    pub(crate) fn got_entity_query_response(res: &World, response_op: EntityQueryResponseOp) {
         let callback = {
             SystemCommandSender::fetch(res)
                 .entity_query_callbacks
@@ -167,40 +454,98 @@ impl SystemCommandSenderRes {
 
         match callback {
             Some(callback) => callback(res, response_op),
-            None => println!("Unknown request ID: {:?}", response_op.request_id),
+            None => logging::warn_unknown_request_id(res, response_op.request_id),
         }
     }
 
-    pub(crate) fn flush_requests(&mut self, connection: &mut WorkerConnection) {
-        for (number, callback) in self.buffered_reserve_entity_ids_requests.drain(..) {
-            let request_id = connection.send_reserve_entity_ids_request(
-                ReserveEntityIdsRequest(number),
-                Default::default(),
+    // Requests sent on a previous connection will never receive a response, so their
+    // callbacks are called with a `Timeout` status containing `message`.
+    pub(crate) fn fail_sent_requests(res: &World, message: &str) {
+        let (reserve_entity_ids, create_entity, delete_entity, entity_query) = {
+            let mut sender = SystemCommandSender::fetch(res);
+            (
+                sender
+                    .reserve_entity_ids_callbacks
+                    .drain()
+                    .collect::<Vec<_>>(),
+                sender.create_entity_callbacks.drain().collect::<Vec<_>>(),
+                sender.delete_entity_callbacks.drain().collect::<Vec<_>>(),
+                sender.entity_query_callbacks.drain().collect::<Vec<_>>(),
+            )
+        };
+
+        for (request_id, callback) in reserve_entity_ids {
+            callback(
+                res,
+                ReserveEntityIdsResponseOp {
+                    request_id,
+                    status_code: StatusCode::Timeout(message.to_string()),
+                },
+            );
+        }
+
+        for (request_id, callback) in create_entity {
+            callback(
+                res,
+                CreateEntityResponseOp {
+                    request_id,
+                    status_code: StatusCode::Timeout(message.to_string()),
+                },
+            );
+        }
+
+        for (request_id, (entity_id, callback)) in delete_entity {
+            callback(
+                res,
+                DeleteEntityResponseOp {
+                    request_id,
+                    entity_id,
+                    status_code: StatusCode::Timeout(message.to_string()),
+                },
             );
+        }
+
+        for (request_id, callback) in entity_query {
+            callback(
+                res,
+                EntityQueryResponseOp {
+                    request_id,
+                    status_code: StatusCode::Timeout(message.to_string()),
+                },
+            );
+        }
+    }
+
+    pub(crate) fn flush_requests<C: SdkConnection>(&mut self, connection: &mut C) -> usize {
+        let count = self.buffered_reserve_entity_ids_requests.len()
+            + self.buffered_create_entity_requests.len()
+            + self.buffered_delete_entity_requests.len()
+            + self.buffered_entity_query_requests.len();
+
+        for (number, timeout, callback) in self.buffered_reserve_entity_ids_requests.drain(..) {
+            let request_id = connection.send_reserve_entity_ids(number, timeout);
             self.reserve_entity_ids_callbacks
                 .insert(request_id, callback);
         }
 
-        for (entity, entity_id, callback) in self.buffered_create_entity_requests.drain(..) {
-            let request_id = connection.send_create_entity_request(
-                entity.get_data(),
-                entity_id,
-                Default::default(),
-            );
+        for (entity, entity_id, timeout, callback) in self.buffered_create_entity_requests.drain(..)
+        {
+            let request_id = connection.send_create_entity(entity.get_data(), entity_id, timeout);
             self.create_entity_callbacks.insert(request_id, callback);
         }
 
-        for (entity_id, callback) in self.buffered_delete_entity_requests.drain(..) {
-            let request_id = connection
-                .send_delete_entity_request(DeleteEntityRequest(entity_id), Default::default());
-            self.delete_entity_callbacks.insert(request_id, callback);
+        for (entity_id, timeout, callback) in self.buffered_delete_entity_requests.drain(..) {
+            let request_id = connection.send_delete_entity(entity_id, timeout);
+            self.delete_entity_callbacks
+                .insert(request_id, (entity_id, callback));
         }
 
-        for (query, callback) in self.buffered_entity_query_requests.drain(..) {
-            let request_id =
-                connection.send_entity_query_request(EntityQueryRequest(query), Default::default());
+        for (query, timeout, callback) in self.buffered_entity_query_requests.drain(..) {
+            let request_id = connection.send_entity_query(query, timeout);
             self.entity_query_callbacks.insert(request_id, callback);
         }
+
+        count
     }
 
     fn status_code_to_result<T>(status_code: StatusCode<T>) -> Result<T, StatusCode<T>> {
@@ -211,9 +556,46 @@ impl SystemCommandSenderRes {
     }
 }
 
+#[cfg(test)]
+impl SystemCommandSenderRes {
+    // Moves buffered requests to the awaiting callbacks without a connection.
+    pub(crate) fn assign_request_ids_without_sending(&mut self) {
+        let mut next_id = 1;
+
+        for (_number, _timeout, callback) in self.buffered_reserve_entity_ids_requests.drain(..) {
+            self.reserve_entity_ids_callbacks
+                .insert(RequestId::new(next_id), callback);
+            next_id += 1;
+        }
+
+        for (_entity, _entity_id, _timeout, callback) in
+            self.buffered_create_entity_requests.drain(..)
+        {
+            self.create_entity_callbacks
+                .insert(RequestId::new(next_id), callback);
+            next_id += 1;
+        }
+
+        for (entity_id, _timeout, callback) in self.buffered_delete_entity_requests.drain(..) {
+            self.delete_entity_callbacks
+                .insert(RequestId::new(next_id), (entity_id, callback));
+            next_id += 1;
+        }
+
+        for (_query, _timeout, callback) in self.buffered_entity_query_requests.drain(..) {
+            self.entity_query_callbacks
+                .insert(RequestId::new(next_id), callback);
+            next_id += 1;
+        }
+    }
+}
+
 impl Default for SystemCommandSenderRes {
     fn default() -> Self {
         SystemCommandSenderRes {
+            default_timeout: None,
+            call_timeout: None,
+
             reserve_entity_ids_callbacks: HashMap::new(),
             buffered_reserve_entity_ids_requests: Vec::new(),
 
@@ -229,6 +611,39 @@ impl Default for SystemCommandSenderRes {
     }
 }
 
+// Converts an error status code for one kind of request into the same error for another.
+// Pairs each entity with its index in the batch and a reserved ID, also returning the
+// indices of any entities left over if fewer IDs were reserved than requested.
+fn pair_reserved_ids<E, I: IntoIterator<Item = WorkerEntityId>>(
+    entities: Vec<E>,
+    reserved_ids: I,
+) -> (Vec<(usize, E, WorkerEntityId)>, Vec<usize>) {
+    let mut reserved_ids = reserved_ids.into_iter();
+    let mut reserved = Vec::new();
+    let mut unreserved = Vec::new();
+
+    for (index, entity) in entities.into_iter().enumerate() {
+        match reserved_ids.next() {
+            Some(entity_id) => reserved.push((index, entity, entity_id)),
+            None => unreserved.push(index),
+        }
+    }
+
+    (reserved, unreserved)
+}
+
+pub(crate) fn map_status_code_error<T, U>(status_code: &StatusCode<T>) -> StatusCode<U> {
+    match status_code {
+        StatusCode::Success(_) => panic!("Attempt to map a successful status code."),
+        StatusCode::Timeout(message) => StatusCode::Timeout(message.clone()),
+        StatusCode::NotFound(message) => StatusCode::NotFound(message.clone()),
+        StatusCode::AuthorityLost(message) => StatusCode::AuthorityLost(message.clone()),
+        StatusCode::PermissionDenied(message) => StatusCode::PermissionDenied(message.clone()),
+        StatusCode::ApplicationError(message) => StatusCode::ApplicationError(message.clone()),
+        StatusCode::InternalError(message) => StatusCode::InternalError(message.clone()),
+    }
+}
+
 struct NoAccessContainer<T> {
     data: T,
 }
@@ -250,7 +665,7 @@ unsafe impl<T> Sync for NoAccessContainer<T> {}
 #[test]
 fn delete_entity_request_should_work() {
     use spatialos_sdk::worker::EntityId as WorkerEntityId;
-    use specs::prelude::{System, World};
+    use specs::prelude::{System, World, WorldExt};
 
     let mut world = World::new();
 
@@ -260,10 +675,10 @@ fn delete_entity_request_should_work() {
         fn run(&mut self, _sys: Self::SystemData) {}
     }
 
-    <Sys as System>::SystemData::setup(&mut world.res);
+    <Sys as System>::SystemData::setup(&mut world);
 
     {
-        let mut system_command_sender = <Sys as System>::SystemData::fetch(&world.res);
+        let mut system_command_sender = <Sys as System>::SystemData::fetch(&world);
         system_command_sender.delete_entity(WorkerEntityId::new(5), |result, system_data| {
             let system_command_sender = system_data.fetch::<Sys>();
             assert_eq!(
@@ -276,21 +691,21 @@ fn delete_entity_request_should_work() {
 
     {
         let mut requests = {
-            <Sys as System>::SystemData::fetch(&world.res)
+            <Sys as System>::SystemData::fetch(&world)
                 .buffered_delete_entity_requests
                 .drain(..)
                 .collect::<Vec<_>>()
         };
 
-        for (_entity_id, callback) in requests.drain(..) {
-            <Sys as System>::SystemData::fetch(&world.res)
+        for (entity_id, _timeout, callback) in requests.drain(..) {
+            <Sys as System>::SystemData::fetch(&world)
                 .delete_entity_callbacks
-                .insert(RequestId::new(1), callback);
+                .insert(RequestId::new(1), (entity_id, callback));
         }
     }
 
     SystemCommandSenderRes::got_delete_entity_response(
-        &world.res,
+        &world,
         DeleteEntityResponseOp {
             request_id: RequestId::new(1),
             entity_id: WorkerEntityId::new(5),
@@ -298,3 +713,116 @@ fn delete_entity_request_should_work() {
         },
     );
 }
+
+#[test]
+fn system_command_timeouts_should_apply_per_call_over_default() {
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let mut sender = SystemCommandSenderRes::default();
+    sender.set_default_timeout(Some(Duration::from_secs(10)));
+
+    sender.delete_entity(WorkerEntityId::new(1), |_, _| {});
+    sender.with_timeout(Duration::from_secs(2), |sender| {
+        sender.delete_entity(WorkerEntityId::new(2), |_, _| {})
+    });
+    sender.set_default_timeout(None);
+    sender.delete_entity(WorkerEntityId::new(3), |_, _| {});
+
+    let timeouts: Vec<Option<Duration>> = sender
+        .buffered_delete_entity_requests
+        .iter()
+        .map(|(_, timeout, _)| *timeout)
+        .collect();
+    assert_eq!(
+        vec![
+            Some(Duration::from_secs(10)),
+            Some(Duration::from_secs(2)),
+            None
+        ],
+        timeouts
+    );
+}
+
+#[test]
+fn create_entities_should_reserve_once_and_fail_every_entity_with_the_reservation() {
+    use specs::prelude::{System, WorldExt};
+
+    let mut world = World::new();
+
+    struct Sys;
+    impl<'a> System<'a> for Sys {
+        type SystemData = SystemCommandSender<'a>;
+        fn run(&mut self, _sys: Self::SystemData) {}
+    }
+
+    <Sys as System>::SystemData::setup(&mut world);
+
+    let progress = {
+        let mut system_command_sender = <Sys as System>::SystemData::fetch(&world);
+        system_command_sender.create_entities(
+            vec![
+                WorkerEntity::new(),
+                WorkerEntity::new(),
+                WorkerEntity::new(),
+            ],
+            |results, _| {
+                assert_eq!(3, results.len());
+                for result in results {
+                    match result {
+                        Err(StatusCode::Timeout(message)) => assert_eq!("timed out", message),
+                        other => panic!("Unexpected result {:?}", other),
+                    }
+                }
+            },
+        )
+    };
+
+    {
+        let mut system_command_sender = <Sys as System>::SystemData::fetch(&world);
+        assert_eq!(
+            1,
+            system_command_sender
+                .buffered_reserve_entity_ids_requests
+                .len()
+        );
+
+        let (number, _timeout, callback) = system_command_sender
+            .buffered_reserve_entity_ids_requests
+            .remove(0);
+        assert_eq!(3, number);
+        system_command_sender
+            .reserve_entity_ids_callbacks
+            .insert(RequestId::new(1), callback);
+    }
+
+    assert_eq!(3, progress.total());
+    assert_eq!(0, progress.completed());
+    assert!(!progress.is_complete());
+
+    SystemCommandSenderRes::got_reserve_entity_ids_response(
+        &world,
+        ReserveEntityIdsResponseOp {
+            request_id: RequestId::new(1),
+            status_code: StatusCode::Timeout("timed out".to_owned()),
+        },
+    );
+
+    assert!(progress.is_complete());
+    assert_eq!(3, progress.failed());
+    assert!(<Sys as System>::SystemData::fetch(&world)
+        .buffered_create_entity_requests
+        .is_empty());
+}
+
+#[test]
+fn create_entities_should_fail_entities_without_a_reserved_id() {
+    let ids: Vec<WorkerEntityId> = (1..=2).map(WorkerEntityId::new).collect();
+
+    let (reserved, unreserved) = pair_reserved_ids(vec!["a", "b", "c"], ids.clone());
+    assert_eq!(vec![(0, "a", ids[0]), (1, "b", ids[1])], reserved);
+    assert_eq!(vec![2], unreserved);
+
+    let (reserved, unreserved) = pair_reserved_ids(vec!["a"], ids.clone());
+    assert_eq!(vec![(0, "a", ids[0])], reserved);
+    assert!(unreserved.is_empty());
+}
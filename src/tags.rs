@@ -0,0 +1,164 @@
+//! Marker components inserted according to the `entity_type` of each entity's `Metadata`,
+//! so that systems can join on a marker rather than comparing strings:
+//!
+//! ```ignore
+//! #[derive(Default)]
+//! struct TreeTag;
+//!
+//! impl Component for TreeTag {
+//!     type Storage = NullStorage<Self>;
+//! }
+//!
+//! world.register::<TreeTag>();
+//! world.insert(
+//!     EntityTags::new(|metadata: &Metadata| metadata.entity_type.clone())
+//!         .with_tag::<TreeTag>("Tree"),
+//! );
+//!
+//! for (_, position) in (&trees, &positions).join() {
+//!     // ...
+//! }
+//! ```
+//!
+//! Tags are inserted by the `SpatialReaderSystem` when the metadata component is added,
+//! replaced if its entity type changes, and removed with it. Tags whose storage has not
+//! been registered are skipped.
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Component, Entity, SystemData, World, WriteStorage};
+use specs::storage::MaskedStorage;
+use std::any::Any;
+use std::collections::HashMap;
+
+type EntityTypeFn = Box<Fn(&Any) -> Option<String> + Send + Sync>;
+type TagFn = Box<Fn(&World, Entity, bool) + Send + Sync>;
+
+/// A resource mapping entity types to the marker components inserted for them.
+pub struct EntityTags {
+    metadata_component: ComponentId,
+    entity_type: EntityTypeFn,
+    tags: HashMap<String, Vec<TagFn>>,
+    entity_types: HashMap<Entity, String>,
+}
+
+impl EntityTags {
+    /// Takes the entity type of each entity from the component `M`, usually `Metadata`.
+    pub fn new<M, F>(entity_type: F) -> EntityTags
+    where
+        M: 'static + WorkerComponent,
+        F: 'static + Fn(&M) -> String + Send + Sync,
+    {
+        EntityTags {
+            metadata_component: M::ID,
+            entity_type: Box::new(move |value| value.downcast_ref::<M>().map(&entity_type)),
+            tags: HashMap::new(),
+            entity_types: HashMap::new(),
+        }
+    }
+
+    /// Inserts the default value of `C` into every entity of the entity type.
+    pub fn with_tag<C>(mut self, entity_type: &str) -> EntityTags
+    where
+        C: Component + Default + Send + Sync,
+    {
+        self.tags
+            .entry(entity_type.to_string())
+            .or_insert_with(Vec::new)
+            .push(Box::new(|res, entity, insert| {
+                if !res.has_value::<MaskedStorage<C>>() {
+                    return;
+                }
+
+                let mut storage = WriteStorage::<C>::fetch(res);
+                if insert {
+                    // The entity is alive, as its metadata has just been received.
+                    let _ = storage.insert(entity, C::default());
+                } else {
+                    storage.remove(entity);
+                }
+            }));
+        self
+    }
+
+    /// The entity type of the entity, if its metadata has been received.
+    pub fn entity_type(&self, entity: Entity) -> Option<&str> {
+        self.entity_types.get(&entity).map(String::as_str)
+    }
+
+    pub(crate) fn is_metadata_component(&self, component_id: ComponentId) -> bool {
+        component_id == self.metadata_component
+    }
+
+    pub(crate) fn metadata_received(&mut self, res: &World, entity: Entity, metadata: &Any) {
+        let entity_type = match (self.entity_type)(metadata) {
+            Some(entity_type) => entity_type,
+            None => return,
+        };
+
+        if self.entity_type(entity) == Some(entity_type.as_str()) {
+            return;
+        }
+
+        self.metadata_removed(res, entity);
+        self.apply(res, entity, &entity_type, true);
+        self.entity_types.insert(entity, entity_type);
+    }
+
+    pub(crate) fn metadata_removed(&mut self, res: &World, entity: Entity) {
+        if let Some(entity_type) = self.entity_types.remove(&entity) {
+            self.apply(res, entity, &entity_type, false);
+        }
+    }
+
+    fn apply(&self, res: &World, entity: Entity, entity_type: &str, insert: bool) {
+        for tag in self.tags.get(entity_type).into_iter().flatten() {
+            tag(res, entity, insert);
+        }
+    }
+}
+
+#[test]
+fn entity_tags_should_follow_the_entity_type() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, NullStorage, ReadStorage, WorldExt};
+
+    #[derive(Default)]
+    struct TreeTag;
+
+    impl Component for TreeTag {
+        type Storage = NullStorage<Self>;
+    }
+
+    let mut world = World::new();
+    world.register::<TreeTag>();
+    let entity = world.create_entity().build();
+
+    // Position stands in for Metadata, with trees at x = 0.
+    let mut tags = EntityTags::new(|position: &Position| {
+        if position.coords.x == 0.0 {
+            "Tree".to_string()
+        } else {
+            "Rock".to_string()
+        }
+    })
+    .with_tag::<TreeTag>("Tree");
+
+    let at = |x| Position {
+        coords: Coordinates { x, y: 0.0, z: 0.0 },
+    };
+    let is_tree = |world: &World| ReadStorage::<TreeTag>::fetch(world).contains(entity);
+
+    assert!(tags.is_metadata_component(Position::ID));
+
+    tags.metadata_received(&world, entity, &at(0.0));
+    assert!(is_tree(&world));
+    assert_eq!(Some("Tree"), tags.entity_type(entity));
+
+    tags.metadata_received(&world, entity, &at(1.0));
+    assert!(!is_tree(&world));
+
+    tags.metadata_received(&world, entity, &at(0.0));
+    tags.metadata_removed(&world, entity);
+    assert!(!is_tree(&world));
+    assert_eq!(None, tags.entity_type(entity));
+}
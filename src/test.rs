@@ -0,0 +1,85 @@
+//! Helpers for unit testing systems against a plain specs `World`, without a connection.
+//!
+//! ```ignore
+//! let mut world = World::new();
+//! SpatialWriteStorage::<Position>::setup(&mut world);
+//! // ... insert entities and run the system under test.
+//!
+//! assert_eq!(
+//!     vec![(entity_id, PositionUpdate { coords: Some(target) })],
+//!     collect_pending_updates::<Position>(&world)
+//! );
+//! ```
+use crate::entities::{EntityId, EntityIds};
+use crate::storage::SpatialWriteStorage;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use specs::prelude::{Join, SystemData, World};
+
+/// Returns the update which the `SpatialWriterSystem` would send for each SpatialOS
+/// entity with the component, ordered by entity ID.
+///
+/// The updates remain pending, so calling this doesn't change what is sent. Components
+/// which have been mutably dereferenced have an update containing every field.
+pub fn collect_pending_updates<T: 'static + WorkerComponent>(
+    world: &World,
+) -> Vec<(EntityId, T::Update)> {
+    let storage = match SpatialWriteStorage::<T>::try_fetch_component_storage(world) {
+        Some(storage) => storage,
+        None => return Vec::new(),
+    };
+    let entity_ids = EntityIds::fetch(world);
+
+    let mut updates: Vec<_> = (&entity_ids, &storage)
+        .join()
+        .filter_map(|(entity_id, component)| {
+            component
+                .outgoing_update()
+                .map(|update| (*entity_id, update))
+        })
+        .collect();
+    updates.sort_by_key(|(entity_id, _)| *entity_id);
+    updates
+}
+
+#[test]
+fn pending_updates_should_be_collected_without_being_sent() {
+    use crate::generated_test::*;
+    use crate::SpatialComponent;
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+    use specs::prelude::{Builder, WorldExt};
+
+    let mut world = World::new();
+    EntityIds::setup(&mut world);
+    SpatialWriteStorage::<Position>::setup(&mut world);
+
+    let coords = |x| Coordinates { x, y: 0.0, z: 0.0 };
+    let mut expected = Vec::new();
+    for id in 1..4 {
+        let entity_id = EntityId(WorkerEntityId::new(id));
+        let entity = world.create_entity().with(entity_id).build();
+
+        let mut component = SpatialComponent::new(Position {
+            coords: coords(0.0),
+        });
+        if id != 2 {
+            let update = PositionUpdate {
+                coords: Some(coords(id as f64)),
+            };
+            component.send_update(update);
+            expected.push((entity_id, Some(id as f64)));
+        }
+
+        SpatialWriteStorage::<Position>::unrestricted(&world)
+            .insert(entity, component)
+            .unwrap();
+    }
+
+    let collect = || {
+        collect_pending_updates::<Position>(&world)
+            .into_iter()
+            .map(|(entity_id, update)| (entity_id, update.coords.map(|coords| coords.x)))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(expected, collect());
+    assert_eq!(expected, collect());
+}
@@ -0,0 +1,174 @@
+//! An optional controller which adapts the tick rate of a worker to its load.
+use specs::prelude::World;
+use std::time::{Duration, Instant};
+
+// The fraction of the interval which the work done in a frame may use before the
+// interval is lengthened.
+const HEADROOM: f64 = 0.8;
+
+// The weight given to the most recent frame when averaging the work done per frame.
+const SMOOTHING: f64 = 0.2;
+
+/// A resource which measures the time spent between the start of the `SpatialReaderSystem`
+/// and the end of the `SpatialWriterSystem`, and recommends how long to sleep before the
+/// next frame.
+///
+/// While the work done per frame fits comfortably in `min_interval`, the worker ticks at
+/// that interval. As the work grows, the interval is lengthened up to `max_interval`, so an
+/// overloaded worker ticks less often rather than falling further behind every frame. If more
+/// ops than the backlog threshold were received in a frame, no sleep is recommended until
+/// the backlog has been drained.
+///
+/// ```ignore
+/// world.insert(TickRateController::new(
+///     Duration::from_millis(30),
+///     Duration::from_millis(200),
+/// ));
+///
+/// loop {
+///     dispatcher.dispatch(&world);
+///     thread::sleep(world.read_resource::<TickRateController>().recommended_sleep());
+/// }
+/// ```
+pub struct TickRateController {
+    min_interval: Duration,
+    max_interval: Duration,
+    backlog_threshold: usize,
+    interval: Duration,
+    average_work: Option<f64>,
+    frame_start: Option<Instant>,
+    ops_received: usize,
+    last_work: Duration,
+    recommended_sleep: Duration,
+}
+
+impl TickRateController {
+    pub fn new(min_interval: Duration, max_interval: Duration) -> TickRateController {
+        TickRateController {
+            min_interval,
+            max_interval,
+            backlog_threshold: 1000,
+            interval: min_interval,
+            average_work: None,
+            frame_start: None,
+            ops_received: 0,
+            last_work: Duration::from_secs(0),
+            recommended_sleep: min_interval,
+        }
+    }
+
+    /// Sets the number of ops received in a single frame above which no sleep is recommended.
+    pub fn set_backlog_threshold(&mut self, backlog_threshold: usize) {
+        self.backlog_threshold = backlog_threshold;
+    }
+
+    /// How long to sleep before dispatching the next frame.
+    pub fn recommended_sleep(&self) -> Duration {
+        self.recommended_sleep
+    }
+
+    /// The current target time between the start of consecutive frames.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The current target number of frames per second.
+    pub fn tick_rate(&self) -> f64 {
+        1.0 / as_secs(self.interval)
+    }
+
+    /// The time spent between the start of the reader and the end of the writer in the
+    /// last frame.
+    pub fn last_frame_work(&self) -> Duration {
+        self.last_work
+    }
+
+    pub(crate) fn reader_started(&mut self, now: Instant) {
+        self.frame_start = Some(now);
+    }
+
+    pub(crate) fn reader_finished(&mut self, ops_received: usize) {
+        self.ops_received = ops_received;
+    }
+
+    pub(crate) fn writer_finished(&mut self, now: Instant) {
+        let frame_start = match self.frame_start.take() {
+            Some(frame_start) => frame_start,
+            None => return,
+        };
+
+        self.last_work = now.duration_since(frame_start);
+
+        let work = as_secs(self.last_work);
+        let average_work = match self.average_work {
+            Some(average) => average + SMOOTHING * (work - average),
+            None => work,
+        };
+        self.average_work = Some(average_work);
+
+        let interval = (average_work / HEADROOM)
+            .max(as_secs(self.min_interval))
+            .min(as_secs(self.max_interval));
+        self.interval = from_secs(interval);
+
+        self.recommended_sleep = if self.ops_received > self.backlog_threshold {
+            Duration::from_secs(0)
+        } else {
+            from_secs((interval - work).max(0.0))
+        };
+    }
+}
+
+impl Default for TickRateController {
+    fn default() -> Self {
+        TickRateController::new(Duration::from_millis(30), Duration::from_millis(200))
+    }
+}
+
+pub(crate) fn with_controller<F: FnOnce(&mut TickRateController)>(res: &World, f: F) {
+    if res.has_value::<TickRateController>() {
+        f(&mut res.fetch_mut::<TickRateController>());
+    }
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+fn from_secs(secs: f64) -> Duration {
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
+
+#[test]
+fn controller_should_lengthen_interval_under_load() {
+    let millis = Duration::from_millis;
+    let close = |a: Duration, b: Duration| (as_secs(a) - as_secs(b)).abs() < 1e-3;
+
+    let mut controller = TickRateController::new(millis(30), millis(200));
+    let start = Instant::now();
+
+    let frame = |controller: &mut TickRateController, work: Duration, ops_received: usize| {
+        controller.reader_started(start);
+        controller.reader_finished(ops_received);
+        controller.writer_finished(start + work);
+    };
+
+    frame(&mut controller, millis(10), 0);
+    assert!(close(millis(30), controller.interval()));
+    assert!(close(millis(20), controller.recommended_sleep()));
+
+    for _ in 0..50 {
+        frame(&mut controller, millis(80), 0);
+    }
+    assert!(close(millis(100), controller.interval()));
+    assert!(close(millis(20), controller.recommended_sleep()));
+
+    frame(&mut controller, millis(80), 5000);
+    assert_eq!(Duration::from_secs(0), controller.recommended_sleep());
+
+    for _ in 0..50 {
+        frame(&mut controller, millis(1000), 0);
+    }
+    assert!(close(millis(200), controller.interval()));
+    assert_eq!(Duration::from_secs(0), controller.recommended_sleep());
+}
@@ -0,0 +1,207 @@
+//! Stamping outgoing updates with the simulation tick, so that receivers can interpolate
+//! by tick rather than by when updates happen to arrive.
+//!
+//! A `TickStamping` resource counts the ticks of this worker, advancing once per frame
+//! after the `SpatialWriterSystem` has sent updates. Components with a schema field for the
+//! tick have it set in every update this worker sends, and in the local value, and the
+//! ticks in updates received for them are recorded. Received updates which don't set the
+//! tick field leave the recorded tick as it was:
+//!
+//! ```ignore
+//! let mut stamping = TickStamping::new();
+//! stamping.stamp::<Transform, _>(|update, tick| update.tick = Some(tick));
+//! stamping.read::<Transform, _>(|update| update.tick);
+//! world.insert(stamping);
+//!
+//! // On the receiving worker.
+//! if let Some(remote) = world.fetch::<TickStamping>().remote_tick(entity, Transform::ID) {
+//!     interpolate(remote.tick, remote.received_at);
+//! }
+//! ```
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use specs::prelude::{Entity, World};
+use std::any::Any;
+use std::collections::HashMap;
+use std::time::Instant;
+
+type StampFn = Box<Fn(&mut Any, u64) + Send + Sync>;
+type ReadFn = Box<Fn(&Any) -> Option<u64> + Send + Sync>;
+
+/// The tick of the most recent update received for a component of an entity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RemoteTick {
+    pub tick: u64,
+    /// When the update was received, according to the `SpatialClock`.
+    pub received_at: Instant,
+}
+
+/// A resource counting simulation ticks, which stamps them into outgoing updates and
+/// records them from received updates.
+pub struct TickStamping {
+    tick: u64,
+    stampers: HashMap<ComponentId, StampFn>,
+    readers: HashMap<ComponentId, ReadFn>,
+    remote_ticks: HashMap<(Entity, ComponentId), RemoteTick>,
+}
+
+impl TickStamping {
+    pub fn new() -> TickStamping {
+        TickStamping {
+            tick: 0,
+            stampers: HashMap::new(),
+            readers: HashMap::new(),
+            remote_ticks: HashMap::new(),
+        }
+    }
+
+    /// The tick which updates sent at the end of this frame are stamped with.
+    pub fn current_tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Sets the current tick, for example to match a tick counted by the game loop.
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    /// Writes the tick into every update of the component sent by this worker, and into
+    /// the local value when the update is sent.
+    pub fn stamp<T, F>(&mut self, stamp: F)
+    where
+        T: 'static + WorkerComponent,
+        F: 'static + Fn(&mut T::Update, u64) + Send + Sync,
+    {
+        self.stampers.insert(
+            T::ID,
+            Box::new(move |update, tick| {
+                if let Some(update) = update.downcast_mut::<T::Update>() {
+                    stamp(update, tick);
+                }
+            }),
+        );
+    }
+
+    /// Reads the tick from each received update of the component, returning `None` if the
+    /// update doesn't set it.
+    pub fn read<T, F>(&mut self, read: F)
+    where
+        T: 'static + WorkerComponent,
+        F: 'static + Fn(&T::Update) -> Option<u64> + Send + Sync,
+    {
+        self.readers.insert(
+            T::ID,
+            Box::new(move |update| {
+                update
+                    .downcast_ref::<T::Update>()
+                    .and_then(|update| read(update))
+            }),
+        );
+    }
+
+    /// The tick of the last update received for the component of the entity, if the
+    /// component's tick is read.
+    pub fn remote_tick(&self, entity: Entity, component_id: ComponentId) -> Option<RemoteTick> {
+        self.remote_ticks.get(&(entity, component_id)).cloned()
+    }
+
+    pub(crate) fn stamps(&self, component_id: ComponentId) -> bool {
+        self.stampers.contains_key(&component_id)
+    }
+
+    pub(crate) fn stamp_update(&self, component_id: ComponentId, update: &mut Any) {
+        if let Some(stamp) = self.stampers.get(&component_id) {
+            stamp(update, self.tick);
+        }
+    }
+
+    pub(crate) fn update_received(
+        &mut self,
+        entity: Entity,
+        component_id: ComponentId,
+        update: &Any,
+        now: Instant,
+    ) {
+        let tick = match self.readers.get(&component_id) {
+            Some(read) => read(update),
+            None => return,
+        };
+
+        if let Some(tick) = tick {
+            self.remote_ticks.insert(
+                (entity, component_id),
+                RemoteTick {
+                    tick,
+                    received_at: now,
+                },
+            );
+        }
+    }
+
+    pub(crate) fn component_removed(&mut self, entity: Entity, component_id: ComponentId) {
+        self.remote_ticks.remove(&(entity, component_id));
+    }
+
+    pub(crate) fn finish_frame(res: &World) {
+        res.fetch_mut::<TickStamping>().tick += 1;
+    }
+}
+
+impl Default for TickStamping {
+    fn default() -> Self {
+        TickStamping::new()
+    }
+}
+
+#[test]
+fn tick_stamping_should_stamp_and_record_ticks() {
+    use crate::generated_test::*;
+    use specs::prelude::{Builder, WorldExt};
+    use std::time::Duration;
+
+    let mut stamping = TickStamping::new();
+    stamping.stamp::<Position, _>(|update, tick| {
+        update.coords = Some(Coordinates {
+            x: tick as f64,
+            y: 0.0,
+            z: 0.0,
+        })
+    });
+    stamping.read::<Position, _>(|update| update.coords.map(|coords| coords.x as u64));
+    stamping.set_tick(41);
+
+    let mut world = World::new();
+    let entity = world.create_entity().build();
+    world.insert(stamping);
+    TickStamping::finish_frame(&world);
+
+    let mut stamping = world.fetch_mut::<TickStamping>();
+    assert_eq!(42, stamping.current_tick());
+    assert!(stamping.stamps(Position::ID));
+    assert!(!stamping.stamps(Blob::ID));
+
+    let mut update = PositionUpdate { coords: None };
+    stamping.stamp_update(Position::ID, &mut update);
+    assert_eq!(42.0, update.coords.unwrap().x);
+
+    let now = Instant::now();
+    stamping.update_received(entity, Position::ID, &update, now);
+    let remote = Some(RemoteTick {
+        tick: 42,
+        received_at: now,
+    });
+    assert_eq!(remote, stamping.remote_tick(entity, Position::ID));
+
+    // Updates without the tick don't change the recorded one.
+    let later = now + Duration::from_secs(1);
+    stamping.update_received(
+        entity,
+        Position::ID,
+        &PositionUpdate { coords: None },
+        later,
+    );
+    assert_eq!(remote, stamping.remote_tick(entity, Position::ID));
+
+    stamping.component_removed(entity, Position::ID);
+    assert_eq!(None, stamping.remote_tick(entity, Position::ID));
+}
@@ -0,0 +1,64 @@
+//! Builders for component updates.
+//!
+//! The code generator does not emit builders, so `update_builder!` generates them from the
+//! fields of a component's update type:
+//!
+//! ```ignore
+//! update_builder!(Player, PlayerUpdate {
+//!     name: String,
+//!     current_direction: u32,
+//! });
+//!
+//! player.send_update(Player::update().name("x").current_direction(2));
+//! ```
+//!
+//! Fields which are not set are left as `None`, so are not sent.
+
+/// Generates `Update::new()`, a builder method for each field of the update, and a
+/// `Component::update()` shortcut. The update type must implement `Default`, which
+/// generated update types do.
+#[macro_export]
+macro_rules! update_builder {
+    ($component:ty, $update:ty { $($field:ident : $field_type:ty),* $(,)* }) => {
+        impl $component {
+            /// Creates an empty update, which sends nothing until fields are set.
+            pub fn update() -> $update {
+                <$update as Default>::default()
+            }
+        }
+
+        impl $update {
+            pub fn new() -> $update {
+                <$update as Default>::default()
+            }
+
+            $(
+                pub fn $field<V: Into<$field_type>>(mut self, value: V) -> $update {
+                    self.$field = Some(value.into());
+                    self
+                }
+            )*
+        }
+    };
+}
+
+#[test]
+fn update_builder_should_set_fields() {
+    use crate::generated_test::*;
+
+    update_builder!(
+        Position,
+        PositionUpdate {
+            coords: Coordinates
+        }
+    );
+
+    assert!(PositionUpdate::new().coords.is_none());
+
+    let update = Position::update().coords(Coordinates {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+    });
+    assert_eq!(2.0, update.coords.unwrap().y);
+}
@@ -0,0 +1,244 @@
+//! A plain data view of the entities, components and authority of a worker, independent
+//! of specs.
+//!
+//! A `View` applies ops in the same way as the `SpatialReaderSystem`, using the same
+//! component registry, so tools which do not use an ECS, such as CLIs, inspectors and
+//! migration scripts, can reuse this crate's op handling:
+//!
+//! ```ignore
+//! View::register::<Position>();
+//!
+//! let mut view = View::new();
+//! for op in &connection.get_op_list(0) {
+//!     view.apply(&op);
+//! }
+//!
+//! for entity_id in view.entity_ids() {
+//!     println!("{}: {:?}", entity_id, view.get::<Position>(entity_id));
+//! }
+//! ```
+//!
+//! Adding a `View` resource to a specs world keeps it in sync with the ops applied by the
+//! `SpatialReaderSystem`. It is cleared by `SpatialReaderSystem::reconnect`, along with the
+//! entities in the world, and refilled as they are checked out again.
+use crate::component_registry::ComponentRegistry;
+use crate::entities::EntityId;
+use spatialos_sdk::worker::component::Component as WorkerComponent;
+use spatialos_sdk::worker::component::ComponentId;
+use spatialos_sdk::worker::op::WorkerOp;
+use spatialos_sdk::worker::Authority;
+use std::any::Any;
+use std::collections::HashMap;
+
+type ComponentValue = Box<Any + Send + Sync>;
+
+#[derive(Default)]
+struct ViewEntity {
+    components: HashMap<ComponentId, ComponentValue>,
+    authority: HashMap<ComponentId, Authority>,
+}
+
+/// The entities checked out by a worker, with the data and authority of their components.
+///
+/// Only components which have been registered, either with `View::register` or by setting
+/// up a specs storage for them, are stored.
+#[derive(Default)]
+pub struct View {
+    entities: HashMap<EntityId, ViewEntity>,
+}
+
+impl View {
+    pub fn new() -> View {
+        View::default()
+    }
+
+    /// Registers a component so that its data is stored by every `View`.
+    pub fn register<T: 'static + WorkerComponent>() {
+        ComponentRegistry::register_component::<T>();
+    }
+
+    /// Applies an op received from SpatialOS.
+    pub fn apply(&mut self, op: &WorkerOp) {
+        match op {
+            WorkerOp::AddEntity(add_entity_op) => {
+                self.add_entity(EntityId(add_entity_op.entity_id));
+            }
+            WorkerOp::RemoveEntity(remove_entity_op) => {
+                self.remove_entity(EntityId(remove_entity_op.entity_id));
+            }
+            WorkerOp::AddComponent(add_component) => {
+                if let Some(interface) =
+                    ComponentRegistry::get_interface(add_component.component_id)
+                {
+                    if let Some(value) = interface.view_data(add_component) {
+                        self.insert_component(
+                            EntityId(add_component.entity_id),
+                            add_component.component_id,
+                            value,
+                        );
+                    }
+                }
+            }
+            WorkerOp::RemoveComponent(remove_component) => {
+                if let Some(entity) = self.entities.get_mut(&EntityId(remove_component.entity_id)) {
+                    entity.components.remove(&remove_component.component_id);
+                    entity.authority.remove(&remove_component.component_id);
+                }
+            }
+            WorkerOp::ComponentUpdate(update) => {
+                let value = self
+                    .entities
+                    .get_mut(&EntityId(update.entity_id))
+                    .and_then(|entity| entity.components.get_mut(&update.component_id));
+
+                if let (Some(value), Some(interface)) =
+                    (value, ComponentRegistry::get_interface(update.component_id))
+                {
+                    interface.view_update(&mut **value, update);
+                }
+            }
+            WorkerOp::AuthorityChange(authority_change) => {
+                self.set_authority(
+                    EntityId(authority_change.entity_id),
+                    authority_change.component_id,
+                    authority_change.authority,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.entities.contains_key(&entity_id)
+    }
+
+    pub fn entity_ids<'a>(&'a self) -> impl Iterator<Item = EntityId> + 'a {
+        self.entities.keys().cloned()
+    }
+
+    pub fn has_component(&self, entity_id: EntityId, component_id: ComponentId) -> bool {
+        self.entities.get(&entity_id).map_or(false, |entity| {
+            entity.components.contains_key(&component_id)
+        })
+    }
+
+    /// Returns the data of the component of the entity, if it is checked out and has the
+    /// component.
+    pub fn get<T: 'static + WorkerComponent>(&self, entity_id: EntityId) -> Option<&T> {
+        self.entities
+            .get(&entity_id)?
+            .components
+            .get(&T::ID)?
+            .downcast_ref::<T>()
+    }
+
+    /// Returns this worker's authority over the component of the entity.
+    pub fn authority(&self, entity_id: EntityId, component_id: ComponentId) -> Authority {
+        self.entities
+            .get(&entity_id)
+            .and_then(|entity| entity.authority.get(&component_id))
+            .cloned()
+            .unwrap_or(Authority::NotAuthoritative)
+    }
+
+    pub fn is_authoritative(&self, entity_id: EntityId, component_id: ComponentId) -> bool {
+        self.authority(entity_id, component_id) != Authority::NotAuthoritative
+    }
+
+    /// Removes every entity, as none are checked out on a new connection.
+    pub fn clear(&mut self) {
+        self.entities.clear();
+    }
+
+    fn add_entity(&mut self, entity_id: EntityId) {
+        self.entities.insert(entity_id, ViewEntity::default());
+    }
+
+    fn remove_entity(&mut self, entity_id: EntityId) {
+        self.entities.remove(&entity_id);
+    }
+
+    fn insert_component(
+        &mut self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        value: ComponentValue,
+    ) {
+        if let Some(entity) = self.entities.get_mut(&entity_id) {
+            entity.components.insert(component_id, value);
+        }
+    }
+
+    fn set_authority(
+        &mut self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        authority: Authority,
+    ) {
+        if let Some(entity) = self.entities.get_mut(&entity_id) {
+            entity.authority.insert(component_id, authority);
+        }
+    }
+}
+
+#[test]
+fn view_should_follow_the_ops_it_is_given() {
+    use crate::generated_test::*;
+    use spatialos_sdk::worker::op::{
+        AddEntityOp, AuthorityChangeOp, RemoveComponentOp, RemoveEntityOp,
+    };
+    use spatialos_sdk::worker::EntityId as WorkerEntityId;
+
+    let worker_entity_id = WorkerEntityId::new(3);
+    let entity_id = EntityId(worker_entity_id);
+    let position = Position {
+        coords: Coordinates {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        },
+    };
+
+    let mut view = View::new();
+    view.apply(&WorkerOp::AuthorityChange(AuthorityChangeOp {
+        entity_id: worker_entity_id,
+        component_id: Position::ID,
+        authority: Authority::Authoritative,
+    }));
+    assert!(!view.contains(entity_id));
+
+    view.apply(&WorkerOp::AddEntity(AddEntityOp {
+        entity_id: worker_entity_id,
+    }));
+    // Component data can only be received from the SDK, so it is inserted directly.
+    view.insert_component(entity_id, Position::ID, Box::new(position));
+    view.apply(&WorkerOp::AuthorityChange(AuthorityChangeOp {
+        entity_id: worker_entity_id,
+        component_id: Position::ID,
+        authority: Authority::Authoritative,
+    }));
+
+    assert_eq!(2.0, view.get::<Position>(entity_id).unwrap().coords.y);
+    assert!(view.get::<Inventory>(entity_id).is_none());
+    assert!(view.is_authoritative(entity_id, Position::ID));
+    assert_eq!(vec![entity_id], view.entity_ids().collect::<Vec<_>>());
+
+    view.apply(&WorkerOp::RemoveComponent(RemoveComponentOp {
+        entity_id: worker_entity_id,
+        component_id: Position::ID,
+    }));
+    assert!(view.contains(entity_id));
+    assert!(!view.has_component(entity_id, Position::ID));
+    assert!(!view.is_authoritative(entity_id, Position::ID));
+
+    view.apply(&WorkerOp::RemoveEntity(RemoveEntityOp {
+        entity_id: worker_entity_id,
+    }));
+    assert!(!view.contains(entity_id));
+
+    view.apply(&WorkerOp::AddEntity(AddEntityOp {
+        entity_id: worker_entity_id,
+    }));
+    view.clear();
+    assert_eq!(0, view.entity_ids().count());
+}